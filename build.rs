@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        // the protobuf compiler is vendored, so building doesn't need one installed
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/transactions.proto"], &["proto"])?;
+    }
+    Ok(())
+}
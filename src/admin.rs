@@ -0,0 +1,105 @@
+#[cfg(feature = "admin")]
+use std::time::Duration;
+
+#[cfg(feature = "admin")]
+use anyhow::ensure;
+use anyhow::Result;
+#[cfg(feature = "admin")]
+use reqwest::{Client, RequestBuilder};
+#[cfg(feature = "admin")]
+use serde_json::{json, Value};
+
+#[cfg(feature = "admin")]
+use crate::api::read_token;
+use crate::cli::AdminArgs;
+#[cfg(feature = "admin")]
+use crate::cli::{AccountsCommand, AdminCommand, DisputesCommand, SnapshotCommand};
+
+/// How long the server may take to answer
+#[cfg(feature = "admin")]
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends a command to the admin API of a running server and prints its answer as json
+///
+/// # Errors
+/// If the server can't be reached or refuses the command, an error will be returned
+pub async fn admin(args: &AdminArgs) -> Result<()> {
+    #[cfg(feature = "admin")]
+    return send(args).await;
+    #[cfg(not(feature = "admin"))]
+    anyhow::bail!(
+        "Can't reach {}: built without the `admin` feature",
+        args.server
+    );
+}
+
+#[cfg(feature = "admin")]
+async fn send(args: &AdminArgs) -> Result<()> {
+    let client = Client::builder().timeout(TIMEOUT).build()?;
+    let server = args.server.trim_end_matches('/');
+    let request = match &args.command {
+        AdminCommand::Accounts(AccountsCommand::Get { client: id }) => {
+            client.get(format!("{server}/accounts/{id}"))
+        }
+        AdminCommand::Accounts(AccountsCommand::Unlock { client: id, tx }) => client
+            .post(format!("{server}/accounts/{id}/unlock"))
+            .json(&json!({ "tx": tx })),
+        AdminCommand::Accounts(AccountsCommand::Tags { client: id }) => {
+            client.get(format!("{server}/accounts/{id}/tags"))
+        }
+        AdminCommand::Accounts(AccountsCommand::Tag { client: id, tag }) => client
+            .post(format!("{server}/accounts/{id}/tags"))
+            .json(&json!({ "tag": tag })),
+        AdminCommand::Accounts(AccountsCommand::Untag { client: id, tag }) => client
+            .delete(format!("{server}/accounts/{id}/tags"))
+            .json(&json!({ "tag": tag })),
+        AdminCommand::Disputes(DisputesCommand::List {
+            client: id,
+            tag,
+            state,
+        }) => {
+            let mut request = client.get(format!("{server}/disputes"));
+            if let Some(id) = id {
+                request = request.query(&[("client", id)]);
+            }
+            if let Some(tag) = tag {
+                request = request.query(&[("tag", tag)]);
+            }
+            if let Some(state) = state {
+                request = request.query(&[("state", state.to_string())]);
+            }
+            request
+        }
+        AdminCommand::Disputes(DisputesCommand::Update {
+            client: id,
+            tx,
+            state,
+            assignee,
+        }) => client
+            .post(format!("{server}/disputes/{id}/{tx}"))
+            .json(&json!({ "state": state, "assignee": assignee })),
+        AdminCommand::Snapshot(SnapshotCommand::Trigger) => {
+            client.post(format!("{server}/snapshot"))
+        }
+    };
+    let request = match &args.token_file {
+        Some(path) => request.bearer_auth(read_token(path).await?),
+        None => request,
+    };
+    println!("{}", serde_json::to_string_pretty(&answer(request).await?)?);
+    Ok(())
+}
+
+/// The json the server answered with
+///
+/// # Errors
+/// If the server can't be reached or answers with an error status, an error will be returned with
+/// the reason it gave
+#[cfg(feature = "admin")]
+async fn answer(request: RequestBuilder) -> Result<Value> {
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    ensure!(status.is_success(), "The server answered {status}: {body}");
+    Ok(serde_json::from_str(&body)?)
+}
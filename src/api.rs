@@ -0,0 +1,1173 @@
+use std::path::Path;
+#[cfg(feature = "http")]
+use std::path::PathBuf;
+#[cfg(feature = "http")]
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+#[cfg(feature = "http")]
+use std::sync::Arc;
+
+#[cfg(feature = "http")]
+use std::future::{ready, Ready};
+
+#[cfg(feature = "http")]
+use actix_web::dev::Payload;
+#[cfg(feature = "http")]
+use actix_web::error::ErrorUnauthorized;
+#[cfg(feature = "http")]
+use actix_web::http::header::AUTHORIZATION;
+#[cfg(feature = "http")]
+use actix_web::{
+    delete, get, post, rt, web, App, FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder,
+    HttpServer, Responder,
+};
+#[cfg(feature = "http")]
+use anyhow::anyhow;
+#[cfg(any(feature = "http", feature = "admin"))]
+use anyhow::ensure;
+use anyhow::Result;
+#[cfg(feature = "http")]
+use csv_async::AsyncWriterBuilder;
+#[cfg(feature = "http")]
+use ring::digest::{digest, SHA256};
+#[cfg(feature = "http")]
+use tokio::sync::{mpsc, Mutex};
+#[cfg(feature = "http")]
+use tokio_stream::wrappers::ReceiverStream;
+#[cfg(feature = "http")]
+use tracing::{error, info};
+
+#[cfg(feature = "http")]
+use crate::cases::{Case, DisputeState};
+use crate::config::ThrottleConfig;
+#[cfg(feature = "http")]
+use crate::engine::Applied;
+use crate::engine::Engine;
+#[cfg(feature = "http")]
+use crate::model::{AccountRecord, AdminAction, Transaction, TransactionError, TransactionType};
+#[cfg(feature = "http")]
+use crate::money::Money;
+#[cfg(feature = "http")]
+use crate::snapshot::Snapshot;
+
+/// The engine shared by the requests, which apply their transactions one at a time
+#[cfg(feature = "http")]
+type SharedEngine = web::Data<Mutex<Engine>>;
+
+/// How many exported accounts wait for the client to read them before the export pauses
+#[cfg(feature = "http")]
+const EXPORT_BUFFER: usize = 64;
+
+/// Where the admin API writes the snapshots it is asked for, if anywhere
+#[cfg(feature = "http")]
+#[derive(Clone, Default)]
+struct SnapshotPath(Option<PathBuf>);
+
+/// The token the admin requests must bear, or none if the admin API is disabled
+#[cfg(feature = "http")]
+#[derive(Clone, Default)]
+struct AdminToken(Option<String>);
+
+/// A request bearing the admin token in its `Authorization` header. The admin routes take it, so
+/// they answer `401` to the requests without it.
+#[cfg(feature = "http")]
+struct Operator;
+
+#[cfg(feature = "http")]
+impl FromRequest for Operator {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = request
+            .app_data::<web::Data<AdminToken>>()
+            .and_then(|token| token.0.as_deref());
+        let bearer = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        // the digests are compared, so the time taken doesn't tell how much of the token matched
+        let authorized = token.zip(bearer).is_some_and(|(token, bearer)| {
+            digest(&SHA256, token.as_bytes()).as_ref()
+                == digest(&SHA256, bearer.as_bytes()).as_ref()
+        });
+        ready(if authorized {
+            Ok(Self)
+        } else {
+            Err(ErrorUnauthorized("The admin API needs the admin token"))
+        })
+    }
+}
+
+/// Reads the admin token from a file, without the whitespace around it
+///
+/// # Errors
+/// If the file can't be read or holds no token, an error will be returned
+#[cfg(any(feature = "http", feature = "admin"))]
+pub async fn read_token(path: &Path) -> Result<String> {
+    let token = tokio::fs::read_to_string(path).await?.trim().to_owned();
+    ensure!(!token.is_empty(), "No token in {}", path.display());
+    Ok(token)
+}
+
+/// Counts the requests waiting for the engine, turning the transactions away once too many wait
+#[cfg(feature = "http")]
+#[derive(Default)]
+struct Backpressure {
+    throttle: Option<ThrottleConfig>,
+    queued: AtomicUsize,
+    throttled: AtomicU64,
+}
+
+/// A request counted as waiting for the engine until it is dropped
+#[cfg(feature = "http")]
+struct Queued<'a>(&'a Backpressure);
+
+#[cfg(feature = "http")]
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "http")]
+impl Backpressure {
+    fn new(throttle: Option<ThrottleConfig>) -> Self {
+        Self {
+            throttle,
+            ..Self::default()
+        }
+    }
+
+    /// Queues a request for the engine, or tells how long the client should wait if too many
+    /// already wait
+    fn admit(&self) -> Result<Queued<'_>, std::time::Duration> {
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed);
+        let permit = Queued(self);
+        match self.throttle {
+            Some(throttle) if queued >= throttle.max_queued => {
+                self.throttled.fetch_add(1, Ordering::Relaxed);
+                Err(throttle.retry_after)
+            }
+            _ => Ok(permit),
+        }
+    }
+
+    /// The gauges and counters of the queue, in the prometheus text format
+    fn metrics(&self) -> String {
+        let limit = self.throttle.map_or_else(String::new, |throttle| {
+            format!(
+                "# TYPE api_max_queued_requests gauge\napi_max_queued_requests {}\n",
+                throttle.max_queued
+            )
+        });
+        format!(
+            "# TYPE api_queued_requests gauge\napi_queued_requests {}\n\
+            # TYPE api_throttled_requests_total counter\napi_throttled_requests_total {}\n{limit}",
+            self.queued.load(Ordering::Relaxed),
+            self.throttled.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// The answer to a transaction turned away, telling the client when to try again
+#[cfg(feature = "http")]
+fn too_many_requests(retry_after: std::time::Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.as_secs().to_string()))
+        .finish()
+}
+
+/// Serves the client and admin APIs on `listen` until the process is stopped, then gives the
+/// engine back. Snapshots are triggered into `snapshot`, transactions are turned away as
+/// `throttle` says, and only the admin requests bearing the token of `admin_token` are answered.
+///
+/// # Errors
+/// If the admin token can't be read or the server can't listen, an error will be returned
+#[cfg(feature = "http")]
+pub async fn serve(
+    listen: &str,
+    engine: Engine,
+    snapshot: Option<&Path>,
+    throttle: Option<ThrottleConfig>,
+    admin_token: Option<&Path>,
+) -> Result<Engine> {
+    let admin_token = if let Some(path) = admin_token {
+        Some(read_token(path).await?)
+    } else {
+        info!("No admin token was given, the admin API is disabled");
+        None
+    };
+    let engine = web::Data::new(Mutex::new(engine));
+    info!("Serving the client API on {listen}");
+    let data = engine.clone();
+    let snapshot = web::Data::new(SnapshotPath(snapshot.map(Path::to_path_buf)));
+    let backpressure = web::Data::new(Backpressure::new(throttle));
+    let admin_token = web::Data::new(AdminToken(admin_token));
+    HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .app_data(snapshot.clone())
+            .app_data(backpressure.clone())
+            .app_data(admin_token.clone())
+            .service(open_client)
+            .service(submit_transaction)
+            .service(export_accounts)
+            .service(get_account)
+            .service(get_client)
+            .service(unlock_account)
+            .service(get_tags)
+            .service(tag_account)
+            .service(untag_account)
+            .service(list_disputes)
+            .service(update_dispute)
+            .service(trigger_snapshot)
+            .service(export_metrics)
+    })
+    .bind(listen)?
+    .run()
+    .await?;
+    Arc::try_unwrap(engine.into_inner())
+        .map(Mutex::into_inner)
+        .map_err(|_| anyhow!("The engine is still used by the server"))
+}
+
+/// Fails, as the crate was built without the `http` feature
+#[cfg(not(feature = "http"))]
+#[allow(clippy::unused_async)]
+pub async fn serve(
+    listen: &str,
+    _engine: Engine,
+    _snapshot: Option<&Path>,
+    _throttle: Option<ThrottleConfig>,
+    _admin_token: Option<&Path>,
+) -> Result<Engine> {
+    anyhow::bail!("Can't serve the client API on {listen}: built without the `http` feature");
+}
+
+/// A client to onboard, with the id of its opening transaction
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct NewClient {
+    client: u16,
+    tx: u32,
+    balance: Option<Money>,
+}
+
+/// Opens the account of a new client, crediting its initial balance through an opening
+/// transaction, which is journaled like any other
+#[cfg(feature = "http")]
+#[post("/clients")]
+async fn open_client(
+    engine: SharedEngine,
+    backpressure: web::Data<Backpressure>,
+    body: web::Json<NewClient>,
+) -> impl Responder {
+    let _queued = match backpressure.admit() {
+        Ok(queued) => queued,
+        Err(retry_after) => return too_many_requests(retry_after),
+    };
+    let NewClient {
+        client,
+        tx,
+        balance,
+    } = body.into_inner();
+    let transaction = Transaction {
+        transaction_type: TransactionType::Open,
+        client,
+        tx,
+        amount: balance,
+        reference: None,
+        to_client: None,
+        action: None,
+    };
+    let mut engine = engine.lock().await;
+    match engine.submit(transaction).await {
+        Ok(applied) => applied_response(&engine, client, applied, HttpResponse::Created()).await,
+        Err(e) => {
+            error!("Could not open the account of client {client}: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Applies a transaction, given like a line of the json input, and answers the account it left
+#[cfg(feature = "http")]
+#[post("/transactions")]
+async fn submit_transaction(
+    engine: SharedEngine,
+    backpressure: web::Data<Backpressure>,
+    body: web::Json<Transaction>,
+) -> impl Responder {
+    let _queued = match backpressure.admit() {
+        Ok(queued) => queued,
+        Err(retry_after) => return too_many_requests(retry_after),
+    };
+    let transaction = body.into_inner();
+    let (client, tx) = (transaction.client, transaction.tx);
+    let mut engine = engine.lock().await;
+    match engine.submit(transaction).await {
+        Ok(applied) => applied_response(&engine, client, applied, HttpResponse::Ok()).await,
+        Err(e) => {
+            error!("Could not apply transaction {tx}: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The registry data of a client, for the tools built on top of the server
+#[cfg(feature = "http")]
+#[get("/clients/{client}")]
+async fn get_client(engine: SharedEngine, client: web::Path<u16>) -> impl Responder {
+    let engine = engine.lock().await;
+    match engine.registry().and_then(|registry| registry.get(*client)) {
+        Some(info) => HttpResponse::Ok().json(info),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// The balances of the account of a client
+#[cfg(feature = "http")]
+#[get("/accounts/{client}")]
+async fn get_account(engine: SharedEngine, client: web::Path<u16>) -> impl Responder {
+    let client = client.into_inner();
+    account_response(&*engine.lock().await, client, HttpResponse::Ok()).await
+}
+
+/// The requests waiting for the engine and the transactions turned away, for prometheus
+#[cfg(feature = "http")]
+#[get("/metrics")]
+async fn export_metrics(backpressure: web::Data<Backpressure>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(backpressure.metrics())
+}
+
+/// The id of the transaction an admin command is journaled with
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct AdminCommand {
+    tx: u32,
+}
+
+/// Unlocks the account of a client through an admin transaction, and answers the account it left
+#[cfg(feature = "http")]
+#[post("/accounts/{client}/unlock")]
+async fn unlock_account(
+    _: Operator,
+    engine: SharedEngine,
+    client: web::Path<u16>,
+    body: web::Json<AdminCommand>,
+) -> impl Responder {
+    let (client, tx) = (client.into_inner(), body.tx);
+    let transaction = Transaction {
+        transaction_type: TransactionType::Admin,
+        client,
+        tx,
+        amount: None,
+        reference: None,
+        to_client: None,
+        action: Some(AdminAction::Unlock),
+    };
+    let mut engine = engine.lock().await;
+    match engine.submit(transaction).await {
+        Ok(applied) => applied_response(&engine, client, applied, HttpResponse::Ok()).await,
+        Err(e) => {
+            error!("Could not unlock the account of client {client}: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// A tag attached to or removed from an account
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct TagCommand {
+    tag: String,
+}
+
+/// The tags of the account of a client
+#[cfg(feature = "http")]
+#[get("/accounts/{client}/tags")]
+async fn get_tags(_: Operator, engine: SharedEngine, client: web::Path<u16>) -> impl Responder {
+    HttpResponse::Ok().json(engine.lock().await.tags().of(client.into_inner()))
+}
+
+/// Tags the account of a client, and answers its tags
+#[cfg(feature = "http")]
+#[post("/accounts/{client}/tags")]
+async fn tag_account(
+    _: Operator,
+    engine: SharedEngine,
+    client: web::Path<u16>,
+    body: web::Json<TagCommand>,
+) -> impl Responder {
+    let client = client.into_inner();
+    let mut engine = engine.lock().await;
+    engine.tags_mut().insert(client, &body.tag);
+    HttpResponse::Ok().json(engine.tags().of(client))
+}
+
+/// Removes a tag of the account of a client, and answers the tags left
+#[cfg(feature = "http")]
+#[delete("/accounts/{client}/tags")]
+async fn untag_account(
+    _: Operator,
+    engine: SharedEngine,
+    client: web::Path<u16>,
+    body: web::Json<TagCommand>,
+) -> impl Responder {
+    let client = client.into_inner();
+    let mut engine = engine.lock().await;
+    engine.tags_mut().remove(client, &body.tag);
+    HttpResponse::Ok().json(engine.tags().of(client))
+}
+
+/// A disputed transaction with where its case is in the workflow and who handles it
+#[cfg(feature = "http")]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct DisputeCase {
+    client: u16,
+    tx: u32,
+    state: DisputeState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    assignee: Option<String>,
+}
+
+#[cfg(feature = "http")]
+impl DisputeCase {
+    fn new(client: u16, tx: u32, case: Case) -> Self {
+        Self {
+            client,
+            tx,
+            state: case.state,
+            assignee: case.assignee,
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct DisputesQuery {
+    client: Option<u16>,
+    tag: Option<String>,
+    state: Option<DisputeState>,
+}
+
+/// The holds report: the transactions in dispute and the cases closed while serving, of every
+/// client or only of the one asked for, only of the accounts with the tag asked for and only in
+/// the state asked for, ordered by client and id
+#[cfg(feature = "http")]
+#[get("/disputes")]
+async fn list_disputes(
+    _: Operator,
+    engine: SharedEngine,
+    query: web::Query<DisputesQuery>,
+) -> impl Responder {
+    let engine = engine.lock().await;
+    let mut clients = query
+        .client
+        .map_or_else(|| engine.clients(), |client| vec![client]);
+    let filter = query.tag.as_slice();
+    clients.retain(|&client| engine.tags().matches(client, filter));
+    let mut disputes = Vec::new();
+    for client in clients {
+        let held = match engine.state(client).await {
+            Ok(state) => state.map(|state| state.disputed()).unwrap_or_default(),
+            Err(e) => {
+                error!("Could not fetch the account of client {client}: {e}");
+                return HttpResponse::ServiceUnavailable().finish();
+            }
+        };
+        let cases = engine.cases();
+        let closed = cases
+            .closed(client)
+            .filter(|(tx, _)| !held.contains(tx))
+            .map(|(tx, case)| (tx, case.clone()));
+        let mut client_cases: Vec<_> = held
+            .iter()
+            .map(|&tx| (tx, cases.held(client, tx)))
+            .chain(closed)
+            .filter(|(_, case)| query.state.is_none_or(|state| state == case.state))
+            .map(|(tx, case)| DisputeCase::new(client, tx, case))
+            .collect();
+        client_cases.sort_unstable_by_key(|case| case.tx);
+        disputes.extend(client_cases);
+    }
+    HttpResponse::Ok().json(disputes)
+}
+
+/// The state a dispute case moves to and who handles it from then on, either left out to keep it
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct CaseUpdate {
+    state: Option<DisputeState>,
+    assignee: Option<String>,
+}
+
+/// Moves the case of a transaction in dispute along the workflow or assigns it, and answers the
+/// case. Resolving or charging it back applies the resolve or chargeback to the account, journaled
+/// like any other transaction.
+#[cfg(feature = "http")]
+#[post("/disputes/{client}/{tx}")]
+async fn update_dispute(
+    _: Operator,
+    engine: SharedEngine,
+    path: web::Path<(u16, u32)>,
+    body: web::Json<CaseUpdate>,
+) -> impl Responder {
+    let (client, tx) = path.into_inner();
+    let CaseUpdate { state, assignee } = body.into_inner();
+    let mut engine = engine.lock().await;
+    let held = match engine.state(client).await {
+        Ok(account) => account.is_some_and(|account| account.disputed().contains(&tx)),
+        Err(e) => {
+            error!("Could not fetch the account of client {client}: {e}");
+            return HttpResponse::ServiceUnavailable().finish();
+        }
+    };
+    if !held {
+        return HttpResponse::NotFound().body(format!("Transaction {tx} isn't in dispute"));
+    }
+    let mut case = engine.cases().held(client, tx);
+    if let Some(state) = state {
+        if !case.state.can_become(state) {
+            let current = case.state;
+            return HttpResponse::Conflict()
+                .body(format!("A dispute {current} can't become {state}"));
+        }
+    }
+    case.assignee = assignee.or(case.assignee);
+    let closing = state.and_then(|state| Some((state, state.closing_type()?)));
+    let Some((state, transaction_type)) = closing else {
+        case.state = state.unwrap_or(case.state);
+        engine.cases_mut().set(client, tx, case.clone());
+        return HttpResponse::Ok().json(DisputeCase::new(client, tx, case));
+    };
+    // the assignee is kept even if the account refuses to close the case
+    engine.cases_mut().set(client, tx, case.clone());
+    let transaction = Transaction {
+        transaction_type,
+        client,
+        tx,
+        amount: None,
+        reference: None,
+        to_client: None,
+        action: None,
+    };
+    match engine.submit(transaction).await {
+        Ok(Applied::Accepted) => {
+            case.state = state;
+            HttpResponse::Ok().json(DisputeCase::new(client, tx, case))
+        }
+        Ok(applied) => applied_response(&engine, client, applied, HttpResponse::Ok()).await,
+        Err(e) => {
+            error!("Could not close the dispute of transaction {tx}: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// What a triggered snapshot holds
+#[cfg(feature = "http")]
+#[derive(Serialize, Deserialize, Debug)]
+struct SnapshotTaken {
+    journal_seq: u64,
+    accounts: usize,
+}
+
+/// Writes the state of every account into the snapshot file given on the command line
+#[cfg(feature = "http")]
+#[post("/snapshot")]
+async fn trigger_snapshot(
+    _: Operator,
+    engine: SharedEngine,
+    path: web::Data<SnapshotPath>,
+) -> impl Responder {
+    let Some(path) = &path.0 else {
+        return HttpResponse::Conflict().body("No snapshot file was given to the server");
+    };
+    let engine = engine.lock().await;
+    let written = match engine.states().await {
+        Ok(states) => {
+            let taken = SnapshotTaken {
+                journal_seq: engine.journal_seq(),
+                accounts: states.len(),
+            };
+            let snapshot = Snapshot::from_states(taken.journal_seq, states);
+            snapshot.write(path).await.map(|()| taken)
+        }
+        Err(e) => Err(e),
+    };
+    match written {
+        Ok(taken) => HttpResponse::Ok().json(taken),
+        Err(e) => {
+            error!("Could not write the snapshot: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Answers the account of the client with the status of the builder
+#[cfg(feature = "http")]
+async fn account_response(
+    engine: &Engine,
+    client: u16,
+    mut builder: HttpResponseBuilder,
+) -> HttpResponse {
+    match engine.state(client).await {
+        Ok(Some(state)) => builder.json(AccountRecord::from_state(
+            &state,
+            engine.rounding_of(client),
+        )),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Could not fetch the account of client {client}: {e}");
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+/// Answers how a transaction of the client ended: the account it left with the status of the
+/// builder if it was accepted, or the reason it was rejected
+#[cfg(feature = "http")]
+async fn applied_response(
+    engine: &Engine,
+    client: u16,
+    applied: Applied,
+    accepted: HttpResponseBuilder,
+) -> HttpResponse {
+    match applied {
+        Applied::Accepted => account_response(engine, client, accepted).await,
+        Applied::Rejected(
+            e @ (TransactionError::AccountExists { .. }
+            | TransactionError::DuplicateTransaction { .. }),
+        ) => HttpResponse::Conflict().body(e.to_string()),
+        Applied::Rejected(e) => HttpResponse::BadRequest().body(e.to_string()),
+        Applied::Skipped => HttpResponse::BadRequest().finish(),
+        Applied::Undelivered => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+/// The formats the accounts can be exported in
+#[cfg(feature = "http")]
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Csv,
+    /// Newline delimited json, an account object per line
+    Ndjson,
+}
+
+#[cfg(feature = "http")]
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// The line of an account, after the header if it is the first one
+    async fn encode(self, record: &AccountRecord, first: bool) -> Result<Vec<u8>> {
+        match self {
+            Self::Csv => {
+                let mut serializer = AsyncWriterBuilder::new()
+                    .has_headers(first)
+                    .create_serializer(Vec::new());
+                serializer.serialize(record).await?;
+                Ok(serializer.into_inner().await?)
+            }
+            Self::Ndjson => {
+                let mut line = serde_json::to_vec(record)?;
+                line.push(b'\n');
+                Ok(line)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    tag: Option<String>,
+}
+
+/// Streams every account, or only the ones with the tag asked for, each one as it is when it is
+/// written. The accounts are only fetched as fast as the client reads them, and other requests are
+/// served in between. Accounts opened or tagged during the export are left out.
+#[cfg(feature = "http")]
+#[get("/accounts/export")]
+async fn export_accounts(engine: SharedEngine, query: web::Query<ExportQuery>) -> impl Responder {
+    let format = query.format;
+    let clients = {
+        let engine = engine.lock().await;
+        let mut clients = engine.clients();
+        clients.retain(|&client| engine.tags().matches(client, query.tag.as_slice()));
+        clients
+    };
+    let (rows, body) = mpsc::channel(EXPORT_BUFFER);
+    rt::spawn(async move {
+        let mut first = true;
+        for client in clients {
+            let (state, rounding) = {
+                let engine = engine.lock().await;
+                (engine.state(client).await, engine.rounding_of(client))
+            };
+            let row = match state {
+                Ok(Some(state)) => {
+                    let record = AccountRecord::from_state(&state, rounding);
+                    format.encode(&record, first).await
+                }
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
+            first = false;
+            let failed = row.is_err();
+            if let Err(e) = &row {
+                error!("Could not export the account of client {client}: {e}");
+            }
+            // the client stopped reading, or the response is cut short on the error
+            if rows.send(row.map(web::Bytes::from)).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .streaming(ReceiverStream::new(body))
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{
+        call_and_read_body, call_and_read_body_json, call_service, init_service, TestRequest,
+    };
+    use actix_web::{web, App};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::api::{
+        export_accounts, export_metrics, get_account, get_client, get_tags, list_disputes,
+        submit_transaction, tag_account, trigger_snapshot, unlock_account, untag_account,
+        update_dispute, AdminToken, Backpressure, DisputeCase, SnapshotPath, SnapshotTaken,
+    };
+    use crate::cases::DisputeState;
+    use crate::config::{DispatchConfig, EngineConfig, ThrottleConfig};
+    use crate::engine::Engine;
+    use crate::model::AccountRecord;
+    use crate::registry::ClientRegistry;
+    use crate::snapshot::Snapshot;
+
+    fn admin_token() -> web::Data<AdminToken> {
+        web::Data::new(AdminToken(Some("s3cret".to_owned())))
+    }
+
+    /// A request of an operator, bearing the admin token
+    fn admin(request: TestRequest) -> TestRequest {
+        request.insert_header((AUTHORIZATION, "Bearer s3cret"))
+    }
+
+    fn open(client: u16, tx: u32) -> DisputeCase {
+        DisputeCase {
+            client,
+            tx,
+            state: DisputeState::Open,
+            assignee: None,
+        }
+    }
+
+    #[actix::test]
+    async fn test_transactions_and_accounts_over_http() {
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .app_data(web::Data::new(Backpressure::default()))
+                .service(submit_transaction)
+                .service(get_account),
+        )
+        .await;
+        let submit = |body: serde_json::Value| {
+            TestRequest::post()
+                .uri("/transactions")
+                .set_json(body)
+                .to_request()
+        };
+
+        let deposit = serde_json::json!({"type": "Deposit", "client": 1, "tx": 1, "amount": "7.5"});
+        let record: AccountRecord = call_and_read_body_json(&app, submit(deposit)).await;
+        assert_eq!(record.available, dec!(7.5));
+        let withdrawal =
+            serde_json::json!({"type": "Withdrawal", "client": 1, "tx": 2, "amount": "10"});
+        let response = call_service(&app, submit(withdrawal)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = TestRequest::get().uri("/accounts/1").to_request();
+        let record: AccountRecord = call_and_read_body_json(&app, request).await;
+        assert_eq!(record.total, dec!(7.5));
+        let request = TestRequest::get().uri("/accounts/2").to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix::test]
+    async fn test_client_lookup_over_http() {
+        let path = std::env::temp_dir().join(format!("api-clients-{}.csv", std::process::id()));
+        tokio::fs::write(&path, "client,name,segment\n1,Ada,retail\n")
+            .await
+            .unwrap();
+        let registry = ClientRegistry::load(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_registry(Arc::new(registry));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .service(get_client),
+        )
+        .await;
+
+        let request = TestRequest::get().uri("/clients/1").to_request();
+        let info: serde_json::Value = call_and_read_body_json(&app, request).await;
+        assert_eq!(
+            (&info["name"], &info["segment"]),
+            (&"Ada".into(), &"retail".into())
+        );
+        let request = TestRequest::get().uri("/clients/2").to_request();
+        assert_eq!(
+            call_service(&app, request).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix::test]
+    async fn test_accounts_are_rounded_like_their_currency() {
+        let dir = std::env::temp_dir().join(format!("api-currencies-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let (clients, rules) = (dir.join("clients.csv"), dir.join("currencies.csv"));
+        let clients_csv = "client,name,segment,currency\n1,Ada,retail,BHD\n2,Bob,retail,\n";
+        tokio::fs::write(&clients, clients_csv).await.unwrap();
+        tokio::fs::write(&rules, "currency,precision,display\nBHD,3,3\n")
+            .await
+            .unwrap();
+        let registry = ClientRegistry::load(&clients)
+            .await
+            .unwrap()
+            .with_currency_rules(&rules)
+            .await
+            .unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_registry(Arc::new(registry));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .app_data(web::Data::new(Backpressure::default()))
+                .service(submit_transaction)
+                .service(export_accounts)
+                .service(get_account),
+        )
+        .await;
+        for client in [1, 2] {
+            let body = serde_json::json!(
+                {"type": "Deposit", "client": client, "tx": client, "amount": "1.2344"}
+            );
+            let request = TestRequest::post()
+                .uri("/transactions")
+                .set_json(body)
+                .to_request();
+            assert!(call_service(&app, request).await.status().is_success());
+        }
+
+        let request = TestRequest::get().uri("/accounts/1").to_request();
+        let record: AccountRecord = call_and_read_body_json(&app, request).await;
+        assert_eq!(record.total, dec!(1.234));
+        let request = TestRequest::get().uri("/accounts/2").to_request();
+        let record: AccountRecord = call_and_read_body_json(&app, request).await;
+        assert_eq!(record.total, dec!(1.2344));
+        let request = TestRequest::get()
+            .uri("/accounts/export?format=ndjson")
+            .to_request();
+        let body = call_and_read_body(&app, request).await;
+        let totals: Vec<_> = body
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice::<AccountRecord>(line).unwrap())
+            .map(|record| (record.client, record.total))
+            .collect();
+        assert_eq!(totals, [(1, dec!(1.234)), (2, dec!(1.2344))]);
+    }
+
+    #[actix::test]
+    async fn test_admin_api() {
+        let path = std::env::temp_dir().join(format!("admin_api_{}.json", std::process::id()));
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .app_data(web::Data::new(Backpressure::default()))
+                .app_data(admin_token())
+                .app_data(web::Data::new(SnapshotPath(Some(path.clone()))))
+                .service(submit_transaction)
+                .service(unlock_account)
+                .service(list_disputes)
+                .service(trigger_snapshot),
+        )
+        .await;
+        for body in [
+            serde_json::json!({"type": "Deposit", "client": 1, "tx": 1, "amount": "5"}),
+            serde_json::json!({"type": "Deposit", "client": 2, "tx": 2, "amount": "3"}),
+            serde_json::json!({"type": "Dispute", "client": 2, "tx": 2}),
+            serde_json::json!({"type": "Admin", "client": 1, "tx": 3, "action": "Freeze"}),
+        ] {
+            let request = TestRequest::post()
+                .uri("/transactions")
+                .set_json(body)
+                .to_request();
+            assert!(call_service(&app, request).await.status().is_success());
+        }
+
+        let request = admin(TestRequest::post())
+            .uri("/accounts/1/unlock")
+            .set_json(serde_json::json!({"tx": 4}))
+            .to_request();
+        let record: AccountRecord = call_and_read_body_json(&app, request).await;
+        assert!(!record.locked);
+
+        let request = admin(TestRequest::get()).uri("/disputes").to_request();
+        let disputes: Vec<DisputeCase> = call_and_read_body_json(&app, request).await;
+        assert_eq!(disputes, [open(2, 2)]);
+        let request = admin(TestRequest::get())
+            .uri("/disputes?client=1")
+            .to_request();
+        let disputes: Vec<DisputeCase> = call_and_read_body_json(&app, request).await;
+        assert!(disputes.is_empty());
+
+        let request = admin(TestRequest::post()).uri("/snapshot").to_request();
+        let taken: SnapshotTaken = call_and_read_body_json(&app, request).await;
+        assert_eq!(taken.accounts, 2);
+        let snapshot = Snapshot::read(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(snapshot.accounts.len(), 2);
+    }
+
+    #[actix::test]
+    async fn test_admin_api_needs_the_token() {
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .app_data(admin_token())
+                .service(get_account)
+                .service(unlock_account),
+        )
+        .await;
+        let unlock = || {
+            TestRequest::post()
+                .uri("/accounts/1/unlock")
+                .set_json(serde_json::json!({"tx": 1}))
+        };
+        let response = call_service(&app, unlock().to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let request = unlock()
+            .insert_header((AUTHORIZATION, "Bearer guess"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        // the client API doesn't need it
+        let request = TestRequest::get().uri("/accounts/1").to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // without a token the admin API is disabled
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .service(unlock_account),
+        )
+        .await;
+        let response = call_service(&app, admin(unlock()).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix::test]
+    async fn test_dispute_cases_move_along_the_workflow() {
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .app_data(web::Data::new(Backpressure::default()))
+                .app_data(admin_token())
+                .service(submit_transaction)
+                .service(list_disputes)
+                .service(update_dispute),
+        )
+        .await;
+        for body in [
+            serde_json::json!({"type": "Deposit", "client": 1, "tx": 1, "amount": "5"}),
+            serde_json::json!({"type": "Deposit", "client": 1, "tx": 2, "amount": "3"}),
+            serde_json::json!({"type": "Dispute", "client": 1, "tx": 1}),
+            serde_json::json!({"type": "Dispute", "client": 1, "tx": 2}),
+        ] {
+            let request = TestRequest::post()
+                .uri("/transactions")
+                .set_json(body)
+                .to_request();
+            assert!(call_service(&app, request).await.status().is_success());
+        }
+        let update = |tx: u32, body: serde_json::Value| {
+            admin(TestRequest::post())
+                .uri(&format!("/disputes/1/{tx}"))
+                .set_json(body)
+                .to_request()
+        };
+        let move_case = |tx, state: &str| update(tx, serde_json::json!({ "state": state }));
+
+        let request = update(1, serde_json::json!({"assignee": "ana"}));
+        let case: DisputeCase = call_and_read_body_json(&app, request).await;
+        assert_eq!(
+            (case.state, case.assignee.as_deref()),
+            (DisputeState::Open, Some("ana"))
+        );
+        let case: DisputeCase =
+            call_and_read_body_json(&app, move_case(1, "evidence_requested")).await;
+        assert_eq!(case.state, DisputeState::EvidenceRequested);
+        assert_eq!(case.assignee.as_deref(), Some("ana"));
+        let response = call_service(&app, move_case(1, "open")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let case: DisputeCase = call_and_read_body_json(&app, move_case(2, "charged_back")).await;
+        assert_eq!(case.state, DisputeState::ChargedBack);
+        let response = call_service(&app, move_case(2, "under_review")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let request = admin(TestRequest::get()).uri("/disputes").to_request();
+        let disputes: Vec<DisputeCase> = call_and_read_body_json(&app, request).await;
+        let states: Vec<_> = disputes.iter().map(|case| (case.tx, case.state)).collect();
+        assert_eq!(
+            states,
+            [
+                (1, DisputeState::EvidenceRequested),
+                (2, DisputeState::ChargedBack)
+            ]
+        );
+        let request = admin(TestRequest::get())
+            .uri("/disputes?state=charged_back")
+            .to_request();
+        let disputes: Vec<DisputeCase> = call_and_read_body_json(&app, request).await;
+        assert_eq!(disputes.len(), 1);
+        assert_eq!(disputes[0].tx, 2);
+    }
+
+    #[actix::test]
+    async fn test_tags_filter_the_exports_and_queries() {
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .app_data(web::Data::new(Backpressure::default()))
+                .app_data(admin_token())
+                .service(submit_transaction)
+                .service(export_accounts)
+                .service(get_tags)
+                .service(tag_account)
+                .service(untag_account)
+                .service(list_disputes),
+        )
+        .await;
+        for body in [
+            serde_json::json!({"type": "Deposit", "client": 1, "tx": 1, "amount": "5"}),
+            serde_json::json!({"type": "Deposit", "client": 2, "tx": 2, "amount": "3"}),
+            serde_json::json!({"type": "Dispute", "client": 1, "tx": 1}),
+            serde_json::json!({"type": "Dispute", "client": 2, "tx": 2}),
+        ] {
+            let request = TestRequest::post()
+                .uri("/transactions")
+                .set_json(body)
+                .to_request();
+            assert!(call_service(&app, request).await.status().is_success());
+        }
+        let tag = |request: TestRequest, tag: &str| {
+            admin(request)
+                .uri("/accounts/2/tags")
+                .set_json(serde_json::json!({ "tag": tag }))
+                .to_request()
+        };
+        let tags: Vec<String> =
+            call_and_read_body_json(&app, tag(TestRequest::post(), "vip")).await;
+        assert_eq!(tags, ["vip"]);
+        call_service(&app, tag(TestRequest::post(), "under_review")).await;
+        let tags: Vec<String> =
+            call_and_read_body_json(&app, tag(TestRequest::delete(), "under_review")).await;
+        assert_eq!(tags, ["vip"]);
+
+        let request = admin(TestRequest::get())
+            .uri("/disputes?tag=vip")
+            .to_request();
+        let disputes: Vec<DisputeCase> = call_and_read_body_json(&app, request).await;
+        assert_eq!(disputes, [open(2, 2)]);
+        let request = TestRequest::get()
+            .uri("/accounts/export?format=ndjson&tag=vip")
+            .to_request();
+        let body = call_and_read_body(&app, request).await;
+        let record: AccountRecord = serde_json::from_slice(&body).unwrap();
+        assert_eq!(record.client, 2);
+        let request = TestRequest::get()
+            .uri("/accounts/export?tag=under_review")
+            .to_request();
+        assert!(call_and_read_body(&app, request).await.is_empty());
+        let request = admin(TestRequest::get())
+            .uri("/accounts/1/tags")
+            .to_request();
+        let tags: Vec<String> = call_and_read_body_json(&app, request).await;
+        assert!(tags.is_empty());
+    }
+
+    #[actix::test]
+    async fn test_transactions_are_turned_away_when_too_many_wait() {
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let backpressure = web::Data::new(Backpressure::new(Some(ThrottleConfig {
+            max_queued: 1,
+            retry_after: Duration::from_secs(3),
+        })));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Mutex::new(engine)))
+                .app_data(backpressure.clone())
+                .service(submit_transaction)
+                .service(export_metrics),
+        )
+        .await;
+        let deposit = || {
+            TestRequest::post()
+                .uri("/transactions")
+                .set_json(
+                    serde_json::json!({"type": "Deposit", "client": 1, "tx": 1, "amount": "1"}),
+                )
+                .to_request()
+        };
+
+        // a request already waits for the engine
+        let waiting = backpressure.admit().unwrap();
+        let response = call_service(&app, deposit()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get("Retry-After").unwrap(), "3");
+        drop(waiting);
+        assert!(call_service(&app, deposit()).await.status().is_success());
+
+        let request = TestRequest::get().uri("/metrics").to_request();
+        let body = String::from_utf8(call_and_read_body(&app, request).await.to_vec()).unwrap();
+        assert!(body.contains("api_queued_requests 0\n"));
+        assert!(body.contains("api_throttled_requests_total 1\n"));
+        assert!(body.contains("api_max_queued_requests 1\n"));
+    }
+}
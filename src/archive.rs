@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::cli::ArchiveArgs;
+use crate::store::FileAccountStore;
+
+/// Seconds in a day, the unit of the idle time of the archived accounts
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Moves the settled accounts of the store that weren't saved for the idle days asked into the
+/// archive, and prints their clients to the std out
+///
+/// # Errors
+/// If the store or the archive can't be read or written, an error will be returned
+pub fn archive(args: &ArchiveArgs) -> Result<()> {
+    let store = FileAccountStore::open(args.store.clone())?.with_archive(args.archive.clone())?;
+    let idle = Duration::from_secs(args.idle_days.saturating_mul(DAY_SECS));
+    let archived = store.archive_idle(idle)?;
+    for client in &archived {
+        println!("{client}");
+    }
+    eprintln!("Archived {} accounts", archived.len());
+    Ok(())
+}
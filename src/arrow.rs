@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, UInt16Array};
+#[cfg(feature = "parquet")]
+use arrow_array::{StringArray, UInt32Array};
+use arrow_schema::{DataType, Field, Schema, DECIMAL128_MAX_PRECISION};
+use rust_decimal::Decimal;
+
+use crate::model::AccountRecord;
+#[cfg(feature = "parquet")]
+use crate::model::Transaction;
+
+/// The decimal places of the amounts of the transactions, more than any input amount has
+#[cfg(feature = "parquet")]
+pub const TRANSACTION_SCALE: i8 = 18;
+
+/// The columns of the accounts, like the csv output. The amounts are decimals with `scale`
+/// decimal places.
+pub fn account_schema(scale: i8) -> Schema {
+    let amount = DataType::Decimal128(DECIMAL128_MAX_PRECISION, scale);
+    Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("available", amount.clone(), false),
+        Field::new("held", amount.clone(), false),
+        Field::new("total", amount, false),
+        Field::new("locked", DataType::Boolean, false),
+    ])
+}
+
+/// The accounts as a batch of columns, for analytics engines like DataFusion to query without
+/// parsing a csv. The amounts keep every decimal place of the records: their scale is the largest
+/// one among them. The columns take over the buffers they are built in, nothing is copied.
+///
+/// # Errors
+/// If an amount doesn't fit in a 128 bits decimal at that scale, an error will be returned
+pub fn record_batch(records: &[AccountRecord]) -> Result<RecordBatch> {
+    let scale = records
+        .iter()
+        .flat_map(|record| [record.available, record.held, record.total])
+        .map(|amount| amount.scale())
+        .max()
+        .unwrap_or_default();
+    let amounts = |amount: fn(&AccountRecord) -> Decimal| -> Result<ArrayRef> {
+        let values = records
+            .iter()
+            .map(|record| rescale(amount(record), scale))
+            .collect::<Result<Vec<_>>>()?;
+        let array = Decimal128Array::from(values)
+            .with_precision_and_scale(DECIMAL128_MAX_PRECISION, i8::try_from(scale)?)?;
+        Ok(Arc::new(array))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt16Array::from_iter_values(
+            records.iter().map(|record| record.client),
+        )),
+        amounts(|record| record.available)?,
+        amounts(|record| record.held)?,
+        amounts(|record| record.total)?,
+        Arc::new(BooleanArray::from(
+            records
+                .iter()
+                .map(|record| record.locked)
+                .collect::<Vec<_>>(),
+        )),
+    ];
+    let schema = account_schema(i8::try_from(scale)?);
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// The columns of the transactions, like the json input. The amounts are decimals with
+/// `TRANSACTION_SCALE` decimal places.
+#[cfg(feature = "parquet")]
+pub fn transaction_schema() -> Schema {
+    let amount = DataType::Decimal128(DECIMAL128_MAX_PRECISION, TRANSACTION_SCALE);
+    Schema::new(vec![
+        Field::new("type", DataType::Utf8, false),
+        Field::new("client", DataType::UInt16, false),
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("amount", amount, true),
+        Field::new("reference", DataType::UInt32, true),
+        Field::new("to_client", DataType::UInt16, true),
+        Field::new("action", DataType::Utf8, true),
+    ])
+}
+
+/// The transactions as a batch of columns, the fields a transaction doesn't have as nulls
+///
+/// # Errors
+/// If an amount has more than `TRANSACTION_SCALE` decimal places or doesn't fit in a 128 bits
+/// decimal, an error will be returned
+#[cfg(feature = "parquet")]
+pub fn transaction_batch(transactions: &[Transaction]) -> Result<RecordBatch> {
+    let scale = TRANSACTION_SCALE.unsigned_abs().into();
+    let amounts = transactions
+        .iter()
+        .map(|t| {
+            t.amount
+                .map(|amount| rescale(amount.amount(), scale))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            transactions
+                .iter()
+                .map(|t| format!("{:?}", t.transaction_type)),
+        )),
+        Arc::new(UInt16Array::from_iter_values(
+            transactions.iter().map(|t| t.client),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            transactions.iter().map(|t| t.tx),
+        )),
+        Arc::new(
+            Decimal128Array::from(amounts)
+                .with_precision_and_scale(DECIMAL128_MAX_PRECISION, TRANSACTION_SCALE)?,
+        ),
+        Arc::new(UInt32Array::from(
+            transactions.iter().map(|t| t.reference).collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt16Array::from(
+            transactions.iter().map(|t| t.to_client).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            transactions
+                .iter()
+                .map(|t| t.action.map(|action| format!("{action:?}")))
+                .collect::<Vec<_>>(),
+        )),
+    ];
+    Ok(RecordBatch::try_new(
+        Arc::new(transaction_schema()),
+        columns,
+    )?)
+}
+
+/// The unscaled value of an amount at `scale` decimal places, at least its own
+fn rescale(amount: Decimal, scale: u32) -> Result<i128> {
+    scale
+        .checked_sub(amount.scale())
+        .and_then(|places| 10i128.checked_pow(places))
+        .and_then(|factor| amount.mantissa().checked_mul(factor))
+        .with_context(|| format!("{amount} doesn't fit in a decimal of scale {scale}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{BooleanArray, Decimal128Array, UInt16Array};
+    use arrow_schema::DataType;
+    use rust_decimal_macros::dec;
+
+    use crate::arrow::record_batch;
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::engine::Engine;
+    use crate::model::{AccountRecord, Transaction};
+
+    #[test]
+    fn test_record_batch_keeps_the_amounts() {
+        let records = [
+            AccountRecord {
+                client: 1,
+                available: dec!(1.5),
+                held: dec!(0),
+                total: dec!(1.5),
+                locked: false,
+            },
+            AccountRecord {
+                client: 12,
+                available: dec!(100.2501),
+                held: dec!(2),
+                total: dec!(102.2501),
+                locked: true,
+            },
+        ];
+        let batch = record_batch(&records).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(
+            batch.schema().field(1).data_type(),
+            &DataType::Decimal128(38, 4)
+        );
+        let column = |name| batch.column_by_name(name).unwrap().as_any();
+        let clients = column("client").downcast_ref::<UInt16Array>().unwrap();
+        assert_eq!(clients.values(), &[1, 12]);
+        let totals = column("total").downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(totals.value_as_string(0), "1.5000");
+        assert_eq!(totals.value_as_string(1), "102.2501");
+        let locked = column("locked").downcast_ref::<BooleanArray>().unwrap();
+        assert!(!locked.value(0) && locked.value(1));
+
+        assert_eq!(record_batch(&[]).unwrap().num_rows(), 0);
+    }
+
+    #[actix::test]
+    async fn test_engine_record_batch() {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        for (client, tx) in [(2, 1), (1, 2)] {
+            let deposit = Transaction::test_deposit(client, tx, dec!(3.25));
+            engine.apply(deposit).await.unwrap();
+        }
+        let batch = engine.record_batch().await.unwrap();
+        let clients = batch.column(0).as_any().downcast_ref::<UInt16Array>();
+        assert_eq!(clients.unwrap().values(), &[1, 2]);
+        let available = batch.column(1).as_any().downcast_ref::<Decimal128Array>();
+        assert_eq!(available.unwrap().value_as_string(1), "3.2500");
+    }
+}
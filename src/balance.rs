@@ -0,0 +1,205 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, ensure, Error, Result};
+use tokio::io::stdout;
+use tracing::info;
+
+use crate::cli::BalanceArgs;
+use crate::csv::write_records;
+use crate::engine::Engine;
+use crate::journal::{JournalEvent, JournalReader};
+use crate::model::{AccountRecord, Transaction, TransactionType};
+use crate::snapshot::Snapshot;
+
+/// A point of the journal, after the event with a sequence number or the events applied until a
+/// time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JournalPoint {
+    Seq(u64),
+    /// Milliseconds since the unix epoch
+    Time(u64),
+}
+
+impl JournalPoint {
+    /// Whether the event happened after the point
+    fn precedes(self, event: &JournalEvent) -> bool {
+        match self {
+            Self::Seq(seq) => event.seq > seq,
+            Self::Time(ms) => event.timestamp_ms > ms,
+        }
+    }
+}
+
+impl FromStr for JournalPoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(seq) = s.trim().parse() {
+            return Ok(Self::Seq(seq));
+        }
+        let time = humantime::parse_rfc3339_weak(s.trim()).map_err(|_| {
+            anyhow!("Expected a sequence number or a timestamp like `2024-05-01T10:00:00Z`")
+        })?;
+        let ms = time.duration_since(UNIX_EPOCH)?.as_millis();
+        Ok(Self::Time(u64::try_from(ms)?))
+    }
+}
+
+impl Display for JournalPoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Seq(seq) => write!(f, "event {seq}"),
+            Self::Time(ms) => write!(f, "{}", format_ms(*ms)),
+        }
+    }
+}
+
+fn format_ms(ms: u64) -> humantime::Rfc3339Timestamp {
+    humantime::format_rfc3339_millis(UNIX_EPOCH + Duration::from_millis(ms))
+}
+
+/// Rebuilds the account of a client from the journal, up to a point, and writes it to the std out
+///
+/// # Errors
+/// If the files can't be read, the snapshot is newer than the point or the client had no account
+/// at that point, an error will be returned
+pub async fn balance(args: &BalanceArgs) -> Result<()> {
+    write_records(stdout(), [account_at(args).await?]).await
+}
+
+/// The event of the journal as it applies to the account of `client`, if it does. A transfer to
+/// the client is credited like a deposit, as the account of the sender isn't rebuilt.
+fn for_client(transaction: Transaction, client: u16) -> Option<Transaction> {
+    if transaction.client == client {
+        return Some(transaction);
+    }
+    let credited = transaction.transaction_type == TransactionType::Transfer
+        && transaction.to_client == Some(client);
+    credited.then_some(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client,
+        to_client: None,
+        ..transaction
+    })
+}
+
+/// The account of the client at the point of the journal the arguments give
+async fn account_at(args: &BalanceArgs) -> Result<AccountRecord> {
+    let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine());
+    let mut after = 0;
+    if let Some(path) = &args.restore {
+        let mut snapshot = Snapshot::read(path).await?;
+        after = snapshot.journal_seq;
+        snapshot
+            .accounts
+            .retain(|state| state.client() == args.client);
+        engine.restore(snapshot)?;
+    }
+    let mut journal = JournalReader::open(&args.journal).await?;
+    ensure!(
+        journal.base_seq() <= after,
+        "The journal starts after event {after}, restore a newer snapshot"
+    );
+    if let JournalPoint::Seq(seq) = args.at {
+        ensure!(seq >= after, "The snapshot is newer than event {seq}");
+    }
+
+    let mut last = None;
+    while let Some(event) = journal.next_event().await? {
+        if event.seq <= after {
+            continue;
+        }
+        if args.at.precedes(&event) {
+            // with a time, a snapshot taken after the point can't be told apart from one taken
+            // right before it, so only snapshots followed by an event before the point are used
+            if last.is_none() && after > 0 && matches!(args.at, JournalPoint::Time(_)) {
+                bail!(
+                    "The snapshot may be newer than {}, restore an older one",
+                    args.at
+                );
+            }
+            break;
+        }
+        last = Some((event.seq, event.timestamp_ms));
+        if let Some(transaction) = for_client(event.transaction, args.client) {
+            engine.apply(transaction).await?;
+        }
+    }
+    if let Some((seq, timestamp_ms)) = last {
+        info!(
+            "Replayed the journal up to event {seq}, applied at {}",
+            format_ms(timestamp_ms)
+        );
+    }
+    let state = engine
+        .state(args.client)
+        .await?
+        .ok_or_else(|| anyhow!("Client {} had no account at {}", args.client, args.at))?;
+    Ok(AccountRecord::from_state(
+        &state,
+        engine.rounding_of(args.client),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rust_decimal_macros::dec;
+
+    use crate::balance::{account_at, JournalPoint};
+    use crate::cli::{Cli, Command};
+    use crate::journal::JournalWriter;
+    use crate::model::{Transaction, TransactionType};
+
+    #[test]
+    fn test_parse_journal_point() {
+        assert_eq!("42".parse::<JournalPoint>().unwrap(), JournalPoint::Seq(42));
+        assert_eq!(
+            "1970-01-01T00:00:01Z".parse::<JournalPoint>().unwrap(),
+            JournalPoint::Time(1000)
+        );
+        assert_eq!(
+            "1970-01-01 00:01:00".parse::<JournalPoint>().unwrap(),
+            JournalPoint::Time(60_000)
+        );
+        assert!("yesterday".parse::<JournalPoint>().is_err());
+    }
+
+    #[actix::test]
+    async fn test_balance_includes_the_transfers_received() {
+        let dir = std::env::temp_dir().join(format!("balance-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("journal.ndjson");
+        let mut journal = JournalWriter::open(&path).await.unwrap();
+        for (transaction_type, client, tx, to_client) in [
+            (TransactionType::Deposit, 1, 1, None),
+            (TransactionType::Deposit, 2, 2, None),
+            (TransactionType::Transfer, 1, 3, Some(2)),
+            (TransactionType::Transfer, 2, 4, Some(3)),
+        ] {
+            let transaction = Transaction::for_test(transaction_type, client, tx, Some(dec!(5)));
+            let transaction = match to_client {
+                Some(to_client) => transaction.with_recipient(to_client),
+                None => transaction,
+            };
+            journal.append(&transaction).await.unwrap();
+        }
+        journal.flush().await.unwrap();
+
+        let journal = path.to_str().unwrap();
+        let at = |seq: &str| {
+            let cli = Cli::parse_from(["test", "balance", "2", "--at", seq, "--journal", journal]);
+            match cli.command {
+                Some(Command::Balance(args)) => args,
+                _ => unreachable!(),
+            }
+        };
+        let record = account_at(&at("3")).await.unwrap();
+        assert_eq!(record.total, dec!(10));
+        let record = account_at(&at("4")).await.unwrap();
+        assert_eq!(record.total, dec!(5));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+/// Thresholds that open the circuit breaker. A rate is only checked once the window holds
+/// `min_samples` transactions, so a couple of early failures don't pause the ingestion.
+#[derive(Clone, Copy, Debug)]
+pub struct BreakerConfig {
+    /// How far back the transactions are counted
+    pub window: Duration,
+    pub min_samples: usize,
+    /// The percentage of rejected transactions in the window that opens the breaker
+    pub max_reject_percent: Option<u8>,
+    /// The percentage of chargebacks in the window that opens the breaker
+    pub max_chargeback_percent: Option<u8>,
+}
+
+/// The result of a transaction, as counted by the breaker
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    Accepted,
+    Chargeback,
+    Rejected,
+}
+
+/// Pauses the ingestion when the reject or chargeback rate spikes, which usually means the
+/// upstream feed is broken. Once open, it stays open until an operator resumes it.
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    window: VecDeque<(Instant, Outcome)>,
+    open: bool,
+    resume: Arc<Notify>,
+}
+
+impl CircuitBreaker {
+    /// Creates a closed breaker. On unix it is resumed with a `SIGUSR1` to the process.
+    pub fn new(config: BreakerConfig) -> Self {
+        let resume = Arc::new(Notify::new());
+        listen_for_resume(Arc::clone(&resume));
+        Self {
+            config,
+            window: VecDeque::new(),
+            open: false,
+            resume,
+        }
+    }
+
+    /// Counts the outcome of a transaction, opening the breaker if a rate goes over its threshold
+    pub fn record(&mut self, outcome: Outcome, now: Instant) {
+        while self
+            .window
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > self.config.window)
+        {
+            self.window.pop_front();
+        }
+        self.window.push_back((now, outcome));
+        if self.open || self.window.len() < self.config.min_samples {
+            return;
+        }
+        let checks = [
+            (Outcome::Rejected, self.config.max_reject_percent, "reject"),
+            (
+                Outcome::Chargeback,
+                self.config.max_chargeback_percent,
+                "chargeback",
+            ),
+        ];
+        for (counted, max_percent, name) in checks {
+            let Some(max_percent) = max_percent else {
+                continue;
+            };
+            let count = self.window.iter().filter(|(_, o)| *o == counted).count();
+            if count * 100 > usize::from(max_percent) * self.window.len() {
+                error!(
+                    "Circuit breaker open: {count} of the last {} transactions were a {name}, over \
+                    {max_percent}%. Ingestion is paused until resumed with SIGUSR1 to process {}",
+                    self.window.len(),
+                    std::process::id()
+                );
+                self.open = true;
+                return;
+            }
+        }
+    }
+
+    /// Whether the ingestion should be paused
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Waits until an operator resumes the ingestion, then starts counting from scratch
+    pub async fn wait_for_resume(&mut self) {
+        self.resume.notified().await;
+        info!("Circuit breaker resumed");
+        self.window.clear();
+        self.open = false;
+    }
+}
+
+#[cfg(unix)]
+fn listen_for_resume(resume: Arc<Notify>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    actix::spawn(async move {
+        let mut signals = match signal(SignalKind::user_defined1()) {
+            Ok(signals) => signals,
+            Err(e) => {
+                error!("Could not listen for SIGUSR1, the circuit breaker can't be resumed: {e}");
+                return;
+            }
+        };
+        while signals.recv().await.is_some() {
+            // only wakes a paused ingestion, a resume while closed is ignored
+            resume.notify_waiters();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn listen_for_resume(_: Arc<Notify>) {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::breaker::{BreakerConfig, CircuitBreaker, Outcome};
+
+    const CONFIG: BreakerConfig = BreakerConfig {
+        window: Duration::from_secs(10),
+        min_samples: 4,
+        max_reject_percent: Some(50),
+        max_chargeback_percent: Some(25),
+    };
+
+    #[actix::test]
+    async fn test_breaker_opens_over_threshold() {
+        let mut breaker = CircuitBreaker::new(CONFIG);
+        let now = Instant::now();
+        for outcome in [Outcome::Rejected, Outcome::Rejected, Outcome::Accepted] {
+            breaker.record(outcome, now);
+        }
+        assert!(!breaker.is_open());
+        breaker.record(Outcome::Accepted, now);
+        assert!(!breaker.is_open());
+        breaker.record(Outcome::Rejected, now);
+        assert!(breaker.is_open());
+
+        let mut breaker = CircuitBreaker::new(CONFIG);
+        for outcome in [Outcome::Chargeback, Outcome::Chargeback] {
+            breaker.record(outcome, now);
+        }
+        // the old chargebacks left the window
+        let later = now + Duration::from_secs(11);
+        for _ in 0..4 {
+            breaker.record(Outcome::Accepted, later);
+        }
+        breaker.record(Outcome::Chargeback, later);
+        assert!(!breaker.is_open());
+        breaker.record(Outcome::Chargeback, later);
+        assert!(breaker.is_open());
+    }
+}
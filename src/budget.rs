@@ -0,0 +1,133 @@
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+/// When the engine degrades: once a transaction takes longer than `budget` to be applied, until
+/// `recovery` transactions in a row are applied within it
+#[derive(Clone, Copy, Debug)]
+pub struct BudgetConfig {
+    pub budget: Duration,
+    pub recovery: u32,
+}
+
+/// What the engine did while degraded
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DegradedStats {
+    /// How many times the engine degraded
+    pub episodes: u64,
+    /// The transactions applied while degraded
+    pub transactions: u64,
+    /// The shadow engine comparisons skipped
+    pub skipped_comparisons: u64,
+    /// The webhook notifications held back until the engine recovered
+    pub deferred_notifications: u64,
+}
+
+impl Display for DegradedStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Degraded {} times over {} transactions: {} shadow comparisons skipped, {} webhook \
+            notifications deferred",
+            self.episodes, self.transactions, self.skipped_comparisons, self.deferred_notifications
+        )
+    }
+}
+
+/// Keeps the balances moving under overload: while the transactions take longer than the budget,
+/// the engine skips the shadow comparisons and holds the webhook notifications back, so only the
+/// work changing the accounts is done
+pub struct LatencyBudget {
+    config: BudgetConfig,
+    degraded: bool,
+    /// The transactions applied within the budget in a row while degraded
+    within: u32,
+    stats: DegradedStats,
+}
+
+impl LatencyBudget {
+    pub fn new(config: BudgetConfig) -> Self {
+        Self {
+            config,
+            degraded: false,
+            within: 0,
+            stats: DegradedStats::default(),
+        }
+    }
+
+    /// Counts how long a transaction took to be applied, degrading or recovering the engine. Tells
+    /// whether the engine just recovered.
+    pub fn record(&mut self, latency: Duration) -> bool {
+        let over = latency > self.config.budget;
+        if !self.degraded {
+            if over {
+                warn!(
+                    "Degrading: a transaction took {latency:?}, over the budget of {:?}",
+                    self.config.budget
+                );
+                self.degraded = true;
+                self.within = 0;
+                self.stats.episodes += 1;
+                self.stats.transactions += 1;
+            }
+            return false;
+        }
+        self.stats.transactions += 1;
+        self.within = if over { 0 } else { self.within + 1 };
+        if self.within < self.config.recovery {
+            return false;
+        }
+        info!(
+            "Recovered: {} transactions in a row within the budget",
+            self.within
+        );
+        self.degraded = false;
+        true
+    }
+
+    /// Whether the non-critical work is skipped or deferred
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    pub fn skip_comparison(&mut self) {
+        self.stats.skipped_comparisons += 1;
+    }
+
+    pub fn defer_notification(&mut self) {
+        self.stats.deferred_notifications += 1;
+    }
+
+    pub fn stats(&self) -> DegradedStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::budget::{BudgetConfig, LatencyBudget};
+
+    #[test]
+    fn test_degrades_over_budget_until_recovered() {
+        let mut budget = LatencyBudget::new(BudgetConfig {
+            budget: Duration::from_millis(10),
+            recovery: 2,
+        });
+        let (fast, slow) = (Duration::from_millis(1), Duration::from_millis(50));
+        assert!(!budget.record(fast));
+        assert!(!budget.is_degraded());
+        assert!(!budget.record(slow));
+        assert!(budget.is_degraded());
+        // a slow transaction starts the recovery over
+        for latency in [fast, slow, fast] {
+            assert!(!budget.record(latency));
+        }
+        assert!(budget.record(fast));
+        assert!(!budget.is_degraded());
+        assert_eq!(budget.stats().episodes, 1);
+        assert_eq!(budget.stats().transactions, 5);
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use crate::model::Transaction;
+
+/// An accepted transaction as it is written in the capture file: the transaction, like a line of
+/// the json input, with the milliseconds since the epoch when it was accepted
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CapturedTransaction {
+    pub at: u64,
+    #[serde(flatten)]
+    pub transaction: Transaction,
+}
+
+/// Records every transaction the engine accepts, with the time it was accepted, so the traffic of
+/// an incident can be replayed in a test environment with `--format capture`. Every line is
+/// flushed as it is written, so a crash loses none of them.
+pub struct CaptureWriter {
+    writer: BufWriter<File>,
+}
+
+impl CaptureWriter {
+    /// Creates the capture file, replacing it if it exists
+    ///
+    /// # Errors
+    /// If the file can't be created, an error will be returned
+    pub async fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path).await?),
+        })
+    }
+
+    /// Writes an accepted transaction, stamped with the current time
+    ///
+    /// # Errors
+    /// If the file can't be written, an error will be returned
+    pub async fn write(&mut self, transaction: &Transaction) -> Result<()> {
+        let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let captured = CapturedTransaction {
+            at: u64::try_from(at)?,
+            transaction: transaction.clone(),
+        };
+        let mut line = serde_json::to_vec(&captured)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use tokio::io::BufReader;
+
+    use crate::capture::CaptureWriter;
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::engine::Engine;
+    use crate::model::{Account, AccountRecord, Transaction, TransactionType};
+    use crate::source::{CaptureSource, InputSource};
+
+    #[actix::test]
+    async fn test_captured_traffic_replays_to_the_same_accounts() {
+        let path = std::env::temp_dir().join(format!("capture-{}.ndjson", std::process::id()));
+        let capture = CaptureWriter::create(&path).await.unwrap();
+        let mut engine =
+            Engine::new(DispatchConfig::default(), EngineConfig::default()).with_capture(capture);
+        let transaction =
+            |transaction_type, tx, amount| Transaction::for_test(transaction_type, 1, tx, amount);
+        let deposit = Some(dec!(10));
+        let overdraft = Some(dec!(50));
+        for transaction in [
+            transaction(TransactionType::Deposit, 1, deposit),
+            transaction(TransactionType::Withdrawal, 2, overdraft),
+            transaction(TransactionType::Dispute, 1, None),
+        ] {
+            engine.submit(transaction).await.unwrap();
+        }
+        let accounts = engine.collect().await.unwrap();
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let mut source = CaptureSource::new(BufReader::new(file), 0.0);
+        let mut replayed = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let mut captured = Vec::new();
+        while let Some((_, transaction)) = source.next().await.unwrap() {
+            captured.push(transaction.tx);
+            replayed.apply(transaction).await.unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+        // the rejected withdrawal isn't captured
+        assert_eq!(captured, [1, 1]);
+        let mut replayed = replayed.collect().await.unwrap();
+        replayed.sort_unstable_by_key(Account::client);
+        assert_eq!(
+            replayed.iter().map(AccountRecord::from).collect::<Vec<_>>(),
+            accounts.iter().map(AccountRecord::from).collect::<Vec<_>>()
+        );
+    }
+}
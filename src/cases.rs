@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::model::{Transaction, TransactionType};
+
+/// Where the case of a disputed transaction is in the workflow of the operations team. The
+/// transaction holds its funds until the case is resolved or charged back.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum DisputeState {
+    #[default]
+    Open,
+    EvidenceRequested,
+    UnderReview,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    /// Whether the case is closed, its funds released or charged back
+    #[cfg(any(test, feature = "http"))]
+    pub fn is_closed(self) -> bool {
+        matches!(self, Self::Resolved | Self::ChargedBack)
+    }
+
+    /// Whether a case can move from this state to the one given. Closed cases only open again
+    /// through a new dispute.
+    #[cfg(any(test, feature = "http"))]
+    pub fn can_become(self, state: Self) -> bool {
+        !self.is_closed() && state != Self::Open && state != self
+    }
+
+    /// The transaction closing a case in this state, if it closes it
+    #[cfg(feature = "http")]
+    pub fn closing_type(self) -> Option<TransactionType> {
+        match self {
+            Self::Resolved => Some(TransactionType::Resolve),
+            Self::ChargedBack => Some(TransactionType::Chargeback),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The case of a disputed transaction
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Case {
+    pub state: DisputeState,
+    /// Who in the operations team handles the case
+    pub assignee: Option<String>,
+}
+
+/// The cases of the disputed transactions, by client and id. Transactions without one are open
+/// and unassigned if they are held, the disputes, resolves and chargebacks applied moving them in
+/// and out of the closed states.
+#[derive(Clone, Debug, Default)]
+pub struct Cases(BTreeMap<(u16, u32), Case>);
+
+impl Cases {
+    /// The case of a transaction holding its funds. A stale closed state, like one rolled back,
+    /// counts as open.
+    #[cfg(any(test, feature = "http"))]
+    pub fn held(&self, client: u16, tx: u32) -> Case {
+        let mut case = self.0.get(&(client, tx)).cloned().unwrap_or_default();
+        if case.state.is_closed() {
+            case.state = DisputeState::Open;
+        }
+        case
+    }
+
+    /// The closed cases of a client, by id
+    #[cfg(any(test, feature = "http"))]
+    pub fn closed(&self, client: u16) -> impl Iterator<Item = (u32, &Case)> {
+        self.0
+            .range((client, 0)..=(client, u32::MAX))
+            .filter(|(_, case)| case.state.is_closed())
+            .map(|(&(_, tx), case)| (tx, case))
+    }
+
+    /// Puts the case of a transaction in the state given, without checking the workflow allows it
+    #[cfg(any(test, feature = "http"))]
+    pub fn set(&mut self, client: u16, tx: u32, case: Case) {
+        self.0.insert((client, tx), case);
+    }
+
+    /// Moves the case of the transaction a dispute step applied to, if it names one, keeping its
+    /// assignee
+    pub fn record(&mut self, transaction: &Transaction) {
+        if transaction.reference.is_some() {
+            return;
+        }
+        let state = match transaction.transaction_type {
+            TransactionType::Dispute => DisputeState::Open,
+            TransactionType::Resolve => DisputeState::Resolved,
+            TransactionType::Chargeback => DisputeState::ChargedBack,
+            _ => return,
+        };
+        let case = self
+            .0
+            .entry((transaction.client, transaction.tx))
+            .or_default();
+        case.state = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cases::{Case, Cases, DisputeState};
+    use crate::model::{Transaction, TransactionType};
+
+    #[test]
+    fn test_cases_follow_the_workflow_and_the_dispute_steps() {
+        let step = |transaction_type, tx| Transaction::for_test(transaction_type, 1, tx, None);
+        let mut cases = Cases::default();
+        cases.record(&step(TransactionType::Dispute, 1));
+        cases.record(&step(TransactionType::Dispute, 2));
+        assert_eq!(cases.held(1, 1), Case::default());
+
+        let state = cases.held(1, 1).state;
+        assert!(state.can_become(DisputeState::EvidenceRequested));
+        assert!(!state.can_become(DisputeState::Open));
+        let review = Case {
+            state: DisputeState::UnderReview,
+            assignee: Some("ana".into()),
+        };
+        cases.set(1, 2, review.clone());
+        assert_eq!(cases.held(1, 2), review);
+
+        cases.record(&step(TransactionType::Chargeback, 2));
+        assert!(!DisputeState::ChargedBack.can_become(DisputeState::UnderReview));
+        assert_eq!(cases.held(1, 2).state, DisputeState::Open);
+        let closed: Vec<_> = cases.closed(1).collect();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].0, 2);
+        assert_eq!(closed[0].1.state, DisputeState::ChargedBack);
+        assert_eq!(closed[0].1.assignee.as_deref(), Some("ana"));
+        assert_eq!(cases.closed(2).count(), 0);
+    }
+}
@@ -0,0 +1,208 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde_json::Value;
+use tokio::fs;
+use tracing::info;
+
+use crate::engine::Engine;
+use crate::snapshot::{write_atomically, Snapshot};
+use crate::source::InputSource;
+
+/// The name of the checkpoint file inside the checkpoint directory
+const CHECKPOINT_FILE: &str = "checkpoint.json";
+
+/// How far a run over the input files got: the accounts after the first `offset` transactions
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    /// The input files, as given to the run
+    inputs: Vec<PathBuf>,
+    /// How many transactions of the input were applied
+    offset: u64,
+    /// The snapshot of the accounts, upgraded like any snapshot when the checkpoint is read
+    snapshot: Value,
+}
+
+/// Where and how often the progress of a run is saved
+pub struct Checkpoints {
+    path: PathBuf,
+    inputs: Vec<PathBuf>,
+    every: u64,
+}
+
+impl Checkpoints {
+    /// Saves the progress over the input files into `dir` every `every` transactions
+    ///
+    /// # Errors
+    /// If the directory can't be created, an error will be returned
+    pub async fn new(dir: &Path, inputs: Vec<PathBuf>, every: u64) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Could not create {}", dir.display()))?;
+        Ok(Self {
+            path: dir.join(CHECKPOINT_FILE),
+            inputs,
+            every,
+        })
+    }
+
+    /// Reads the checkpoint left by a run that didn't finish, if any
+    ///
+    /// # Errors
+    /// If the checkpoint can't be read or was saved for other input files, an error will be
+    /// returned
+    async fn read(&self) -> Result<Option<(u64, Snapshot)>> {
+        let content = match fs::read(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let checkpoint: Checkpoint = serde_json::from_slice(&content)?;
+        ensure!(
+            checkpoint.inputs == self.inputs,
+            "The checkpoint at {} was saved for other input files: {:?}",
+            self.path.display(),
+            checkpoint.inputs
+        );
+        let snapshot = Snapshot::from_value(checkpoint.snapshot)?;
+        Ok(Some((checkpoint.offset, snapshot)))
+    }
+
+    /// Saves the accounts of the engine after `offset` transactions of the input, replacing the
+    /// last checkpoint atomically
+    ///
+    /// # Errors
+    /// If an account doesn't answer or the checkpoint can't be written, an error will be returned
+    async fn write(&self, engine: &mut Engine, offset: u64) -> Result<()> {
+        engine.persist().await?;
+        let snapshot = Snapshot::from_states(engine.journal_seq(), engine.states().await?);
+        let checkpoint = Checkpoint {
+            inputs: self.inputs.clone(),
+            offset,
+            snapshot: serde_json::to_value(snapshot)?,
+        };
+        let mut content = serde_json::to_vec(&checkpoint)?;
+        content.push(b'\n');
+        write_atomically(&self.path, &content).await
+    }
+}
+
+/// Applies the transactions of the source, saving the accounts and how many transactions were
+/// applied every so often. A run that finds the checkpoint of a run over the same input that
+/// didn't finish starts from its accounts and skips the transactions it applied. The checkpoint
+/// is removed once the whole source was applied.
+///
+/// # Errors
+/// If the source fails, the engine fails to record a transaction or the checkpoint can't be read
+/// or written, an error will be returned
+pub async fn process_with_checkpoints(
+    mut source: impl InputSource,
+    engine: &mut Engine,
+    checkpoints: &Checkpoints,
+) -> Result<()> {
+    let mut offset = 0;
+    if let Some((applied, snapshot)) = checkpoints.read().await? {
+        info!("Resuming after transaction {applied} of the input");
+        engine.restore(snapshot)?;
+        while offset < applied {
+            if source.next().await?.is_none() {
+                bail!("The input ends before the {applied} transactions of the checkpoint");
+            }
+            offset += 1;
+        }
+    }
+    while let Some((_, transaction)) = source.next().await? {
+        if engine.sample_complete() {
+            break;
+        }
+        engine.apply(transaction).await?;
+        offset += 1;
+        if offset % checkpoints.every == 0 {
+            checkpoints.write(engine, offset).await?;
+        }
+    }
+    match fs::remove_file(&checkpoints.path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::{bail, Result};
+    use rust_decimal_macros::dec;
+
+    use crate::checkpoint::{process_with_checkpoints, Checkpoints};
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::engine::Engine;
+    use crate::model::{Account, AccountRecord, Transaction, TransactionType};
+    use crate::source::{DeliveryTag, InputSource};
+
+    /// Delivers the transactions, failing once `fail_at` were delivered, like a crashed run
+    struct CrashingSource {
+        transactions: Vec<Transaction>,
+        delivered: usize,
+        fail_at: Option<usize>,
+    }
+
+    impl InputSource for CrashingSource {
+        async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+            if self.fail_at == Some(self.delivered) {
+                bail!("crashed");
+            }
+            let transaction = self.transactions.get(self.delivered).cloned();
+            self.delivered += 1;
+            Ok(transaction.map(|transaction| (self.delivered as DeliveryTag, transaction)))
+        }
+    }
+
+    fn input() -> Vec<Transaction> {
+        vec![
+            Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::for_test(TransactionType::Deposit, 2, 2, Some(dec!(5))),
+            Transaction::for_test(TransactionType::Dispute, 1, 1, None),
+            Transaction::for_test(TransactionType::Withdrawal, 2, 3, Some(dec!(2))),
+            Transaction::for_test(TransactionType::Resolve, 1, 1, None),
+            Transaction::for_test(TransactionType::Withdrawal, 1, 4, Some(dec!(3))),
+        ]
+    }
+
+    fn records(mut accounts: Vec<Account>) -> Vec<AccountRecord> {
+        accounts.sort_unstable_by_key(Account::client);
+        accounts.iter().map(AccountRecord::from).collect()
+    }
+
+    async fn run(checkpoints: &Checkpoints, fail_at: Option<usize>) -> Result<Vec<AccountRecord>> {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let source = CrashingSource {
+            transactions: input(),
+            delivered: 0,
+            fail_at,
+        };
+        process_with_checkpoints(source, &mut engine, checkpoints).await?;
+        Ok(records(engine.collect().await?))
+    }
+
+    #[actix::test]
+    async fn test_resumes_from_the_last_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("checkpoint-{}", std::process::id()));
+        let inputs = vec![PathBuf::from("transactions.csv")];
+        let checkpoints = Checkpoints::new(&dir, inputs, 2).await.unwrap();
+        let uninterrupted = run(&checkpoints, None).await.unwrap();
+        assert!(!dir.join("checkpoint.json").exists());
+
+        // the transactions after the checkpoint of the 4th are applied again
+        assert!(run(&checkpoints, Some(5)).await.is_err());
+        assert!(dir.join("checkpoint.json").exists());
+        assert_eq!(run(&checkpoints, None).await.unwrap(), uninterrupted);
+
+        let other = vec![PathBuf::from("other.csv")];
+        assert!(run(&checkpoints, Some(3)).await.is_err());
+        let checkpoints = Checkpoints::new(&dir, other, 2).await.unwrap();
+        assert!(run(&checkpoints, None).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{ensure, Result};
+use tracing::info;
+
+use crate::cli::CompactArgs;
+use crate::engine::Engine;
+use crate::journal::{rewrite_journal, JournalReader};
+use crate::snapshot::Snapshot;
+
+/// Collapses the journal events up to the requested point into the snapshot and removes them from
+/// the journal. The snapshot is written before the journal is rewritten, so a failure in between
+/// leaves events that are both in the snapshot and the journal, which are skipped when replaying.
+///
+/// # Errors
+/// If the files can't be read or written, the snapshot is ahead of the requested point or missing
+/// for a journal that was already compacted, an error will be returned
+pub async fn compact(args: &CompactArgs) -> Result<()> {
+    let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine());
+    let mut journal = JournalReader::open(&args.journal).await?;
+    let journal_base_seq = journal.base_seq();
+    let mut base_seq = journal_base_seq;
+    ensure!(
+        args.snapshot.exists() || journal_base_seq == 0,
+        "The journal starts after event {journal_base_seq}, the snapshot it was compacted into is missing"
+    );
+    if args.snapshot.exists() {
+        let snapshot = Snapshot::read(&args.snapshot).await?;
+        ensure!(
+            snapshot.journal_seq >= base_seq,
+            "The snapshot is older than the start of the journal"
+        );
+        base_seq = snapshot.journal_seq;
+        engine.restore(snapshot)?;
+    }
+    if let Some(until) = args.until {
+        ensure!(
+            until >= base_seq,
+            "The snapshot already includes events up to {base_seq}"
+        );
+    }
+
+    let journal_seq = engine.replay(&mut journal, base_seq, args.until).await?;
+    let accounts = engine.collect().await?;
+    Snapshot::new(journal_seq, &accounts)
+        .write(&args.snapshot)
+        .await?;
+    // an unchanged journal isn't rewritten, so readers following it keep reading the same file
+    if journal_seq > journal_base_seq {
+        rewrite_journal(&args.journal, journal_seq).await?;
+    }
+    info!(
+        "Collapsed {} events into the snapshot",
+        journal_seq - base_seq
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rust_decimal_macros::dec;
+
+    use crate::cli::{Cli, Command, CompactArgs};
+    use crate::compact::compact;
+    use crate::journal::{JournalReader, JournalWriter};
+    use crate::model::Transaction;
+    use crate::snapshot::Snapshot;
+
+    #[actix::test]
+    async fn test_compact_collapses_the_journal_into_the_snapshot() {
+        let dir = std::env::temp_dir().join(format!("compact-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (journal, snapshot) = (dir.join("journal.ndjson"), dir.join("snapshot.json"));
+        let mut writer = JournalWriter::open(&journal).await.unwrap();
+        for tx in 1..=3 {
+            let deposit = Transaction::test_deposit(1, tx, dec!(1));
+            writer.append(&deposit).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        let args = |until: Option<&str>| -> CompactArgs {
+            let mut argv = vec!["test", "compact", journal.to_str().unwrap()];
+            argv.extend(["--snapshot", snapshot.to_str().unwrap()]);
+            argv.extend(until.iter().flat_map(|until| ["--until", until]));
+            match Cli::parse_from(argv).command {
+                Some(Command::Compact(args)) => args,
+                _ => unreachable!(),
+            }
+        };
+
+        compact(&args(Some("2"))).await.unwrap();
+        let collapsed = Snapshot::read(&snapshot).await.unwrap();
+        assert_eq!(collapsed.journal_seq, 2);
+        let mut reader = JournalReader::open(&journal).await.unwrap();
+        assert_eq!(reader.base_seq(), 2);
+        assert_eq!(reader.next_event().await.unwrap().unwrap().seq, 3);
+        assert!(reader.next_event().await.unwrap().is_none());
+
+        compact(&args(None)).await.unwrap();
+        let collapsed = Snapshot::read(&snapshot).await.unwrap();
+        assert_eq!(collapsed.journal_seq, 3);
+        assert_eq!(collapsed.accounts.len(), 1);
+
+        // the events before the journal only live in the snapshot
+        std::fs::remove_file(&snapshot).unwrap();
+        assert!(compact(&args(None)).await.is_err());
+        assert_eq!(JournalReader::open(&journal).await.unwrap().base_seq(), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio::fs::File;
+use tokio::io::{stdout, BufReader};
+use tracing::info;
+
+use crate::cli::{CompareArgs, RunConfig};
+use crate::csv::{process_transactions, write_records};
+use crate::delta::records_of;
+use crate::engine::Engine;
+use crate::model::AccountRecord;
+use crate::registry::ClientRegistry;
+use crate::snapshot::Snapshot;
+
+/// A client whose account differs between the two runs. The values of a side are empty when the
+/// client has no account in it.
+#[derive(Serialize, Debug, PartialEq)]
+struct Divergence {
+    client: u16,
+    left_available: Option<Decimal>,
+    right_available: Option<Decimal>,
+    left_held: Option<Decimal>,
+    right_held: Option<Decimal>,
+    left_total: Option<Decimal>,
+    right_total: Option<Decimal>,
+    left_locked: Option<bool>,
+    right_locked: Option<bool>,
+}
+
+impl Divergence {
+    fn new(client: u16, left: Option<&AccountRecord>, right: Option<&AccountRecord>) -> Self {
+        Self {
+            client,
+            left_available: left.map(|r| r.available),
+            right_available: right.map(|r| r.available),
+            left_held: left.map(|r| r.held),
+            right_held: right.map(|r| r.held),
+            left_total: left.map(|r| r.total),
+            right_total: right.map(|r| r.total),
+            left_locked: left.map(|r| r.locked),
+            right_locked: right.map(|r| r.locked),
+        }
+    }
+}
+
+/// Processes the input under both configurations, or takes a side from a snapshot, and writes
+/// the clients whose accounts diverge to the std out
+///
+/// # Errors
+/// If a run fails, a snapshot can't be read or the output can't be written, an error will be
+/// returned
+pub async fn compare_runs(args: &CompareArgs) -> Result<()> {
+    let left = side(&args.input, &args.left, args.left_snapshot.as_deref()).await?;
+    let right = side(&args.input, &args.right, args.right_snapshot.as_deref()).await?;
+    let divergences = diverge(&left, &right);
+    let clients = left.len() + right.len() - common(&left, &right);
+    info!("{} of {clients} clients diverge", divergences.len());
+    write_records(stdout(), divergences).await
+}
+
+async fn side(
+    input: &Path,
+    config: &RunConfig,
+    snapshot: Option<&Path>,
+) -> Result<BTreeMap<u16, AccountRecord>> {
+    match snapshot {
+        Some(path) => Ok(records_of(&Snapshot::read(path).await?)),
+        None => run(input, config).await,
+    }
+}
+
+/// Processes the input with a fresh engine and returns the resulting accounts
+async fn run(input: &Path, config: &RunConfig) -> Result<BTreeMap<u16, AccountRecord>> {
+    let mut engine = Engine::new(config.engine.dispatch(), config.engine.engine());
+    if let Some(path) = &config.clients {
+        let mut registry = ClientRegistry::load(path).await?;
+        if let Some(policies) = &config.segment_policies {
+            registry = registry.with_policies(policies).await?;
+        }
+        engine = engine.with_registry(Arc::new(registry));
+    }
+    let file = File::open(input).await?;
+    process_transactions(BufReader::new(file), &mut engine).await?;
+    let accounts = engine.collect().await?;
+    Ok(accounts
+        .iter()
+        .map(|account| (account.client(), AccountRecord::from(account)))
+        .collect())
+}
+
+/// The number of clients present on both sides
+fn common(left: &BTreeMap<u16, AccountRecord>, right: &BTreeMap<u16, AccountRecord>) -> usize {
+    left.keys()
+        .filter(|client| right.contains_key(client))
+        .count()
+}
+
+/// The clients whose printed values differ between the sides, including clients missing from one
+/// of them, ordered by client
+fn diverge(
+    left: &BTreeMap<u16, AccountRecord>,
+    right: &BTreeMap<u16, AccountRecord>,
+) -> Vec<Divergence> {
+    let mut clients: Vec<_> = left.keys().chain(right.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+    clients
+        .into_iter()
+        .filter_map(|client| {
+            let (l, r) = (left.get(&client), right.get(&client));
+            (l != r).then(|| Divergence::new(client, l, r))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rust_decimal_macros::dec;
+
+    use crate::compare::diverge;
+    use crate::model::AccountRecord;
+
+    #[test]
+    fn test_diverge() {
+        let left = BTreeMap::from([
+            (1, AccountRecord::for_test(1, dec!(1), dec!(0))),
+            (2, AccountRecord::for_test(2, dec!(2), dec!(0))),
+            (3, AccountRecord::for_test(3, dec!(3), dec!(0))),
+        ]);
+        let right = BTreeMap::from([
+            (1, AccountRecord::for_test(1, dec!(1), dec!(0))),
+            (
+                2,
+                AccountRecord {
+                    locked: true,
+                    ..AccountRecord::for_test(2, dec!(2), dec!(0))
+                },
+            ),
+            (4, AccountRecord::for_test(4, dec!(4), dec!(0))),
+        ]);
+        let divergences: Vec<_> = diverge(&left, &right)
+            .iter()
+            .map(|d| (d.client, d.left_locked, d.right_locked))
+            .collect();
+        assert_eq!(
+            divergences,
+            vec![
+                (2, Some(false), Some(true)),
+                (3, Some(false), None),
+                (4, None, Some(false)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+use rust_decimal::RoundingStrategy;
+
+use crate::money::{Currency, DEFAULT_SCALE};
+use crate::policy::Policy;
+
+/// Settings used when delivering messages to the account actors
+#[derive(Clone, Copy, Debug)]
+pub struct DispatchConfig {
+    /// How long to wait for an actor to answer before trying again
+    pub timeout: Duration,
+    /// How many extra attempts are made before the transaction is declared failed
+    pub retries: u32,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            retries: 3,
+        }
+    }
+}
+
+/// Settings that change how accounts apply transactions
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EngineConfig {
+    /// Locks the account when its balances are found to be inconsistent
+    pub freeze_on_inconsistency: bool,
+    /// Rejects the disputes of transactions whose earlier dispute was resolved
+    pub block_redisputes: bool,
+    /// What is done with the amounts given by disputes, resolves and chargebacks
+    pub dispute_amounts: DisputeAmounts,
+    /// Whether withdrawals can be disputed too
+    pub withdrawal_disputes: WithdrawalDisputes,
+    /// The product of the account with its limits, selecting the rules of its withdrawals,
+    /// disputes and chargebacks
+    pub policy: Policy,
+    /// Keeps the amounts unrounded, only rounding them when the accounts are written
+    pub late_rounding: bool,
+    /// How the amounts of the accounts are rounded
+    pub rounding: Rounding,
+    /// The currency the account is kept in
+    pub currency: Currency,
+}
+
+/// How amounts are rounded, as different ledgers have different conventions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rounding {
+    /// The decimal places amounts are rounded to
+    pub precision: u32,
+    /// The decimal places amounts are written with, at most `precision`
+    pub display: u32,
+    pub mode: RoundingMode,
+}
+
+impl Rounding {
+    /// The rounding of the amounts as they are written
+    #[must_use]
+    pub fn for_display(self) -> Self {
+        Self {
+            precision: self.display.min(self.precision),
+            ..self
+        }
+    }
+}
+
+impl Default for Rounding {
+    fn default() -> Self {
+        Self {
+            precision: DEFAULT_SCALE,
+            display: DEFAULT_SCALE,
+            mode: RoundingMode::HalfEven,
+        }
+    }
+}
+
+/// Where an amount halfway between two rounded amounts goes
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoundingMode {
+    /// Away from zero
+    HalfUp,
+    /// To the even digit, so the roundings don't drift in one direction
+    #[default]
+    HalfEven,
+    /// Every extra digit is dropped, rounding towards zero
+    Truncate,
+}
+
+impl RoundingMode {
+    #[must_use]
+    pub fn strategy(self) -> RoundingStrategy {
+        match self {
+            Self::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Self::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Self::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+/// How the amounts given by disputes, resolves and chargebacks, which only refer to a deposit by
+/// its id, are validated
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum DisputeAmounts {
+    /// The amount is ignored, the one of the deposit is used
+    #[default]
+    Ignore,
+    /// Rows whose amount differs from the one of the deposit are rejected
+    Verify,
+}
+
+/// What is done with the disputes of withdrawals
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum WithdrawalDisputes {
+    /// They are rejected as invalid operations, only deposits can be disputed
+    #[default]
+    Reject,
+    /// The withdrawn amount is held as a pending credit, which a resolve drops and a chargeback
+    /// credits to the available funds
+    Hold,
+}
+
+/// How the ids of the transactions generated by the tool, like compensating transactions, are
+/// chosen. Every scheme skips the ids already used.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum IdScheme {
+    /// The highest unused ids, counting down from `u32::MAX`, away from the ids of the input
+    #[default]
+    Descending,
+    /// Counting up from a start id
+    Sequential,
+    /// The node id of the deployment in the high 8 bits and a sequence in the low 24, so
+    /// deployments sharing a journal never generate the same id
+    Snowflake,
+    /// Ids drawn from random (v4) UUIDs. Transaction ids are 32 bits, so the 128 bits of a UUID
+    /// are folded into one instead of being kept whole.
+    #[value(alias = "random")]
+    Uuid,
+}
+
+/// Settings of the id generator
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IdConfig {
+    pub scheme: IdScheme,
+    /// The first id of the sequential scheme
+    pub start: u32,
+    /// The node id of the snowflake scheme
+    pub node: u8,
+}
+
+/// How the fields of the csv input are quoted
+#[derive(Clone, Copy, Debug)]
+pub struct CsvConfig {
+    /// The character quoting a field, which may then hold delimiters and line breaks
+    pub quote: u8,
+    /// The character escaping the quotes inside a quoted field, instead of doubling them
+    pub escape: Option<u8>,
+    /// Also unquotes the fields whose quote doesn't open the field, like after a space, joining
+    /// back the delimiters inside them
+    pub lenient: bool,
+}
+
+impl Default for CsvConfig {
+    fn default() -> Self {
+        Self {
+            quote: b'"',
+            escape: None,
+            lenient: false,
+        }
+    }
+}
+
+/// When the client API turns transactions away instead of queueing them for the engine
+#[derive(Clone, Copy, Debug)]
+pub struct ThrottleConfig {
+    /// How many requests may wait for the engine before the next ones are answered `429`
+    pub max_queued: usize,
+    /// How long the clients turned away are told to wait before trying again
+    pub retry_after: Duration,
+}
+
+/// When the transactions of a source that takes acknowledgements are acknowledged
+#[derive(Clone, Copy, Debug)]
+pub struct AckConfig {
+    /// Acknowledges once this many transactions are applied
+    pub batch_size: usize,
+    /// Acknowledges the transactions applied when this much time passed since the last
+    /// acknowledgement, even if the batch isn't complete
+    pub interval: Duration,
+}
+
+impl Default for AckConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// How often the journal is written to disk and when it is split into segments. By default it is
+/// only written out when its buffer fills up and at the end of the run, in a single file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JournalConfig {
+    /// Writes the journal to disk once this many events were appended since the last time
+    pub sync_every: Option<usize>,
+    /// Writes the journal to disk on the first event appended this long after the last time
+    pub sync_interval: Option<Duration>,
+    /// Moves the events into a compressed segment once the journal file reaches this many bytes
+    pub segment_size: Option<u64>,
+}
@@ -0,0 +1,169 @@
+//! Checks that the engine keeps the order of every client's transactions, whatever the
+//! interleaving of the clients and the workers running the actors: the accounts must equal the
+//! ones of applying every transaction in order on a single thread. Every run is generated from a
+//! seed, so a failing one can be replayed. Transactions sent ahead through mailboxes are settled
+//! at random points of the run.
+
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+use crate::config::{DispatchConfig, EngineConfig};
+use crate::engine::Engine;
+use crate::model::{Account, AccountRecord, Transaction, TransactionType};
+
+const SEEDS: u64 = 32;
+const CLIENTS: u16 = 12;
+const TRANSACTIONS_PER_CLIENT: usize = 40;
+
+/// A splitmix64 generator, enough to derive the runs from a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`
+    fn below(&mut self, bound: usize) -> usize {
+        usize::try_from(self.next() % bound as u64).unwrap()
+    }
+}
+
+/// The transactions of every client, in the order they must be applied. Disputes, resolves and
+/// chargebacks mostly refer to earlier transactions of the same client, sometimes to unknown ones.
+fn scripts(rng: &mut Rng) -> Vec<Vec<Transaction>> {
+    let mut tx = 0;
+    (0..CLIENTS)
+        .map(|client| {
+            let mut script: Vec<Transaction> = Vec::with_capacity(TRANSACTIONS_PER_CLIENT);
+            for _ in 0..TRANSACTIONS_PER_CLIENT {
+                let kind = rng.below(10);
+                let transaction = if kind < 7 || script.is_empty() {
+                    tx += 1;
+                    let transaction_type = if kind < 4 {
+                        TransactionType::Deposit
+                    } else {
+                        TransactionType::Withdrawal
+                    };
+                    let cents = i64::try_from(rng.below(100_000)).unwrap();
+                    Transaction::for_test(
+                        transaction_type,
+                        client,
+                        tx,
+                        Some(Decimal::new(cents, 2)),
+                    )
+                } else {
+                    let transaction_type = match kind {
+                        7 => TransactionType::Dispute,
+                        8 => TransactionType::Resolve,
+                        _ => TransactionType::Chargeback,
+                    };
+                    let target = match rng.below(8) {
+                        0 => u32::MAX,
+                        _ => script[rng.below(script.len())].tx,
+                    };
+                    Transaction::for_test(transaction_type, client, target, None)
+                };
+                script.push(transaction);
+            }
+            script
+        })
+        .collect()
+}
+
+/// Merges the scripts in a random order, keeping the order of every client's transactions
+fn interleave(rng: &mut Rng, scripts: &[Vec<Transaction>]) -> Vec<Transaction> {
+    let mut next = vec![0; scripts.len()];
+    let mut pending: Vec<_> = (0..scripts.len()).collect();
+    let mut order = Vec::new();
+    while !pending.is_empty() {
+        let pick = rng.below(pending.len());
+        let client = pending[pick];
+        order.push(scripts[client][next[client]].clone());
+        next[client] += 1;
+        if next[client] == scripts[client].len() {
+            pending.swap_remove(pick);
+        }
+    }
+    order
+}
+
+/// The accounts of applying every script in order, without actors
+fn reference(scripts: &[Vec<Transaction>]) -> BTreeMap<u16, AccountRecord> {
+    scripts
+        .iter()
+        .filter_map(|script| {
+            let first = script.first()?;
+            let mut account = Account::new(first.client, EngineConfig::default());
+            for transaction in script {
+                // rejections are part of the expected result
+                let _ = account.apply(transaction);
+            }
+            Some((account.client(), AccountRecord::from(&account)))
+        })
+        .collect()
+}
+
+/// Applies the transactions in order, settling the ones sent ahead at random points
+async fn run(
+    rng: &mut Rng,
+    mut engine: Engine,
+    order: Vec<Transaction>,
+) -> BTreeMap<u16, AccountRecord> {
+    for transaction in order {
+        engine.apply(transaction).await.unwrap();
+        if rng.below(16) == 0 {
+            engine.settle().await.unwrap();
+        }
+    }
+    engine.settle().await.unwrap();
+    engine
+        .collect()
+        .await
+        .unwrap()
+        .iter()
+        .map(|account| (account.client(), AccountRecord::from(account)))
+        .collect()
+}
+
+#[actix::test]
+async fn test_engine_matches_single_threaded_reference() {
+    for seed in 0..SEEDS {
+        let mut rng = Rng(seed);
+        let scripts = scripts(&mut rng);
+        let expected = reference(&scripts);
+        let order = interleave(&mut rng, &scripts);
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        // no workers runs every actor on the test thread
+        let workers = rng.below(4);
+        if workers > 0 {
+            engine = engine.with_workers(&vec![0; workers]);
+        }
+        let accounts = run(&mut rng, engine, order).await;
+        assert_eq!(accounts, expected, "seed {seed} with {workers} workers");
+    }
+}
+
+#[actix::test]
+async fn test_mailboxes_match_single_threaded_reference() {
+    for seed in 0..SEEDS {
+        let mut rng = Rng(seed);
+        let scripts = scripts(&mut rng);
+        let expected = reference(&scripts);
+        let order = interleave(&mut rng, &scripts);
+        let (workers, capacity) = (2 + rng.below(3), 1 + rng.below(8));
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_workers(&vec![0; workers])
+            .with_mailboxes(capacity);
+        let accounts = run(&mut rng, engine, order).await;
+        assert_eq!(
+            accounts, expected,
+            "seed {seed} with {workers} workers and mailboxes of {capacity}"
+        );
+    }
+}
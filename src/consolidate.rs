@@ -0,0 +1,189 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{ensure, Result};
+use rust_decimal::Decimal;
+use tokio::fs::File;
+use tokio::io::{stdout, BufReader};
+use tracing::info;
+
+use crate::cli::ConsolidateArgs;
+use crate::csv::{read_records, write_records};
+use crate::engine::Engine;
+use crate::journal::JournalReader;
+use crate::model::AccountRecord;
+use crate::snapshot::Snapshot;
+
+/// What could reconcile an imported row with the balances of the journal
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Suggestion {
+    /// The journal has no account for the client, an `Open` transaction would create it
+    Open,
+    /// The imported available funds are higher, a deposit of the difference would match them
+    Deposit,
+    /// The imported available funds are lower, a withdrawal of the difference would match them
+    Withdrawal,
+    /// Another row of the client matches the journal, this duplicate can be dropped
+    Drop,
+    /// The held funds or the lock differ, which no transaction can adjust
+    Review,
+}
+
+/// A row of the import that disagrees with the journal, with the suggested adjustment
+#[derive(Serialize, Debug, PartialEq)]
+struct ConsolidationEntry {
+    client: u16,
+    /// The line of the row in the imported file
+    line: usize,
+    /// How many rows the import has for the client
+    rows: usize,
+    imported_available: Decimal,
+    imported_held: Decimal,
+    imported_locked: bool,
+    journal_available: Option<Decimal>,
+    journal_held: Option<Decimal>,
+    journal_locked: Option<bool>,
+    suggestion: Suggestion,
+    /// The amount of the suggested transaction
+    amount: Option<Decimal>,
+}
+
+/// Compares the imported accounts with the ones the journal recomputes, writing a report of every
+/// imported row that disagrees to the std out. No source is preferred: every difference is
+/// reported with the adjustment that would reconcile it, for an operator to choose.
+///
+/// # Errors
+/// If the files can't be read or the snapshot is older than the start of the journal, an error
+/// will be returned
+pub async fn consolidate(args: &ConsolidateArgs) -> Result<()> {
+    let file = File::open(&args.accounts).await?;
+    let imported = read_records::<AccountRecord>(BufReader::new(file)).await;
+
+    let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine());
+    let mut journal = JournalReader::open(&args.journal).await?;
+    let mut after = journal.base_seq();
+    if let Some(path) = &args.restore {
+        let snapshot = Snapshot::read(path).await?;
+        ensure!(
+            snapshot.journal_seq >= after,
+            "The snapshot is older than the start of the journal"
+        );
+        after = snapshot.journal_seq;
+        engine.restore(snapshot)?;
+    }
+    engine.replay(&mut journal, after, None).await?;
+    let recomputed: HashMap<u16, AccountRecord> = engine
+        .collect()
+        .await?
+        .iter()
+        .map(|account| (account.client(), AccountRecord::from(account)))
+        .collect();
+
+    let entries = reconcile(&imported, &recomputed);
+    info!(
+        "Found {} imported rows disagreeing with the journal",
+        entries.len()
+    );
+    write_records(stdout(), entries).await
+}
+
+/// The entries of the imported rows that disagree with the recomputed accounts, by client
+fn reconcile(
+    imported: &[AccountRecord],
+    recomputed: &HashMap<u16, AccountRecord>,
+) -> Vec<ConsolidationEntry> {
+    // the header is the first line of the file
+    let mut rows: BTreeMap<u16, Vec<(usize, &AccountRecord)>> = BTreeMap::new();
+    for (index, record) in imported.iter().enumerate() {
+        rows.entry(record.client)
+            .or_default()
+            .push((index + 2, record));
+    }
+    let mut entries = Vec::new();
+    for (client, rows) in rows {
+        let journal = recomputed.get(&client);
+        let matches = |record: &AccountRecord| journal.is_some_and(|j| agrees(record, j));
+        let any_match = rows.iter().any(|(_, record)| matches(record));
+        for (line, record) in &rows {
+            if matches(record) {
+                continue;
+            }
+            let (suggestion, amount) = match journal {
+                _ if any_match => (Suggestion::Drop, None),
+                None => (Suggestion::Open, Some(record.available)),
+                Some(j) if j.held != record.held || j.locked != record.locked => {
+                    (Suggestion::Review, None)
+                }
+                Some(j) if record.available > j.available => {
+                    (Suggestion::Deposit, Some(record.available - j.available))
+                }
+                Some(j) => (Suggestion::Withdrawal, Some(j.available - record.available)),
+            };
+            entries.push(ConsolidationEntry {
+                client,
+                line: *line,
+                rows: rows.len(),
+                imported_available: record.available,
+                imported_held: record.held,
+                imported_locked: record.locked,
+                journal_available: journal.map(|j| j.available),
+                journal_held: journal.map(|j| j.held),
+                journal_locked: journal.map(|j| j.locked),
+                suggestion,
+                amount,
+            });
+        }
+    }
+    entries
+}
+
+/// Whether an imported row holds the balances of the recomputed account. The total is derived,
+/// so it isn't compared.
+fn agrees(imported: &AccountRecord, recomputed: &AccountRecord) -> bool {
+    imported.available == recomputed.available
+        && imported.held == recomputed.held
+        && imported.locked == recomputed.locked
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal_macros::dec;
+
+    use crate::consolidate::{reconcile, Suggestion};
+    use crate::model::AccountRecord;
+
+    #[test]
+    fn test_reconcile_suggests_adjustments() {
+        let imported = [
+            AccountRecord::for_test(1, dec!(10), dec!(0)),
+            AccountRecord::for_test(1, dec!(12), dec!(0)),
+            AccountRecord::for_test(2, dec!(15), dec!(0)),
+            AccountRecord::for_test(3, dec!(5), dec!(1)),
+            AccountRecord::for_test(4, dec!(7), dec!(0)),
+            AccountRecord::for_test(5, dec!(3), dec!(0)),
+        ];
+        let recomputed = HashMap::from([
+            (1, AccountRecord::for_test(1, dec!(10), dec!(0))),
+            (2, AccountRecord::for_test(2, dec!(20), dec!(0))),
+            (3, AccountRecord::for_test(3, dec!(5), dec!(0))),
+            (5, AccountRecord::for_test(5, dec!(3), dec!(0))),
+        ]);
+        let entries = reconcile(&imported, &recomputed);
+        let suggestions: Vec<_> = entries
+            .iter()
+            .map(|e| (e.client, e.line, e.suggestion, e.amount))
+            .collect();
+        assert_eq!(
+            suggestions,
+            vec![
+                (1, 3, Suggestion::Drop, None),
+                (2, 4, Suggestion::Withdrawal, Some(dec!(5))),
+                (3, 5, Suggestion::Review, None),
+                (4, 6, Suggestion::Open, Some(dec!(7))),
+            ]
+        );
+        assert_eq!(entries[0].rows, 2);
+    }
+}
@@ -1,64 +1,433 @@
-use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 
 use anyhow::Result;
+use clap::ValueEnum;
 use csv_async::Trim::All;
-use csv_async::{AsyncReaderBuilder, AsyncSerializer};
-use log::{error, warn};
+use csv_async::{
+    AsyncDeserializer, AsyncReaderBuilder, AsyncSerializer, ErrorKind, Position, StringRecord,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use tokio::io::{AsyncBufRead, AsyncWrite, BufWriter};
 use tokio_stream::StreamExt;
+use tracing::error;
 
-use crate::model::{Collect, Transaction, TransactionError};
-use crate::transaction::AccountHandler;
+use crate::config::{AckConfig, CsvConfig};
+use crate::diagnostics::{Diagnostics, Observation};
+use crate::engine::Engine;
+use crate::model::{Account, Transaction};
+use crate::source::{process_source, DeliveryTag, InputSource};
 
-/// Parse the transactions of the provided reader and outputs the accounts into the provided writer
-pub async fn parse_transactions(
-    buf_reader: impl AsyncBufRead + Send + Unpin,
-    buf_writer: impl AsyncWrite + Unpin,
-) -> Result<()> {
-    let mut csv_reader = AsyncReaderBuilder::new()
+/// A row of the input that doesn't follow the contract exported by the `schema` command
+#[derive(Debug, PartialEq)]
+pub struct ValidationError {
+    pub line: Option<u64>,
+    /// The column holding the invalid value, when the row could be split into columns
+    pub field: Option<String>,
+    pub reason: String,
+}
+
+impl ValidationError {
+    fn from_csv(e: &csv_async::Error, headers: &StringRecord) -> Self {
+        let line = e.position().map(Position::line);
+        let (field, reason) = match e.kind() {
+            ErrorKind::Deserialize { err, .. } => {
+                let field = err
+                    .field()
+                    .and_then(|i| usize::try_from(i).ok())
+                    .and_then(|i| headers.get(i));
+                (field, err.kind().to_string())
+            }
+            ErrorKind::UnequalLengths {
+                expected_len, len, ..
+            } => (None, format!("found {len} fields, expected {expected_len}")),
+            ErrorKind::Utf8 { err, .. } => (headers.get(err.field()), "invalid UTF-8".into()),
+            _ => (None, e.to_string()),
+        };
+        Self {
+            line,
+            field: field.map(String::from),
+            reason,
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {line}, ")?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, "field `{field}`, ")?;
+        }
+        f.write_str(&self.reason)
+    }
+}
+
+const DELIMITER: u8 = b',';
+
+fn create_deserializer<R: AsyncBufRead + Send + Unpin>(buf_reader: R) -> AsyncDeserializer<R> {
+    AsyncReaderBuilder::new()
         .has_headers(true)
-        .delimiter(b',')
+        .delimiter(DELIMITER)
         .trim(All)
-        .create_deserializer(buf_reader);
-    let mut client_accounts = HashMap::new();
-    let mut record_stream = csv_reader.deserialize::<Transaction>();
+        .create_deserializer(buf_reader)
+}
+
+/// Reads every record of the provided csv reader. Lines that can't be parsed are logged and ignored
+pub async fn read_records<T: DeserializeOwned + 'static>(
+    buf_reader: impl AsyncBufRead + Send + Unpin,
+) -> Vec<T> {
+    let mut csv_reader = create_deserializer(buf_reader);
+    let headers = csv_reader.headers().await.cloned().unwrap_or_default();
+    let mut records = Vec::new();
+    let mut record_stream = csv_reader.deserialize::<T>();
     while let Some(record) = record_stream.next().await {
-        let transaction = match record {
-            Ok(t) => t,
-            Err(e) => {
-                error!("Could not parse line: {e}");
-                continue;
+        match record {
+            Ok(r) => records.push(r),
+            Err(e) => log_invalid(&e, &headers),
+        }
+    }
+    records
+}
+
+/// The transactions of a csv reader. Lines that can't be parsed are logged and skipped, and the
+/// reader doesn't take acknowledgements. Failing to read is an error. Blank lines and empty
+/// trailing columns are only noted in the diagnostics.
+pub struct CsvSource<R: AsyncBufRead + Send + Unpin> {
+    reader: AsyncDeserializer<R>,
+    headers: StringRecord,
+    record: StringRecord,
+    delivered: DeliveryTag,
+    diagnostics: Diagnostics,
+    config: CsvConfig,
+    /// The lines of the input before the ones read, for a reader of a chunk of it
+    line_offset: u64,
+}
+
+impl<R: AsyncBufRead + Send + Unpin> CsvSource<R> {
+    /// Reads the headers of the csv
+    ///
+    /// # Errors
+    /// If the headers can't be read, an error will be returned
+    pub async fn open(buf_reader: R) -> Result<Self> {
+        Self::open_with_config(buf_reader, CsvConfig::default()).await
+    }
+
+    /// Reads the headers of a csv quoted as the config says
+    ///
+    /// # Errors
+    /// If the headers can't be read, an error will be returned
+    pub async fn open_with_config(buf_reader: R, config: CsvConfig) -> Result<Self> {
+        let mut reader = AsyncReaderBuilder::new()
+            .has_headers(true)
+            .delimiter(DELIMITER)
+            .trim(All)
+            .flexible(true)
+            .quote(config.quote)
+            .escape(config.escape)
+            .double_quote(config.escape.is_none())
+            .create_deserializer(buf_reader);
+        let headers = reader.headers().await?.clone();
+        Ok(Self {
+            reader,
+            headers,
+            record: StringRecord::new(),
+            delivered: 0,
+            diagnostics: Diagnostics::default(),
+            config,
+            line_offset: 0,
+        })
+    }
+
+    /// Notes the data quality observations into `diagnostics`
+    #[must_use]
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Shifts the lines reported by `offset`, for a reader of the rows following the first
+    /// `offset` lines of the input, its header repeated before them
+    #[must_use]
+    pub fn with_line_offset(mut self, offset: u64) -> Self {
+        self.line_offset = offset;
+        self
+    }
+
+    /// The index of a column of the header, if the csv has it
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.headers.iter().position(|header| header == name)
+    }
+
+    /// The value of a column in the row of the last transaction delivered
+    pub fn column(&self, index: usize) -> Option<&str> {
+        self.record.get(index)
+    }
+
+    /// The transaction of the current record, if it has one
+    fn parse(&mut self) -> Result<Option<Transaction>, ValidationError> {
+        let line = self.record.position().map_or(0, Position::line) + self.line_offset;
+        if self.config.lenient {
+            self.record = unquote(&self.record, self.config);
+        }
+        let (len, expected_len) = (self.record.len(), self.headers.len());
+        if self.record.iter().all(str::is_empty) {
+            self.diagnostics.observe(Observation::BlankLine, line);
+            return Ok(None);
+        }
+        if len > expected_len && self.record.iter().skip(expected_len).all(str::is_empty) {
+            self.diagnostics.observe(Observation::TrailingColumns, line);
+            self.record.truncate(expected_len);
+        } else if len != expected_len {
+            return Err(ValidationError {
+                line: Some(line),
+                field: None,
+                reason: format!("found {len} fields, expected {expected_len}"),
+            });
+        }
+        let transaction: Transaction =
+            self.record
+                .deserialize(Some(&self.headers))
+                .map_err(|e| ValidationError {
+                    line: Some(line),
+                    ..ValidationError::from_csv(&e, &self.headers)
+                })?;
+        self.diagnostics.inspect(&transaction, line);
+        Ok(Some(transaction))
+    }
+}
+
+impl<R: AsyncBufRead + Send + Unpin> InputSource for CsvSource<R> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        loop {
+            match self.reader.read_record(&mut self.record).await {
+                Ok(false) => return Ok(None),
+                Ok(true) => {}
+                Err(e) if e.is_io_error() => return Err(e.into()),
+                Err(e) => {
+                    let mut invalid = ValidationError::from_csv(&e, &self.headers);
+                    invalid.line = invalid.line.map(|line| line + self.line_offset);
+                    error!("Could not parse {invalid}");
+                    continue;
+                }
             }
-        };
-        let actor = client_accounts
-            .entry(transaction.client)
-            .or_insert_with(|| AccountHandler::new(transaction.client));
-        if let Err(e) = actor.send(transaction).await? {
-            match e {
-                TransactionError::InsufficientFunds => error!("Insuficient funds"),
-                TransactionError::InvalidOperation => error!("Invalid opertation"),
-                TransactionError::AccountLocked => error!("Account locked"),
-                TransactionError::TransactionAlreadyInDispute => {
-                    error!("Transaction already in dispute");
+            match self.parse() {
+                Ok(Some(transaction)) => {
+                    self.delivered += 1;
+                    return Ok(Some((self.delivered, transaction)));
                 }
-                TransactionError::TransactionNotInDispute => error!("Transaction not in dispute"),
-                TransactionError::TransactionNotFound => warn!("Transaction not found"),
+                Ok(None) => {}
+                Err(invalid) => error!("Could not parse {invalid}"),
             }
         }
     }
+}
 
-    let buf_writer = BufWriter::new(buf_writer);
-    let mut serializer = AsyncSerializer::from_writer(buf_writer);
-    for (client, actor) in client_accounts {
-        match actor.send(Collect).await {
-            Ok(account) => {
-                serializer.serialize(account).await?;
-            }
-            Err(e) => {
-                error!("Could not collect account data from client {client}: {e}");
+/// Parse the transactions of the provided reader and applies them through the engine
+///
+/// # Errors
+/// If the engine fails to record a transaction, an error will be returned
+pub async fn process_transactions(
+    buf_reader: impl AsyncBufRead + Send + Unpin,
+    engine: &mut Engine,
+) -> Result<()> {
+    let source = CsvSource::open(buf_reader).await?;
+    process_source(source, engine, AckConfig::default()).await
+}
+
+/// Joins back the fields the reader split inside quotes it didn't take as such, since it only
+/// takes a quote opening the field, and removes the quotes left around the fields
+fn unquote(record: &StringRecord, config: CsvConfig) -> StringRecord {
+    let quote = char::from(config.quote);
+    let escaped = match config.escape {
+        Some(escape) => format!("{}{quote}", char::from(escape)),
+        None => format!("{quote}{quote}"),
+    };
+    let closes = |field: &str| field.ends_with(quote) && !field.ends_with(escaped.as_str());
+    let mut fields = StringRecord::with_capacity(record.as_slice().len(), record.len());
+    let mut open: Option<String> = None;
+    for field in record {
+        let field = match open.take() {
+            Some(mut joined) => {
+                joined.push(char::from(DELIMITER));
+                joined.push_str(field);
+                joined
             }
+            None => field.to_owned(),
+        };
+        let quoted = field.len() > 1 && field.starts_with(quote);
+        if quoted && closes(&field) {
+            let inner = &field[1..field.len() - 1];
+            fields.push_field(&inner.replace(escaped.as_str(), &quote.to_string()));
+        } else if quoted || field == quote.to_string() {
+            open = Some(field);
+        } else {
+            fields.push_field(&field);
+        }
+    }
+    // an unterminated quote is left as it was
+    if let Some(field) = open {
+        fields.push_field(&field);
+    }
+    fields.set_position(record.position().cloned());
+    fields
+}
+
+fn log_invalid(e: &csv_async::Error, headers: &StringRecord) {
+    error!("Could not parse {}", ValidationError::from_csv(e, headers));
+}
+
+/// The order the accounts are written in once collected
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SortOutput {
+    /// By client id, so the outputs of two runs can be diffed
+    #[default]
+    Client,
+    /// By total funds, the largest first, and by client id among equal totals
+    Total,
+    /// In the order the accounts were collected, which changes between runs
+    None,
+}
+
+impl SortOutput {
+    pub fn sort(self, accounts: &mut [Account]) {
+        match self {
+            Self::Client => accounts.sort_unstable_by_key(Account::client),
+            Self::Total => accounts.sort_unstable_by(|a, b| {
+                let total = |account: &Account| account.total().amount();
+                total(b).cmp(&total(a)).then(a.client().cmp(&b.client()))
+            }),
+            Self::None => {}
         }
     }
+}
 
+/// Writes the records in csv format into the provided writer
+///
+/// # Errors
+/// If the writer fails, an error will be returned
+pub async fn write_records(
+    buf_writer: impl AsyncWrite + Unpin,
+    records: impl IntoIterator<Item = impl Serialize>,
+) -> Result<()> {
+    let buf_writer = BufWriter::new(buf_writer);
+    let mut serializer = AsyncSerializer::from_writer(buf_writer);
+    for record in records {
+        serializer.serialize(record).await?;
+    }
+    serializer.flush().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use csv_async::StringRecord;
+    use tokio_stream::StreamExt;
+
+    use rust_decimal_macros::dec;
+
+    use crate::config::{CsvConfig, EngineConfig};
+    use crate::csv::{create_deserializer, unquote, CsvSource, SortOutput, ValidationError};
+    use crate::diagnostics::Diagnostics;
+    use crate::model::{Account, Transaction};
+    use crate::source::InputSource;
+
+    #[actix::test]
+    async fn test_validation_error_names_the_field() {
+        let input: &[u8] = b"type,client,tx,amount\nDeposit,1,1,1.0\nDeposit,1,x,1.0\nDeposit,1\n";
+        let mut csv_reader = create_deserializer(input);
+        let headers = csv_reader.headers().await.unwrap().clone();
+        let errors: Vec<_> = csv_reader
+            .deserialize::<Transaction>()
+            .filter_map(Result::err)
+            .map(|e| ValidationError::from_csv(&e, &headers))
+            .collect()
+            .await;
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            (errors[0].line, errors[0].field.as_deref()),
+            (Some(3), Some("tx"))
+        );
+        assert_eq!(errors[1].to_string(), "line 4, found 2 fields, expected 4");
+    }
+
+    #[actix::test]
+    async fn test_csv_source_notes_data_quality() {
+        let input: &[u8] = b"type,client,tx,amount\nDeposit,1,1,1.0,,\n   \nDispute,1,1,1.0\n\
+            Deposit,1,2,1.0,x\nDispute,1,1\n";
+        let diagnostics = Diagnostics::default();
+        let mut source = CsvSource::open(input)
+            .await
+            .unwrap()
+            .with_diagnostics(diagnostics.clone());
+        let mut delivered = Vec::new();
+        while let Some((_, transaction)) = source.next().await.unwrap() {
+            delivered.push(transaction.tx);
+        }
+        assert_eq!(delivered, vec![1, 1]);
+        assert_eq!(
+            diagnostics.to_string(),
+            "Data quality:\n  1 dispute, resolve or chargeback rows with an amount (first at line 4)\
+            \n  1 rows with empty trailing columns (first at line 2)\n  1 blank lines (first at line 3)"
+        );
+    }
+
+    #[actix::test]
+    async fn test_lenient_quoting() {
+        let input: &[u8] = b"type,client,tx,amount\nDeposit,1,1,'1.5'\nDeposit, 1, 2, '2.5'\n\
+            Deposit, 1, 3, '3.5\n";
+        let config = CsvConfig {
+            quote: b'\'',
+            escape: None,
+            lenient: true,
+        };
+        let mut source = CsvSource::open_with_config(input, config).await.unwrap();
+        let mut amounts = Vec::new();
+        while let Some((_, transaction)) = source.next().await.unwrap() {
+            amounts.push((transaction.tx, transaction.amount));
+        }
+        // the quote of the last row is never closed
+        assert_eq!(
+            amounts,
+            vec![(1, Some(dec!(1.5).into())), (2, Some(dec!(2.5).into()))]
+        );
+    }
+
+    #[test]
+    fn test_unquote_joins_the_split_fields() {
+        let config = CsvConfig {
+            quote: b'\'',
+            escape: Some(b'\\'),
+            lenient: true,
+        };
+        let record = StringRecord::from(vec!["Deposit", "'it\\'s", "a'", "'1", "5'", "'"]);
+        let fields = unquote(&record, config);
+        assert_eq!(fields, vec!["Deposit", "it's,a", "1,5", "'"]);
+    }
+
+    #[test]
+    fn test_sort_output_orders_the_accounts() {
+        let account = |client, amount| {
+            let mut account = Account::new(client, EngineConfig::default());
+            let deposit = Transaction::test_deposit(client, u32::from(client), amount);
+            account.apply(&deposit).unwrap();
+            account
+        };
+        let mut accounts = vec![
+            account(3, dec!(9)),
+            account(1, dec!(5)),
+            account(2, dec!(5)),
+        ];
+        let clients =
+            |accounts: &[Account]| accounts.iter().map(Account::client).collect::<Vec<_>>();
+        SortOutput::None.sort(&mut accounts);
+        assert_eq!(clients(&accounts), [3, 1, 2]);
+        SortOutput::Client.sort(&mut accounts);
+        assert_eq!(clients(&accounts), [1, 2, 3]);
+        // equal totals keep the order of their clients
+        accounts.reverse();
+        SortOutput::Total.sort(&mut accounts);
+        assert_eq!(clients(&accounts), [3, 1, 2]);
+    }
+}
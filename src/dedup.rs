@@ -0,0 +1,132 @@
+use clap::ValueEnum;
+use roaring::RoaringBitmap;
+use tracing::warn;
+
+/// The slices of the bloom filter, one bit is set in each for every id
+const SLICES: usize = 7;
+
+/// What is done with a transaction whose id the bloom filter has maybe seen, as it can't tell an
+/// id already applied from a false positive
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum FalsePositivePolicy {
+    /// It is rejected as a duplicate, so no duplicate is ever applied
+    #[default]
+    Reject,
+    /// It is applied with a warning, so no new transaction is ever rejected
+    Warn,
+}
+
+/// The ids of the transactions applied to any account, to reject the ones reused. A bitmap keeps
+/// them exactly, taking at most 512MB over every possible id. A bloom filter fits them in the
+/// memory given, at the price of false positives.
+pub enum TxIdFilter {
+    Exact(RoaringBitmap),
+    Bloom {
+        /// Every id sets one bit in each of the `SLICES` equal slices, so the probes of an id
+        /// never collide with each other
+        bits: Vec<u64>,
+        /// The bits in a slice
+        slice: u64,
+        policy: FalsePositivePolicy,
+    },
+}
+
+impl TxIdFilter {
+    pub fn exact() -> Self {
+        Self::Exact(RoaringBitmap::new())
+    }
+
+    /// A bloom filter taking about `memory` bytes
+    pub fn bloom(memory: usize, policy: FalsePositivePolicy) -> Self {
+        let words = (memory / 8).max(SLICES);
+        let slice = words as u64 * 64 / SLICES as u64;
+        Self::Bloom {
+            bits: vec![0; words],
+            slice,
+            policy,
+        }
+    }
+
+    /// Whether the id was already applied, maybe falsely for a bloom filter
+    pub fn contains(&self, tx: u32) -> bool {
+        match self {
+            Self::Exact(ids) => ids.contains(tx),
+            Self::Bloom {
+                bits,
+                slice,
+                policy,
+            } => {
+                let seen = probes(tx, *slice).all(|bit| bits[bit / 64] & (1 << (bit % 64)) != 0);
+                if seen && *policy == FalsePositivePolicy::Warn {
+                    warn!("Transaction {tx} may reuse the id of one already applied, accepting it");
+                    return false;
+                }
+                seen
+            }
+        }
+    }
+
+    pub fn insert(&mut self, tx: u32) {
+        match self {
+            Self::Exact(ids) => {
+                ids.insert(tx);
+            }
+            Self::Bloom { bits, slice, .. } => {
+                for bit in probes(tx, *slice) {
+                    bits[bit / 64] |= 1 << (bit % 64);
+                }
+            }
+        }
+    }
+}
+
+impl Extend<u32> for TxIdFilter {
+    fn extend<T: IntoIterator<Item = u32>>(&mut self, ids: T) {
+        for tx in ids {
+            self.insert(tx);
+        }
+    }
+}
+
+/// The bit an id sets in every slice of a bloom filter
+fn probes(tx: u32, slice: u64) -> impl Iterator<Item = usize> {
+    (0..SLICES as u64).map(move |i| {
+        let bit = i * slice + mix(u64::from(tx) << 3 | i) % slice;
+        usize::try_from(bit).unwrap_or(usize::MAX)
+    })
+}
+
+/// The splitmix64 finalizer, spreading close ids across the whole slice
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dedup::{FalsePositivePolicy, TxIdFilter};
+
+    #[test]
+    fn test_exact_filter_keeps_every_id() {
+        let mut filter = TxIdFilter::exact();
+        filter.extend([1, 7, u32::MAX]);
+        assert!(filter.contains(7));
+        assert!(filter.contains(u32::MAX));
+        assert!(!filter.contains(2));
+    }
+
+    #[test]
+    fn test_bloom_filter_never_misses_an_id() {
+        let mut filter = TxIdFilter::bloom(64 * 1024, FalsePositivePolicy::Reject);
+        filter.extend(0..20_000);
+        assert!((0..20_000).all(|tx| filter.contains(tx)));
+        // well under 0.5% false positives at 26 bits per id
+        let false_positives = (20_000..120_000).filter(|tx| filter.contains(*tx)).count();
+        assert!(false_positives < 500, "{false_positives} false positives");
+
+        let mut warning = TxIdFilter::bloom(64 * 1024, FalsePositivePolicy::Warn);
+        warning.insert(1);
+        assert!(!warning.contains(1));
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio::io::stdout;
+use tracing::info;
+
+use crate::cli::DeltaArgs;
+use crate::csv::write_records;
+use crate::model::AccountRecord;
+use crate::snapshot::Snapshot;
+
+/// How an account changed between two snapshots
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Updated,
+    Removed,
+}
+
+/// A row of the change file. Added and updated accounts carry their new values, removed ones only
+/// their client.
+#[derive(Serialize, Debug, PartialEq)]
+struct DeltaEntry {
+    client: u16,
+    change: Change,
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+    total: Option<Decimal>,
+    locked: Option<bool>,
+}
+
+impl DeltaEntry {
+    fn new(change: Change, record: &AccountRecord) -> Self {
+        Self {
+            client: record.client,
+            change,
+            available: Some(record.available),
+            held: Some(record.held),
+            total: Some(record.total),
+            locked: Some(record.locked),
+        }
+    }
+
+    fn removed(client: u16) -> Self {
+        Self {
+            client,
+            change: Change::Removed,
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+        }
+    }
+}
+
+/// Writes the accounts that changed from one snapshot to another to the std out
+///
+/// # Errors
+/// If the snapshots can't be read or the output can't be written, an error will be returned
+pub async fn delta(args: &DeltaArgs) -> Result<()> {
+    let from = records_of(&Snapshot::read(&args.from).await?);
+    let to = records_of(&Snapshot::read(&args.to).await?);
+    let entries = diff(&from, &to);
+    info!("{} accounts changed", entries.len());
+    write_records(stdout(), entries).await
+}
+
+/// The printed values of the accounts of a snapshot, by client
+pub fn records_of(snapshot: &Snapshot) -> BTreeMap<u16, AccountRecord> {
+    snapshot
+        .accounts
+        .iter()
+        .map(|state| (state.client(), AccountRecord::from(state)))
+        .collect()
+}
+
+/// The changes taking the `from` accounts to the `to` accounts, ordered by client. Only the
+/// printed values are compared, so changes to the transaction history alone are not reported.
+fn diff(from: &BTreeMap<u16, AccountRecord>, to: &BTreeMap<u16, AccountRecord>) -> Vec<DeltaEntry> {
+    let mut entries: Vec<_> = to
+        .values()
+        .filter_map(|record| match from.get(&record.client) {
+            None => Some(DeltaEntry::new(Change::Added, record)),
+            Some(old) if old != record => Some(DeltaEntry::new(Change::Updated, record)),
+            Some(_) => None,
+        })
+        .chain(
+            from.keys()
+                .filter(|client| !to.contains_key(client))
+                .map(|client| DeltaEntry::removed(*client)),
+        )
+        .collect();
+    entries.sort_by_key(|entry| entry.client);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rust_decimal_macros::dec;
+
+    use crate::delta::{diff, Change};
+    use crate::model::AccountRecord;
+
+    #[test]
+    fn test_diff() {
+        let from = BTreeMap::from([
+            (1, AccountRecord::for_test(1, dec!(1), dec!(0))),
+            (2, AccountRecord::for_test(2, dec!(2), dec!(0))),
+            (3, AccountRecord::for_test(3, dec!(3), dec!(0))),
+        ]);
+        let to = BTreeMap::from([
+            (1, AccountRecord::for_test(1, dec!(1), dec!(0))),
+            (2, AccountRecord::for_test(2, dec!(5), dec!(0))),
+            (4, AccountRecord::for_test(4, dec!(4), dec!(0))),
+        ]);
+        let changes: Vec<_> = diff(&from, &to)
+            .iter()
+            .map(|entry| (entry.client, entry.change, entry.total))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (2, Change::Updated, Some(dec!(5))),
+                (3, Change::Removed, None),
+                (4, Change::Added, Some(dec!(4))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+use crate::model::Transaction;
+
+/// Something odd about a row of the input that doesn't keep its transaction from being applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Observation {
+    /// A dispute, resolve or chargeback gives an amount, which is only checked against the
+    /// deposit with `--dispute-amounts verify`
+    DisputeAmount,
+    /// A row has more columns than the header, all of them empty
+    TrailingColumns,
+    /// A line holds nothing but whitespace
+    BlankLine,
+}
+
+impl Observation {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::DisputeAmount => "dispute, resolve or chargeback rows with an amount",
+            Self::TrailingColumns => "rows with empty trailing columns",
+            Self::BlankLine => "blank lines",
+        }
+    }
+}
+
+/// How often an observation was made and where first
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Tally {
+    count: u64,
+    first_line: u64,
+}
+
+/// The data quality observations of a run, gathered by the input sources and printed once at the
+/// end instead of a warning per line. Clones share the same observations.
+#[derive(Clone, Default)]
+pub struct Diagnostics(Rc<RefCell<BTreeMap<Observation, Tally>>>);
+
+impl Diagnostics {
+    /// Records an observation about the row at `line`
+    pub fn observe(&self, observation: Observation, line: u64) {
+        self.0
+            .borrow_mut()
+            .entry(observation)
+            .and_modify(|tally| tally.count += 1)
+            .or_insert(Tally {
+                count: 1,
+                first_line: line,
+            });
+    }
+
+    /// Records the observations about a parsed transaction
+    pub fn inspect(&self, transaction: &Transaction, line: u64) {
+        if transaction.transaction_type.is_dispute_step() && transaction.amount.is_some() {
+            self.observe(Observation::DisputeAmount, line);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    /// Takes the observations made so far, to merge them into diagnostics of another thread
+    pub fn take(&self) -> Tallies {
+        Tallies(self.0.take())
+    }
+
+    /// Adds observations taken from other diagnostics, about rows after the ones observed here
+    pub fn merge(&self, tallies: Tallies) {
+        let mut observed = self.0.borrow_mut();
+        for (observation, tally) in tallies.0 {
+            observed
+                .entry(observation)
+                .and_modify(|observed| observed.count += tally.count)
+                .or_insert(tally);
+        }
+    }
+}
+
+/// Observations taken out of diagnostics, which can't be shared across threads
+pub struct Tallies(BTreeMap<Observation, Tally>);
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let tallies = self.0.borrow();
+        f.write_str("Data quality:")?;
+        for (observation, tally) in tallies.iter() {
+            write!(
+                f,
+                "\n  {} {} (first at line {})",
+                tally.count,
+                observation.describe(),
+                tally.first_line
+            )?;
+        }
+        Ok(())
+    }
+}
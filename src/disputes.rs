@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use tokio::io::{stdout, AsyncWriteExt};
+use tracing::info;
+
+use crate::cli::DisputesArgs;
+use crate::config::WithdrawalDisputes;
+use crate::journal::JournalReader;
+use crate::model::TransactionType;
+use crate::money::Money;
+
+/// The formats the dispute graph can be exported in
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum GraphFormat {
+    /// A Graphviz digraph
+    Dot,
+    /// A Mermaid flowchart
+    Mermaid,
+}
+
+/// A dispute, resolve or chargeback of a deposit or withdrawal, as it was journaled
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Step {
+    transaction_type: TransactionType,
+    seq: u64,
+}
+
+/// The lifecycle of a disputed deposit or withdrawal
+#[derive(Debug, Default, PartialEq)]
+struct Chain {
+    /// The amount of the transaction, unknown when it is older than the journal
+    amount: Option<Money>,
+    withdrawal: bool,
+    steps: Vec<Step>,
+}
+
+impl Chain {
+    /// Takes the amount and kind of the disputed transaction, when the journal has it
+    fn start(&mut self, origin: Option<&(Option<Money>, bool)>) {
+        if let Some((amount, withdrawal)) = origin {
+            (self.amount, self.withdrawal) = (*amount, *withdrawal);
+        }
+    }
+}
+
+/// The disputed transactions of every flagged client, by client and id
+type Chains = BTreeMap<u16, BTreeMap<u32, Chain>>;
+
+/// Writes a graph of the disputes of the journal to the std out, with a cluster per client going
+/// from every disputed transaction through its disputes, resolves and chargebacks
+///
+/// # Errors
+/// If the journal can't be read or the output can't be written, an error will be returned
+pub async fn export_disputes(args: &DisputesArgs) -> Result<()> {
+    let mut journal = JournalReader::open(&args.journal).await?;
+    // the amount of every deposit and withdrawal, and whether it is a withdrawal
+    let mut origins = HashMap::new();
+    // the disputable transactions of every reference, by client
+    let mut groups: HashMap<(u16, u32), Vec<u32>> = HashMap::new();
+    let mut chains = Chains::new();
+    while let Some(event) = journal.next_event().await? {
+        let transaction = event.transaction;
+        let involved = |client| args.client.contains(&client);
+        if !args.client.is_empty()
+            && !involved(transaction.client)
+            && !transaction.to_client.is_some_and(involved)
+        {
+            continue;
+        }
+        let key = (transaction.client, transaction.tx);
+        match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                let withdrawal = transaction.transaction_type == TransactionType::Withdrawal;
+                origins.insert(key, (transaction.amount, withdrawal));
+                let disputable =
+                    !withdrawal || args.withdrawal_disputes == WithdrawalDisputes::Hold;
+                if let (true, Some(reference)) = (disputable, transaction.reference) {
+                    let group = groups.entry((transaction.client, reference)).or_default();
+                    group.push(transaction.tx);
+                }
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                let client_chains = chains.entry(transaction.client).or_default();
+                let step = Step {
+                    transaction_type: transaction.transaction_type,
+                    seq: event.seq,
+                };
+                let Some(reference) = transaction.reference else {
+                    let chain = client_chains.entry(transaction.tx).or_default();
+                    chain.start(origins.get(&key));
+                    chain.steps.push(step);
+                    continue;
+                };
+                // a group step disputes the transactions of the group not in dispute, or resolves
+                // or charges back the ones in dispute
+                let members = groups.get(&(transaction.client, reference));
+                for tx in members.into_iter().flatten() {
+                    let disputed = client_chains
+                        .get(tx)
+                        .and_then(|chain| chain.steps.last())
+                        .is_some_and(|last| last.transaction_type == TransactionType::Dispute);
+                    if disputed != (step.transaction_type == TransactionType::Dispute) {
+                        let chain = client_chains.entry(*tx).or_default();
+                        chain.start(origins.get(&(transaction.client, *tx)));
+                        chain.steps.push(step);
+                    }
+                }
+            }
+            // a withdrawal of the debited account and a deposit of the credited one
+            TransactionType::Transfer => {
+                origins.insert(key, (transaction.amount, true));
+                if let Some(to_client) = transaction.to_client {
+                    origins.insert((to_client, transaction.tx), (transaction.amount, false));
+                }
+            }
+            TransactionType::Savepoint | TransactionType::Open | TransactionType::Admin => {}
+        }
+    }
+    chains.retain(|_, disputed| !disputed.is_empty());
+    info!(
+        "Found {} disputed transactions of {} clients",
+        chains.values().map(BTreeMap::len).sum::<usize>(),
+        chains.len()
+    );
+    let graph = match args.format {
+        GraphFormat::Dot => dot(&chains),
+        GraphFormat::Mermaid => mermaid(&chains),
+    };
+    let mut out = stdout();
+    out.write_all(graph.as_bytes()).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+fn step_name(transaction_type: TransactionType) -> &'static str {
+    match transaction_type {
+        TransactionType::Dispute => "dispute",
+        TransactionType::Resolve => "resolve",
+        TransactionType::Chargeback => "chargeback",
+        _ => "",
+    }
+}
+
+fn amount_label(amount: Option<Money>) -> String {
+    amount.map_or_else(|| "?".into(), |amount| amount.to_string())
+}
+
+/// The nodes of a chain, the disputed transaction first, with their labels and whether they are
+/// chargebacks
+fn nodes(client: u16, tx: u32, chain: &Chain) -> Vec<(String, String, bool)> {
+    let kind = if chain.withdrawal {
+        "withdrawal"
+    } else {
+        "deposit"
+    };
+    let origin = (format!("c{client}_t{tx}"), format!("{kind} {tx}"), false);
+    let steps = chain.steps.iter().enumerate().map(|(n, step)| {
+        (
+            format!("c{client}_t{tx}_{n}"),
+            format!("{} (event {})", step_name(step.transaction_type), step.seq),
+            step.transaction_type == TransactionType::Chargeback,
+        )
+    });
+    std::iter::once(origin).chain(steps).collect()
+}
+
+fn dot(chains: &Chains) -> String {
+    let mut graph = String::from("digraph disputes {\n    rankdir=LR;\n    node [shape=box];\n");
+    for (client, disputed) in chains {
+        let _ = writeln!(graph, "    subgraph cluster_{client} {{");
+        let _ = writeln!(graph, "        label=\"client {client}\";");
+        for (tx, chain) in disputed {
+            let nodes = nodes(*client, *tx, chain);
+            for (id, label, chargeback) in &nodes {
+                let color = if *chargeback { ", color=red" } else { "" };
+                let _ = writeln!(graph, "        {id} [label=\"{label}\"{color}];");
+            }
+            for pair in nodes.windows(2) {
+                let _ = writeln!(
+                    graph,
+                    "        {} -> {} [label=\"{}\"];",
+                    pair[0].0,
+                    pair[1].0,
+                    amount_label(chain.amount)
+                );
+            }
+        }
+        graph.push_str("    }\n");
+    }
+    graph.push_str("}\n");
+    graph
+}
+
+fn mermaid(chains: &Chains) -> String {
+    let mut graph = String::from("flowchart LR\n    classDef chargeback stroke:#d00\n");
+    for (client, disputed) in chains {
+        let _ = writeln!(graph, "    subgraph client_{client} [\"client {client}\"]");
+        for (tx, chain) in disputed {
+            let nodes = nodes(*client, *tx, chain);
+            for (id, label, chargeback) in &nodes {
+                let class = if *chargeback { ":::chargeback" } else { "" };
+                let _ = writeln!(graph, "        {id}[\"{label}\"]{class}");
+            }
+            for pair in nodes.windows(2) {
+                let _ = writeln!(
+                    graph,
+                    "        {} -->|{}| {}",
+                    pair[0].0,
+                    amount_label(chain.amount),
+                    pair[1].0
+                );
+            }
+        }
+        graph.push_str("    end\n");
+    }
+    graph
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rust_decimal_macros::dec;
+
+    use crate::disputes::{dot, mermaid, Chain, Chains, Step};
+    use crate::model::TransactionType;
+
+    fn chains() -> Chains {
+        let step = |transaction_type, seq| Step {
+            transaction_type,
+            seq,
+        };
+        let chain = Chain {
+            amount: Some(dec!(10).into()),
+            withdrawal: false,
+            steps: vec![
+                step(TransactionType::Dispute, 2),
+                step(TransactionType::Chargeback, 3),
+            ],
+        };
+        BTreeMap::from([(1, BTreeMap::from([(5, chain)]))])
+    }
+
+    #[test]
+    fn test_dispute_graphs() {
+        let dot = dot(&chains());
+        assert!(dot.contains("c1_t5 [label=\"deposit 5\"];"));
+        assert!(dot.contains("c1_t5_1 [label=\"chargeback (event 3)\", color=red];"));
+        assert!(dot.contains("c1_t5 -> c1_t5_0 [label=\"10\"];"));
+        assert!(dot.contains("c1_t5_0 -> c1_t5_1 [label=\"10\"];"));
+        let mermaid = mermaid(&chains());
+        assert!(mermaid.contains("subgraph client_1 [\"client 1\"]"));
+        assert!(mermaid.contains("c1_t5_1[\"chargeback (event 3)\"]:::chargeback"));
+        assert!(mermaid.contains("c1_t5 -->|10| c1_t5_0"));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio::fs::{self, File};
+use tokio::io::{stdout, BufReader};
+
+use crate::cli::DriftArgs;
+use crate::csv::{read_records, write_records};
+use crate::model::AccountRecord;
+
+/// How the funds of a client moved from the output of the current rounding to the one of the
+/// proposed rounding. The drifts are empty when the client has an account on a single side.
+#[derive(Serialize, Debug, PartialEq)]
+struct ClientDrift {
+    client: u16,
+    before_total: Option<Decimal>,
+    after_total: Option<Decimal>,
+    available_drift: Option<Decimal>,
+    held_drift: Option<Decimal>,
+    total_drift: Option<Decimal>,
+}
+
+/// The drift of all the clients, for the sign-off of a rounding change
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct DriftSummary {
+    /// The clients with an account on both sides
+    clients: usize,
+    /// The clients among them whose funds moved
+    drifted: usize,
+    /// The clients with an account on a single side, left out of the drift
+    unmatched: usize,
+    /// What the accounts gain altogether, or lose when negative
+    net_available_drift: Decimal,
+    net_held_drift: Decimal,
+    net_total_drift: Decimal,
+    /// The sum of how far every total moved, whatever the direction
+    absolute_total_drift: Decimal,
+    /// The client whose total moved the most, and by how much
+    max_drift_client: Option<u16>,
+    max_total_drift: Decimal,
+}
+
+impl Display for DriftSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} clients drift by {} net and {} absolute",
+            self.drifted, self.clients, self.net_total_drift, self.absolute_total_drift
+        )?;
+        if let Some(client) = self.max_drift_client {
+            write!(f, ", at most {} for client {client}", self.max_total_drift)?;
+        }
+        if self.unmatched > 0 {
+            write!(f, ", {} clients on a single side", self.unmatched)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two outputs of the same input written under different roundings, writing the clients
+/// whose funds moved to the std out and how much the whole output moved to the std err
+///
+/// # Errors
+/// If an output can't be read or the report can't be written, an error will be returned
+pub async fn drift(args: &DriftArgs) -> Result<()> {
+    let before = read_output(&args.before).await?;
+    let after = read_output(&args.after).await?;
+    let (rows, summary) = measure(&before, &after);
+    eprintln!("{summary}");
+    if let Some(path) = &args.summary {
+        let mut content = serde_json::to_vec_pretty(&summary)?;
+        content.push(b'\n');
+        fs::write(path, content).await?;
+    }
+    write_records(stdout(), rows).await
+}
+
+/// The accounts of an output by client. The last row of a client counts, like in a streamed
+/// output.
+async fn read_output(path: &Path) -> Result<BTreeMap<u16, AccountRecord>> {
+    let file = File::open(path).await?;
+    let records = read_records::<AccountRecord>(BufReader::new(file)).await;
+    Ok(records
+        .into_iter()
+        .map(|record| (record.client, record))
+        .collect())
+}
+
+/// The drift of every client whose funds moved or that is on a single side, by client, and of the
+/// whole output
+fn measure(
+    before: &BTreeMap<u16, AccountRecord>,
+    after: &BTreeMap<u16, AccountRecord>,
+) -> (Vec<ClientDrift>, DriftSummary) {
+    let mut clients: Vec<u16> = before.keys().chain(after.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+    let mut summary = DriftSummary::default();
+    let mut rows = Vec::new();
+    for client in clients {
+        let (old, new) = (before.get(&client), after.get(&client));
+        let mut row = ClientDrift {
+            client,
+            before_total: old.map(|r| r.total),
+            after_total: new.map(|r| r.total),
+            available_drift: None,
+            held_drift: None,
+            total_drift: None,
+        };
+        let (Some(old), Some(new)) = (old, new) else {
+            summary.unmatched += 1;
+            rows.push(row);
+            continue;
+        };
+        summary.clients += 1;
+        let available = new.available - old.available;
+        let held = new.held - old.held;
+        let total = new.total - old.total;
+        if available.is_zero() && held.is_zero() && total.is_zero() {
+            continue;
+        }
+        summary.drifted += 1;
+        summary.net_available_drift += available;
+        summary.net_held_drift += held;
+        summary.net_total_drift += total;
+        summary.absolute_total_drift += total.abs();
+        if total.abs() > summary.max_total_drift {
+            summary.max_total_drift = total.abs();
+            summary.max_drift_client = Some(client);
+        }
+        row.available_drift = Some(available);
+        row.held_drift = Some(held);
+        row.total_drift = Some(total);
+        rows.push(row);
+    }
+    (rows, summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rust_decimal_macros::dec;
+
+    use crate::drift::measure;
+    use crate::model::AccountRecord;
+
+    #[test]
+    fn test_measure_the_drift_of_a_rounding_change() {
+        let before = BTreeMap::from([
+            (1, AccountRecord::for_test(1, dec!(1.23), dec!(0))),
+            (2, AccountRecord::for_test(2, dec!(2.34), dec!(0.01))),
+            (3, AccountRecord::for_test(3, dec!(5), dec!(0))),
+            (4, AccountRecord::for_test(4, dec!(1), dec!(0))),
+        ]);
+        let after = BTreeMap::from([
+            (1, AccountRecord::for_test(1, dec!(1.2), dec!(0))),
+            (2, AccountRecord::for_test(2, dec!(2.35), dec!(0.05))),
+            (3, AccountRecord::for_test(3, dec!(5), dec!(0))),
+            (5, AccountRecord::for_test(5, dec!(1), dec!(0))),
+        ]);
+        let (rows, summary) = measure(&before, &after);
+        let drifts: Vec<_> = rows
+            .iter()
+            .map(|row| (row.client, row.total_drift))
+            .collect();
+        assert_eq!(
+            drifts,
+            vec![
+                (1, Some(dec!(-0.03))),
+                (2, Some(dec!(0.05))),
+                (4, None),
+                (5, None)
+            ]
+        );
+        assert_eq!(
+            (summary.clients, summary.drifted, summary.unmatched),
+            (3, 2, 2)
+        );
+        assert_eq!(summary.net_total_drift, dec!(0.02));
+        assert_eq!(summary.net_held_drift, dec!(0.04));
+        assert_eq!(summary.absolute_total_drift, dec!(0.08));
+        assert_eq!(summary.max_drift_client, Some(2));
+        assert_eq!(
+            summary.to_string(),
+            "2 of 3 clients drift by 0.02 net and 0.08 absolute, at most 0.05 for client 2, 2 \
+            clients on a single side"
+        );
+    }
+}
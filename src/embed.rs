@@ -0,0 +1,80 @@
+//! An api for embedders and tests driving the engine without readers or writers, the binary
+//! itself reads its input through the sources
+
+use anyhow::Result;
+
+use crate::config::{DispatchConfig, EngineConfig};
+use crate::engine::Engine;
+use crate::model::{Account, Transaction};
+
+/// Applies the transactions in order to fresh accounts with the default settings, like the rows
+/// of a file, and returns the resulting accounts. Rejected transactions leave the accounts as
+/// they were. It starts its own actor system, so it can't be called from inside one, use
+/// `process_transactions_async` there instead.
+///
+/// # Errors
+/// If a transaction can't be delivered to its account or an account doesn't answer the
+/// collection, an error will be returned
+pub fn process_transactions(
+    transactions: impl IntoIterator<Item = Transaction>,
+) -> Result<Vec<Account>> {
+    actix::System::new().block_on(process_transactions_async(transactions))
+}
+
+/// Applies the transactions in order to fresh accounts with the default settings and returns the
+/// resulting accounts. It must run inside an actor system, like under `actix::main`.
+///
+/// # Errors
+/// If a transaction can't be delivered to its account or an account doesn't answer the
+/// collection, an error will be returned
+pub async fn process_transactions_async(
+    transactions: impl IntoIterator<Item = Transaction>,
+) -> Result<Vec<Account>> {
+    let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+    for transaction in transactions {
+        engine.apply(transaction).await?;
+    }
+    engine.collect().await
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::embed::{process_transactions, process_transactions_async};
+    use crate::model::{Account, AccountRecord, Transaction, TransactionType};
+
+    fn transactions() -> Vec<Transaction> {
+        vec![
+            Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::for_test(TransactionType::Deposit, 2, 2, Some(dec!(3))),
+            Transaction::for_test(TransactionType::Withdrawal, 1, 3, Some(dec!(4))),
+            Transaction::for_test(TransactionType::Dispute, 2, 2, None),
+            // rejected, the funds aren't available
+            Transaction::for_test(TransactionType::Withdrawal, 2, 4, Some(dec!(1))),
+        ]
+    }
+
+    fn records(accounts: &[Account]) -> Vec<AccountRecord> {
+        let mut records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+        records.sort_unstable_by_key(|record| record.client);
+        records
+    }
+
+    #[test]
+    fn test_process_transactions() {
+        let records = records(&process_transactions(transactions()).unwrap());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].available, dec!(6));
+        assert_eq!(records[1].available, dec!(0));
+        assert_eq!(records[1].held, dec!(3));
+    }
+
+    #[actix::test]
+    async fn test_process_transactions_async() {
+        let records = records(&process_transactions_async(transactions()).await.unwrap());
+        assert_eq!(records[0].total, dec!(6));
+        assert_eq!(records[1].total, dec!(3));
+        assert!(!records[1].locked);
+    }
+}
@@ -0,0 +1,1935 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix::{Actor, Addr, Arbiter, ArbiterHandle};
+use anyhow::{bail, Result};
+use tracing::{error, error_span, info, instrument, warn, Instrument};
+
+use crate::breaker::{CircuitBreaker, Outcome};
+use crate::budget::{BudgetConfig, DegradedStats, LatencyBudget};
+use crate::capture::CaptureWriter;
+use crate::cases::Cases;
+use crate::config::{DispatchConfig, EngineConfig, Rounding};
+use crate::dedup::TxIdFilter;
+use crate::history::TxStore;
+use crate::hooks::{HookAction, HookPoint, ProcessingHook};
+use crate::journal::{JournalReader, JournalWriter};
+use crate::mailbox::{Answer, Full, Mailboxes};
+use crate::model::{
+    Account, AccountState, Collect, GetState, Restore, Transaction, TransactionError,
+    TransactionType,
+};
+use crate::parquet::TransactionParquetWriter;
+use crate::registry::ClientRegistry;
+use crate::rejects::RejectWriter;
+use crate::sample::{SampleSpec, Sampler};
+use crate::shadow::Shadow;
+use crate::shard::Shards;
+use crate::snapshot::Snapshot;
+use crate::store::{AccountStore, Flush, StoreWriter};
+use crate::tags::Tags;
+use crate::transaction::{send_with_retry, AccountHandler, AccountRef, Deliver, Ticket};
+use crate::wal::WriteAheadLog;
+use crate::webhook::{Event, Outbox};
+use crate::workers::Workers;
+
+/// Routes transactions to the actor of their client's account, starting actors as new clients
+/// are found
+pub struct Engine {
+    dispatch: DispatchConfig,
+    /// How long the accounts may take to answer the collection at the end
+    collection: DispatchConfig,
+    config: EngineConfig,
+    journal: Option<JournalWriter>,
+    /// Logs every transaction before its account applies it
+    wal: Option<WriteAheadLog>,
+    /// Writes the transactions that failed, with the reason
+    rejects: Option<RejectWriter>,
+    /// Records every accepted transaction with the time it was accepted
+    capture: Option<CaptureWriter>,
+    /// Writes the accepted transactions into a Parquet file
+    parquet: Option<TransactionParquetWriter>,
+    /// Keeps the notifications of chargebacks and frozen accounts until they are delivered
+    outbox: Option<Arc<Outbox>>,
+    store: Option<(Arc<dyn AccountStore>, Addr<StoreWriter>)>,
+    /// Keeps the transaction history of every account, instead of the accounts themselves
+    tx_store: Option<Arc<dyn TxStore>>,
+    client_accounts: HashMap<u16, AccountRef>,
+    /// The clients whose account was loaded ahead by `prewarm` and no transaction reached yet,
+    /// left out of the collected accounts
+    warm: HashSet<u16>,
+    stage: Option<Stage>,
+    breaker: Option<CircuitBreaker>,
+    /// Skips or defers the non-critical work while the transactions are slow
+    budget: Option<LatencyBudget>,
+    /// The notifications held back while degraded
+    deferred: Vec<Event>,
+    sampler: Option<Sampler>,
+    /// Resolves the settings of the accounts by the segment of their client
+    registry: Option<Arc<ClientRegistry>>,
+    tags: Tags,
+    /// Where the case of every disputed transaction is in the workflow of the operations team
+    cases: Cases,
+    /// Runs the account actors on pinned worker threads instead of the current one
+    workers: Option<Workers>,
+    /// Holds the accounts in a fixed pool of actors instead of an actor per client
+    shards: Option<Shards>,
+    /// The transactions sent ahead to the accounts, whose answer is awaited later
+    mailboxes: Option<Mailboxes>,
+    /// The arbiter of the thread that created the engine
+    arbiter: ArbiterHandle,
+    /// Rejects the transactions of clients without an account, until an opening transaction
+    /// creates it
+    require_open: bool,
+    /// The ids of the deposits, withdrawals and openings applied to any account, to reject the
+    /// transactions reusing one, if strict
+    tx_ids: Option<TxIdFilter>,
+    /// Applies the transactions to a second implementation, checking the actors against it
+    shadow: Option<Shadow>,
+    /// The clients whose account was started or changed by a transaction since the last `drain`
+    changed: HashSet<u16>,
+    /// The clients whose actor crashed, whose transactions are no longer delivered
+    crashed: BTreeMap<u16, Crash>,
+    /// Injects delays and failures in the processing, for tests
+    hook: Option<Arc<dyn ProcessingHook>>,
+    stats: Stats,
+}
+
+/// How a transaction given to the engine ended
+#[derive(Debug, PartialEq)]
+pub enum Applied {
+    /// Not meant to be applied, like savepoint markers or transactions outside the sample
+    Skipped,
+    Accepted,
+    Rejected(TransactionError),
+    Undelivered,
+}
+
+/// How the transactions given to the engine ended
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub applied: u64,
+    pub rejected: u64,
+    /// Disputes, resolves and chargebacks of unknown transactions, which are not errors
+    pub not_found: u64,
+    pub undelivered: u64,
+}
+
+impl Stats {
+    /// How many transactions were given to the engine
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.applied + self.rejected + self.not_found + self.undelivered
+    }
+}
+
+/// The accounts collected at the end of a run
+pub struct Collected {
+    pub accounts: Vec<Account>,
+    /// The accounts whose actor didn't answer, ordered by client
+    pub stragglers: Vec<Straggler>,
+    /// The accounts whose actor crashed, ordered by client. They are stragglers too.
+    pub crashes: Vec<Crash>,
+}
+
+/// An account whose actor crashed, losing its state. The other actors, on the same worker or not,
+/// keep going.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Crash {
+    pub client: u16,
+    /// The worker running the actor, if the actors run on workers
+    pub worker: Option<usize>,
+    /// The transaction whose delivery found the actor crashed
+    pub tx: u32,
+    /// How many transactions of the client weren't delivered since the crash, that one included
+    pub undelivered: u64,
+}
+
+/// An account left out of the collection because its actor didn't answer
+#[derive(Serialize, Debug)]
+pub struct Straggler {
+    pub client: u16,
+    pub reason: String,
+}
+
+/// The changes made since `Engine::begin`, kept until they are committed or rolled back
+#[derive(Default)]
+struct Stage {
+    /// The state of every account touched, before its first transaction of the stage
+    checkpoints: HashMap<u16, AccountState>,
+    /// The clients whose actor was started during the stage
+    started: Vec<u16>,
+    /// The clients whose pre-warmed account was first reached during the stage
+    warmed: Vec<u16>,
+    /// The accepted transactions, journaled on commit
+    accepted: Vec<Transaction>,
+    /// The first transaction that couldn't be delivered, so the stage can't be committed
+    undelivered: Option<u32>,
+    /// The first transaction rejected
+    rejected: Option<u32>,
+    /// The events of the stage, notified on commit
+    events: Vec<Event>,
+}
+
+impl Engine {
+    /// Creates an engine without any account
+    #[must_use]
+    pub fn new(dispatch: DispatchConfig, config: EngineConfig) -> Self {
+        Self {
+            dispatch,
+            collection: DispatchConfig::default(),
+            config,
+            journal: None,
+            wal: None,
+            rejects: None,
+            capture: None,
+            parquet: None,
+            outbox: None,
+            store: None,
+            tx_store: None,
+            client_accounts: HashMap::new(),
+            warm: HashSet::new(),
+            stage: None,
+            breaker: None,
+            budget: None,
+            deferred: Vec::new(),
+            sampler: None,
+            registry: None,
+            tags: Tags::default(),
+            cases: Cases::default(),
+            workers: None,
+            shards: None,
+            mailboxes: None,
+            arbiter: Arbiter::current(),
+            require_open: false,
+            tx_ids: None,
+            shadow: None,
+            changed: HashSet::new(),
+            crashed: BTreeMap::new(),
+            hook: None,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Waits `config.timeout` for every account to answer the collection, asking again
+    /// `config.retries` times before leaving it out as a straggler
+    #[must_use]
+    pub fn with_collection(mut self, config: DispatchConfig) -> Self {
+        self.collection = config;
+        self
+    }
+
+    /// Calls the hook at every point of the processing of the transactions, for tests to inject
+    /// delays and failures. Tests outside of the crate need the `test-support` feature.
+    #[cfg(any(test, feature = "test-support"))]
+    #[must_use]
+    pub fn with_hook(mut self, hook: Arc<dyn ProcessingHook>) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Sends the transactions given to `apply` to their account without waiting for the answer,
+    /// keeping at most `capacity` of them in flight per client. A client with no room left waits
+    /// for the answers to its oldest transactions.
+    #[must_use]
+    pub fn with_mailboxes(mut self, capacity: usize) -> Self {
+        self.mailboxes = Some(Mailboxes::new(capacity));
+        self
+    }
+
+    /// Spreads the account actors over worker threads pinned to the cores given, by client
+    #[must_use]
+    pub fn with_workers(mut self, cores: &[usize]) -> Self {
+        self.workers = Some(Workers::pinned(cores));
+        self
+    }
+
+    /// Holds the accounts in `count` actors, each with the accounts of the clients whose id falls
+    /// in its partition, instead of starting an actor per client
+    #[must_use]
+    pub fn with_shards(mut self, count: u16) -> Self {
+        self.shards = Some(Shards::new(count));
+        self
+    }
+
+    /// Applies every delivered transaction to a map based engine too, logging where it diverges
+    /// from the actors
+    #[must_use]
+    pub fn with_shadow(mut self) -> Self {
+        self.shadow = Some(Shadow::default());
+        self
+    }
+
+    /// The shadow engine, if the transactions are applied to one
+    pub fn shadow(&self) -> Option<&Shadow> {
+        self.shadow.as_ref()
+    }
+
+    /// Only creates accounts from opening transactions, instead of from the first transaction of
+    /// every client
+    #[must_use]
+    pub fn with_required_open(mut self) -> Self {
+        self.require_open = true;
+        self
+    }
+
+    /// Rejects the deposits, withdrawals and openings reusing the id of one already applied to
+    /// any account in the filter given, instead of recording them again
+    #[must_use]
+    pub fn with_strict_tx_ids(mut self, filter: TxIdFilter) -> Self {
+        self.tx_ids = Some(filter);
+        self
+    }
+
+    /// Resolves the settings of every account through the segment policies of the registry, and
+    /// tags the accounts with the tags of their client
+    #[must_use]
+    pub fn with_registry(mut self, registry: Arc<ClientRegistry>) -> Self {
+        self.tags = registry.tags();
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Records every accepted transaction into the journal
+    #[must_use]
+    pub fn with_journal(mut self, journal: JournalWriter) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Logs every transaction delivered to an account, before it is applied
+    #[must_use]
+    pub fn with_wal(mut self, wal: WriteAheadLog) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Writes every accepted transaction into a Parquet file, completed when the accounts are
+    /// collected
+    #[must_use]
+    pub fn with_parquet_transactions(mut self, writer: TransactionParquetWriter) -> Self {
+        self.parquet = Some(writer);
+        self
+    }
+
+    /// Records every accepted transaction into the capture file, with the time it was accepted
+    #[must_use]
+    pub fn with_capture(mut self, capture: CaptureWriter) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Writes every rejected or undelivered transaction into the rejects file, with the reason
+    #[must_use]
+    pub fn with_rejects(mut self, rejects: RejectWriter) -> Self {
+        self.rejects = Some(rejects);
+        self
+    }
+
+    /// Adds a notification to the outbox for every chargeback applied, every account frozen and
+    /// every time the circuit breaker opens
+    #[must_use]
+    pub fn with_outbox(mut self, outbox: Arc<Outbox>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Loads the accounts from the store when their client is first found and saves every change
+    /// back, flushing the changed accounts every `flush_interval`
+    #[must_use]
+    pub fn with_store(mut self, store: Arc<dyn AccountStore>, flush_interval: Duration) -> Self {
+        let writer = StoreWriter::new(Arc::clone(&store), flush_interval).start();
+        self.store = Some((store, writer));
+        self
+    }
+
+    /// Starts the actors of the `limit` accounts of the store saved most recently, so the first
+    /// transactions of their clients don't wait for a load. They are only collected once a
+    /// transaction reaches them, like the accounts loaded on demand. Returns how many were started.
+    ///
+    /// # Errors
+    /// If the engine has no store, or an account can't be loaded from it, an error will be
+    /// returned
+    pub fn prewarm(&mut self, limit: usize) -> Result<usize> {
+        let store = match &self.store {
+            Some((store, _)) => Arc::clone(store),
+            None => bail!("The engine has no store to pre-warm the accounts from"),
+        };
+        let mut started = 0;
+        for client in store.recent(limit)? {
+            if self.client_accounts.contains_key(&client) {
+                continue;
+            }
+            let Some(state) = store.load(client)? else {
+                continue;
+            };
+            let account = Account::from_state(state, self.config_for(client));
+            let actor = self.start_actor(account)?;
+            self.client_accounts.insert(client, actor);
+            self.warm.insert(client);
+            started += 1;
+        }
+        info!("Pre-warmed {started} accounts from the store");
+        Ok(started)
+    }
+
+    /// Keeps the transaction history of the accounts in the store, so it doesn't have to fit in
+    /// memory
+    #[must_use]
+    pub fn with_tx_store(mut self, store: Arc<dyn TxStore>) -> Self {
+        self.tx_store = Some(store);
+        self
+    }
+
+    /// Pauses before applying transactions whenever the breaker opens, until it is resumed
+    #[must_use]
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Skips the shadow comparisons and defers the webhook notifications while the transactions
+    /// take longer than the budget
+    #[must_use]
+    pub fn with_latency_budget(mut self, config: BudgetConfig) -> Self {
+        self.budget = Some(LatencyBudget::new(config));
+        self
+    }
+
+    /// What was skipped or deferred while degraded, if there's a latency budget
+    pub fn degraded_stats(&self) -> Option<DegradedStats> {
+        self.budget.as_ref().map(LatencyBudget::stats)
+    }
+
+    /// Only applies the transactions of the sample, skipping the others
+    #[must_use]
+    pub fn with_sample(mut self, spec: SampleSpec) -> Self {
+        self.sampler = Some(Sampler::new(spec));
+        self
+    }
+
+    /// The sampler picking the transactions applied, if sampling
+    pub fn sampler(&self) -> Option<&Sampler> {
+        self.sampler.as_ref()
+    }
+
+    /// Whether the sample is complete, so the rest of the input can be skipped
+    pub fn sample_complete(&self) -> bool {
+        self.sampler.as_ref().is_some_and(Sampler::is_complete)
+    }
+
+    /// How the amounts of the account of a client are rounded, with the overrides of its currency
+    /// and segment
+    pub fn rounding_of(&self, client: u16) -> Rounding {
+        self.config_for(client).rounding
+    }
+
+    /// The tags of the accounts
+    pub fn tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    /// Tags and untags the accounts
+    #[cfg(feature = "http")]
+    pub fn tags_mut(&mut self) -> &mut Tags {
+        &mut self.tags
+    }
+
+    /// The client registry the settings of the accounts are resolved through, if any
+    #[cfg(feature = "http")]
+    pub fn registry(&self) -> Option<&ClientRegistry> {
+        self.registry.as_deref()
+    }
+
+    /// The dispute cases
+    #[cfg(feature = "http")]
+    pub fn cases(&self) -> &Cases {
+        &self.cases
+    }
+
+    /// Moves the dispute cases along their workflow
+    #[cfg(feature = "http")]
+    pub fn cases_mut(&mut self) -> &mut Cases {
+        &mut self.cases
+    }
+
+    /// How the transactions given to the engine ended so far
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Starts the accounts of a snapshot, replacing any existing account of the same clients
+    ///
+    /// # Errors
+    /// If the transaction history of an account can't be moved into the transaction store, an
+    /// error will be returned
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<()> {
+        for state in snapshot.accounts {
+            if let Some(ids) = &mut self.tx_ids {
+                ids.extend(state.tx_ids());
+            }
+            let config = self.config_for(state.client());
+            let account = Account::from_state(state, config);
+            let client = account.client();
+            let actor = self.start_actor(account)?;
+            self.client_accounts.insert(client, actor);
+            self.warm.remove(&client);
+        }
+        Ok(())
+    }
+
+    /// Starts the actor of an account, along with its copy in the shadow engine
+    ///
+    /// # Errors
+    /// If the transaction history of the account can't be moved into the transaction store, an
+    /// error will be returned
+    fn start_actor(&mut self, account: Account) -> Result<AccountRef> {
+        if let Some(shadow) = &mut self.shadow {
+            shadow.insert(account.clone());
+        }
+        let account = match &self.tx_store {
+            Some(store) => account.with_tx_store(Arc::clone(store))?,
+            None => account,
+        };
+        let arbiter = self.arbiter_for(account.client());
+        let (store, hook) = (self.store_writer(), self.hook.clone());
+        Ok(match &mut self.shards {
+            Some(shards) => shards.adopt(account, store, hook, &arbiter),
+            None => AccountHandler::from_account(account, store, hook, Some(&arbiter)),
+        })
+    }
+
+    fn store_writer(&self) -> Option<Addr<StoreWriter>> {
+        self.store.as_ref().map(|(_, writer)| writer.clone())
+    }
+
+    /// The worker running the actor of a client, or the thread that created the engine. Requests
+    /// of the client API run on other threads, whose arbiters stop with the server.
+    fn arbiter_for(&self, client: u16) -> ArbiterHandle {
+        match &self.workers {
+            Some(workers) => workers.for_client(self.placement(client)),
+            None => self.arbiter.clone(),
+        }
+    }
+
+    /// The id the worker of a client's actor is picked by: the id of its shard, if the accounts
+    /// are sharded, so all the accounts of a shard share its worker
+    fn placement(&self, client: u16) -> u16 {
+        self.shards
+            .as_ref()
+            .map_or(client, |shards| shards.index_for(client))
+    }
+
+    /// The settings of a client's account, with the policy of its segment applied
+    fn config_for(&self, client: u16) -> EngineConfig {
+        self.registry.as_ref().map_or(self.config, |registry| {
+            registry.config_for(client, self.config)
+        })
+    }
+
+    /// The account of a client found for the first time, loaded from the store or new. Clients
+    /// without an account only get one from an opening transaction, or from any transaction unless
+    /// accounts must be opened first.
+    fn load_account(&self, transaction: &Transaction) -> Result<Result<Account, TransactionError>> {
+        let (client, tx) = (transaction.client, transaction.tx);
+        let config = self.config_for(client);
+        let opening = transaction.transaction_type == TransactionType::Open;
+        if let Some((store, _)) = &self.store {
+            if let Some(state) = store.load(client)? {
+                if opening {
+                    return Ok(Err(TransactionError::AccountExists { client, tx }));
+                }
+                return Ok(Ok(Account::from_state(state, config)));
+            }
+        }
+        if self.require_open && !opening {
+            return Ok(Err(TransactionError::AccountNotOpen { client, tx }));
+        }
+        Ok(Ok(Account::new(client, config)))
+    }
+
+    /// The sequence number of the last transaction written to the journal
+    pub fn journal_seq(&self) -> u64 {
+        self.journal.as_ref().map_or(0, JournalWriter::seq)
+    }
+
+    /// Applies a transaction to its client's account. Rejected and undelivered transactions are
+    /// logged and don't interrupt the processing. With mailboxes, the transaction may only be sent
+    /// ahead to its account, see `settle`.
+    ///
+    /// # Errors
+    /// If the journal can't be written or the account can't be loaded from the store, an error
+    /// will be returned
+    pub async fn apply(&mut self, transaction: Transaction) -> Result<()> {
+        if self.sends_ahead(&transaction) {
+            return self.send_ahead(transaction).await;
+        }
+        self.submit(transaction).await.map(|_| ())
+    }
+
+    /// Whether a transaction can be sent ahead: with mailboxes, outside of stages, and only when
+    /// nothing waits for how it ends before the next transaction, unlike transfers, strict ids or
+    /// the shadow engine
+    fn sends_ahead(&self, transaction: &Transaction) -> bool {
+        self.mailboxes.is_some()
+            && self.stage.is_none()
+            && self.tx_ids.is_none()
+            && self.shadow.is_none()
+            && self.hook.is_none()
+            && transaction.transaction_type != TransactionType::Transfer
+    }
+
+    /// Sends a transaction to its account without waiting for the answer, once its client has room
+    /// in its mailbox. Until then, the answers to the oldest transactions sent ahead are recorded.
+    ///
+    /// # Errors
+    /// If the journal can't be written or the account can't be loaded from the store, an error
+    /// will be returned
+    async fn send_ahead(&mut self, transaction: Transaction) -> Result<()> {
+        let Ok(mut transaction) = self.admit(transaction).await? else {
+            return Ok(());
+        };
+        if let Some(wal) = &mut self.wal {
+            wal.append(&transaction).await?;
+        }
+        while let Some(mailboxes) = &mut self.mailboxes {
+            let actor = &self.client_accounts[&transaction.client].addr;
+            match mailboxes.try_send(actor, transaction) {
+                Ok(()) => break,
+                Err(Full(back)) => {
+                    transaction = back;
+                    self.settle_next().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records how every transaction sent ahead by `apply` ended. The statistics, the journal and
+    /// the states fetched only account for them once settled, which is done before any other
+    /// transaction is submitted and before the accounts are persisted, drained or collected.
+    ///
+    /// # Errors
+    /// If the journal can't be written, an error will be returned
+    pub async fn settle(&mut self) -> Result<()> {
+        while self.mailboxes.as_ref().is_some_and(|m| !m.is_empty()) {
+            self.settle_next().await?;
+        }
+        Ok(())
+    }
+
+    /// Records how the oldest transaction sent ahead ended, once its account answered
+    async fn settle_next(&mut self) -> Result<()> {
+        let Some(mailboxes) = &mut self.mailboxes else {
+            return Ok(());
+        };
+        let Some((delivery, sent, answer)) = mailboxes.next(self.dispatch).await else {
+            return Ok(());
+        };
+        let Deliver {
+            transaction,
+            ticket,
+        } = delivery;
+        let span = error_span!(
+            "transaction",
+            client = transaction.client,
+            tx = transaction.tx,
+            r#type = ?transaction.transaction_type,
+        );
+        async {
+            let Some(result) = self.answered(&transaction, &ticket, sent, answer).await? else {
+                return Ok(());
+            };
+            if result.is_ok() {
+                self.mark_applied(&transaction);
+            }
+            self.record(transaction, result).await.map(|_| ())
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Applies a transaction like `apply`, telling how it ended
+    ///
+    /// # Errors
+    /// If the journal can't be written or the account can't be loaded from the store, an error
+    /// will be returned
+    // at the error level, so the span is kept whenever its errors are logged
+    #[instrument(
+        name = "transaction",
+        level = "error",
+        skip_all,
+        fields(
+            client = transaction.client,
+            tx = transaction.tx,
+            r#type = ?transaction.transaction_type,
+        )
+    )]
+    pub async fn submit(&mut self, transaction: Transaction) -> Result<Applied> {
+        self.settle().await?;
+        let transaction = match self.admit(transaction).await? {
+            Ok(transaction) => transaction,
+            Err(applied) => return Ok(applied),
+        };
+        let client = transaction.client;
+        if !self.checkpoint(client, &transaction).await? {
+            return Ok(Applied::Undelivered);
+        }
+        if let Some(wal) = &mut self.wal {
+            wal.append(&transaction).await?;
+        }
+        let Some(result) = self.deliver(client, &transaction).await? else {
+            return Ok(Applied::Undelivered);
+        };
+        if result.is_ok() {
+            self.mark_applied(&transaction);
+        }
+        self.check_shadow(&transaction, &result).await;
+        self.record(transaction, result).await
+    }
+
+    /// Takes a transaction through the checks made before it is sent to its account, starting the
+    /// account if it has none yet. Gives the transaction back if it can be sent, or else how it
+    /// ended. Transfers are applied right away.
+    ///
+    /// # Errors
+    /// If the journal can't be written or an account can't be loaded from the store, an error
+    /// will be returned
+    async fn admit(&mut self, transaction: Transaction) -> Result<Result<Transaction, Applied>> {
+        self.run_parse_hook(&transaction).await?;
+        // markers only matter to the reader splitting the input in segments
+        if transaction.transaction_type == TransactionType::Savepoint {
+            return Ok(Err(Applied::Skipped));
+        }
+        if let Some(sampler) = &mut self.sampler {
+            if !sampler.take(&transaction) {
+                return Ok(Err(Applied::Skipped));
+            }
+        }
+        if let Some(breaker) = &mut self.breaker {
+            if breaker.is_open() {
+                breaker.wait_for_resume().await;
+            }
+        }
+        let (client, tx) = (transaction.client, transaction.tx);
+        if self.crashed.contains_key(&client) {
+            return self.reject_crashed(&transaction).await.map(Err);
+        }
+        if self.is_duplicate(&transaction) {
+            let duplicate = TransactionError::DuplicateTransaction { client, tx };
+            return self.record(transaction, Err(duplicate)).await.map(Err);
+        }
+        if transaction.transaction_type == TransactionType::Transfer {
+            return self.transfer(transaction).await.map(Err);
+        }
+        if let Err(e) = self.start_account(&transaction)? {
+            return self.record(transaction, Err(e)).await.map(Err);
+        }
+        Ok(Ok(transaction))
+    }
+
+    /// Moves the amount of a transfer between two accounts: the debit is applied to the account of
+    /// the client, then the credit to the one of `to_client`, started only after the debit. If the
+    /// credit fails, the debited account is restored to its state before the transfer, so either
+    /// both apply or neither.
+    ///
+    /// # Errors
+    /// If the journal can't be written, an account can't be loaded from the store or the debited
+    /// account can't be restored, an error will be returned
+    async fn transfer(&mut self, transaction: Transaction) -> Result<Applied> {
+        let (client, tx) = (transaction.client, transaction.tx);
+        let Some(to_client) = transaction
+            .to_client
+            .filter(|to_client| *to_client != client)
+        else {
+            let invalid = TransactionError::InvalidOperation { client, tx };
+            return self.record(transaction, Err(invalid)).await;
+        };
+        if self.crashed.contains_key(&to_client) {
+            return self.reject_crashed(&transaction).await;
+        }
+        if let Err(e) = self.start_account(&transaction)? {
+            return self.record(transaction, Err(e)).await;
+        }
+        if !self.checkpoint(client, &transaction).await? {
+            return Ok(Applied::Undelivered);
+        }
+        let source = &self.client_accounts[&client].addr;
+        let before = match send_with_retry(source, GetState, self.dispatch).await {
+            Ok(state) => state,
+            Err(e) => {
+                let reason = format!("Could not fetch the state of the account: {e}");
+                return self.undelivered(&transaction, &reason).await;
+            }
+        };
+        if let Some(wal) = &mut self.wal {
+            wal.append(&transaction).await?;
+        }
+        let Some(debit) = self.deliver(client, &transaction).await? else {
+            return Ok(Applied::Undelivered);
+        };
+        self.check_shadow(&transaction, &debit).await;
+        if debit.is_err() {
+            return self.record(transaction, debit).await;
+        }
+        let credit = Transaction {
+            client: to_client,
+            ..transaction.clone()
+        };
+        // the account credited is only started once the debit went through, so a rejected
+        // transfer doesn't open one
+        let started = match self.start_account(&credit) {
+            Ok(Ok(())) => self.checkpoint(to_client, &transaction).await,
+            Ok(Err(e)) => {
+                self.restore_account(before, tx).await?;
+                return self.record(transaction, Err(e)).await;
+            }
+            Err(e) => Err(e),
+        };
+        if !matches!(started, Ok(true)) {
+            self.restore_account(before, tx).await?;
+            return started.map(|_| Applied::Undelivered);
+        }
+        let credited = self.deliver(to_client, &credit).await?;
+        if let Some(result) = &credited {
+            self.check_shadow(&credit, result).await;
+        }
+        match credited {
+            Some(Ok(())) => {
+                self.mark_applied(&transaction);
+                self.changed.insert(to_client);
+                self.record(transaction, Ok(())).await
+            }
+            Some(Err(e)) => {
+                self.restore_account(before, tx).await?;
+                self.record(transaction, Err(e)).await
+            }
+            None => {
+                self.restore_account(before, tx).await?;
+                Ok(Applied::Undelivered)
+            }
+        }
+    }
+
+    /// Starts the account of the transaction's client if it has none yet, telling whether the
+    /// transaction can reach it: an opening transaction can't reach an account that already
+    /// exists.
+    ///
+    /// # Errors
+    /// If the account can't be loaded from the store, an error will be returned
+    fn start_account(&mut self, transaction: &Transaction) -> Result<Result<(), TransactionError>> {
+        let (client, tx) = (transaction.client, transaction.tx);
+        if self.client_accounts.contains_key(&client) {
+            if self.warm.remove(&client) {
+                self.changed.insert(client);
+                if let Some(stage) = &mut self.stage {
+                    stage.warmed.push(client);
+                }
+            }
+            if transaction.transaction_type == TransactionType::Open {
+                return Ok(Err(TransactionError::AccountExists { client, tx }));
+            }
+            return Ok(Ok(()));
+        }
+        let account = match self.load_account(transaction)? {
+            Ok(account) => account,
+            Err(e) => return Ok(Err(e)),
+        };
+        let actor = self.start_actor(account)?;
+        self.client_accounts.insert(client, actor);
+        self.changed.insert(client);
+        if let Some(stage) = &mut self.stage {
+            stage.started.push(client);
+        }
+        Ok(Ok(()))
+    }
+
+    /// Keeps the state of a client's account before the first transaction of the stage touching
+    /// it, to restore it on a rollback. Tells whether the transaction can go on, leaving it
+    /// undelivered if the state can't be fetched.
+    async fn checkpoint(&mut self, client: u16, transaction: &Transaction) -> Result<bool> {
+        let Some(stage) = &mut self.stage else {
+            return Ok(true);
+        };
+        let Entry::Vacant(checkpoint) = stage.checkpoints.entry(client) else {
+            return Ok(true);
+        };
+        let actor = &self.client_accounts[&client].addr;
+        match send_with_retry(actor, GetState, self.dispatch).await {
+            Ok(before) => {
+                checkpoint.insert(before);
+                Ok(true)
+            }
+            Err(e) => {
+                error!("Could not fetch the state of client {client}: {e}");
+                let reason = format!("Could not fetch the state of the account: {e}");
+                self.undelivered(transaction, &reason).await?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Sends a transaction to the account of a client, counting how long it took against the
+    /// latency budget. Tells how the account ended it, or nothing if it couldn't be delivered.
+    async fn deliver(
+        &mut self,
+        client: u16,
+        transaction: &Transaction,
+    ) -> Result<Option<Result<(), TransactionError>>> {
+        let actor = &self.client_accounts[&client].addr;
+        let sent = Instant::now();
+        let delivery = Deliver::new(transaction.clone());
+        let ticket = delivery.ticket.clone();
+        let answer = send_with_retry(actor, delivery, self.dispatch).await;
+        self.answered(transaction, &ticket, sent, answer).await
+    }
+
+    /// Counts how long the account of a transaction took to answer against the latency budget,
+    /// telling how the account ended it. A transaction the account didn't answer is cancelled
+    /// through its ticket and left undelivered, unless the account applied it already: it then
+    /// ended as the account left it, so the outcome reported matches the ledger.
+    async fn answered(
+        &mut self,
+        transaction: &Transaction,
+        ticket: &Ticket,
+        sent: Instant,
+        answer: Answer,
+    ) -> Result<Option<Result<(), TransactionError>>> {
+        let result = match answer {
+            Ok(result) => result,
+            Err(e) => {
+                let (client, tx) = (transaction.client, transaction.tx);
+                let crashed = if let Some(crash) = self.crashed.get_mut(&client) {
+                    // sent ahead before the crash was found
+                    crash.undelivered += 1;
+                    true
+                } else if !self.client_accounts[&client].addr.connected() {
+                    self.record_crash(client, tx);
+                    true
+                } else {
+                    false
+                };
+                // the account of a crashed actor is lost along with what it applied
+                let Some(result) = ticket.cancel().filter(|_| !crashed) else {
+                    error!("Could not deliver transaction {tx} to client {client}: {e}");
+                    let reason = format!("Could not deliver the transaction: {e}");
+                    self.undelivered(transaction, &reason).await?;
+                    return Ok(None);
+                };
+                warn!("Transaction {tx} of client {client} was applied, but not answered: {e}");
+                result
+            }
+        };
+        let recovered = self
+            .budget
+            .as_mut()
+            .is_some_and(|budget| budget.record(sent.elapsed()));
+        if recovered {
+            self.notify_deferred().await?;
+        }
+        Ok(Some(result))
+    }
+
+    /// Counts a transaction that couldn't be delivered and writes it into the rejects file
+    async fn undelivered(&mut self, transaction: &Transaction, reason: &str) -> Result<Applied> {
+        self.stats.undelivered += 1;
+        if let Some(stage) = &mut self.stage {
+            stage.undelivered.get_or_insert(transaction.tx);
+        }
+        self.write_reject(transaction, reason).await?;
+        Ok(Applied::Undelivered)
+    }
+
+    /// Puts an account back in the state it had before transaction `tx`, along with its copy in
+    /// the shadow engine
+    ///
+    /// # Errors
+    /// If the account can't be restored or `tx` can't be removed from the transaction store, an
+    /// error will be returned
+    async fn restore_account(&mut self, state: AccountState, tx: u32) -> Result<()> {
+        let client = state.client();
+        let config = self.config_for(client);
+        if let Some(shadow) = &mut self.shadow {
+            shadow.insert(Account::from_state(state.clone(), config));
+        }
+        if let Some(store) = &self.tx_store {
+            store.remove(client, tx)?;
+        }
+        let actor = &self.client_accounts[&client].addr;
+        send_with_retry(actor, Restore(state), self.dispatch).await?;
+        Ok(())
+    }
+
+    /// Leaves a transaction of a client whose actor crashed undelivered
+    async fn reject_crashed(&mut self, transaction: &Transaction) -> Result<Applied> {
+        if let Some(crash) = self.crashed.get_mut(&transaction.client) {
+            crash.undelivered += 1;
+        }
+        self.undelivered(transaction, "The account crashed").await
+    }
+
+    /// Keeps track of a transaction its account applied
+    fn mark_applied(&mut self, transaction: &Transaction) {
+        self.changed.insert(transaction.client);
+        if let Some(ids) = &mut self.tx_ids {
+            if !transaction.transaction_type.is_dispute_step() {
+                ids.insert(transaction.tx);
+            }
+        }
+    }
+
+    /// Whether the transaction reuses the id of a deposit, withdrawal or opening already applied,
+    /// if strict
+    fn is_duplicate(&self, transaction: &Transaction) -> bool {
+        !transaction.transaction_type.is_dispute_step()
+            && self
+                .tx_ids
+                .as_ref()
+                .is_some_and(|ids| ids.contains(transaction.tx))
+    }
+
+    /// Calls the hook after the transaction was parsed, if there's one
+    async fn run_parse_hook(&self, transaction: &Transaction) -> Result<()> {
+        if let Some(hook) = &self.hook {
+            match hook.on(HookPoint::PostParse, transaction) {
+                HookAction::Continue => {}
+                HookAction::Delay(delay) => tokio::time::sleep(delay).await,
+                HookAction::Fail => bail!("Transaction {} failed after parsing", transaction.tx),
+                HookAction::Panic => panic!("Crashed after parsing {}", transaction.tx),
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops delivering the transactions of a client whose actor crashed
+    fn record_crash(&mut self, client: u16, tx: u32) {
+        error!("The account of client {client} crashed, its transactions are no longer delivered");
+        let worker = self
+            .workers
+            .as_ref()
+            .map(|workers| workers.index_for(self.placement(client)));
+        self.crashed.insert(
+            client,
+            Crash {
+                client,
+                worker,
+                tx,
+                undelivered: 1,
+            },
+        );
+    }
+
+    /// Applies a delivered transaction to the shadow engine too, if there's one, comparing how it
+    /// ended and the resulting balances
+    async fn check_shadow(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), TransactionError>,
+    ) {
+        let Some(shadow) = &mut self.shadow else {
+            return;
+        };
+        shadow.apply(transaction, result);
+        if let Some(budget) = self.budget.as_mut().filter(|budget| budget.is_degraded()) {
+            budget.skip_comparison();
+            return;
+        }
+        let (client, tx) = (transaction.client, transaction.tx);
+        let actor = &self.client_accounts[&client];
+        let fetched = match actor.priority_balances(self.dispatch.timeout).await {
+            Ok(balances) if shadow.compare(client, balances, tx) => return,
+            // only a diverged account is copied whole, to resync the shadow one
+            Ok(_) => actor
+                .priority_state(self.dispatch.timeout)
+                .await
+                .map(|state| shadow.resync(state)),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = fetched {
+            warn!("Could not fetch the state of client {client} for the shadow engine: {e}");
+        }
+    }
+
+    /// Counts how a delivered transaction ended and journals it if it was accepted
+    async fn record(
+        &mut self,
+        transaction: Transaction,
+        result: Result<(), TransactionError>,
+    ) -> Result<Applied> {
+        if let Some(breaker) = &mut self.breaker {
+            let was_open = breaker.is_open();
+            match &result {
+                Ok(()) if transaction.transaction_type == TransactionType::Chargeback => {
+                    breaker.record(Outcome::Chargeback, Instant::now());
+                }
+                Ok(()) => breaker.record(Outcome::Accepted, Instant::now()),
+                Err(e) if e.is_not_found() => {}
+                Err(_) => breaker.record(Outcome::Rejected, Instant::now()),
+            }
+            // the ingestion pauses right away, so this notification can't wait for a commit or
+            // for the engine to recover
+            if let (false, true, Some(outbox)) = (was_open, breaker.is_open(), &self.outbox) {
+                let (client, tx) = (transaction.client, transaction.tx);
+                outbox.push(Event::BreakerOpen { client, tx }).await?;
+            }
+        }
+        match &result {
+            Ok(()) => self.stats.applied += 1,
+            Err(e) if e.is_not_found() => self.stats.not_found += 1,
+            Err(_) => self.stats.rejected += 1,
+        }
+        let (client, tx) = (transaction.client, transaction.tx);
+        match result {
+            Ok(()) => {
+                if transaction.transaction_type == TransactionType::Chargeback {
+                    self.notify(Event::Chargeback { client, tx }).await?;
+                }
+                self.cases.record(&transaction);
+                if let Some(stage) = &mut self.stage {
+                    stage.accepted.push(transaction);
+                } else {
+                    self.keep(&transaction).await?;
+                }
+                Ok(Applied::Accepted)
+            }
+            Err(e) => {
+                log_rejection(&e);
+                if matches!(e, TransactionError::InconsistentState { .. })
+                    && self.config_for(client).freeze_on_inconsistency
+                {
+                    self.notify(Event::Frozen { client, tx }).await?;
+                }
+                if !e.is_not_found() {
+                    if let Some(stage) = &mut self.stage {
+                        stage.rejected.get_or_insert(transaction.tx);
+                    }
+                    self.write_reject(&transaction, &e.to_string()).await?;
+                }
+                Ok(Applied::Rejected(e))
+            }
+        }
+    }
+
+    /// Writes an accepted transaction into the journal, the capture file and the Parquet file, if
+    /// there are
+    async fn keep(&mut self, transaction: &Transaction) -> Result<()> {
+        if let Some(journal) = &mut self.journal {
+            journal.append(transaction).await?;
+        }
+        if let Some(capture) = &mut self.capture {
+            capture.write(transaction).await?;
+        }
+        if let Some(parquet) = &mut self.parquet {
+            parquet.write(transaction)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the notification of an event to the outbox, if there's one. The events of a stage
+    /// wait for its commit, and the events found while degraded wait for the engine to recover.
+    async fn notify(&mut self, event: Event) -> Result<()> {
+        match (&mut self.stage, &self.outbox) {
+            (_, None) => Ok(()),
+            (Some(stage), Some(_)) => {
+                stage.events.push(event);
+                Ok(())
+            }
+            (None, Some(_)) if self.budget.as_ref().is_some_and(LatencyBudget::is_degraded) => {
+                if let Some(budget) = &mut self.budget {
+                    budget.defer_notification();
+                }
+                self.deferred.push(event);
+                Ok(())
+            }
+            (None, Some(outbox)) => outbox.push(event).await.map(|_| ()),
+        }
+    }
+
+    /// Adds the notifications held back while degraded to the outbox
+    async fn notify_deferred(&mut self) -> Result<()> {
+        let Some(outbox) = &self.outbox else {
+            return Ok(());
+        };
+        for event in self.deferred.drain(..) {
+            outbox.push(event).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes a failed transaction into the rejects file, if there's one
+    async fn write_reject(&mut self, transaction: &Transaction, reason: &str) -> Result<()> {
+        match &mut self.rejects {
+            Some(rejects) => rejects.write(transaction, reason).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Starts staging the transactions applied from now on, so they can be undone together
+    pub fn begin(&mut self) {
+        self.stage = Some(Stage::default());
+    }
+
+    /// The first transaction applied since `begin` that was rejected or couldn't be delivered.
+    /// Transactions not found are not counted, as they are not errors.
+    pub fn staged_failure(&self) -> Option<u32> {
+        self.stage
+            .as_ref()
+            .and_then(|stage| stage.undelivered.or(stage.rejected))
+    }
+
+    /// Keeps the transactions applied since `begin`, writing them to the journal and the capture
+    /// file. If any of them couldn't be delivered, they are all rolled back instead.
+    ///
+    /// # Errors
+    /// If a transaction couldn't be delivered or the journal can't be written, an error will be
+    /// returned
+    pub async fn commit(&mut self) -> Result<()> {
+        let Some(stage) = self.stage.take() else {
+            return Ok(());
+        };
+        if stage.undelivered.is_some() {
+            self.undo(stage).await?;
+            bail!("Some transactions could not be delivered, every staged transaction was undone");
+        }
+        for transaction in &stage.accepted {
+            self.keep(transaction).await?;
+        }
+        if let Some(outbox) = &self.outbox {
+            for event in stage.events {
+                outbox.push(event).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes the transactions applied since `begin`, restoring the accounts they touched
+    ///
+    /// # Errors
+    /// If an account can't be restored or the store can't be written, an error will be returned
+    pub async fn rollback(&mut self) -> Result<()> {
+        match self.stage.take() {
+            Some(stage) => self.undo(stage).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn undo(&mut self, stage: Stage) -> Result<()> {
+        for (client, state) in stage.checkpoints {
+            let config = self.config_for(client);
+            if let Some(shadow) = &mut self.shadow {
+                shadow.insert(Account::from_state(state.clone(), config));
+            }
+            let actor = &self.client_accounts[&client].addr;
+            send_with_retry(actor, Restore(state), self.dispatch).await?;
+        }
+        if let Some(store) = &self.tx_store {
+            let recorded = stage.accepted.iter().filter(|t| {
+                matches!(
+                    t.transaction_type,
+                    TransactionType::Deposit
+                        | TransactionType::Withdrawal
+                        | TransactionType::Transfer
+                )
+            });
+            for transaction in recorded {
+                store.remove(transaction.client, transaction.tx)?;
+                if let Some(to_client) = transaction.to_client {
+                    store.remove(to_client, transaction.tx)?;
+                }
+            }
+        }
+        self.warm.extend(stage.warmed);
+        // restored first, so the store doesn't keep staged values of the clients started
+        for client in stage.started {
+            self.client_accounts.remove(&client);
+            if let Some(shadow) = &mut self.shadow {
+                shadow.remove(client);
+            }
+        }
+        if let Some((_, writer)) = &self.store {
+            writer.send(Flush).await??;
+        }
+        Ok(())
+    }
+
+    /// Fetches the current state of a client's account, if the client was ever found. The query
+    /// goes through the priority lane of the actor, ahead of any queued transaction.
+    ///
+    /// # Errors
+    /// If the account actor doesn't answer, an error will be returned
+    pub async fn state(&self, client: u16) -> Result<Option<AccountState>> {
+        match self.client_accounts.get(&client) {
+            Some(account) => Ok(Some(account.priority_state(self.dispatch.timeout).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The clients with an account, in order
+    pub fn clients(&self) -> Vec<u16> {
+        let mut clients: Vec<_> = self.client_accounts.keys().copied().collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    /// Fetches the current state of every account, ordered by client
+    ///
+    /// # Errors
+    /// If an account actor doesn't answer, an error will be returned
+    pub async fn states(&self) -> Result<Vec<AccountState>> {
+        let mut states = Vec::with_capacity(self.client_accounts.len());
+        for client in self.clients() {
+            if let Some(state) = self.state(client).await? {
+                states.push(state);
+            }
+        }
+        Ok(states)
+    }
+
+    /// Fetches the current state of every account as an Arrow record batch, ordered by client and
+    /// rounded like the output, for embedders to query the live accounts
+    ///
+    /// # Errors
+    /// If an account actor doesn't answer or an amount doesn't fit in the batch, an error will be
+    /// returned
+    #[cfg(feature = "arrow")]
+    pub async fn record_batch(&self) -> Result<arrow_array::RecordBatch> {
+        let records: Vec<_> = self
+            .states()
+            .await?
+            .iter()
+            .map(|state| {
+                let rounding = self.config_for(state.client()).rounding;
+                crate::model::AccountRecord::from_state(state, rounding)
+            })
+            .collect();
+        crate::arrow::record_batch(&records)
+    }
+
+    /// Applies the journal events with a sequence number after `after` and up to `until`,
+    /// returning the sequence number of the last event applied
+    ///
+    /// # Errors
+    /// If the journal can't be read, an error will be returned
+    pub async fn replay(
+        &mut self,
+        journal: &mut JournalReader,
+        after: u64,
+        until: Option<u64>,
+    ) -> Result<u64> {
+        let mut last_seq = after;
+        while let Some(event) = journal.next_event().await? {
+            if event.seq <= after {
+                continue;
+            }
+            if until.is_some_and(|until| event.seq > until) {
+                break;
+            }
+            self.apply(event.transaction).await?;
+            last_seq = event.seq;
+        }
+        Ok(last_seq)
+    }
+
+    /// Writes the journal to disk and saves the changed accounts into the store, so the transactions
+    /// applied so far survive a crash
+    ///
+    /// # Errors
+    /// If the journal or the store can't be written, an error will be returned
+    pub async fn persist(&mut self) -> Result<()> {
+        self.settle().await?;
+        if let Some(journal) = &mut self.journal {
+            journal.sync().await?;
+        }
+        if let Some(store) = &self.tx_store {
+            store.flush()?;
+        }
+        if let Some((_, writer)) = &self.store {
+            writer.send(Flush).await??;
+        }
+        Ok(())
+    }
+
+    /// Takes the accounts started or changed since the last time, ordered by client. With a store,
+    /// their actors are stopped once the store saved them, so only the accounts in use are kept in
+    /// memory and the store loads them back when their clients are found again.
+    ///
+    /// # Errors
+    /// If transactions are staged, an account doesn't answer or the store can't be written, an
+    /// error will be returned
+    pub async fn drain(&mut self) -> Result<Vec<Account>> {
+        self.settle().await?;
+        if self.stage.is_some() {
+            bail!("The accounts can't be drained while transactions are staged");
+        }
+        let mut clients: Vec<u16> = self.changed.drain().collect();
+        clients.sort_unstable();
+        let mut accounts = Vec::with_capacity(clients.len());
+        // crashed accounts have nothing left to drain
+        clients.retain(|client| !self.crashed.contains_key(client));
+        let Some(writer) = self.store_writer() else {
+            // clients started by a rolled back stage are gone
+            for client in clients {
+                if let Some(account) = self.client_accounts.get(&client) {
+                    let state = send_with_retry(&account.addr, GetState, self.dispatch).await?;
+                    accounts.push(Account::from_state(state, self.config_for(client)));
+                }
+            }
+            return Ok(accounts);
+        };
+        writer.send(Flush).await??;
+        for client in clients {
+            if let Some(account) = self.client_accounts.remove(&client) {
+                accounts.push(account.addr.send(Collect).await?);
+            }
+            if let Some(shadow) = &mut self.shadow {
+                shadow.remove(client);
+            }
+        }
+        Ok(accounts)
+    }
+
+    /// Collects the current state of the accounts, stopping their actors. The accounts that don't
+    /// answer in time are logged and left out.
+    ///
+    /// # Errors
+    /// If the journal or the store can't be written, an error will be returned
+    pub async fn collect(self) -> Result<Vec<Account>> {
+        Ok(self.collect_all().await?.accounts)
+    }
+
+    /// Collects the current state of the accounts like `collect`, along with the accounts that
+    /// didn't answer in time, so one stuck actor doesn't hang the run
+    ///
+    /// # Errors
+    /// If the journal or the store can't be written, an error will be returned
+    pub async fn collect_all(mut self) -> Result<Collected> {
+        self.settle().await?;
+        self.notify_deferred().await?;
+        if let Some(journal) = &mut self.journal {
+            journal.flush().await?;
+        }
+        if let Some(rejects) = &mut self.rejects {
+            rejects.flush().await?;
+        }
+        if let Some(parquet) = self.parquet.take() {
+            parquet.finish()?;
+        }
+        if let Some(store) = &self.tx_store {
+            store.flush()?;
+        }
+        if let Some((_, writer)) = &self.store {
+            writer.send(Flush).await??;
+        }
+        let mut accounts = Vec::with_capacity(self.client_accounts.len());
+        let mut stragglers = Vec::new();
+        for (client, account) in self.client_accounts {
+            if self.warm.contains(&client) {
+                continue;
+            }
+            if self.crashed.contains_key(&client) {
+                stragglers.push(Straggler {
+                    client,
+                    reason: "The account crashed".into(),
+                });
+                continue;
+            }
+            match send_with_retry(&account.addr, Collect, self.collection).await {
+                Ok(account) => accounts.push(account),
+                Err(e) => {
+                    error!("Could not collect account data from client {client}: {e}");
+                    stragglers.push(Straggler {
+                        client,
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+        stragglers.sort_unstable_by_key(|straggler| straggler.client);
+        if let Some(workers) = self.workers {
+            workers.stop();
+        }
+        Ok(Collected {
+            accounts,
+            stragglers,
+            crashes: self.crashed.into_values().collect(),
+        })
+    }
+}
+
+fn log_rejection(e: &TransactionError) {
+    if e.is_not_found() {
+        warn!("{e}");
+    } else {
+        error!("{e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rust_decimal_macros::dec;
+
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::dedup::TxIdFilter;
+    use crate::engine::{Applied, Engine};
+    use crate::journal::{JournalReader, JournalWriter};
+    use crate::model::{
+        Account, AccountRecord, AccountState, Transaction, TransactionError, TransactionType,
+    };
+    use crate::store::{AccountStore, FileAccountStore};
+    use crate::webhook::{Event, Outbox};
+
+    #[actix::test]
+    async fn test_rollback_restores_accounts() {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        engine
+            .apply(Transaction::test_deposit(1, 1, dec!(10)))
+            .await
+            .unwrap();
+        engine.begin();
+        engine
+            .apply(Transaction::test_deposit(1, 2, dec!(5)))
+            .await
+            .unwrap();
+        engine
+            .apply(Transaction::test_deposit(2, 3, dec!(7)))
+            .await
+            .unwrap();
+        engine.rollback().await.unwrap();
+        engine
+            .apply(Transaction::test_deposit(1, 4, dec!(1)))
+            .await
+            .unwrap();
+
+        let accounts = engine.collect().await.unwrap();
+        let records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].client, records[0].total), (1, dec!(11)));
+    }
+
+    #[actix::test]
+    async fn test_replay_applies_the_events_between_two_points() {
+        let path = std::env::temp_dir().join(format!("engine-replay-{}", std::process::id()));
+        let mut journal = JournalWriter::open(&path).await.unwrap();
+        for tx in 1..=4 {
+            journal
+                .append(&Transaction::test_deposit(1, tx, dec!(1)))
+                .await
+                .unwrap();
+        }
+        journal.flush().await.unwrap();
+
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let mut reader = JournalReader::open(&path).await.unwrap();
+        let last_seq = engine.replay(&mut reader, 1, Some(3)).await.unwrap();
+        assert_eq!(last_seq, 3);
+        let accounts = engine.collect().await.unwrap();
+        assert_eq!(AccountRecord::from(&accounts[0]).total, dec!(2));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[actix::test]
+    async fn test_collection_reports_stragglers() {
+        let collection = DispatchConfig {
+            timeout: Duration::from_millis(250),
+            retries: 0,
+        };
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_workers(&[0, 0])
+            .with_collection(collection);
+        engine
+            .apply(Transaction::test_deposit(1, 1, dec!(5)))
+            .await
+            .unwrap();
+        engine
+            .apply(Transaction::test_deposit(2, 2, dec!(3)))
+            .await
+            .unwrap();
+        // the worker running client 2 is stuck through the collection
+        let workers = engine.workers.as_ref().unwrap();
+        let (blocked, started) = tokio::sync::oneshot::channel();
+        workers.for_client(2).spawn_fn(move || {
+            blocked.send(()).unwrap();
+            std::thread::sleep(Duration::from_secs(3));
+        });
+        started.await.unwrap();
+
+        let collected = engine.collect_all().await.unwrap();
+        assert_eq!(collected.accounts.len(), 1);
+        assert_eq!(collected.accounts[0].client(), 1);
+        assert_eq!(collected.stragglers.len(), 1);
+        assert_eq!(collected.stragglers[0].client, 2);
+        assert_eq!(collected.stragglers[0].reason, "Message delivery timed out");
+    }
+
+    #[actix::test]
+    async fn test_accounts_on_workers() {
+        let mut engine =
+            Engine::new(DispatchConfig::default(), EngineConfig::default()).with_workers(&[0, 0]);
+        for tx in 0..10u16 {
+            engine
+                .apply(Transaction::test_deposit(tx % 3, u32::from(tx), dec!(1)))
+                .await
+                .unwrap();
+        }
+        let accounts = engine.collect().await.unwrap();
+        let mut records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+        records.sort_by_key(|record| record.client);
+        let totals: Vec<_> = records.iter().map(|r| (r.client, r.total)).collect();
+        assert_eq!(totals, vec![(0, dec!(4)), (1, dec!(3)), (2, dec!(3))]);
+    }
+
+    #[actix::test]
+    async fn test_accounts_in_shards() {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_workers(&[0, 0])
+            .with_shards(2);
+        for tx in 0..10u16 {
+            engine
+                .apply(Transaction::test_deposit(tx % 5, u32::from(tx), dec!(1)))
+                .await
+                .unwrap();
+        }
+        let withdrawal = Transaction::for_test(TransactionType::Withdrawal, 3, 10, Some(dec!(5)));
+        assert!(matches!(
+            engine.submit(withdrawal).await.unwrap(),
+            Applied::Rejected(TransactionError::InsufficientFunds { .. })
+        ));
+        let state = engine.state(3).await.unwrap().unwrap();
+        assert_eq!(AccountRecord::from(&state).total, dec!(2));
+        assert_eq!(engine.placement(3), 1);
+
+        let mut accounts = engine.collect().await.unwrap();
+        accounts.sort_unstable_by_key(Account::client);
+        let totals: Vec<_> = accounts
+            .iter()
+            .map(AccountRecord::from)
+            .map(|r| (r.client, r.total))
+            .collect();
+        assert_eq!(totals, (0..5).map(|c| (c, dec!(2))).collect::<Vec<_>>());
+    }
+
+    #[actix::test]
+    async fn test_transactions_sent_ahead_end_as_the_ones_awaited() {
+        let mut transactions: Vec<_> = (1..=200)
+            .map(|tx| Transaction::test_deposit(if tx % 10 == 0 { 2 } else { 1 }, tx, dec!(1)))
+            .collect();
+        transactions.push(Transaction::for_test(
+            TransactionType::Withdrawal,
+            2,
+            201,
+            Some(dec!(50)),
+        ));
+        transactions.push(Transaction::for_test(TransactionType::Dispute, 1, 3, None));
+        let run = |engine: Engine| {
+            let transactions = transactions.clone();
+            async move {
+                let mut engine = engine;
+                for transaction in transactions {
+                    engine.apply(transaction).await.unwrap();
+                }
+                engine.settle().await.unwrap();
+                let stats = engine.stats();
+                let mut accounts = engine.collect().await.unwrap();
+                accounts.sort_unstable_by_key(Account::client);
+                let records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+                (stats.applied, stats.rejected, records)
+            }
+        };
+
+        let awaited = run(Engine::new(
+            DispatchConfig::default(),
+            EngineConfig::default(),
+        ))
+        .await;
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_workers(&[0, 0])
+            .with_mailboxes(4);
+        let sent_ahead = run(engine).await;
+        assert_eq!(sent_ahead, awaited);
+        assert_eq!((sent_ahead.0, sent_ahead.1), (201, 1));
+        assert_eq!(sent_ahead.2[0].held, dec!(1));
+    }
+
+    #[actix::test]
+    async fn test_accounts_must_be_opened() {
+        let mut engine =
+            Engine::new(DispatchConfig::default(), EngineConfig::default()).with_required_open();
+        let open = Transaction::for_test(TransactionType::Open, 1, 2, Some(dec!(10)));
+        let rejected = engine
+            .submit(Transaction::test_deposit(1, 1, dec!(5)))
+            .await
+            .unwrap();
+        assert_eq!(
+            rejected,
+            Applied::Rejected(TransactionError::AccountNotOpen { client: 1, tx: 1 })
+        );
+        assert_eq!(
+            engine.submit(open.clone()).await.unwrap(),
+            Applied::Accepted
+        );
+        let reopened = engine.submit(open).await.unwrap();
+        assert_eq!(
+            reopened,
+            Applied::Rejected(TransactionError::AccountExists { client: 1, tx: 2 })
+        );
+        engine
+            .apply(Transaction::test_deposit(1, 3, dec!(5)))
+            .await
+            .unwrap();
+        let dispute = Transaction::for_test(TransactionType::Dispute, 1, 2, None);
+        let not_found = engine.submit(dispute).await.unwrap();
+        assert_eq!(
+            not_found,
+            Applied::Rejected(TransactionError::TransactionNotFound { client: 1, tx: 2 })
+        );
+
+        let accounts = engine.collect().await.unwrap();
+        let records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].total, dec!(15));
+    }
+
+    #[actix::test]
+    async fn test_strict_tx_ids_reject_duplicates_across_clients() {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_strict_tx_ids(TxIdFilter::exact());
+        engine
+            .apply(Transaction::test_deposit(1, 1, dec!(10)))
+            .await
+            .unwrap();
+        for duplicate in [
+            Transaction::test_deposit(1, 1, dec!(4)),
+            Transaction::test_deposit(2, 1, dec!(6)),
+        ] {
+            let client = duplicate.client;
+            assert_eq!(
+                engine.submit(duplicate).await.unwrap(),
+                Applied::Rejected(TransactionError::DuplicateTransaction { client, tx: 1 })
+            );
+        }
+        // a rejected transaction doesn't take its id
+        let overdrawn = Transaction::for_test(TransactionType::Withdrawal, 1, 2, Some(dec!(50)));
+        assert!(matches!(
+            engine.submit(overdrawn).await.unwrap(),
+            Applied::Rejected(TransactionError::InsufficientFunds { .. })
+        ));
+        engine
+            .apply(Transaction::test_deposit(2, 2, dec!(6)))
+            .await
+            .unwrap();
+        let dispute = Transaction::for_test(TransactionType::Dispute, 1, 1, None);
+        assert_eq!(engine.submit(dispute).await.unwrap(), Applied::Accepted);
+
+        let mut accounts = engine.collect().await.unwrap();
+        accounts.sort_unstable_by_key(Account::client);
+        let records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+        assert_eq!(records[0].held, dec!(10));
+        assert_eq!(records[1].total, dec!(6));
+    }
+
+    #[actix::test]
+    async fn test_transfers_apply_both_sides_or_neither() {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let transfer = |client, tx, to_client, amount| {
+            Transaction::for_test(TransactionType::Transfer, client, tx, Some(amount))
+                .with_recipient(to_client)
+        };
+        let step = |transaction_type, client, tx| {
+            Transaction::for_test(transaction_type, client, tx, None)
+        };
+        engine
+            .apply(Transaction::test_deposit(1, 1, dec!(10)))
+            .await
+            .unwrap();
+        engine
+            .apply(Transaction::test_deposit(3, 2, dec!(1)))
+            .await
+            .unwrap();
+        engine
+            .apply(step(TransactionType::Dispute, 3, 2))
+            .await
+            .unwrap();
+        engine
+            .apply(step(TransactionType::Chargeback, 3, 2))
+            .await
+            .unwrap();
+        let accepted = engine.submit(transfer(1, 3, 2, dec!(4))).await.unwrap();
+        assert_eq!(accepted, Applied::Accepted);
+        let overdrawn = engine.submit(transfer(1, 4, 2, dec!(7))).await.unwrap();
+        assert!(matches!(
+            overdrawn,
+            Applied::Rejected(TransactionError::InsufficientFunds { client: 1, .. })
+        ));
+        // the locked account refuses the credit, so the debit is undone
+        let locked = engine.submit(transfer(1, 5, 3, dec!(2))).await.unwrap();
+        assert_eq!(
+            locked,
+            Applied::Rejected(TransactionError::AccountLocked { client: 3, tx: 5 })
+        );
+        let to_itself = engine.submit(transfer(1, 6, 1, dec!(2))).await.unwrap();
+        assert_eq!(
+            to_itself,
+            Applied::Rejected(TransactionError::InvalidOperation { client: 1, tx: 6 })
+        );
+        // the credit is a deposit of the credited account, which can be disputed
+        engine
+            .apply(step(TransactionType::Dispute, 2, 3))
+            .await
+            .unwrap();
+
+        let mut accounts = engine.collect().await.unwrap();
+        accounts.sort_unstable_by_key(Account::client);
+        let records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+        let balances: Vec<_> = records.iter().map(|r| (r.available, r.held)).collect();
+        assert_eq!(
+            balances,
+            [(dec!(6), dec!(0)), (dec!(0), dec!(4)), (dec!(0), dec!(0))]
+        );
+    }
+
+    #[actix::test]
+    async fn test_rejected_transfer_opens_no_account() {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        engine
+            .apply(Transaction::test_deposit(1, 1, dec!(10)))
+            .await
+            .unwrap();
+        let transfer = Transaction::for_test(TransactionType::Transfer, 1, 2, Some(dec!(20)))
+            .with_recipient(2);
+        let overdrawn = engine.submit(transfer).await.unwrap();
+        assert!(matches!(
+            overdrawn,
+            Applied::Rejected(TransactionError::InsufficientFunds { client: 1, .. })
+        ));
+        assert_eq!(engine.clients(), [1]);
+        assert!(engine.state(2).await.unwrap().is_none());
+    }
+
+    #[actix::test]
+    async fn test_shadow_follows_rollbacks() {
+        let mut engine =
+            Engine::new(DispatchConfig::default(), EngineConfig::default()).with_shadow();
+        engine
+            .apply(Transaction::test_deposit(1, 1, dec!(10)))
+            .await
+            .unwrap();
+        engine.begin();
+        engine
+            .apply(Transaction::test_deposit(1, 2, dec!(5)))
+            .await
+            .unwrap();
+        engine
+            .apply(Transaction::test_deposit(2, 3, dec!(7)))
+            .await
+            .unwrap();
+        engine.rollback().await.unwrap();
+        engine
+            .apply(Transaction::test_deposit(1, 4, dec!(1)))
+            .await
+            .unwrap();
+        engine
+            .apply(Transaction::test_deposit(2, 5, dec!(1)))
+            .await
+            .unwrap();
+        assert_eq!(engine.shadow().unwrap().divergences(), 0);
+    }
+
+    #[actix::test]
+    async fn test_chargebacks_are_notified_on_commit() {
+        let dir = std::env::temp_dir().join(format!("engine-outbox-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let outbox = Arc::new(Outbox::open(&dir).await.unwrap());
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_outbox(Arc::clone(&outbox));
+        let step = |transaction_type, tx| Transaction::for_test(transaction_type, 1, tx, None);
+        engine
+            .apply(Transaction::test_deposit(1, 1, dec!(10)))
+            .await
+            .unwrap();
+        engine
+            .apply(Transaction::test_deposit(1, 2, dec!(10)))
+            .await
+            .unwrap();
+        engine
+            .apply(step(TransactionType::Dispute, 1))
+            .await
+            .unwrap();
+        engine
+            .apply(step(TransactionType::Dispute, 2))
+            .await
+            .unwrap();
+        engine.begin();
+        engine
+            .apply(step(TransactionType::Chargeback, 1))
+            .await
+            .unwrap();
+        engine.rollback().await.unwrap();
+        assert!(outbox.pending().await.unwrap().is_empty());
+        engine.begin();
+        engine
+            .apply(step(TransactionType::Chargeback, 2))
+            .await
+            .unwrap();
+        engine.commit().await.unwrap();
+
+        let events: Vec<_> = outbox
+            .pending()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.notification.event)
+            .collect();
+        assert_eq!(events, [Event::Chargeback { client: 1, tx: 2 }]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix::test]
+    async fn test_prewarmed_accounts_are_collected_once_reached() {
+        let dir = std::env::temp_dir().join(format!("engine-prewarm-{}", std::process::id()));
+        let store = FileAccountStore::open(dir.clone()).unwrap();
+        let saved = std::time::SystemTime::now();
+        for client in 1..=3 {
+            let mut account = Account::new(client, EngineConfig::default());
+            account.deposit(dec!(10).into(), u32::from(client)).unwrap();
+            store.save(&[AccountState::from(&account)]).unwrap();
+            // the later the client, the more recently its account was saved
+            std::fs::File::options()
+                .write(true)
+                .open(dir.join(format!("{client}.json")))
+                .unwrap()
+                .set_modified(saved + Duration::from_secs(u64::from(client)))
+                .unwrap();
+        }
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_store(Arc::new(store), Duration::from_mins(1));
+        assert_eq!(engine.prewarm(2).unwrap(), 2);
+        assert!(engine.client_accounts.contains_key(&3));
+        assert!(!engine.client_accounts.contains_key(&1));
+        engine
+            .apply(Transaction::test_deposit(2, 4, dec!(5)))
+            .await
+            .unwrap();
+        engine
+            .apply(Transaction::test_deposit(1, 5, dec!(1)))
+            .await
+            .unwrap();
+
+        let collected = engine.collect_all().await.unwrap();
+        let mut records: Vec<_> = collected
+            .accounts
+            .iter()
+            .map(|account| {
+                let record = AccountRecord::from(account);
+                (record.client, record.total)
+            })
+            .collect();
+        records.sort_unstable();
+        assert_eq!(records, [(1, dec!(11)), (2, dec!(15))]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio::io::stdout;
+use tracing::info;
+
+use crate::cli::GroupsArgs;
+use crate::csv::write_records;
+use crate::journal::JournalReader;
+use crate::model::{Transaction, TransactionType};
+
+/// A row of the group report: the funds moved by the transactions sharing a reference and the
+/// dispute steps that reached them
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct GroupRow {
+    client: u16,
+    reference: u32,
+    /// The deposits and withdrawals of the group
+    transactions: u64,
+    deposited: Decimal,
+    withdrawn: Decimal,
+    disputes: u64,
+    resolves: u64,
+    chargebacks: u64,
+}
+
+/// The groups of the journal, by client and reference
+#[derive(Default)]
+struct Groups {
+    rows: BTreeMap<(u16, u32), GroupRow>,
+    /// The reference of every grouped deposit and withdrawal, by client and id
+    members: HashMap<(u16, u32), u32>,
+}
+
+impl Groups {
+    fn add(&mut self, transaction: &Transaction) {
+        let client = transaction.client;
+        let reference = match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                let Some(reference) = transaction.reference else {
+                    return;
+                };
+                self.members.insert((client, transaction.tx), reference);
+                reference
+            }
+            // steps with a reference apply to the whole group, the others to a single member
+            kind if kind.is_dispute_step() => {
+                let member = self.members.get(&(client, transaction.tx));
+                match transaction.reference.or(member.copied()) {
+                    Some(reference) => reference,
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+        let row = self
+            .rows
+            .entry((client, reference))
+            .or_insert_with(|| GroupRow {
+                client,
+                reference,
+                ..GroupRow::default()
+            });
+        let amount = transaction.amount.unwrap_or_default().amount();
+        match transaction.transaction_type {
+            TransactionType::Deposit => {
+                row.transactions += 1;
+                row.deposited += amount;
+            }
+            TransactionType::Withdrawal => {
+                row.transactions += 1;
+                row.withdrawn += amount;
+            }
+            TransactionType::Dispute => row.disputes += 1,
+            TransactionType::Resolve => row.resolves += 1,
+            _ => row.chargebacks += 1,
+        }
+    }
+}
+
+/// Writes a row for every group of related transactions of the journal to the std out, in the
+/// order of their client and reference
+///
+/// # Errors
+/// If the journal can't be read or the output can't be written, an error will be returned
+pub async fn export_groups(args: &GroupsArgs) -> Result<()> {
+    let mut journal = JournalReader::open(&args.journal).await?;
+    let mut groups = Groups::default();
+    while let Some(event) = journal.next_event().await? {
+        if args.client.is_empty() || args.client.contains(&event.transaction.client) {
+            groups.add(&event.transaction);
+        }
+    }
+    info!("Found {} groups", groups.rows.len());
+    write_records(stdout(), groups.rows.values()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::groups::Groups;
+    use crate::model::{Transaction, TransactionType};
+
+    #[test]
+    fn test_groups_sum_their_members() {
+        let mut groups = Groups::default();
+        let transactions = [
+            Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(10))).with_reference(7),
+            Transaction::for_test(TransactionType::Deposit, 1, 2, Some(dec!(5))).with_reference(7),
+            Transaction::for_test(TransactionType::Withdrawal, 1, 3, Some(dec!(4)))
+                .with_reference(7),
+            Transaction::for_test(TransactionType::Deposit, 1, 4, Some(dec!(1))),
+            Transaction::for_test(TransactionType::Dispute, 1, 1, None),
+            Transaction::for_test(TransactionType::Dispute, 1, 4, None),
+            Transaction::for_test(TransactionType::Chargeback, 1, 5, None).with_reference(7),
+        ];
+        for transaction in &transactions {
+            groups.add(transaction);
+        }
+        assert_eq!(groups.rows.len(), 1);
+        let row = &groups.rows[&(1, 7)];
+        assert_eq!(row.transactions, 3);
+        assert_eq!(row.deposited, dec!(15));
+        assert_eq!(row.withdrawn, dec!(4));
+        assert_eq!((row.disputes, row.resolves, row.chargebacks), (1, 0, 1));
+    }
+}
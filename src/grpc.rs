@@ -0,0 +1,346 @@
+#[cfg(feature = "grpc")]
+use std::net::SocketAddr;
+
+#[cfg(feature = "grpc")]
+use anyhow::Context;
+use anyhow::Result;
+#[cfg(feature = "grpc")]
+use tokio::io::{stdout, AsyncWriteExt};
+#[cfg(feature = "grpc")]
+use tokio::sync::{mpsc, oneshot};
+#[cfg(feature = "grpc")]
+use tokio_stream::wrappers::ReceiverStream;
+#[cfg(feature = "grpc")]
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+#[cfg(feature = "grpc")]
+use tracing::{error, info};
+
+#[cfg(feature = "grpc")]
+use crate::capture::CaptureWriter;
+use crate::cli::ServeArgs;
+#[cfg(feature = "grpc")]
+use crate::csv::write_records;
+#[cfg(feature = "grpc")]
+use crate::engine::{Applied, Engine};
+#[cfg(feature = "grpc")]
+use crate::journal::JournalWriter;
+#[cfg(feature = "grpc")]
+use crate::model::{AccountRecord, AdminAction, Transaction, TransactionType};
+#[cfg(feature = "grpc")]
+use crate::money::Money;
+#[cfg(feature = "grpc")]
+use crate::snapshot::Snapshot;
+
+#[cfg(feature = "grpc")]
+pub mod proto {
+    #![allow(clippy::pedantic)]
+    tonic::include_proto!("transactions");
+}
+
+#[cfg(feature = "grpc")]
+use proto::ingestion_server::{Ingestion, IngestionServer};
+
+/// How many requests of the clients wait for the engine
+#[cfg(feature = "grpc")]
+const REQUEST_BUFFER: usize = 256;
+/// How many outcomes of a stream wait for the client to read them before the stream pauses
+#[cfg(feature = "grpc")]
+const OUTCOME_BUFFER: usize = 64;
+
+/// What the service asks of the engine, which answers through the sender
+#[cfg(feature = "grpc")]
+enum EngineRequest {
+    Submit(Transaction, oneshot::Sender<Result<Applied>>),
+    GetAccount(u16, oneshot::Sender<Result<Option<AccountRecord>>>),
+}
+
+/// Serves the ingestion service over grpc until the process is stopped, applying the streamed
+/// transactions to the account actors, then prints the accounts
+///
+/// # Errors
+/// If the journal or the snapshot can't be opened or the server can't listen, an error will be
+/// returned
+#[cfg(feature = "grpc")]
+pub async fn serve_grpc(args: &ServeArgs) -> Result<()> {
+    let address: SocketAddr = args
+        .listen
+        .parse()
+        .with_context(|| format!("Invalid address {}", args.listen))?;
+    let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine());
+    if let Some(path) = &args.journal {
+        engine = engine.with_journal(JournalWriter::open(path).await?);
+    }
+    if let Some(path) = &args.capture {
+        engine = engine.with_capture(CaptureWriter::create(path).await?);
+    }
+    if let Some(path) = &args.restore {
+        engine.restore(Snapshot::read(path).await?)?;
+    }
+    let (requests, received) = mpsc::channel(REQUEST_BUFFER);
+    let service = IngestionServer::new(IngestionService { requests });
+    info!("Serving the ingestion service on {address}");
+    // the engine stops once the server and its streams let go of the service
+    let server = Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(address, async {
+            let _ = tokio::signal::ctrl_c().await;
+        });
+    let (result, engine) = tokio::join!(server, run_engine(engine, received));
+    result?;
+    let accounts = engine.collect().await?;
+    let mut output = Vec::new();
+    write_records(&mut output, &accounts).await?;
+    let mut out = stdout();
+    out.write_all(&output).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+/// Fails, as the crate was built without the `grpc` feature
+#[cfg(not(feature = "grpc"))]
+#[allow(clippy::unused_async)]
+pub async fn serve_grpc(args: &ServeArgs) -> Result<()> {
+    anyhow::bail!(
+        "Can't serve the ingestion service on {}: built without the `grpc` feature",
+        args.listen
+    )
+}
+
+/// Answers the requests of the service one at a time, until every sender is dropped
+#[cfg(feature = "grpc")]
+async fn run_engine(mut engine: Engine, mut requests: mpsc::Receiver<EngineRequest>) -> Engine {
+    while let Some(request) = requests.recv().await {
+        match request {
+            EngineRequest::Submit(transaction, reply) => {
+                let _ = reply.send(engine.submit(transaction).await);
+            }
+            EngineRequest::GetAccount(client, reply) => {
+                let rounding = engine.rounding_of(client);
+                let state = engine.state(client).await;
+                let record = |state: &_| AccountRecord::from_state(state, rounding);
+                let _ = reply.send(state.map(|state| state.as_ref().map(record)));
+            }
+        }
+    }
+    engine
+}
+
+/// Hands the requests over to the engine, which runs on the actors' thread
+#[cfg(feature = "grpc")]
+struct IngestionService {
+    requests: mpsc::Sender<EngineRequest>,
+}
+
+#[cfg(feature = "grpc")]
+impl IngestionService {
+    async fn ask<T>(
+        requests: &mpsc::Sender<EngineRequest>,
+        request: impl FnOnce(oneshot::Sender<Result<T>>) -> EngineRequest,
+    ) -> Result<T, Status> {
+        let (reply, answer) = oneshot::channel();
+        let stopped = || Status::unavailable("The engine is stopping");
+        requests.send(request(reply)).await.map_err(|_| stopped())?;
+        answer.await.map_err(|_| stopped())?.map_err(|e| {
+            error!("The engine failed: {e}");
+            Status::internal(e.to_string())
+        })
+    }
+}
+
+#[cfg(feature = "grpc")]
+#[tonic::async_trait]
+impl Ingestion for IngestionService {
+    type SubmitStream = ReceiverStream<Result<proto::Outcome, Status>>;
+
+    async fn submit(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<Self::SubmitStream>, Status> {
+        let mut transactions = request.into_inner();
+        let requests = self.requests.clone();
+        let (outcomes, stream) = mpsc::channel(OUTCOME_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let outcome = match transactions.message().await {
+                    Ok(Some(message)) => submit(&requests, message).await,
+                    Ok(None) => break,
+                    Err(status) => Err(status),
+                };
+                let failed = outcome.is_err();
+                // the client stopped reading, or the stream is cut short on the error
+                if outcomes.send(outcome).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client = client_id(request.into_inner().client).map_err(Status::invalid_argument)?;
+        let record = IngestionService::ask(&self.requests, |reply| {
+            EngineRequest::GetAccount(client, reply)
+        })
+        .await?
+        .ok_or_else(|| Status::not_found(format!("Client {client} has no account")))?;
+        Ok(Response::new(proto::Account {
+            client: record.client.into(),
+            available: record.available.to_string(),
+            held: record.held.to_string(),
+            total: record.total.to_string(),
+            locked: record.locked,
+        }))
+    }
+}
+
+/// Applies a transaction of the stream, failing the malformed ones with an invalid argument
+#[cfg(feature = "grpc")]
+async fn submit(
+    requests: &mpsc::Sender<EngineRequest>,
+    message: proto::Transaction,
+) -> Result<proto::Outcome, Status> {
+    let transaction = transaction(message).map_err(Status::invalid_argument)?;
+    let tx = transaction.tx;
+    let applied =
+        IngestionService::ask(requests, |reply| EngineRequest::Submit(transaction, reply)).await?;
+    let (status, reason) = match applied {
+        Applied::Accepted => (proto::Status::Accepted, String::new()),
+        Applied::Rejected(e) => (proto::Status::Rejected, e.to_string()),
+        Applied::Skipped => (proto::Status::Skipped, String::new()),
+        Applied::Undelivered => (
+            proto::Status::Undelivered,
+            "Could not deliver the transaction to its account".into(),
+        ),
+    };
+    Ok(proto::Outcome {
+        tx,
+        status: status.into(),
+        reason,
+    })
+}
+
+#[cfg(feature = "grpc")]
+/// The transaction of a message, or why it is malformed
+pub fn transaction(message: proto::Transaction) -> Result<Transaction, String> {
+    let transaction_type = match message.r#type() {
+        proto::TransactionType::Deposit => TransactionType::Deposit,
+        proto::TransactionType::Withdrawal => TransactionType::Withdrawal,
+        proto::TransactionType::Dispute => TransactionType::Dispute,
+        proto::TransactionType::Resolve => TransactionType::Resolve,
+        proto::TransactionType::Chargeback => TransactionType::Chargeback,
+        proto::TransactionType::Open => TransactionType::Open,
+        proto::TransactionType::Transfer => TransactionType::Transfer,
+        proto::TransactionType::Admin => TransactionType::Admin,
+        proto::TransactionType::Unspecified => {
+            return Err(format!("Transaction {} has no type", message.tx))
+        }
+    };
+    let action = match message.action.map(|_| message.action()) {
+        Some(proto::AdminAction::Unlock) => Some(AdminAction::Unlock),
+        Some(proto::AdminAction::Freeze) => Some(AdminAction::Freeze),
+        Some(proto::AdminAction::Close) => Some(AdminAction::Close),
+        Some(proto::AdminAction::Unspecified) | None => None,
+    };
+    let amount = message
+        .amount
+        .map(|amount| amount.parse::<Money>())
+        .transpose()
+        .map_err(|e| format!("Invalid amount of transaction {}: {e}", message.tx))?;
+    Ok(Transaction {
+        transaction_type,
+        client: client_id(message.client)?,
+        tx: message.tx,
+        amount,
+        reference: message.reference,
+        to_client: message.to_client.map(client_id).transpose()?,
+        action,
+    })
+}
+
+#[cfg(feature = "grpc")]
+fn client_id(client: u32) -> Result<u16, String> {
+    u16::try_from(client).map_err(|_| format!("Client {client} is out of range"))
+}
+
+#[cfg(all(test, feature = "grpc"))]
+/// The message of a transaction, the inverse of `transaction`
+pub fn message(transaction: &Transaction) -> proto::Transaction {
+    let transaction_type = match transaction.transaction_type {
+        TransactionType::Deposit => proto::TransactionType::Deposit,
+        TransactionType::Withdrawal => proto::TransactionType::Withdrawal,
+        TransactionType::Dispute => proto::TransactionType::Dispute,
+        TransactionType::Resolve => proto::TransactionType::Resolve,
+        TransactionType::Chargeback => proto::TransactionType::Chargeback,
+        TransactionType::Open => proto::TransactionType::Open,
+        TransactionType::Transfer => proto::TransactionType::Transfer,
+        TransactionType::Admin => proto::TransactionType::Admin,
+        TransactionType::Savepoint => proto::TransactionType::Unspecified,
+    };
+    let action = transaction.action.map(|action| match action {
+        AdminAction::Unlock => proto::AdminAction::Unlock,
+        AdminAction::Freeze => proto::AdminAction::Freeze,
+        AdminAction::Close => proto::AdminAction::Close,
+    });
+    proto::Transaction {
+        r#type: transaction_type.into(),
+        client: transaction.client.into(),
+        tx: transaction.tx,
+        amount: transaction.amount.map(|amount| amount.to_string()),
+        reference: transaction.reference,
+        to_client: transaction.to_client.map(Into::into),
+        action: action.map(Into::into),
+    }
+}
+
+#[cfg(all(test, feature = "grpc"))]
+mod tests {
+    use rust_decimal_macros::dec;
+    use tokio::sync::mpsc;
+    use tonic::{Code, Request};
+
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::engine::Engine;
+    use crate::grpc::proto::{self, ingestion_server::Ingestion};
+    use crate::grpc::{message, run_engine, submit, IngestionService};
+    use crate::model::{Transaction, TransactionType};
+
+    #[actix::test]
+    async fn test_streamed_transactions_reach_the_accounts() {
+        let engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let (requests, received) = mpsc::channel(8);
+        let engine = actix::spawn(run_engine(engine, received));
+        let service = IngestionService { requests };
+
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(5.5)));
+        let deposit = message(&deposit);
+        let outcome = submit(&service.requests, deposit).await.unwrap();
+        assert_eq!(outcome.status(), proto::Status::Accepted);
+        let withdrawal = Transaction::for_test(TransactionType::Withdrawal, 1, 2, Some(dec!(10)));
+        let withdrawal = message(&withdrawal);
+        let outcome = submit(&service.requests, withdrawal).await.unwrap();
+        assert_eq!(outcome.status(), proto::Status::Rejected);
+        assert!(outcome.reason.contains("insufficient funds"));
+        let untyped = Transaction::for_test(TransactionType::Deposit, 1, 3, Some(dec!(1)));
+        let untyped = proto::Transaction {
+            r#type: proto::TransactionType::Unspecified.into(),
+            ..message(&untyped)
+        };
+        let status = submit(&service.requests, untyped).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let request = Request::new(proto::GetAccountRequest { client: 1 });
+        let account = service.get_account(request).await.unwrap().into_inner();
+        assert_eq!(account.available, "5.5");
+        assert!(!account.locked);
+        let request = Request::new(proto::GetAccountRequest { client: 2 });
+        let status = service.get_account(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        drop(service);
+        let accounts = engine.await.unwrap().collect().await.unwrap();
+        assert_eq!(accounts.len(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::model::MoneyTransaction;
+
+#[cfg(feature = "sled")]
+mod sled;
+
+/// Keeps the deposits and withdrawals of the accounts, looked up by their disputes, outside the
+/// memory of the accounts. Every account of the engine shares the same store.
+pub trait TxStore: Send + Sync {
+    /// The deposit or withdrawal of a client with the id, if any
+    ///
+    /// # Errors
+    /// If the store can't be read, an error will be returned
+    fn get(&self, client: u16, tx: u32) -> Result<Option<MoneyTransaction>>;
+
+    /// Records a deposit or withdrawal of a client, replacing any with the same id
+    ///
+    /// # Errors
+    /// If the store can't be written, an error will be returned
+    fn insert(&self, client: u16, tx: u32, transaction: MoneyTransaction) -> Result<()>;
+
+    /// Forgets a deposit or withdrawal of a client, like the ones of a rolled back segment
+    ///
+    /// # Errors
+    /// If the store can't be written, an error will be returned
+    fn remove(&self, client: u16, tx: u32) -> Result<()>;
+
+    /// Writes the recorded transactions to disk
+    ///
+    /// # Errors
+    /// If the store can't be written, an error will be returned
+    fn flush(&self) -> Result<()>;
+}
+
+/// Opens the on-disk transaction history kept in `dir`, creating it if needed
+///
+/// # Errors
+/// If the history can't be opened or the binary was built without the `sled` feature, an error
+/// will be returned
+pub fn open_tx_store(dir: &Path) -> Result<Arc<dyn TxStore>> {
+    #[cfg(feature = "sled")]
+    return Ok(Arc::new(sled::SledTxStore::open(dir)?));
+    #[cfg(not(feature = "sled"))]
+    anyhow::bail!(
+        "Can't keep the transaction history in {}: built without the `sled` feature",
+        dir.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    use crate::config::EngineConfig;
+    use crate::history::TxStore;
+    use crate::model::{Account, AccountState, MoneyTransaction};
+
+    #[derive(Default)]
+    struct MapTxStore(Mutex<HashMap<(u16, u32), MoneyTransaction>>);
+
+    impl TxStore for MapTxStore {
+        fn get(&self, client: u16, tx: u32) -> Result<Option<MoneyTransaction>> {
+            Ok(self.0.lock().unwrap().get(&(client, tx)).copied())
+        }
+
+        fn insert(&self, client: u16, tx: u32, transaction: MoneyTransaction) -> Result<()> {
+            self.0.lock().unwrap().insert((client, tx), transaction);
+            Ok(())
+        }
+
+        fn remove(&self, client: u16, tx: u32) -> Result<()> {
+            self.0.lock().unwrap().remove(&(client, tx));
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_disputes_look_up_the_stored_history() {
+        let store = Arc::new(MapTxStore::default());
+        let mut memory = Account::new(1, EngineConfig::default());
+        memory.deposit(dec!(5).into(), 1).unwrap();
+        let mut account = memory.with_tx_store(store.clone()).unwrap();
+        account.deposit(dec!(3).into(), 2).unwrap();
+        assert_eq!(store.0.lock().unwrap().len(), 2);
+
+        account.dispute(1).unwrap();
+        account.dispute(2).unwrap();
+        assert_eq!(account.held().amount(), dec!(8));
+        assert_eq!(account.disputed_total().amount(), dec!(8));
+        // the history stays in the store, out of the state of the account
+        let state = AccountState::from(&account);
+        let mut restored = Account::from_state(state, EngineConfig::default())
+            .with_tx_store(store)
+            .unwrap();
+        restored.resolve(2).unwrap();
+        assert_eq!(restored.held().amount(), dec!(5));
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::history::TxStore;
+use crate::model::MoneyTransaction;
+
+/// Keeps the transactions in a sled database, keyed by client and id
+pub struct SledTxStore {
+    db: sled::Db,
+}
+
+impl SledTxStore {
+    /// Opens the database in `dir`, creating it if needed
+    ///
+    /// # Errors
+    /// If the database can't be opened, an error will be returned
+    pub fn open(dir: &Path) -> Result<Self> {
+        Ok(Self {
+            db: sled::open(dir)?,
+        })
+    }
+}
+
+/// The key of a transaction, the client and the id in big endian, so the transactions of a client
+/// are stored together
+fn key(client: u16, tx: u32) -> [u8; 6] {
+    let mut key = [0; 6];
+    key[..2].copy_from_slice(&client.to_be_bytes());
+    key[2..].copy_from_slice(&tx.to_be_bytes());
+    key
+}
+
+impl TxStore for SledTxStore {
+    fn get(&self, client: u16, tx: u32) -> Result<Option<MoneyTransaction>> {
+        match self.db.get(key(client, tx))? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&self, client: u16, tx: u32, transaction: MoneyTransaction) -> Result<()> {
+        self.db
+            .insert(key(client, tx), serde_json::to_vec(&transaction)?)?;
+        Ok(())
+    }
+
+    fn remove(&self, client: u16, tx: u32) -> Result<()> {
+        self.db.remove(key(client, tx))?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::history::sled::SledTxStore;
+    use crate::history::TxStore;
+    use crate::model::MoneyTransaction;
+
+    #[test]
+    fn test_sled_keeps_transactions_by_client() {
+        let dir = std::env::temp_dir().join(format!("sled-history-{}", std::process::id()));
+        let store = SledTxStore::open(&dir).unwrap();
+        let deposit = MoneyTransaction::Deposit(dec!(2.5).into());
+        store.insert(1, 7, deposit).unwrap();
+        store.flush().unwrap();
+        assert_eq!(store.get(1, 7).unwrap(), Some(deposit));
+        assert_eq!(store.get(2, 7).unwrap(), None);
+        store.remove(1, 7).unwrap();
+        assert_eq!(store.get(1, 7).unwrap(), None);
+        drop(store);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+use crate::model::Transaction;
+
+/// The points of the processing of a transaction where a hook is called
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HookPoint {
+    /// Once the transaction is read from the input, before the engine routes it
+    PostParse,
+    /// In the account actor, before the transaction is applied
+    PreApply,
+    /// In the account actor, after the transaction is applied and before the engine is answered
+    PostApply,
+}
+
+/// What the processing does at a hook point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookAction {
+    Continue,
+    /// Waits before going on. The account actors block their thread, like a slow account would.
+    Delay(Duration),
+    /// Fails the transaction. The engine stops with an error after parsing, while the actors
+    /// drop their answer, as if they stopped before replying, so the engine sends it again.
+    Fail,
+    /// Panics, like a bug crashing the engine or the actor of the account
+    Panic,
+}
+
+/// Lets tests inject delays and failures in the processing of the transactions, to check how the
+/// timeouts, retries and backpressure behave. Hooks are called from the engine and the account
+/// actors, possibly on several threads.
+pub trait ProcessingHook: Send + Sync {
+    fn on(&self, point: HookPoint, transaction: &Transaction) -> HookAction;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rust_decimal_macros::dec;
+
+    use crate::budget::BudgetConfig;
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::engine::{Applied, Engine};
+    use crate::hooks::{HookAction, HookPoint, ProcessingHook};
+    use crate::model::{Account, AccountRecord, Transaction, TransactionType};
+    use crate::webhook::{Event, Outbox};
+
+    /// Takes the action given at one point for the first `times` transactions
+    struct Inject {
+        point: HookPoint,
+        action: HookAction,
+        times: u32,
+        calls: AtomicU32,
+    }
+
+    impl Inject {
+        fn new(point: HookPoint, action: HookAction, times: u32) -> Arc<Self> {
+            Arc::new(Self {
+                point,
+                action,
+                times,
+                calls: AtomicU32::new(0),
+            })
+        }
+    }
+
+    impl ProcessingHook for Inject {
+        fn on(&self, point: HookPoint, _: &Transaction) -> HookAction {
+            if point != self.point || self.calls.fetch_add(1, Ordering::SeqCst) >= self.times {
+                return HookAction::Continue;
+            }
+            self.action
+        }
+    }
+
+    fn engine(retries: u32, hook: Arc<Inject>) -> Engine {
+        let dispatch = DispatchConfig {
+            timeout: Duration::from_millis(100),
+            retries,
+        };
+        // the accounts run on a worker, so their delays don't block the engine
+        Engine::new(dispatch, EngineConfig::default())
+            .with_workers(&[0])
+            .with_hook(hook)
+    }
+
+    async fn total(engine: Engine) -> rust_decimal::Decimal {
+        let accounts = engine.collect().await.unwrap();
+        AccountRecord::from(&accounts[0]).total
+    }
+
+    #[actix::test]
+    async fn test_slow_account_times_out() {
+        let hook = Inject::new(
+            HookPoint::PreApply,
+            HookAction::Delay(Duration::from_millis(300)),
+            1,
+        );
+        let mut engine = engine(0, hook);
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1)));
+        assert_eq!(engine.submit(deposit).await.unwrap(), Applied::Undelivered);
+        assert_eq!(engine.stats().undelivered, 1);
+        // the account got to it once the engine gave up, so it isn't applied behind its back
+        assert_eq!(total(engine).await, dec!(0));
+    }
+
+    #[actix::test]
+    async fn test_late_answer_of_an_applied_transaction_is_kept() {
+        let hook = Inject::new(
+            HookPoint::PostApply,
+            HookAction::Delay(Duration::from_millis(300)),
+            1,
+        );
+        let mut engine = engine(0, hook);
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1)));
+        assert_eq!(engine.submit(deposit).await.unwrap(), Applied::Accepted);
+        assert_eq!(engine.stats().undelivered, 0);
+        assert_eq!(total(engine).await, dec!(1));
+    }
+
+    #[actix::test]
+    async fn test_answer_lost_after_applying_is_not_applied_again() {
+        let hook = Inject::new(HookPoint::PostApply, HookAction::Fail, 2);
+        let mut engine = engine(2, Arc::clone(&hook));
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1)));
+        assert_eq!(engine.submit(deposit).await.unwrap(), Applied::Accepted);
+        assert_eq!(hook.calls.load(Ordering::SeqCst), 3);
+        assert_eq!(total(engine).await, dec!(1));
+    }
+
+    #[actix::test]
+    async fn test_retry_outlasts_a_slow_account() {
+        let hook = Inject::new(
+            HookPoint::PreApply,
+            HookAction::Delay(Duration::from_millis(150)),
+            1,
+        );
+        let mut engine = engine(3, hook);
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1)));
+        assert_eq!(engine.submit(deposit).await.unwrap(), Applied::Accepted);
+        assert_eq!(total(engine).await, dec!(1));
+    }
+
+    #[actix::test]
+    async fn test_lost_answer_is_sent_again() {
+        let hook = Inject::new(HookPoint::PreApply, HookAction::Fail, 2);
+        let mut engine = engine(2, Arc::clone(&hook));
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1)));
+        assert_eq!(engine.submit(deposit).await.unwrap(), Applied::Accepted);
+        assert_eq!(hook.calls.load(Ordering::SeqCst), 3);
+        assert_eq!(total(engine).await, dec!(1));
+    }
+
+    #[actix::test]
+    async fn test_parse_failure_stops_the_engine() {
+        let hook = Inject::new(HookPoint::PostParse, HookAction::Fail, 1);
+        let mut engine = engine(0, hook);
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1)));
+        let err = engine.submit(deposit).await.unwrap_err();
+        assert_eq!(err.to_string(), "Transaction 1 failed after parsing");
+    }
+
+    #[actix::test]
+    async fn test_notifications_wait_for_the_engine_to_recover() {
+        let dir = std::env::temp_dir().join(format!("budget-outbox-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let outbox = Arc::new(Outbox::open(&dir).await.unwrap());
+        let hook = Inject::new(
+            HookPoint::PreApply,
+            HookAction::Delay(Duration::from_millis(50)),
+            2,
+        );
+        let mut engine = engine(0, hook)
+            .with_outbox(Arc::clone(&outbox))
+            .with_latency_budget(BudgetConfig {
+                budget: Duration::from_millis(20),
+                recovery: 3,
+            });
+        let deposit = |tx| Transaction::for_test(TransactionType::Deposit, 1, tx, Some(dec!(1)));
+        let step = |transaction_type| Transaction::for_test(transaction_type, 1, 2, None);
+        engine.apply(deposit(1)).await.unwrap();
+        engine.apply(deposit(2)).await.unwrap();
+        engine.apply(step(TransactionType::Dispute)).await.unwrap();
+        engine
+            .apply(step(TransactionType::Chargeback))
+            .await
+            .unwrap();
+        assert!(outbox.pending().await.unwrap().is_empty());
+        engine.apply(deposit(3)).await.unwrap();
+        let events: Vec<_> = outbox
+            .pending()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.notification.event)
+            .collect();
+        assert_eq!(events, [Event::Chargeback { client: 1, tx: 2 }]);
+        let stats = engine.degraded_stats().unwrap();
+        assert_eq!((stats.episodes, stats.deferred_notifications), (1, 1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix::test]
+    async fn test_crashed_account_leaves_the_other_accounts() {
+        let hook = Inject::new(HookPoint::PreApply, HookAction::Panic, 1);
+        // clients 1 and 3 run on the second worker, client 2 on the first
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_workers(&[0, 0])
+            .with_hook(hook);
+        let deposit =
+            |client, tx| Transaction::for_test(TransactionType::Deposit, client, tx, Some(dec!(1)));
+        let outcomes = [
+            (deposit(1, 1), Applied::Undelivered),
+            (deposit(2, 2), Applied::Accepted),
+            (deposit(1, 3), Applied::Undelivered),
+            (deposit(3, 4), Applied::Accepted),
+        ];
+        for (transaction, outcome) in outcomes {
+            assert_eq!(engine.submit(transaction).await.unwrap(), outcome);
+        }
+
+        let collected = engine.collect_all().await.unwrap();
+        let mut clients: Vec<_> = collected.accounts.iter().map(Account::client).collect();
+        clients.sort_unstable();
+        assert_eq!(clients, [2, 3]);
+        let crashed: Vec<_> = collected
+            .crashes
+            .iter()
+            .map(|crash| (crash.client, crash.worker, crash.tx, crash.undelivered))
+            .collect();
+        assert_eq!(crashed, [(1, Some(1), 1, 2)]);
+        assert_eq!(collected.stragglers.len(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use crate::config::{IdConfig, IdScheme};
+
+/// How many UUIDs are drawn before giving up on finding an unused id
+const RANDOM_ATTEMPTS: usize = 64;
+/// The bits of a snowflake id taken by the sequence, below the node id
+const SEQUENCE_BITS: u32 = 24;
+
+/// Chooses the ids of the transactions generated by the tool, so they don't collide with the ids
+/// of the input
+pub trait IdGenerator {
+    /// An id that isn't in `used`, which is added to it
+    ///
+    /// # Errors
+    /// If the generator has no unused id left, an error will be returned
+    fn next_id(&mut self, used: &mut HashSet<u32>) -> Result<u32> {
+        let id = self.unused_id(used)?;
+        used.insert(id);
+        Ok(id)
+    }
+
+    /// An id that isn't in `used`
+    ///
+    /// # Errors
+    /// If the generator has no unused id left, an error will be returned
+    fn unused_id(&mut self, used: &HashSet<u32>) -> Result<u32>;
+}
+
+/// The generator of a scheme
+pub fn generator(config: IdConfig) -> Box<dyn IdGenerator> {
+    match config.scheme {
+        IdScheme::Descending => Box::new(Descending),
+        IdScheme::Sequential => Box::new(Sequential(Some(config.start))),
+        IdScheme::Snowflake => Box::new(Snowflake(config.node)),
+        IdScheme::Uuid => Box::new(UuidBacked),
+    }
+}
+
+/// The highest unused id
+struct Descending;
+
+impl IdGenerator for Descending {
+    fn unused_id(&mut self, used: &HashSet<u32>) -> Result<u32> {
+        (0..=u32::MAX)
+            .rev()
+            .find(|id| !used.contains(id))
+            .ok_or_else(|| anyhow!("Every transaction id is used"))
+    }
+}
+
+/// The next unused id from a cursor, which is `None` past `u32::MAX`
+struct Sequential(Option<u32>);
+
+impl IdGenerator for Sequential {
+    fn unused_id(&mut self, used: &HashSet<u32>) -> Result<u32> {
+        while let Some(id) = self.0 {
+            self.0 = id.checked_add(1);
+            if !used.contains(&id) {
+                return Ok(id);
+            }
+        }
+        Err(anyhow!("The sequential ids are exhausted"))
+    }
+}
+
+/// The id following the highest one used by the node
+struct Snowflake(u8);
+
+impl IdGenerator for Snowflake {
+    fn unused_id(&mut self, used: &HashSet<u32>) -> Result<u32> {
+        let node = u32::from(self.0) << SEQUENCE_BITS;
+        let last = node | ((1 << SEQUENCE_BITS) - 1);
+        let next = used
+            .iter()
+            .filter(|id| (node..=last).contains(*id))
+            .max()
+            .map_or(Some(node), |id| id.checked_add(1));
+        next.filter(|id| *id <= last)
+            .ok_or_else(|| anyhow!("The ids of node {} are exhausted", self.0))
+    }
+}
+
+/// An unused id drawn from a random UUID, its four 32 bit words folded into one
+struct UuidBacked;
+
+impl IdGenerator for UuidBacked {
+    fn unused_id(&mut self, used: &HashSet<u32>) -> Result<u32> {
+        for _ in 0..RANDOM_ATTEMPTS {
+            let id = Uuid::new_v4()
+                .as_bytes()
+                .chunks_exact(4)
+                .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+                .fold(0, |id, word| id ^ word);
+            if !used.contains(&id) {
+                return Ok(id);
+            }
+        }
+        Err(anyhow!("Could not draw an unused id"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::config::{IdConfig, IdScheme};
+    use crate::ids::generator;
+
+    fn next_ids(scheme: IdScheme, used: &[u32]) -> Vec<u32> {
+        let mut used: HashSet<u32> = used.iter().copied().collect();
+        let mut ids = generator(IdConfig {
+            scheme,
+            start: 10,
+            node: 2,
+        });
+        (0..2).map(|_| ids.next_id(&mut used).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_generators_skip_used_ids() {
+        let used = [10, 11, u32::MAX, 2 << 24, (2 << 24) + 5];
+        assert_eq!(
+            next_ids(IdScheme::Descending, &used),
+            vec![u32::MAX - 1, u32::MAX - 2]
+        );
+        assert_eq!(next_ids(IdScheme::Sequential, &used), vec![12, 13]);
+        assert_eq!(
+            next_ids(IdScheme::Snowflake, &used),
+            vec![(2 << 24) + 6, (2 << 24) + 7]
+        );
+        let uuid = next_ids(IdScheme::Uuid, &used);
+        assert!(uuid[0] != uuid[1] && uuid.iter().all(|id| !used.contains(id)));
+
+        let mut exhausted = HashSet::from([(3 << 24) - 1]);
+        let mut snowflake = generator(IdConfig {
+            scheme: IdScheme::Snowflake,
+            node: 2,
+            ..IdConfig::default()
+        });
+        assert!(snowflake.next_id(&mut exhausted).is_err());
+    }
+}
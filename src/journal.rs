@@ -0,0 +1,598 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{ensure, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, BufWriter};
+use tracing::info;
+
+use crate::config::JournalConfig;
+use crate::migration::{header_of, migrate, DocumentKind, Migration};
+use crate::model::Transaction;
+use crate::snapshot::write_atomically;
+
+/// The current version of the journal format
+pub const JOURNAL_VERSION: u32 = 1;
+
+/// Migrations from older event versions, applied to every event when a journal is read
+const EVENT_MIGRATIONS: &[Migration] = &[];
+
+/// The first line of a journal file
+#[derive(Serialize, Deserialize)]
+struct JournalHeader {
+    kind: DocumentKind,
+    version: u32,
+    /// The sequence number of the last event removed by a compaction
+    #[serde(default)]
+    base_seq: u64,
+}
+
+impl JournalHeader {
+    fn current(base_seq: u64) -> Self {
+        Self {
+            kind: DocumentKind::Journal,
+            version: JOURNAL_VERSION,
+            base_seq,
+        }
+    }
+}
+
+/// An accepted transaction, in the order it was applied
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JournalEvent {
+    pub seq: u64,
+    /// Milliseconds since the unix epoch when the transaction was applied
+    pub timestamp_ms: u64,
+    pub transaction: Transaction,
+    /// The transaction a compensating transaction of `rollback` reverses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverses: Option<u32>,
+}
+
+/// Appends accepted transactions to a journal file, one json event per line
+pub struct JournalWriter {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    seq: u64,
+    /// The sequence number the journal file starts after, older events are in its segments
+    base_seq: u64,
+    /// The size of the journal file, including what is still buffered
+    size: u64,
+    config: JournalConfig,
+    /// How many events were appended since the journal was last written to disk
+    unsynced: usize,
+    last_sync: Instant,
+    /// The transaction the events appended reverse
+    reverses: Option<u32>,
+}
+
+impl JournalWriter {
+    /// Opens the journal for appending, creating it if needed. Existing journals continue from
+    /// their last sequence number.
+    ///
+    /// # Errors
+    /// If the file can't be read or written or it has an outdated version, an error will be
+    /// returned
+    pub async fn open(path: &Path) -> Result<Self> {
+        let (mut seq, mut base_seq) = (0, 0);
+        let exists = tokio::fs::metadata(path).await.is_ok_and(|m| m.len() > 0);
+        if exists {
+            // the segments only hold older events
+            let mut reader = JournalReader::open_after(path, u64::MAX).await?;
+            ensure!(
+                reader.current.version == JOURNAL_VERSION,
+                "Journal version {} is outdated, migrate it first",
+                reader.current.version
+            );
+            base_seq = reader.active_base_seq;
+            seq = base_seq;
+            while let Some(event) = reader.next_event().await? {
+                seq = event.seq;
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let mut size = file.metadata().await?.len();
+        let mut writer = BufWriter::new(file);
+        if !exists {
+            size += write_line(&mut writer, &JournalHeader::current(0)).await?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer,
+            seq,
+            base_seq,
+            size,
+            config: JournalConfig::default(),
+            unsynced: 0,
+            last_sync: Instant::now(),
+            reverses: None,
+        })
+    }
+
+    /// Writes the journal to disk and splits it into segments as set by the config
+    pub fn with_config(mut self, config: JournalConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Records the events appended as reversing the transaction `tx`
+    #[must_use]
+    pub fn reversing(mut self, tx: u32) -> Self {
+        self.reverses = Some(tx);
+        self
+    }
+
+    /// Appends a transaction to the journal
+    ///
+    /// # Errors
+    /// If the journal can't be written, an error will be returned
+    pub async fn append(&mut self, transaction: &Transaction) -> Result<()> {
+        self.seq += 1;
+        let event = JournalEvent {
+            seq: self.seq,
+            timestamp_ms: now_ms(),
+            transaction: transaction.clone(),
+            reverses: self.reverses,
+        };
+        self.size += write_line(&mut self.writer, &event).await?;
+        self.unsynced += 1;
+        let due = self
+            .config
+            .sync_interval
+            .is_some_and(|interval| self.last_sync.elapsed() >= interval);
+        if due
+            || self
+                .config
+                .sync_every
+                .is_some_and(|every| self.unsynced >= every)
+        {
+            self.sync().await?;
+        }
+        if self
+            .config
+            .segment_size
+            .is_some_and(|size| self.size >= size)
+        {
+            self.rotate().await?;
+        }
+        Ok(())
+    }
+
+    /// The sequence number of the last event written
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Writes the buffered events into the file
+    ///
+    /// # Errors
+    /// If the journal can't be written, an error will be returned
+    pub async fn flush(&mut self) -> Result<()> {
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Writes the buffered events into the file and waits until they reach the disk
+    ///
+    /// # Errors
+    /// If the journal can't be written, an error will be returned
+    pub async fn sync(&mut self) -> Result<()> {
+        self.writer.flush().await?;
+        self.writer.get_ref().sync_data().await?;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Moves the events of the journal file into a compressed segment and starts the file again
+    /// after them. A failure after the segment is written leaves its events in both files, they
+    /// are only read once.
+    async fn rotate(&mut self) -> Result<()> {
+        self.sync().await?;
+        let content = tokio::fs::read(&self.path).await?;
+        let compressed = tokio::task::spawn_blocking(move || compress(&content)).await??;
+        write_atomically(&segment_path(&self.path, self.base_seq), &compressed).await?;
+        let mut header = serde_json::to_vec(&JournalHeader::current(self.seq))?;
+        header.push(b'\n');
+        write_atomically(&self.path, &header).await?;
+        let file = OpenOptions::new().append(true).open(&self.path).await?;
+        self.writer = BufWriter::new(file);
+        info!(
+            "Moved journal events {} to {} into a segment",
+            self.base_seq + 1,
+            self.seq
+        );
+        self.base_seq = self.seq;
+        self.size = header.len() as u64;
+        Ok(())
+    }
+}
+
+/// A journal file or segment being read
+struct Source {
+    reader: BufReader<Box<dyn AsyncRead + Send + Unpin>>,
+    version: u32,
+    /// The sequence number of the last event before the file
+    base_seq: u64,
+}
+
+impl Source {
+    async fn open(reader: Box<dyn AsyncRead + Send + Unpin>) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut header = String::new();
+        ensure!(reader.read_line(&mut header).await? > 0, "Empty journal");
+        let header: Value = serde_json::from_str(&header)?;
+        let (kind, version) = header_of(&header)?;
+        ensure!(kind == DocumentKind::Journal, "Not a journal");
+        let base_seq = header.get("base_seq").and_then(Value::as_u64).unwrap_or(0);
+        Ok(Self {
+            reader,
+            version,
+            base_seq,
+        })
+    }
+
+    async fn open_segment(path: &Path) -> Result<Self> {
+        let compressed = tokio::fs::read(path).await?;
+        let content = tokio::task::spawn_blocking(move || decompress(&compressed)).await??;
+        Self::open(Box::new(Cursor::new(content))).await
+    }
+}
+
+/// Reads the events of a journal, its segments first, upgrading them to the current version
+pub struct JournalReader {
+    current: Source,
+    /// The segments not read yet, oldest first
+    segments: VecDeque<PathBuf>,
+    /// The journal file, when segments are read before it
+    active: Option<Source>,
+    /// The sequence number the journal file starts after
+    active_base_seq: u64,
+    /// The read part of a line whose end wasn't written yet
+    partial: String,
+    base_seq: u64,
+    /// The sequence number of the last event read. Events found again, left in a segment and
+    /// the journal file by an interrupted rotation, are skipped.
+    last_seq: u64,
+}
+
+impl JournalReader {
+    /// Opens a journal, along with its segments, and reads its header
+    ///
+    /// # Errors
+    /// If the files can't be read or are not a journal, an error will be returned
+    pub async fn open(path: &Path) -> Result<Self> {
+        Self::open_after(path, 0).await
+    }
+
+    /// Opens a journal skipping the segments that only hold events up to `after`
+    ///
+    /// # Errors
+    /// If the files can't be read or are not a journal, an error will be returned
+    pub async fn open_after(path: &Path, after: u64) -> Result<Self> {
+        let active = Source::open(Box::new(File::open(path).await?)).await?;
+        let active_base_seq = active.base_seq;
+        let mut segments = list_segments(path).await?;
+        // a segment ends where the next one, or the journal file, starts
+        let ends = segments
+            .iter()
+            .skip(1)
+            .map(|(base_seq, _)| *base_seq)
+            .chain([active_base_seq]);
+        let read = ends
+            .take(segments.len())
+            .take_while(|end| *end <= after)
+            .count();
+        segments.drain(..read);
+        let mut segments: VecDeque<_> = segments.into_iter().map(|(_, path)| path).collect();
+        let (current, active) = match segments.pop_front() {
+            Some(segment) => (Source::open_segment(&segment).await?, Some(active)),
+            None => (active, None),
+        };
+        Ok(Self {
+            base_seq: current.base_seq,
+            current,
+            segments,
+            active,
+            active_base_seq,
+            partial: String::new(),
+            last_seq: 0,
+        })
+    }
+
+    /// The sequence number of the last event removed by a compaction
+    pub fn base_seq(&self) -> u64 {
+        self.base_seq
+    }
+
+    /// The sequence number the journal file starts after, which changes when the journal is
+    /// compacted or its events are moved into a segment
+    pub fn active_base_seq(&self) -> u64 {
+        self.active_base_seq
+    }
+
+    /// Reads the next event, or `None` at the end of the journal
+    ///
+    /// # Errors
+    /// If the file can't be read or an event is invalid, an error will be returned
+    pub async fn next_event(&mut self) -> Result<Option<JournalEvent>> {
+        loop {
+            if self.current.reader.read_line(&mut self.partial).await? == 0
+                && self.partial.is_empty()
+            {
+                if self.next_source().await? {
+                    continue;
+                }
+                return Ok(None);
+            }
+            if let Some(event) = self.parse_line()? {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Reads the next event completely written, or `None` when the journal has nothing more yet.
+    /// Used to follow a journal still being appended, a line whose end wasn't written yet is kept
+    /// until the next call.
+    ///
+    /// # Errors
+    /// If the file can't be read or an event is invalid, an error will be returned
+    pub async fn poll_event(&mut self) -> Result<Option<JournalEvent>> {
+        loop {
+            self.current.reader.read_line(&mut self.partial).await?;
+            if !self.partial.ends_with('\n') {
+                if self.partial.is_empty() && self.next_source().await? {
+                    continue;
+                }
+                return Ok(None);
+            }
+            if let Some(event) = self.parse_line()? {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Moves to the next segment or the journal file, returns false after the journal file
+    async fn next_source(&mut self) -> Result<bool> {
+        if let Some(segment) = self.segments.pop_front() {
+            self.current = Source::open_segment(&segment).await?;
+            return Ok(true);
+        }
+        match self.active.take() {
+            Some(active) => {
+                self.current = active;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Parses the line read, skipping blank lines and events already read
+    fn parse_line(&mut self) -> Result<Option<JournalEvent>> {
+        let line = std::mem::take(&mut self.partial);
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let value: Value = serde_json::from_str(&line)?;
+        let value = migrate(
+            value,
+            self.current.version,
+            JOURNAL_VERSION,
+            EVENT_MIGRATIONS,
+        )?;
+        let event: JournalEvent = serde_json::from_value(value)?;
+        if event.seq <= self.last_seq {
+            return Ok(None);
+        }
+        self.last_seq = event.seq;
+        Ok(Some(event))
+    }
+}
+
+/// Rewrites a journal file with every event upgraded to the current version
+///
+/// # Errors
+/// If the file can't be read or written or an event can't be upgraded, an error will be returned
+pub async fn migrate_journal(path: &Path) -> Result<()> {
+    rewrite_journal(path, 0).await
+}
+
+/// Rewrites a journal file keeping only the events after `base_seq`, upgraded to the current
+/// version
+///
+/// # Errors
+/// If the file can't be read or written or an event can't be upgraded, an error will be returned
+pub async fn rewrite_journal(path: &Path, base_seq: u64) -> Result<()> {
+    let mut reader = JournalReader::open(path).await?;
+    let base_seq = base_seq.max(reader.base_seq);
+    let mut content = serde_json::to_vec(&JournalHeader::current(base_seq))?;
+    content.push(b'\n');
+    while let Some(event) = reader.next_event().await? {
+        if event.seq > base_seq {
+            serde_json::to_writer(&mut content, &event)?;
+            content.push(b'\n');
+        }
+    }
+    write_atomically(path, &content).await?;
+    // the events kept were moved into the journal file
+    for (_, segment) in list_segments(path).await? {
+        tokio::fs::remove_file(segment).await?;
+    }
+    Ok(())
+}
+
+/// The segment holding the events after `base_seq`, named after the journal file
+fn segment_path(path: &Path, base_seq: u64) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(format!(".{base_seq:020}.gz"));
+    path.with_file_name(name)
+}
+
+/// The segments of a journal, with the sequence number each one starts after, oldest first
+async fn list_segments(path: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut prefix = path.file_name().map(OsString::from).unwrap_or_default();
+    prefix.push(".");
+    let prefix = prefix.to_string_lossy().into_owned();
+    let mut segments = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let base_seq = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|rest| rest.strip_suffix(".gz"))
+            .and_then(|base_seq| base_seq.parse::<u64>().ok());
+        if let Some(base_seq) = base_seq {
+            segments.push((base_seq, entry.path()));
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+fn compress(content: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+fn decompress(compressed: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    GzDecoder::new(compressed).read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Writes the value as a json line, returning its size
+async fn write_line(writer: &mut BufWriter<File>, value: &impl Serialize) -> Result<u64> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(line.len() as u64)
+}
+
+/// Milliseconds since the unix epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use tokio::io::AsyncWriteExt;
+
+    use crate::config::JournalConfig;
+    use crate::journal::{list_segments, rewrite_journal, JournalReader, JournalWriter};
+    use crate::model::Transaction;
+
+    #[actix::test]
+    async fn test_poll_event_waits_for_complete_lines() {
+        let path = std::env::temp_dir().join(format!("journal-poll-{}", std::process::id()));
+        let mut file = tokio::fs::File::create(&path).await.unwrap();
+        file.write_all(b"{\"kind\":\"journal\",\"version\":1}\n{\"seq\":1,\"timestamp_ms\":0,")
+            .await
+            .unwrap();
+        file.flush().await.unwrap();
+
+        let mut reader = JournalReader::open(&path).await.unwrap();
+        assert!(reader.poll_event().await.unwrap().is_none());
+        file.write_all(
+            b"\"transaction\":{\"type\":\"Deposit\",\"client\":1,\"tx\":1,\"amount\":\"2\"}}\n",
+        )
+        .await
+        .unwrap();
+        file.flush().await.unwrap();
+        let event = reader.poll_event().await.unwrap().unwrap();
+        assert_eq!((event.seq, event.transaction.tx), (1, 1));
+        assert!(reader.poll_event().await.unwrap().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[actix::test]
+    async fn test_rotated_segments_are_read_in_order() {
+        let dir = std::env::temp_dir().join(format!("journal-segments-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("journal");
+        let config = JournalConfig {
+            sync_every: Some(2),
+            segment_size: Some(400),
+            ..JournalConfig::default()
+        };
+        let mut writer = JournalWriter::open(&path)
+            .await
+            .unwrap()
+            .with_config(config);
+        for tx in 1..=10 {
+            let deposit = Transaction::test_deposit(1, tx, Decimal::from(tx));
+            writer.append(&deposit).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        assert!(list_segments(&path).await.unwrap().len() > 1);
+        let writer = JournalWriter::open(&path).await.unwrap();
+        assert_eq!(writer.seq(), 10);
+
+        let mut reader = JournalReader::open(&path).await.unwrap();
+        let mut txs = Vec::new();
+        while let Some(event) = reader.next_event().await.unwrap() {
+            assert_eq!(u64::from(event.transaction.tx), event.seq);
+            txs.push(event.transaction.tx);
+        }
+        assert_eq!(txs, (1..=10).collect::<Vec<_>>());
+        // only the journal file holds events after the last rotation
+        let reader = JournalReader::open_after(&path, 10).await.unwrap();
+        assert!(reader.base_seq() > 0);
+        assert_eq!(reader.base_seq(), reader.active_base_seq());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[actix::test]
+    async fn test_rewrite_journal_keeps_the_events_after_its_base() {
+        let dir = std::env::temp_dir().join(format!("journal-rewrite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("journal");
+        let config = JournalConfig {
+            segment_size: Some(400),
+            ..JournalConfig::default()
+        };
+        let mut writer = JournalWriter::open(&path)
+            .await
+            .unwrap()
+            .with_config(config);
+        for tx in 1..=6 {
+            let deposit = Transaction::test_deposit(1, tx, Decimal::from(tx));
+            writer.append(&deposit).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        assert!(!list_segments(&path).await.unwrap().is_empty());
+
+        rewrite_journal(&path, 4).await.unwrap();
+        assert!(list_segments(&path).await.unwrap().is_empty());
+        let mut reader = JournalReader::open(&path).await.unwrap();
+        assert_eq!(reader.base_seq(), 4);
+        let mut seqs = Vec::new();
+        while let Some(event) = reader.next_event().await.unwrap() {
+            seqs.push(event.seq);
+        }
+        assert_eq!(seqs, [5, 6]);
+        // new events keep counting after the ones removed
+        assert_eq!(JournalWriter::open(&path).await.unwrap().seq(), 6);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
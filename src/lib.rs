@@ -0,0 +1,714 @@
+#![deny(clippy::pedantic)]
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use tokio::{
+    fs::{self, File},
+    io::{stdin, stdout, AsyncBufRead, AsyncWrite, BufReader},
+};
+use tracing::error;
+
+use self::admin::admin;
+use self::api::serve;
+use self::archive::archive;
+use self::balance::balance;
+use self::breaker::CircuitBreaker;
+use self::budget::BudgetConfig;
+use self::capture::CaptureWriter;
+use self::checkpoint::{process_with_checkpoints, Checkpoints};
+use self::cli::{Cli, Command, ProcessArgs};
+use self::compact::compact;
+use self::compare::compare_runs;
+use self::config::AckConfig;
+use self::consolidate::consolidate;
+use self::csv::{write_records, CsvSource};
+use self::dedup::TxIdFilter;
+use self::delta::delta;
+use self::diagnostics::Diagnostics;
+use self::disputes::export_disputes;
+use self::drift::drift;
+use self::engine::{Collected, Crash, Engine, Stats, Straggler};
+use self::groups::export_groups;
+use self::grpc::serve_grpc;
+use self::history::open_tx_store;
+use self::journal::JournalWriter;
+use self::manifest::{FailureManifest, OutputDigest, RunManifest};
+use self::migration::migrate_file;
+use self::model::{Account, AccountRecord};
+use self::parquet::{write_accounts, TransactionParquetWriter};
+use self::partition::write_partitioned;
+use self::position::position;
+use self::processed::ExactlyOnce;
+use self::quality::write_quality_report;
+use self::registry::ClientRegistry;
+use self::rejects::RejectWriter;
+use self::repair::repair;
+use self::replica::replica;
+use self::rollback::rollback;
+use self::rounding::write_rounding_report;
+use self::sample::Sampler;
+use self::schema::export_schema;
+use self::signing::{generate_keys, sign_file, verify, DigestWriter};
+use self::sink::write_to_database;
+use self::snapshot::{temp_path_for, Snapshot};
+use self::source::{
+    decompressed, expand_globs, process_atomically, process_cdc, process_kafka, process_source,
+    process_sqs, process_with_savepoints, AvroSchema, AvroSource, CaptureSource, InputFormat,
+    InputSource, MergedSource, NdjsonSource, ParallelCsvSource, ProtobufSource, RiskFirst,
+};
+use self::split::split;
+use self::store::FileAccountStore;
+use self::stream::{process_streaming, AccountStream};
+use self::vectors::test_vectors;
+use self::wal::{replay, WriteAheadLog};
+use self::webhook::{start_delivery, DeliveryWorker, Outbox};
+
+#[macro_use]
+extern crate serde;
+
+mod admin;
+mod api;
+mod archive;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod balance;
+mod breaker;
+mod budget;
+mod capture;
+mod cases;
+mod checkpoint;
+mod cli;
+mod compact;
+mod compare;
+pub mod config;
+#[cfg(test)]
+mod conformance;
+mod consolidate;
+mod csv;
+mod dedup;
+mod delta;
+mod diagnostics;
+mod disputes;
+mod drift;
+pub mod embed;
+pub mod engine;
+mod groups;
+mod grpc;
+mod history;
+pub mod hooks;
+mod ids;
+mod journal;
+mod logging;
+mod mailbox;
+mod manifest;
+mod migration;
+pub mod model;
+mod money;
+mod output;
+mod parquet;
+mod partition;
+pub mod policy;
+mod position;
+mod processed;
+mod quality;
+#[cfg(any(test, feature = "test-support"))]
+pub mod reference;
+mod registry;
+mod rejects;
+mod repair;
+mod replica;
+mod rollback;
+mod rounding;
+mod sample;
+mod schema;
+mod shadow;
+mod shard;
+mod signing;
+mod sink;
+mod snapshot;
+mod source;
+mod split;
+mod store;
+mod stream;
+mod tags;
+mod transaction;
+mod vectors;
+mod wal;
+mod webhook;
+mod workers;
+
+/// The file name standing for the std in
+const STDIN: &str = "-";
+
+/// Runs the command line of the binary. It must run inside an actor system, like under
+/// `actix::main`.
+///
+/// # Errors
+/// If the command fails, an error will be returned
+pub async fn run() -> Result<()> {
+    let cli = Cli::parse();
+    logging::init(cli.log_format);
+    let Some(command) = cli.command else {
+        if let Err(e) = process(&cli.process).await {
+            error!("Error processing file: {e}");
+        }
+        return Ok(());
+    };
+    // checks whose failure must fail the process too
+    let fatal = matches!(
+        command,
+        Command::Admin(_) | Command::TestVectors(_) | Command::Verify(_)
+    );
+    if let Err(e) = run_command(command).await {
+        error!("{e:#}");
+        if fatal {
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Runs a subcommand, its error saying what failed
+async fn run_command(command: Command) -> Result<()> {
+    match command {
+        Command::Repair(args) => repair(&args).await.context("Error repairing accounts"),
+        Command::Consolidate(args) => consolidate(&args)
+            .await
+            .context("Error consolidating accounts"),
+        Command::Compact(args) => compact(&args).await.context("Error compacting journal"),
+        Command::Archive(args) => archive(&args).context("Error archiving accounts"),
+        Command::Replay(args) => replay(&args).await.context("Error replaying the log"),
+        Command::Rollback(args) => rollback(&args)
+            .await
+            .with_context(|| format!("Error rolling back transaction {}", args.tx)),
+        Command::Replica(args) => replica(&args).await.context("Error running replica"),
+        Command::Admin(args) => admin(&args)
+            .await
+            .context("Error sending the admin command"),
+        Command::Serve(args) => serve_grpc(&args)
+            .await
+            .context("Error serving the ingestion service"),
+        Command::Balance(args) => balance(&args)
+            .await
+            .with_context(|| format!("Error rebuilding the account of client {}", args.client)),
+        Command::Delta(args) => delta(&args).await.context("Error comparing snapshots"),
+        Command::Disputes(args) => export_disputes(&args)
+            .await
+            .context("Error exporting disputes"),
+        Command::Groups(args) => export_groups(&args).await.context("Error reporting groups"),
+        Command::Position(args) => position(&args)
+            .await
+            .context("Error reporting the position"),
+        Command::Split(args) => split(&args).await.context("Error splitting the input"),
+        Command::CompareRuns(args) => compare_runs(&args).await.context("Error comparing runs"),
+        Command::Drift(args) => drift(&args).await.context("Error measuring the drift"),
+        Command::Schema { format } => export_schema(format)
+            .await
+            .context("Error exporting schema"),
+        Command::Keygen {
+            private_key,
+            public_key,
+        } => generate_keys(&private_key, &public_key)
+            .await
+            .context("Error generating keys"),
+        Command::TestVectors(args) => test_vectors(&args).await.context("Test vectors failed"),
+        Command::Verify(args) => verify(&args).await.context("Verification failed"),
+        Command::Migrate { path } => migrate_file(&path)
+            .await
+            .with_context(|| format!("Error migrating {}", path.display())),
+    }
+}
+
+/// Creates the engine the options of the command ask for, restoring its snapshot if any
+async fn build_engine(
+    args: &ProcessArgs,
+    registry: Option<&Arc<ClientRegistry>>,
+    outbox: Option<&Arc<Outbox>>,
+) -> Result<Engine> {
+    let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine())
+        .with_collection(args.collection());
+    if let Some(registry) = registry {
+        engine = engine.with_registry(Arc::clone(registry));
+    }
+    if let Some(path) = &args.journal {
+        let journal = JournalWriter::open(path).await?.with_config(args.journal());
+        engine = engine.with_journal(journal);
+    }
+    if let Some(path) = &args.wal {
+        engine = engine.with_wal(WriteAheadLog::open(path).await?);
+    }
+    if let Some(path) = &args.rejects {
+        engine = engine.with_rejects(RejectWriter::create(path).await?);
+    }
+    if let Some(path) = &args.capture {
+        engine = engine.with_capture(CaptureWriter::create(path).await?);
+    }
+    if let Some(path) = &args.parquet_transactions {
+        engine = engine.with_parquet_transactions(TransactionParquetWriter::create(path)?);
+    }
+    if let Some(outbox) = outbox {
+        engine = engine.with_outbox(Arc::clone(outbox));
+    }
+    if let Some(dir) = &args.store {
+        let mut store = FileAccountStore::open(dir.clone())?;
+        if let Some(archive) = &args.store_archive {
+            store = store.with_archive(archive.clone())?;
+        }
+        let store = Arc::new(store);
+        let interval = Duration::from_millis(args.store_flush_interval);
+        engine = engine.with_store(store, interval);
+        if let Some(limit) = args.prewarm {
+            engine.prewarm(limit)?;
+        }
+    }
+    if let Some(dir) = &args.tx_history {
+        engine = engine.with_tx_store(open_tx_store(dir)?);
+    }
+    if !args.worker_cores.is_empty() {
+        engine = engine.with_workers(&args.worker_cores);
+    }
+    if let Some(shards) = args.shards {
+        engine = engine.with_shards(shards);
+    }
+    if let Some(capacity) = args.mailbox_capacity {
+        engine = engine.with_mailboxes(capacity);
+    }
+    if let Some(spec) = args.sample {
+        engine = engine.with_sample(spec);
+    }
+    if let Some(config) = args.breaker() {
+        engine = engine.with_breaker(CircuitBreaker::new(config));
+    }
+    if let Some(budget) = args.latency_budget {
+        engine = engine.with_latency_budget(BudgetConfig {
+            budget: Duration::from_millis(budget),
+            recovery: args.latency_recovery,
+        });
+    }
+    if args.require_onboarding {
+        engine = engine.with_required_open();
+    }
+    if args.strict_tx_ids {
+        let filter = match args.tx_id_memory {
+            Some(megabytes) => {
+                let memory = megabytes
+                    .checked_mul(1 << 20)
+                    .context("The --tx-id-memory doesn't fit in the address space")?;
+                TxIdFilter::bloom(memory, args.tx_id_false_positives)
+            }
+            None => TxIdFilter::exact(),
+        };
+        engine = engine.with_strict_tx_ids(filter);
+    }
+    if args.shadow {
+        engine = engine.with_shadow();
+    }
+    match &args.restore {
+        // restored by the exactly-once run, which checks the snapshot first
+        Some(_) if args.exactly_once => {}
+        Some(path) => engine.restore(Snapshot::read(path).await?)?,
+        None => {}
+    }
+    Ok(engine)
+}
+
+/// Applies the transactions of the input and prints the resulting accounts
+async fn process(args: &ProcessArgs) -> Result<()> {
+    let registry = match &args.clients {
+        Some(path) => Some(Arc::new(load_registry(path, args).await?)),
+        None => None,
+    };
+    let exactly_once = if args.exactly_once {
+        // `None` when every input file was already applied
+        let Some(start) = start_exactly_once(args).await? else {
+            return Ok(());
+        };
+        Some(start)
+    } else {
+        None
+    };
+    let (outbox, delivery) = open_outbox(args).await?;
+    let mut engine = build_engine(args, registry.as_ref(), outbox.as_ref()).await?;
+    let exactly_once = exactly_once
+        .map(|(run, snapshot)| engine.restore(snapshot).map(|()| run))
+        .transpose()?;
+    let diagnostics = Diagnostics::default();
+    process_input(args, &mut engine, &diagnostics, registry.as_ref()).await?;
+    if let Some(listen) = &args.listen {
+        let (snapshot, admin_token) = (args.snapshot.as_deref(), args.admin_token_file.as_deref());
+        engine = serve(listen, engine, snapshot, args.throttle(), admin_token).await?;
+    }
+    print_run_summary(&engine, &diagnostics);
+    let (journal_seq, tags, rows) = (
+        engine.journal_seq(),
+        engine.tags().clone(),
+        engine.stats().total(),
+    );
+    let Collected {
+        mut accounts,
+        stragglers,
+        crashes,
+    } = engine.collect_all().await?;
+    args.sort_output.sort(&mut accounts);
+    write_failures(args, &crashes, &stragglers).await?;
+    if let Some(delivery) = delivery {
+        let pending = delivery.stop().await?;
+        if pending > 0 {
+            eprintln!("{pending} notifications left in the outbox for the next run");
+        }
+    }
+    if args.stream_every.is_some() {
+        // already written while processing. The options that need the whole output, like
+        // `--snapshot` and `--manifest`, can't be combined with `--stream-every`
+        return Ok(());
+    }
+    if let Some(path) = &args.snapshot {
+        let mut snapshot = Snapshot::new(journal_seq, &accounts);
+        if let Some(run) = exactly_once {
+            snapshot = run.finish(rows, snapshot)?;
+        }
+        snapshot.write(path).await?;
+    }
+    let accounts = tags.filter(accounts, &args.tags);
+    let output = write_output(args, registry.as_deref(), &accounts).await?;
+    write_reports(args, &accounts).await?;
+    if let Some(path) = &args.manifest {
+        let manifest = RunManifest::new(
+            args.filename.as_deref(),
+            journal_seq,
+            accounts.len(),
+            output,
+        );
+        manifest.write(path).await?;
+        if let Some(key) = &args.signing_key {
+            sign_file(path, key).await?;
+        }
+    }
+    if let Some(url) = &args.db {
+        let records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+        write_to_database(url, &records, args).await?;
+    }
+    Ok(())
+}
+
+/// Writes the accounts into the partitions, the `--out` file or the std out, digesting them as
+/// they are written
+async fn write_output(
+    args: &ProcessArgs,
+    registry: Option<&ClientRegistry>,
+    accounts: &[Account],
+) -> Result<OutputDigest> {
+    let (dir, scheme) = (&args.partition_dir, args.partition_by);
+    if let Some(partitions) = args.output_partitions.map(usize::from) {
+        let files = if let Some(registry) = registry {
+            let records = accounts.iter().map(|a| (a.client(), registry.enrich(a)));
+            write_partitioned(dir, partitions, scheme, records).await?
+        } else {
+            let records = accounts.iter().map(|a| (a.client(), a));
+            write_partitioned(dir, partitions, scheme, records).await?
+        };
+        return Ok(OutputDigest::Partitioned(files));
+    }
+    let Some(path) = &args.out else {
+        let mut out = DigestWriter::new(stdout());
+        write_accounts_as(args, registry, accounts, &mut out).await?;
+        return Ok(OutputDigest::Single(out.finish()));
+    };
+    // written beside the file and renamed over it, so it never holds a partial output
+    let temp_path = temp_path_for(path);
+    let mut out = DigestWriter::new(File::create(&temp_path).await?);
+    write_accounts_as(args, registry, accounts, &mut out).await?;
+    fs::rename(&temp_path, path).await?;
+    Ok(OutputDigest::Single(out.finish()))
+}
+
+/// Writes the accounts in the `--output-format`
+async fn write_accounts_as(
+    args: &ProcessArgs,
+    registry: Option<&ClientRegistry>,
+    accounts: &[Account],
+    writer: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    if let Some(registry) = registry {
+        let records = accounts.iter().map(|a| registry.enrich(a));
+        args.output_format.write(writer, records).await
+    } else {
+        args.output_format.write(writer, accounts).await
+    }
+}
+
+/// Writes the accounts left out of the output, if the options ask for them
+async fn write_failures(
+    args: &ProcessArgs,
+    crashes: &[Crash],
+    stragglers: &[Straggler],
+) -> Result<()> {
+    if let Some(path) = &args.stragglers {
+        write_records(File::create(path).await?, stragglers).await?;
+    }
+    if let Some(path) = &args.failure_manifest {
+        FailureManifest {
+            crashes,
+            stragglers,
+        }
+        .write(path)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Writes the reports and the Parquet file of the accounts the options ask for
+async fn write_reports(args: &ProcessArgs, accounts: &[Account]) -> Result<()> {
+    if let Some(path) = &args.rounding_report {
+        write_rounding_report(path, accounts).await?;
+    }
+    if let Some(path) = &args.quality_report {
+        write_quality_report(path, accounts).await?;
+    }
+    if let Some(path) = &args.parquet {
+        let records: Vec<_> = accounts.iter().map(AccountRecord::from).collect();
+        write_accounts(path, &records).await?;
+    }
+    Ok(())
+}
+
+/// Checks the input files of a run with `--exactly-once` against the restored snapshot, `None`
+/// if they were all applied already
+async fn start_exactly_once(args: &ProcessArgs) -> Result<Option<(ExactlyOnce, Snapshot)>> {
+    let (Some(filename), Some(restore)) = (&args.filename, &args.restore) else {
+        bail!("--exactly-once needs an input file and a snapshot to restore");
+    };
+    let paths = [std::slice::from_ref(filename), &args.other_files].concat();
+    let files = expand_globs(&paths)?;
+    ensure!(
+        files.iter().all(|file| file != Path::new(STDIN)),
+        "The std in can't be processed exactly once"
+    );
+    ExactlyOnce::start(&files, restore).await
+}
+
+/// Applies the transactions of the queue, database or topic the options give, or else of the file
+async fn process_input(
+    args: &ProcessArgs,
+    engine: &mut Engine,
+    diagnostics: &Diagnostics,
+    registry: Option<&Arc<ClientRegistry>>,
+) -> Result<()> {
+    let (ack, risk_first_batch) = (args.ack(), args.risk_first_batch());
+    match (args.sqs(), args.cdc(), args.kafka()) {
+        (Some(config), _, _) => process_sqs(&config, engine, ack, risk_first_batch).await,
+        (None, Some(config), _) => process_cdc(&config, engine, ack, risk_first_batch).await,
+        (None, None, Some(config)) => process_kafka(&config, engine, ack, risk_first_batch).await,
+        (None, None, None) if args.filename.is_none() && args.listen.is_some() => Ok(()),
+        (None, None, None) => process_file(args, engine, diagnostics, registry).await,
+    }
+}
+
+/// Applies the transactions of the input file, or of the std in without a file or with `-`, in
+/// the format given by the options
+async fn process_file(
+    args: &ProcessArgs,
+    engine: &mut Engine,
+    diagnostics: &Diagnostics,
+    registry: Option<&Arc<ClientRegistry>>,
+) -> Result<()> {
+    let Some(filename) = &args.filename else {
+        let input = BufReader::new(stdin());
+        return process_reader(args, input, None, engine, diagnostics, registry).await;
+    };
+    let paths = [std::slice::from_ref(filename), &args.other_files].concat();
+    let files = expand_globs(&paths)?;
+    if files.len() > 1 {
+        return process_merged(args, &files, engine, diagnostics, registry).await;
+    }
+    if files[0] == Path::new(STDIN) {
+        let input = BufReader::new(stdin());
+        return process_reader(args, input, None, engine, diagnostics, registry).await;
+    }
+    let file = File::open(&files[0])
+        .await
+        .expect("Could not open specified file");
+    let input = BufReader::new(file);
+    process_reader(args, input, Some(&files[0]), engine, diagnostics, registry).await
+}
+
+/// Applies the transactions read from the file at `path`, if any, in the format given by the
+/// options. Gzip and zstd compressed inputs are decompressed.
+async fn process_reader(
+    args: &ProcessArgs,
+    input: impl AsyncBufRead + Send + Unpin + 'static,
+    path: Option<&Path>,
+    engine: &mut Engine,
+    diagnostics: &Diagnostics,
+    registry: Option<&Arc<ClientRegistry>>,
+) -> Result<()> {
+    let input = decompressed(input, path).await?;
+    let diagnostics = diagnostics.clone();
+    match args.format {
+        InputFormat::Csv if args.parse_threads.is_some() => {
+            let parsers = args.parse_threads.map_or(1, usize::from);
+            let source = ParallelCsvSource::open(input, args.csv(), parsers).await?;
+            apply_file(args, source.with_diagnostics(diagnostics), engine, registry).await
+        }
+        InputFormat::Csv => {
+            let source = CsvSource::open_with_config(input, args.csv()).await?;
+            apply_file(args, source.with_diagnostics(diagnostics), engine, registry).await
+        }
+        InputFormat::Json => {
+            let source = NdjsonSource::new(input);
+            apply_file(args, source.with_diagnostics(diagnostics), engine, registry).await
+        }
+        InputFormat::Capture => {
+            let source = CaptureSource::new(input, args.replay_speed);
+            apply_file(args, source, engine, registry).await
+        }
+        InputFormat::Avro => {
+            let schema = match &args.schema {
+                Some(path) => Some(AvroSchema::parse(&fs::read(path).await?)?),
+                None => None,
+            };
+            let source = AvroSource::new(input, schema);
+            apply_file(args, source, engine, registry).await
+        }
+        InputFormat::Protobuf => {
+            let source = ProtobufSource::new(input)?;
+            apply_file(args, source, engine, registry).await
+        }
+    }
+}
+
+/// Applies the transactions of several csv files, merged by their timestamp column
+async fn process_merged(
+    args: &ProcessArgs,
+    files: &[PathBuf],
+    engine: &mut Engine,
+    diagnostics: &Diagnostics,
+    registry: Option<&Arc<ClientRegistry>>,
+) -> Result<()> {
+    ensure!(
+        matches!(args.format, InputFormat::Csv),
+        "Only csv files can be merged"
+    );
+    ensure!(
+        files.iter().all(|path| path != Path::new(STDIN)),
+        "The std in can't be merged with other files"
+    );
+    let mut sources = Vec::with_capacity(files.len());
+    for path in files {
+        let file = File::open(path)
+            .await
+            .with_context(|| format!("Could not open {}", path.display()))?;
+        let input = decompressed(BufReader::new(file), Some(path)).await?;
+        let source = CsvSource::open_with_config(input, args.csv()).await?;
+        let name = path.display().to_string();
+        sources.push((name, source.with_diagnostics(diagnostics.clone())));
+    }
+    let source = MergedSource::open(sources, &args.merge_by).await?;
+    apply_file(args, source, engine, registry).await
+}
+
+/// Applies the transactions of the file as the options of the command ask for
+async fn apply_file(
+    args: &ProcessArgs,
+    source: impl InputSource,
+    engine: &mut Engine,
+    registry: Option<&Arc<ClientRegistry>>,
+) -> Result<()> {
+    if let Some(every) = args.stream_every {
+        let mut stream = AccountStream::new(stdout(), registry.cloned());
+        process_streaming(source, engine, &mut stream, every).await
+    } else if args.atomic_file {
+        process_atomically(source, engine).await
+    } else if args.savepoints || args.savepoint_every.is_some() {
+        process_with_savepoints(source, engine, args.savepoint_every).await
+    } else if let Some(dir) = &args.checkpoint {
+        let inputs = [args.filename.as_slice(), &args.other_files].concat();
+        let checkpoints = Checkpoints::new(dir, inputs, args.checkpoint_every).await?;
+        process_with_checkpoints(source, engine, &checkpoints).await
+    } else if args.risk_first_batch.is_some() {
+        let source = RiskFirst::new(source, args.risk_first_batch());
+        process_source(source, engine, AckConfig::default()).await
+    } else {
+        process_source(source, engine, AckConfig::default()).await
+    }
+}
+
+/// Opens the outbox of the notifications, if any, starting their delivery when there are
+/// channels
+async fn open_outbox(args: &ProcessArgs) -> Result<(Option<Arc<Outbox>>, Option<DeliveryWorker>)> {
+    let Some(dir) = &args.webhook_outbox else {
+        return Ok((None, None));
+    };
+    let channels = args.channels()?;
+    let outbox = Arc::new(Outbox::open(dir).await?);
+    let delivery = if channels.is_empty() {
+        None
+    } else {
+        Some(start_delivery(
+            Arc::clone(&outbox),
+            &channels,
+            args.webhooks(),
+        )?)
+    };
+    Ok((Some(outbox), delivery))
+}
+
+/// Loads the client registry along with the segment policies and currency rules, if any
+async fn load_registry(path: &Path, args: &ProcessArgs) -> Result<ClientRegistry> {
+    let mut registry = ClientRegistry::load(path).await?;
+    if let Some(policies) = &args.segment_policies {
+        registry = registry.with_policies(policies).await?;
+    }
+    match &args.currency_rules {
+        Some(rules) => registry.with_currency_rules(rules).await,
+        None => Ok(registry),
+    }
+}
+
+/// Prints how the run went besides the accounts: the sample, the shadow engine, the degraded
+/// mode and the data quality
+fn print_run_summary(engine: &Engine, diagnostics: &Diagnostics) {
+    if let Some(sampler) = engine.sampler() {
+        print_sample_summary(sampler, engine.stats());
+    }
+    if let Some(shadow) = engine.shadow() {
+        eprintln!(
+            "Shadow engine: {} divergences from the account actors",
+            shadow.divergences()
+        );
+    }
+    if let Some(stats) = engine.degraded_stats() {
+        eprintln!("{stats}");
+    }
+    if !diagnostics.is_empty() {
+        eprintln!("{diagnostics}");
+    }
+}
+
+/// Prints how the sampled transactions ended, as an estimate for the whole input
+fn print_sample_summary(sampler: &Sampler, stats: Stats) {
+    let (seen, taken) = sampler.counts();
+    let failed = stats.rejected + stats.undelivered;
+    let total = stats.applied + stats.not_found + failed;
+    eprintln!(
+        "Sampled {taken} of {seen} transactions read ({}): {} applied, {} rejected, {} not found, \
+        {} undelivered",
+        sampler.spec(),
+        stats.applied,
+        stats.rejected,
+        stats.not_found,
+        stats.undelivered
+    );
+    if total > 0 {
+        eprintln!("Estimated reject rate: {:.2}%", percent(failed, total));
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn percent(count: u64, total: u64) -> f64 {
+    count as f64 * 100.0 / total as f64
+}
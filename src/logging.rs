@@ -0,0 +1,86 @@
+use clap::ValueEnum;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// How the log lines are written to the std err
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    /// Human readable lines, prefixed with the spans they were logged in
+    #[default]
+    Text,
+    /// A json object per line, with the fields of the spans they were logged in, for log
+    /// aggregators
+    Json,
+}
+
+/// The subscriber writing the log lines of the level set in `RUST_LOG`, `error` by default, to
+/// `writer`. Every line logged while applying a transaction carries the `client`, `tx` and `type`
+/// fields of its span.
+pub fn subscriber<W>(format: LogFormat, writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().with_current_span(false).finish()),
+    }
+}
+
+/// Writes the log lines to the std err, along with the records of the `log` crate the
+/// dependencies emit
+pub fn init(format: LogFormat) {
+    subscriber(format, std::io::stderr).init();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use rust_decimal_macros::dec;
+
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::engine::Engine;
+    use crate::logging::{subscriber, LogFormat};
+    use crate::model::{Transaction, TransactionType};
+
+    /// The log lines written, shared with the test
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[actix::test]
+    async fn test_rejections_are_logged_with_the_transaction_fields() {
+        let lines = Lines::default();
+        let writer = lines.clone();
+        let _guard =
+            tracing::subscriber::set_default(subscriber(LogFormat::Json, move || writer.clone()));
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let withdrawal = Transaction::for_test(TransactionType::Withdrawal, 3, 8, Some(dec!(5)));
+        engine.submit(withdrawal).await.unwrap();
+
+        let lines = lines.0.lock().unwrap();
+        let line: serde_json::Value = serde_json::from_slice(&lines).unwrap();
+        assert_eq!(line["level"], "ERROR");
+        let span = &line["spans"][0];
+        assert_eq!(span["name"], "transaction");
+        assert_eq!(span["client"], 3);
+        assert_eq!(span["tx"], 8);
+        assert_eq!(span["type"], "Withdrawal");
+    }
+}
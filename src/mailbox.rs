@@ -0,0 +1,128 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use actix::MailboxError;
+use tokio::time::timeout;
+
+use crate::config::DispatchConfig;
+use crate::model::{Transaction, TransactionError};
+use crate::transaction::{AccountAddr, Deliver, Pending};
+
+/// How an account answered a transaction sent ahead, if it did
+pub type Answer = Result<Result<(), TransactionError>, MailboxError>;
+
+/// A transaction sent to its account whose answer wasn't awaited yet
+struct InFlight {
+    delivery: Deliver,
+    sent: Instant,
+    answer: Pending<Result<(), TransactionError>>,
+}
+
+/// The transaction given back by `Mailboxes::try_send` when its client has no room left
+pub struct Full(pub Transaction);
+
+/// The transactions sent to the account actors without waiting for their answer, at most
+/// `capacity` per client, so a client dominating the input can't pile up its transactions in
+/// memory. The answers are taken in the order the transactions were sent.
+pub struct Mailboxes {
+    capacity: usize,
+    in_flight: VecDeque<InFlight>,
+    /// The transactions in flight of every client
+    queued: HashMap<u16, usize>,
+}
+
+impl Mailboxes {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            in_flight: VecDeque::new(),
+            queued: HashMap::new(),
+        }
+    }
+
+    /// Sends a transaction to its account, unless its client already has `capacity` transactions
+    /// in flight, giving it back then
+    pub fn try_send(&mut self, actor: &AccountAddr, transaction: Transaction) -> Result<(), Full> {
+        let queued = self.queued.entry(transaction.client).or_default();
+        if *queued >= self.capacity {
+            return Err(Full(transaction));
+        }
+        *queued += 1;
+        let delivery = Deliver::new(transaction);
+        self.in_flight.push_back(InFlight {
+            answer: actor.send(delivery.clone()),
+            delivery,
+            sent: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Waits for the answer to the oldest transaction in flight, at most as long as
+    /// `send_with_retry` would, giving it back with its ticket along with when it was sent.
+    /// Unlike `send_with_retry`, a transaction whose request was closed isn't sent again, as it
+    /// would overtake the ones sent after it.
+    pub async fn next(&mut self, config: DispatchConfig) -> Option<(Deliver, Instant, Answer)> {
+        let InFlight {
+            delivery,
+            sent,
+            answer,
+        } = self.in_flight.pop_front()?;
+        let client = delivery.transaction.client;
+        if let Some(queued) = self.queued.get_mut(&client) {
+            *queued -= 1;
+            if *queued == 0 {
+                self.queued.remove(&client);
+            }
+        }
+        let wait = config.timeout * (config.retries + 1);
+        let answer = timeout(wait, answer)
+            .await
+            .unwrap_or(Err(MailboxError::Timeout));
+        Some((delivery, sent, answer))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::mailbox::{Full, Mailboxes};
+    use crate::model::{Account, Transaction, TransactionType};
+    use crate::transaction::AccountHandler;
+
+    #[actix::test]
+    async fn test_a_client_can_only_send_its_capacity_ahead() {
+        let actor = |client| {
+            let account = Account::new(client, EngineConfig::default());
+            AccountHandler::from_account(account, None, None, None).addr
+        };
+        let (first, second) = (actor(1), actor(2));
+        let deposit =
+            |client, tx| Transaction::for_test(TransactionType::Deposit, client, tx, Some(dec!(1)));
+        let mut mailboxes = Mailboxes::new(2);
+        assert!(mailboxes.try_send(&first, deposit(1, 1)).is_ok());
+        assert!(mailboxes.try_send(&first, deposit(1, 2)).is_ok());
+        let Err(Full(back)) = mailboxes.try_send(&first, deposit(1, 3)) else {
+            panic!("The third transaction of client 1 was sent");
+        };
+        assert_eq!(back.tx, 3);
+        // other clients still have room
+        assert!(mailboxes.try_send(&second, deposit(2, 4)).is_ok());
+
+        let (answered, _, answer) = mailboxes.next(DispatchConfig::default()).await.unwrap();
+        assert_eq!(answered.transaction.tx, 1);
+        assert_eq!(answer.unwrap(), Ok(()));
+        assert!(mailboxes.try_send(&first, back).is_ok());
+        let mut order = Vec::new();
+        while let Some((delivery, _, _)) = mailboxes.next(DispatchConfig::default()).await {
+            order.push(delivery.transaction.tx);
+        }
+        assert_eq!(order, [2, 4, 3]);
+        assert!(mailboxes.is_empty());
+    }
+}
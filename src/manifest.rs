@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use anyhow::{ensure, Result};
+use serde_json::Value;
+use tokio::fs;
+
+use crate::engine::{Crash, Straggler};
+use crate::journal::now_ms;
+use crate::migration::{header_of, migrate, DocumentKind, Migration};
+use crate::partition::PartitionFile;
+use crate::snapshot::write_atomically;
+
+/// What a run wrote, digested as it was written
+pub enum OutputDigest {
+    /// The hex encoded sha256 digest of the accounts printed into a single output
+    Single(String),
+    /// The partition files written
+    Partitioned(Vec<PartitionFile>),
+}
+
+/// The current version of the run manifest format
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Migrations from older manifest versions, applied when a manifest is read
+const MANIFEST_MIGRATIONS: &[Migration] = &[];
+
+/// Describes a run and the accounts it printed, so consumers can check the output they received.
+/// A single output is described by `output_sha256`, partitioned output by `partitions`.
+#[derive(Serialize, Deserialize)]
+pub struct RunManifest {
+    kind: DocumentKind,
+    version: u32,
+    /// The transactions file processed
+    pub input: Option<String>,
+    /// Milliseconds since the unix epoch when the run finished
+    pub finished_ms: u64,
+    /// The sequence number of the last journal event written by the run
+    pub journal_seq: u64,
+    /// How many accounts were printed
+    pub accounts: usize,
+    /// The hex encoded sha256 digest of the printed accounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_sha256: Option<String>,
+    /// The partition files the accounts were written into
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<PartitionFile>,
+}
+
+impl RunManifest {
+    /// Describes a run that wrote `output`
+    pub fn new(
+        input: Option<&Path>,
+        journal_seq: u64,
+        accounts: usize,
+        output: OutputDigest,
+    ) -> Self {
+        let (output_sha256, partitions) = match output {
+            OutputDigest::Single(digest) => (Some(digest), Vec::new()),
+            OutputDigest::Partitioned(partitions) => (None, partitions),
+        };
+        Self {
+            kind: DocumentKind::Manifest,
+            version: MANIFEST_VERSION,
+            input: input.map(|path| path.display().to_string()),
+            finished_ms: now_ms(),
+            journal_seq,
+            accounts,
+            output_sha256,
+            partitions,
+        }
+    }
+
+    /// The digest expected for an output file: the one of the partition with its name, or of the
+    /// single output when the run wasn't partitioned
+    pub fn sha256_of(&self, output: &Path) -> Option<&str> {
+        if self.partitions.is_empty() {
+            return self.output_sha256.as_deref();
+        }
+        let name = output.file_name()?.to_str()?;
+        self.partitions
+            .iter()
+            .find(|partition| partition.file == name)
+            .map(|partition| partition.sha256.as_str())
+    }
+
+    /// Parses a manifest of any supported version, upgrading it to the current one
+    ///
+    /// # Errors
+    /// If the document is not a manifest or can't be upgraded, an error will be returned
+    pub fn from_value(value: Value) -> Result<Self> {
+        let (kind, version) = header_of(&value)?;
+        ensure!(kind == DocumentKind::Manifest, "Not a manifest");
+        let mut value = migrate(value, version, MANIFEST_VERSION, MANIFEST_MIGRATIONS)?;
+        value["version"] = MANIFEST_VERSION.into();
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Reads a manifest file, upgrading it to the current version
+    ///
+    /// # Errors
+    /// If the file can't be read or is not a valid manifest, an error will be returned
+    pub async fn read(path: &Path) -> Result<Self> {
+        let content = fs::read(path).await?;
+        Self::from_value(serde_json::from_slice(&content)?)
+    }
+
+    /// The content of the manifest file, as it is written and signed
+    ///
+    /// # Errors
+    /// If the manifest can't be serialized, an error will be returned
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut content = serde_json::to_vec(self)?;
+        content.push(b'\n');
+        Ok(content)
+    }
+
+    /// Writes the manifest atomically into a file
+    ///
+    /// # Errors
+    /// If the file can't be written, an error will be returned
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        write_atomically(path, &self.to_bytes()?).await
+    }
+}
+
+/// The clients left out of the output of a run, so consumers know which accounts are missing
+/// from the partial results
+#[derive(Serialize)]
+pub struct FailureManifest<'a> {
+    /// The accounts whose actor crashed
+    pub crashes: &'a [Crash],
+    /// Every account left out, crashed or not answering the collection
+    pub stragglers: &'a [Straggler],
+}
+
+impl FailureManifest<'_> {
+    /// Writes the manifest atomically into a file
+    ///
+    /// # Errors
+    /// If the file can't be written, an error will be returned
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        let mut content = serde_json::to_vec(self)?;
+        content.push(b'\n');
+        write_atomically(path, &content).await
+    }
+}
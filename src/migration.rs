@@ -0,0 +1,133 @@
+use std::path::Path;
+
+use anyhow::{anyhow, ensure, Result};
+use serde_json::Value;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::journal::migrate_journal;
+use crate::manifest::RunManifest;
+use crate::snapshot::Snapshot;
+
+/// The kinds of documents persisted by the engine
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentKind {
+    Snapshot,
+    Journal,
+    Manifest,
+}
+
+/// Upgrades a persisted document from version `from` to the next one
+pub struct Migration {
+    pub from: u32,
+    pub apply: fn(Value) -> Result<Value>,
+}
+
+/// Applies every migration needed to take a document from `version` up to `target`
+///
+/// # Errors
+/// If the document is newer than `target` or a migration is missing or fails, an error will be
+/// returned
+pub fn migrate(
+    mut value: Value,
+    mut version: u32,
+    target: u32,
+    migrations: &[Migration],
+) -> Result<Value> {
+    ensure!(
+        version <= target,
+        "Version {version} is newer than the supported version {target}"
+    );
+    while version < target {
+        let migration = migrations
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| anyhow!("No migration from version {version}"))?;
+        value = (migration.apply)(value)?;
+        version += 1;
+    }
+    Ok(value)
+}
+
+/// Reads the kind and version fields of a persisted document
+///
+/// # Errors
+/// If the document has no kind or numeric version, an error will be returned
+pub fn header_of(value: &Value) -> Result<(DocumentKind, u32)> {
+    let kind = value
+        .get("kind")
+        .cloned()
+        .ok_or_else(|| anyhow!("Missing kind field"))?;
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .and_then(|v| u32::try_from(v).ok())
+        .ok_or_else(|| anyhow!("Missing version field"))?;
+    Ok((serde_json::from_value(kind)?, version))
+}
+
+/// Upgrades a snapshot, journal or manifest file to the current version, in place
+///
+/// # Errors
+/// If the file can't be read or written or is not a supported document, an error will be returned
+pub async fn migrate_file(path: &Path) -> Result<()> {
+    let file = File::open(path).await?;
+    let first_line = BufReader::new(file)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("Empty file"))?;
+    match header_of(&serde_json::from_str(&first_line)?)?.0 {
+        DocumentKind::Snapshot => Snapshot::read(path).await?.write(path).await,
+        DocumentKind::Journal => migrate_journal(path).await,
+        DocumentKind::Manifest => RunManifest::read(path).await?.write(path).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, ensure, Result};
+    use serde_json::{json, Value};
+
+    use crate::migration::{migrate, Migration};
+
+    fn rename_amount(mut value: Value) -> Result<Value> {
+        let amount = value
+            .as_object_mut()
+            .and_then(|o| o.remove("amount"))
+            .ok_or_else(|| anyhow!("Missing amount"))?;
+        value["value"] = amount;
+        Ok(value)
+    }
+
+    fn add_currency(mut value: Value) -> Result<Value> {
+        ensure!(value.get("currency").is_none(), "Currency already set");
+        value["currency"] = json!("USD");
+        Ok(value)
+    }
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            from: 2,
+            apply: add_currency,
+        },
+        Migration {
+            from: 1,
+            apply: rename_amount,
+        },
+    ];
+
+    #[test]
+    fn test_migrate_chain() {
+        let value = migrate(json!({"amount": "1.5"}), 1, 3, MIGRATIONS).unwrap();
+        assert_eq!(value, json!({"value": "1.5", "currency": "USD"}));
+    }
+
+    #[test]
+    fn test_migrate_newer_version() {
+        assert!(migrate(json!({}), 4, 3, MIGRATIONS).is_err());
+        assert!(migrate(json!({}), 0, 3, MIGRATIONS).is_err());
+        assert!(migrate(json!({}), 1, 3, MIGRATIONS).is_err());
+    }
+}
@@ -1,203 +1,1160 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
 
 use actix::Message;
 use bail_out::{ensure, ensure_not};
 use rust_decimal::Decimal;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{Serialize, Serializer};
+use thiserror::Error;
+
+use crate::config::{DisputeAmounts, EngineConfig, Rounding, WithdrawalDisputes};
+use crate::history::TxStore;
+use crate::money::{Currency, CurrencyMismatch, Money};
+use crate::policy::AccountPolicy;
 
 /// A transaction
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
 pub enum TransactionType {
+    /// Credits the amount to the account
     Deposit,
+    /// Debits the amount from the account, if the funds are available
     Withdrawal,
+    /// Holds the amount of a previous deposit until it is resolved or charged back
     Dispute,
+    /// Releases the funds held by a dispute
     Resolve,
+    /// Removes the funds held by a dispute and locks the account
     Chargeback,
+    /// A marker row ending a segment of the input, see `--savepoint-every`. It doesn't touch any
+    /// account.
+    Savepoint,
+    /// Opens the account of a new client, crediting the amount, if any, as its opening balance.
+    /// The opening balance can't be disputed.
+    Open,
+    /// Moves the amount from the account of the client to the one of `to_client`, either both or
+    /// neither. It is a withdrawal of the debited account and a deposit of the credited one.
+    Transfer,
+    /// An operator's command on the account, given by `action`, like unlocking it after a
+    /// chargeback was reviewed
+    Admin,
+}
+
+/// What an `Admin` transaction does to the account
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub enum AdminAction {
+    /// Lets the account take transactions again, unless it was closed
+    Unlock,
+    /// Locks the account, like a chargeback does
+    Freeze,
+    /// Locks the account for good. Only accounts holding no funds and without disputes in
+    /// progress can be closed.
+    Close,
+}
+
+impl TransactionType {
+    /// Whether the transaction is a dispute, resolve or chargeback, referring to a deposit
+    #[must_use]
+    pub fn is_dispute_step(self) -> bool {
+        matches!(self, Self::Dispute | Self::Resolve | Self::Chargeback)
+    }
 }
 
-#[derive(Deserialize, Message)]
+/// A row of the input
+#[derive(Serialize, Deserialize, JsonSchema, Message, Clone, Debug)]
 #[rtype(result = "Result<(), TransactionError>")]
+#[allow(clippy::struct_field_names)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
+    /// The client owning the account
     pub client: u16,
+    /// The id of the transaction. Disputes, resolves and chargebacks refer to the deposit's id.
     pub tx: u32,
+    /// The amount of deposits and withdrawals, with up to four decimal places
     #[serde(default)]
-    pub amount: Option<Decimal>,
+    #[schemars(schema_with = "decimal_schema")]
+    pub amount: Option<Money>,
+    /// Groups related transactions, like an authorization, its capture and its refund. A
+    /// dispute, resolve or chargeback with a reference applies to every deposit of the group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<u32>,
+    /// The client whose account a transfer credits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_client: Option<u16>,
+    /// What an `Admin` transaction does to the account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<AdminAction>,
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl Transaction {
+    /// A transaction without a reference, recipient or action, for the tests
+    #[must_use]
+    pub fn for_test(
+        transaction_type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    ) -> Self {
+        Self {
+            transaction_type,
+            client,
+            tx,
+            amount: amount.map(Money::from),
+            reference: None,
+            to_client: None,
+            action: None,
+        }
+    }
+
+    /// A deposit of `amount`, for the tests
+    #[must_use]
+    pub fn test_deposit(client: u16, tx: u32, amount: Decimal) -> Self {
+        Self::for_test(TransactionType::Deposit, client, tx, Some(amount))
+    }
+
+    /// The transaction in the group `reference`
+    #[must_use]
+    pub fn with_reference(self, reference: u32) -> Self {
+        Self {
+            reference: Some(reference),
+            ..self
+        }
+    }
+
+    /// The transfer crediting `to_client`
+    #[must_use]
+    pub fn with_recipient(self, to_client: u16) -> Self {
+        Self {
+            to_client: Some(to_client),
+            ..self
+        }
+    }
+
+    /// The admin transaction taking `action`
+    #[must_use]
+    pub fn with_action(self, action: AdminAction) -> Self {
+        Self {
+            action: Some(action),
+            ..self
+        }
+    }
+}
+
+/// Decimals are written as strings to keep their precision
+fn decimal_schema(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "type": ["string", "null"],
+        "format": "decimal",
+        "pattern": "^-?[0-9]+(\\.[0-9]+)?$"
+    })
 }
 
 /// To store transaction history
-#[derive(Clone)]
-enum MoneyTransaction {
-    Deposit(Decimal),
-    Withdraw(Decimal),
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum MoneyTransaction {
+    Deposit(Money),
+    Withdraw(Money),
 }
 
 impl MoneyTransaction {
-    fn value(&self) -> &Decimal {
+    fn value(&self) -> &Money {
         match self {
             MoneyTransaction::Deposit(v) | MoneyTransaction::Withdraw(v) => v,
         }
     }
+
+    fn is_deposit(&self) -> bool {
+        matches!(self, MoneyTransaction::Deposit(_))
+    }
+
+    /// The transaction with its value out of its currency, as it is written
+    fn plain(self) -> Self {
+        match self {
+            MoneyTransaction::Deposit(v) => MoneyTransaction::Deposit(Money::new(v.amount())),
+            MoneyTransaction::Withdraw(v) => MoneyTransaction::Withdraw(Money::new(v.amount())),
+        }
+    }
+
+    /// The transaction with its value in `currency`
+    fn of(self, currency: Currency) -> Result<Self, CurrencyMismatch> {
+        Ok(match self {
+            MoneyTransaction::Deposit(v) => MoneyTransaction::Deposit(v.of(currency)?),
+            MoneyTransaction::Withdraw(v) => MoneyTransaction::Withdraw(v.of(currency)?),
+        })
+    }
 }
 
 /// A message to instruct the actor to return the current account status of the actor
 /// This will also instruct the system to stop the `AccountHandler` actor
-#[derive(Message)]
+#[derive(Message, Clone)]
 #[rtype(result = "Account")]
 pub struct Collect;
 
-/// Possible errors for transactions' operations.
-#[derive(Debug)]
+/// A message to ask the actor for the complete state of its account
+#[derive(Message, Clone)]
+#[rtype(result = "AccountState")]
+pub struct GetState;
+
+/// A message to replace the account of the actor with a previous state of it
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct Restore(pub AccountState);
+
+/// Possible errors for transactions' operations, with the client and the id of the transaction
+/// rejected
+#[derive(Error, Clone, Debug, PartialEq)]
 pub enum TransactionError {
-    InsufficientFunds,
-    InvalidOperation,
-    AccountLocked,
-    TransactionAlreadyInDispute,
-    TransactionNotInDispute,
-    TransactionNotFound,
+    #[error("insufficient funds in the account of client {client} for {amount} (tx {tx})")]
+    InsufficientFunds { client: u16, tx: u32, amount: Money },
+    /// The transaction misses its amount or can't be applied to an account, like a savepoint or
+    /// a dispute of an operation that can't be disputed
+    #[error("invalid operation for the account of client {client} (tx {tx})")]
+    InvalidOperation { client: u16, tx: u32 },
+    #[error("the account of client {client} is locked (tx {tx})")]
+    AccountLocked { client: u16, tx: u32 },
+    #[error("transaction {tx} of client {client} is already in dispute")]
+    TransactionAlreadyInDispute { client: u16, tx: u32 },
+    #[error("transaction {tx} of client {client} is not in dispute")]
+    TransactionNotInDispute { client: u16, tx: u32 },
+    /// A dispute of the transaction was already resolved and it can't be disputed again
+    #[error("a dispute of transaction {tx} of client {client} was already resolved")]
+    TransactionAlreadyResolved { client: u16, tx: u32 },
+    #[error("transaction {tx} of client {client} not found")]
+    TransactionNotFound { client: u16, tx: u32 },
+    /// The withdrawal is above the limit of the account
+    #[error("withdrawal of {amount} above the limit of {limit} of client {client} (tx {tx})")]
+    LimitExceeded {
+        client: u16,
+        tx: u32,
+        amount: Money,
+        limit: Money,
+    },
+    /// The policy of the account doesn't allow disputes
+    #[error("disputes not allowed for the account of client {client} (tx {tx})")]
+    DisputesBlocked { client: u16, tx: u32 },
+    /// The account already has as many open disputes as allowed
+    #[error("too many open disputes for the account of client {client} (tx {tx})")]
+    TooManyOpenDisputes { client: u16, tx: u32 },
+    /// The amount of a dispute, resolve or chargeback differs from the amount of its deposit
+    #[error("amount {amount} differs from the {expected} of transaction {tx} of client {client}")]
+    AmountMismatch {
+        client: u16,
+        tx: u32,
+        amount: Money,
+        expected: Money,
+    },
+    /// The client of an opening transaction already has an account
+    #[error("the account of client {client} is already open (tx {tx})")]
+    AccountExists { client: u16, tx: u32 },
+    /// The client has no account and accounts must be opened first
+    #[error("the account of client {client} is not open (tx {tx})")]
+    AccountNotOpen { client: u16, tx: u32 },
+    /// Another deposit, withdrawal or opening with the same id was applied, to this or another
+    /// client
+    #[error("transaction {tx} was already applied (client {client})")]
+    DuplicateTransaction { client: u16, tx: u32 },
+    /// A closed account can't be unlocked
+    #[error("the account of client {client} is closed (tx {tx})")]
+    AccountClosed { client: u16, tx: u32 },
+    /// The account holds funds or has disputes in progress, so it can't be closed
+    #[error("the account of client {client} is not settled (tx {tx})")]
+    AccountNotSettled { client: u16, tx: u32 },
+    /// The account holds less than the amount of a disputed transaction
+    #[error(
+        "inconsistent state in the account of client {client}: held {held} doesn't cover the \
+        {amount} of transaction {tx}"
+    )]
+    InconsistentState {
+        client: u16,
+        tx: u32,
+        held: Money,
+        amount: Money,
+    },
+    /// An amount of the transaction is in another currency than the account
+    #[error("amount in {currency} for the {expected} account of client {client} (tx {tx})")]
+    CurrencyMismatch {
+        client: u16,
+        tx: u32,
+        currency: Currency,
+        expected: Currency,
+    },
+    /// The transaction history of the account can't be read or written
+    #[error("transaction history of client {client} unavailable (tx {tx}): {reason}")]
+    HistoryUnavailable {
+        client: u16,
+        tx: u32,
+        reason: String,
+    },
 }
 
-/// An entity containing a client's account values
-#[derive(Serialize, Clone)]
+impl TransactionError {
+    /// Maps the currencies of two amounts of a transaction that can't be combined to its error
+    pub fn currency_mismatch(client: u16, tx: u32) -> impl Fn(CurrencyMismatch) -> Self {
+        move |CurrencyMismatch(currency, expected)| Self::CurrencyMismatch {
+            client,
+            tx,
+            currency,
+            expected,
+        }
+    }
+
+    /// Whether the transaction referred to an unknown deposit or withdrawal, which is not counted
+    /// as a rejection
+    #[must_use]
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::TransactionNotFound { .. })
+    }
+
+    /// A short code of the error, the same for every client and transaction
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InsufficientFunds { .. } => "insufficient_funds",
+            Self::InvalidOperation { .. } => "invalid_operation",
+            Self::AccountLocked { .. } => "account_locked",
+            Self::TransactionAlreadyInDispute { .. } => "already_in_dispute",
+            Self::TransactionNotInDispute { .. } => "not_in_dispute",
+            Self::TransactionAlreadyResolved { .. } => "already_resolved",
+            Self::TransactionNotFound { .. } => "not_found",
+            Self::LimitExceeded { .. } => "limit_exceeded",
+            Self::DisputesBlocked { .. } => "disputes_blocked",
+            Self::TooManyOpenDisputes { .. } => "too_many_open_disputes",
+            Self::AmountMismatch { .. } => "amount_mismatch",
+            Self::AccountExists { .. } => "account_exists",
+            Self::AccountNotOpen { .. } => "account_not_open",
+            Self::DuplicateTransaction { .. } => "duplicate_transaction",
+            Self::AccountClosed { .. } => "account_closed",
+            Self::AccountNotSettled { .. } => "account_not_settled",
+            Self::InconsistentState { .. } => "inconsistent_state",
+            Self::CurrencyMismatch { .. } => "currency_mismatch",
+            Self::HistoryUnavailable { .. } => "history_unavailable",
+        }
+    }
+}
+
+/// How the transactions of an account ended, kept along with its state. The transactions of a
+/// rolled back segment are left out, like their effects.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct AccountCounters {
+    pub applied: u64,
+    /// The rejected transactions by the kind of their error, unknown transactions included
+    pub rejected: BTreeMap<String, u64>,
+    pub disputes_opened: u64,
+    /// The disputes resolved or charged back
+    pub disputes_closed: u64,
+}
+
+impl AccountCounters {
+    fn count(&mut self, transaction_type: TransactionType, error: Option<&TransactionError>) {
+        if let Some(e) = error {
+            *self.rejected.entry(e.kind().into()).or_default() += 1;
+            return;
+        }
+        self.applied += 1;
+        match transaction_type {
+            TransactionType::Dispute => self.disputes_opened += 1,
+            TransactionType::Resolve | TransactionType::Chargeback => self.disputes_closed += 1,
+            _ => {}
+        }
+    }
+
+    /// How many transactions were rejected, whatever the reason
+    #[must_use]
+    pub fn rejections(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+}
+
+/// The deposits and withdrawals of an account by id, looked up by their disputes
+#[derive(Clone)]
+enum TxHistory {
+    /// Kept by the account itself
+    Memory(HashMap<u32, MoneyTransaction>),
+    /// Kept in a store shared by the accounts, outside of their state
+    Stored(Arc<dyn TxStore>),
+}
+
+/// An entity containing a client's account values. It is written as its `AccountRecord`.
+#[derive(Clone)]
 pub struct Account {
     client: u16,
-    available: Decimal,
-    held: Decimal,
-    total: Decimal,
+    available: Money,
+    held: Money,
+    total: Money,
     locked: bool,
-    #[serde(skip)]
+    /// Closed by an operator, so it stays locked
+    closed: bool,
     disputed: HashSet<u32>,
-    #[serde(skip)]
+    /// The transactions whose dispute was resolved
+    resolved: HashSet<u32>,
+    tx_history: TxHistory,
+    /// The deposits and withdrawals of every reference
+    groups: HashMap<u32, BTreeSet<u32>>,
+    counters: AccountCounters,
+    config: EngineConfig,
+}
+
+impl Serialize for Account {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AccountRecord::from(self).serialize(serializer)
+    }
+}
+
+/// The values of an account as they are written in the output
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct AccountRecord {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl AccountRecord {
+    /// An unlocked record whose total is the sum of the amounts, for the tests
+    #[cfg(test)]
+    #[must_use]
+    pub fn for_test(client: u16, available: Decimal, held: Decimal) -> Self {
+        Self {
+            client,
+            available,
+            held,
+            total: available + held,
+            locked: false,
+        }
+    }
+
+    /// The record of an account, with its amounts rounded as they are written. The total is the
+    /// sum of the rounded amounts, so the record stays consistent when the account keeps more
+    /// decimal places than it writes.
+    fn new(client: u16, available: Money, held: Money, locked: bool, rounding: Rounding) -> Self {
+        let rounding = rounding.for_display();
+        let (available, held) = (available.round(rounding), held.round(rounding));
+        Self {
+            client,
+            available: available.amount(),
+            held: held.amount(),
+            total: available.amount() + held.amount(),
+            locked,
+        }
+    }
+}
+
+impl From<&Account> for AccountRecord {
+    fn from(account: &Account) -> Self {
+        Self::new(
+            account.client,
+            account.available,
+            account.held,
+            account.locked,
+            account.config.rounding,
+        )
+    }
+}
+
+impl AccountRecord {
+    /// The record of an account state, rounded as set by the engine that applied it
+    #[must_use]
+    pub fn from_state(state: &AccountState, rounding: Rounding) -> Self {
+        Self::new(
+            state.client,
+            state.available,
+            state.held,
+            state.locked,
+            rounding,
+        )
+    }
+}
+
+impl From<&AccountState> for AccountRecord {
+    fn from(state: &AccountState) -> Self {
+        Self::from_state(state, Rounding::default())
+    }
+}
+
+/// The balances of an account and whether it is locked, without its history
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Balances {
+    pub available: Money,
+    pub held: Money,
+    pub locked: bool,
+}
+
+impl From<&Account> for Balances {
+    fn from(account: &Account) -> Self {
+        Self {
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+        }
+    }
+}
+
+/// The complete state of an account, as persisted in snapshots. The transaction history of an
+/// account keeping it in a `TxStore` is left out, the store persists it. The amounts are kept
+/// without their currency, which is the one of the settings the account is restored with.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct AccountState {
+    client: u16,
+    available: Money,
+    held: Money,
+    total: Money,
+    locked: bool,
+    #[serde(default)]
+    closed: bool,
+    disputed: HashSet<u32>,
+    #[serde(default)]
+    resolved: HashSet<u32>,
     tx_history: HashMap<u32, MoneyTransaction>,
+    #[serde(default)]
+    groups: HashMap<u32, BTreeSet<u32>>,
+    #[serde(default)]
+    counters: AccountCounters,
+}
+
+impl AccountState {
+    /// The client owning the account
+    #[must_use]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// The ids of the deposits and withdrawals kept in the history of the account
+    pub fn tx_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.tx_history.keys().copied()
+    }
+
+    /// The ids of the transactions in dispute, in order
+    #[must_use]
+    pub fn disputed(&self) -> Vec<u32> {
+        let mut disputed: Vec<_> = self.disputed.iter().copied().collect();
+        disputed.sort_unstable();
+        disputed
+    }
+
+    /// Whether the account holds no funds and has no dispute in progress, so it can be archived
+    #[must_use]
+    pub fn is_settled(&self) -> bool {
+        self.total.amount().is_zero() && self.held.amount().is_zero() && self.disputed.is_empty()
+    }
+}
+
+impl From<&Account> for AccountState {
+    fn from(account: &Account) -> Self {
+        Self {
+            client: account.client,
+            available: Money::new(account.available.amount()),
+            held: Money::new(account.held.amount()),
+            total: Money::new(account.total.amount()),
+            locked: account.locked,
+            closed: account.closed,
+            disputed: account.disputed.clone(),
+            resolved: account.resolved.clone(),
+            tx_history: match &account.tx_history {
+                TxHistory::Memory(history) => history.clone(),
+                TxHistory::Stored(_) => HashMap::new(),
+            },
+            groups: account.groups.clone(),
+            counters: account.counters.clone(),
+        }
+    }
 }
 
 impl Account {
-    /// Creates a new instance of an account.
-    pub fn new(client: u16) -> Self {
+    /// Creates a new instance of an account using the provided engine settings.
+    #[must_use]
+    pub fn new(client: u16, config: EngineConfig) -> Self {
+        let zero = Money::with_currency(Decimal::ZERO, config.currency);
         Self {
             client,
-            available: Decimal::default(),
-            held: Decimal::default(),
-            total: Decimal::default(),
+            available: zero,
+            held: zero,
+            total: zero,
             locked: false,
+            closed: false,
             disputed: HashSet::new(),
-            tx_history: HashMap::new(),
+            resolved: HashSet::new(),
+            tx_history: TxHistory::Memory(HashMap::new()),
+            groups: HashMap::new(),
+            counters: AccountCounters::default(),
+            config,
         }
     }
 
-    /// Deposit funds
+    /// Restores an account from its persisted state using the provided engine settings. The state
+    /// is written without its currency, so its amounts take the one of the settings.
+    #[must_use]
+    pub fn from_state(state: AccountState, config: EngineConfig) -> Self {
+        let of = |money: Money| Money::with_currency(money.amount(), config.currency);
+        Self {
+            client: state.client,
+            available: of(state.available),
+            held: of(state.held),
+            total: of(state.total),
+            locked: state.locked,
+            closed: state.closed,
+            disputed: state.disputed,
+            resolved: state.resolved,
+            tx_history: TxHistory::Memory(state.tx_history),
+            groups: state.groups,
+            counters: state.counters,
+            config,
+        }
+    }
+
+    /// Keeps the transaction history in a store instead of the account, moving the transactions
+    /// the account holds into it
     ///
     /// # Errors
-    /// If the account is locked, an error will be returned
-    pub fn deposit(&mut self, value: Decimal, tx: u32) -> Result<(), TransactionError> {
-        ensure_not!(self.locked, TransactionError::AccountLocked);
-        self.available += value;
-        self.tx_history.insert(tx, MoneyTransaction::Deposit(value));
-        self.update_total_round();
+    /// If the store can't be written, an error will be returned
+    pub fn with_tx_store(mut self, store: Arc<dyn TxStore>) -> anyhow::Result<Self> {
+        if let TxHistory::Memory(history) = &self.tx_history {
+            for (tx, transaction) in history {
+                store.insert(self.client, *tx, *transaction)?;
+            }
+        }
+        self.tx_history = TxHistory::Stored(store);
+        Ok(self)
+    }
+
+    /// Replaces the account with a previous state of it, keeping its settings and where its
+    /// transaction history is kept. A stored history is left as it is.
+    pub fn restore(&mut self, state: AccountState) {
+        let mut restored = Self::from_state(state, self.config);
+        if let TxHistory::Stored(store) = &self.tx_history {
+            restored.tx_history = TxHistory::Stored(Arc::clone(store));
+        }
+        *self = restored;
+    }
+
+    /// The settings the account was created with
+    #[must_use]
+    pub fn config(&self) -> EngineConfig {
+        self.config
+    }
+
+    /// Applies a transaction of the account's client
+    ///
+    /// # Errors
+    /// If the operation of the transaction fails or the transaction is a savepoint marker, an
+    /// error will be returned
+    pub fn apply(&mut self, tx: &Transaction) -> Result<(), TransactionError> {
+        let result = self.apply_operation(tx);
+        self.counters
+            .count(tx.transaction_type, result.as_ref().err());
+        result
+    }
+
+    fn apply_operation(&mut self, tx: &Transaction) -> Result<(), TransactionError> {
+        if let (true, Some(reference)) = (tx.transaction_type.is_dispute_step(), tx.reference) {
+            return self.apply_to_group(tx.transaction_type, tx.tx, reference);
+        }
+        if let (DisputeAmounts::Verify, Some(amount)) = (self.config.dispute_amounts, tx.amount) {
+            self.verify_amount(tx.transaction_type, tx.tx, amount)?;
+        }
+        let invalid = TransactionError::InvalidOperation {
+            client: self.client,
+            tx: tx.tx,
+        };
+        // the account credited by a transfer takes it as a deposit, the debited one as a withdrawal
+        let transaction_type = match tx.transaction_type {
+            TransactionType::Transfer if tx.to_client == Some(self.client) => {
+                TransactionType::Deposit
+            }
+            TransactionType::Transfer => TransactionType::Withdrawal,
+            transaction_type => transaction_type,
+        };
+        let result = match transaction_type {
+            TransactionType::Deposit => self.deposit(tx.amount.ok_or(invalid)?, tx.tx),
+            TransactionType::Withdrawal => self.withdraw(tx.amount.ok_or(invalid)?, tx.tx),
+            TransactionType::Dispute => self.dispute(tx.tx),
+            TransactionType::Resolve => self.resolve(tx.tx),
+            TransactionType::Chargeback => self.chargeback(tx.tx),
+            TransactionType::Savepoint | TransactionType::Transfer => Err(invalid),
+            TransactionType::Open => self.open(tx.amount, tx.tx),
+            TransactionType::Admin => self.administer(tx.action.ok_or(invalid)?, tx.tx),
+        };
+        let grouped = matches!(
+            tx.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        );
+        if let (Ok(()), true, Some(reference)) = (&result, grouped, tx.reference) {
+            self.groups.entry(reference).or_default().insert(tx.tx);
+        }
+        result
+    }
+
+    /// Disputes the deposits, and withdrawals if they can be disputed, of a group that aren't
+    /// disputed yet, or resolves or charges back the disputed ones, in the order of their ids.
+    /// Either all of them are or none is.
+    ///
+    /// # Errors
+    /// If the account is locked, the group is unknown, none of its deposits can take the step or
+    /// the step fails for one of them, an error will be returned. The errors of the group itself
+    /// are reported for the step's transaction id `step`.
+    fn apply_to_group(
+        &mut self,
+        transaction_type: TransactionType,
+        step: u32,
+        reference: u32,
+    ) -> Result<(), TransactionError> {
+        let client = self.client;
+        ensure_not!(
+            self.locked,
+            TransactionError::AccountLocked { client, tx: step }
+        );
+        let group = self
+            .groups
+            .get(&reference)
+            .ok_or(TransactionError::TransactionNotFound { client, tx: step })?;
+        let dispute = transaction_type == TransactionType::Dispute;
+        let mut txs = Vec::new();
+        for &tx in group {
+            let disputable = self.history(tx)?.is_some_and(|t| self.disputable(&t));
+            if disputable && self.disputed.contains(&tx) != dispute {
+                txs.push(tx);
+            }
+        }
+        if txs.is_empty() {
+            return Err(if dispute {
+                TransactionError::TransactionAlreadyInDispute { client, tx: step }
+            } else {
+                TransactionError::TransactionNotInDispute { client, tx: step }
+            });
+        }
+        let checkpoint = self.clone();
+        for tx in txs {
+            let result = match transaction_type {
+                TransactionType::Dispute => self.dispute(tx),
+                TransactionType::Resolve => self.resolve(tx),
+                _ => self.remove_disputed(tx),
+            };
+            if let Err(e) = result {
+                // an inconsistency found on the way may freeze the account
+                let frozen = self.locked;
+                *self = checkpoint;
+                self.locked |= frozen;
+                return Err(e);
+            }
+        }
+        if transaction_type == TransactionType::Chargeback {
+            self.locked = self.policy().locks_on_chargeback();
+        }
+        Ok(())
+    }
+
+    /// Checks the amount given by a dispute, resolve or chargeback matches the deposit it refers
+    /// to. Unknown transactions are left to the operation, which rejects them.
+    fn verify_amount(
+        &self,
+        transaction_type: TransactionType,
+        tx: u32,
+        amount: Money,
+    ) -> Result<(), TransactionError> {
+        let settles = transaction_type.is_dispute_step();
+        if !settles {
+            return Ok(());
+        }
+        if let Some(MoneyTransaction::Deposit(value)) = self.history(tx)? {
+            let amount = self.in_currency(amount, tx)?;
+            ensure!(
+                value == amount,
+                TransactionError::AmountMismatch {
+                    client: self.client,
+                    tx,
+                    amount,
+                    expected: value,
+                }
+            );
+        }
         Ok(())
     }
 
-    /// Withdraw funds
+    /// Credits the opening balance of a new account. Whether the account is new is up to the
+    /// engine, which knows every account.
     ///
     /// # Errors
-    /// If the account is locked or there's no available funds, an error will be returned
-    pub fn withdraw(&mut self, value: Decimal, tx: u32) -> Result<(), TransactionError> {
-        ensure_not!(self.locked, TransactionError::AccountLocked);
-        ensure!(self.available >= value, TransactionError::InsufficientFunds);
-        self.available -= value;
-        self.tx_history
-            .insert(tx, MoneyTransaction::Withdraw(value));
-        self.update_total_round();
+    /// If the account is locked, an error will be returned
+    pub fn open(&mut self, balance: Option<Money>, tx: u32) -> Result<(), TransactionError> {
+        self.ensure_unlocked(tx)?;
+        let balance = self.in_currency(balance.unwrap_or_default(), tx)?;
+        self.available = self.add(self.available, balance, tx)?;
+        self.update_total_round(tx)
+    }
+
+    /// Applies an operator's command: unlocks the account after a review, freezes it or closes it
+    /// for good once it is settled
+    ///
+    /// # Errors
+    /// If a closed account is unlocked or an account holding funds or with disputes in progress is
+    /// closed, an error will be returned
+    pub fn administer(&mut self, action: AdminAction, tx: u32) -> Result<(), TransactionError> {
+        let client = self.client;
+        match action {
+            AdminAction::Unlock => {
+                ensure_not!(self.closed, TransactionError::AccountClosed { client, tx });
+                self.locked = false;
+            }
+            AdminAction::Freeze => self.locked = true,
+            AdminAction::Close => {
+                let settled = self.total.amount().is_zero()
+                    && self.held.amount().is_zero()
+                    && self.disputed.is_empty();
+                ensure!(settled, TransactionError::AccountNotSettled { client, tx });
+                self.locked = true;
+                self.closed = true;
+            }
+        }
         Ok(())
     }
 
-    /// Dispute funds
+    /// Deposit funds
     ///
     /// # Errors
-    /// If the account is locked, there's no available funds, the transaction is already in dispute,
-    /// the origin transaction could not be found or the origin operation is not a deposit, an error
-    /// will be returned
+    /// If the account is locked, an error will be returned
+    pub fn deposit(&mut self, value: Money, tx: u32) -> Result<(), TransactionError> {
+        self.ensure_unlocked(tx)?;
+        let value = self.in_currency(value, tx)?;
+        self.record(tx, MoneyTransaction::Deposit(value))?;
+        self.available = self.add(self.available, value, tx)?;
+        self.update_total_round(tx)
+    }
+
+    /// Withdraw funds, plus the withdrawal fee of the account
+    ///
+    /// # Errors
+    /// If the account is locked, the amount is above the withdrawal limit or there's no available
+    /// funds, an error will be returned
+    pub fn withdraw(&mut self, value: Money, tx: u32) -> Result<(), TransactionError> {
+        self.ensure_unlocked(tx)?;
+        let value = self.in_currency(value, tx)?;
+        if let Some(limit) = self.policy().max_withdrawal() {
+            let limit = self.in_currency(limit, tx)?;
+            ensure!(
+                value <= limit,
+                TransactionError::LimitExceeded {
+                    client: self.client,
+                    tx,
+                    amount: value,
+                    limit,
+                }
+            );
+        }
+        let fee = self.in_currency(self.policy().withdrawal_fee(), tx)?;
+        let debit = self.add(value, fee.round(self.config.rounding), tx)?;
+        let allowed = self
+            .policy()
+            .allows_withdrawal(self.available, debit)
+            .map_err(TransactionError::currency_mismatch(self.client, tx))?;
+        ensure!(
+            allowed,
+            TransactionError::InsufficientFunds {
+                client: self.client,
+                tx,
+                amount: debit,
+            }
+        );
+        self.record(tx, MoneyTransaction::Withdraw(debit))?;
+        self.available = self.subtract(self.available, debit, tx)?;
+        self.update_total_round(tx)
+    }
+
+    /// Dispute funds. A disputed deposit moves its amount from the available funds to the held
+    /// ones, a disputed withdrawal holds its amount as a pending credit.
+    ///
+    /// # Errors
+    /// If the account is locked or doesn't allow disputes, there's no available funds, the
+    /// transaction is already in dispute or was resolved and can't be disputed again, the origin
+    /// transaction could not be found or the origin operation can't be disputed, an error will be
+    /// returned
     pub fn dispute(&mut self, tx: u32) -> Result<(), TransactionError> {
-        ensure_not!(self.locked, TransactionError::AccountLocked);
+        self.ensure_unlocked(tx)?;
+        let client = self.client;
+        ensure!(
+            self.policy().allows_disputes(),
+            TransactionError::DisputesBlocked { client, tx }
+        );
         ensure_not!(
             self.disputed.contains(&tx),
-            TransactionError::TransactionAlreadyInDispute
+            TransactionError::TransactionAlreadyInDispute { client, tx }
+        );
+        ensure_not!(
+            self.config.block_redisputes && self.resolved.contains(&tx),
+            TransactionError::TransactionAlreadyResolved { client, tx }
         );
         let origin_tx = self
-            .tx_history
-            .get(&tx)
-            .ok_or(TransactionError::TransactionNotFound)?;
+            .history(tx)?
+            .ok_or(TransactionError::TransactionNotFound {
+                client: self.client,
+                tx,
+            })?;
         ensure!(
-            matches!(origin_tx, MoneyTransaction::Deposit(_)),
-            TransactionError::InvalidOperation
+            self.disputable(&origin_tx),
+            TransactionError::InvalidOperation { client, tx }
         );
-        let value = origin_tx.value();
         ensure!(
-            self.available >= *value,
-            TransactionError::InsufficientFunds
+            self.policy().allows_open_dispute(self.disputed.len()),
+            TransactionError::TooManyOpenDisputes { client, tx }
         );
-        self.available -= value;
-        self.held += value;
+        let value = *origin_tx.value();
+        if let MoneyTransaction::Deposit(_) = origin_tx {
+            let allowed = self
+                .policy()
+                .allows_dispute(self.available, value)
+                .map_err(TransactionError::currency_mismatch(client, tx))?;
+            ensure!(
+                allowed,
+                TransactionError::InsufficientFunds {
+                    client,
+                    tx,
+                    amount: value,
+                }
+            );
+            self.available = self.subtract(self.available, value, tx)?;
+        }
+        self.held = self.add(self.held, value, tx)?;
         self.disputed.insert(tx);
-        self.update_total_round();
-        Ok(())
+        self.update_total_round(tx)
     }
 
-    /// Resolves a dispute
+    /// Resolves a dispute, releasing the held amount of a deposit or dropping the pending credit
+    /// of a withdrawal
     ///
     /// # Errors
     /// If the account is locked, the origin transaction is not in
-    /// dispute or the origin transaction doesn't exist, an error will be returned
+    /// dispute, the origin transaction doesn't exist or the held funds don't cover it, an error
+    /// will be returned
     pub fn resolve(&mut self, tx: u32) -> Result<(), TransactionError> {
-        ensure_not!(self.locked, TransactionError::AccountLocked);
-        let value = self
-            .tx_history
-            .get(&tx)
-            .ok_or(TransactionError::TransactionNotFound)?
-            .value();
+        self.ensure_unlocked(tx)?;
+        let origin_tx = self
+            .history(tx)?
+            .ok_or(TransactionError::TransactionNotFound {
+                client: self.client,
+                tx,
+            })?;
+        let (value, deposit) = (*origin_tx.value(), origin_tx.is_deposit());
         ensure!(
             self.disputed.contains(&tx),
-            TransactionError::TransactionNotInDispute
+            TransactionError::TransactionNotInDispute {
+                client: self.client,
+                tx,
+            }
         );
-        assert!(self.held >= *value); // this should never happen, so panic
-        self.available += value;
-        self.held -= value;
-        self.update_total_round();
+        self.ensure_held(value, tx)?;
+        if deposit {
+            self.available = self.add(self.available, value, tx)?;
+        }
+        self.held = self.subtract(self.held, value, tx)?;
+        self.update_total_round(tx)?;
         self.disputed.remove(&tx);
+        self.resolved.insert(tx);
         Ok(())
     }
 
-    /// Chargebacks a dispute. The account will be locked and no more transactions will be accepted
+    /// Chargebacks a dispute. Unless the policy of the account says otherwise, the account will be
+    /// locked and no more transactions will be accepted
     ///
     /// # Errors
     /// If the account is locked, the origin transaction is not in
-    /// dispute or the origin transaction doesn't exist, an error will be returned
+    /// dispute, the origin transaction doesn't exist or the held funds don't cover it, an error
+    /// will be returned
     pub fn chargeback(&mut self, tx: u32) -> Result<(), TransactionError> {
-        ensure_not!(self.locked, TransactionError::AccountLocked);
-        let value = self
-            .tx_history
-            .get(&tx)
-            .ok_or(TransactionError::TransactionNotFound)?
-            .value();
+        self.ensure_unlocked(tx)?;
+        self.remove_disputed(tx)?;
+        self.locked = self.policy().locks_on_chargeback();
+        Ok(())
+    }
+
+    /// Removes the funds held by a disputed deposit from the account, or credits the pending
+    /// credit of a disputed withdrawal to the available funds, without locking it
+    fn remove_disputed(&mut self, tx: u32) -> Result<(), TransactionError> {
+        let origin_tx = self
+            .history(tx)?
+            .ok_or(TransactionError::TransactionNotFound {
+                client: self.client,
+                tx,
+            })?;
+        let (value, deposit) = (*origin_tx.value(), origin_tx.is_deposit());
         ensure!(
             self.disputed.contains(&tx),
-            TransactionError::TransactionNotInDispute
+            TransactionError::TransactionNotInDispute {
+                client: self.client,
+                tx,
+            }
         );
-        assert!(self.held >= *value); // this should never happen, so panic
-        self.held -= value;
-        self.locked = true;
-        self.update_total_round();
+        self.ensure_held(value, tx)?;
+        if !deposit {
+            self.available = self.add(self.available, value, tx)?;
+        }
+        self.held = self.subtract(self.held, value, tx)?;
+        self.update_total_round(tx)?;
         self.disputed.remove(&tx);
         Ok(())
     }
 
-    /// Updates the total value of the account and rounds the decimal numbers to 4 digits.
-    /// Should be called after every transaction.
-    fn update_total_round(&mut self) {
-        self.total = self.held + self.available;
-        self.total = self.total.round_dp(4);
-        self.available = self.available.round_dp(4);
-        self.held = self.held.round_dp(4);
+    /// Fails with `AccountLocked` for the transaction if the account is locked
+    fn ensure_unlocked(&self, tx: u32) -> Result<(), TransactionError> {
+        ensure_not!(
+            self.locked,
+            TransactionError::AccountLocked {
+                client: self.client,
+                tx,
+            }
+        );
+        Ok(())
+    }
+
+    /// The client owning the account
+    #[must_use]
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// How the transactions of the account ended so far
+    #[must_use]
+    pub fn counters(&self) -> &AccountCounters {
+        &self.counters
+    }
+
+    /// The deposit or withdrawal with the id, if any, with its value in the account's currency
+    fn history(&self, tx: u32) -> Result<Option<MoneyTransaction>, TransactionError> {
+        let transaction =
+            match &self.tx_history {
+                TxHistory::Memory(history) => history.get(&tx).copied(),
+                TxHistory::Stored(store) => store.get(self.client, tx).map_err(|e| {
+                    TransactionError::HistoryUnavailable {
+                        client: self.client,
+                        tx,
+                        reason: e.to_string(),
+                    }
+                })?,
+            };
+        transaction
+            .map(|transaction| transaction.of(self.config.currency))
+            .transpose()
+            .map_err(TransactionError::currency_mismatch(self.client, tx))
+    }
+
+    /// Adds a deposit or withdrawal to the history, without its currency
+    fn record(&mut self, tx: u32, transaction: MoneyTransaction) -> Result<(), TransactionError> {
+        let transaction = transaction.plain();
+        match &mut self.tx_history {
+            TxHistory::Memory(history) => {
+                history.insert(tx, transaction);
+                Ok(())
+            }
+            TxHistory::Stored(store) => store.insert(self.client, tx, transaction).map_err(|e| {
+                TransactionError::HistoryUnavailable {
+                    client: self.client,
+                    tx,
+                    reason: e.to_string(),
+                }
+            }),
+        }
+    }
+
+    /// Whether a transaction of the history can be disputed
+    fn disputable(&self, transaction: &MoneyTransaction) -> bool {
+        match transaction {
+            MoneyTransaction::Deposit(_) => true,
+            MoneyTransaction::Withdraw(_) => {
+                self.config.withdrawal_disputes == WithdrawalDisputes::Hold
+            }
+        }
+    }
+
+    /// The rules of the account's product
+    fn policy(&self) -> &dyn AccountPolicy {
+        self.config.policy.rules()
+    }
+
+    /// The held funds of the account
+    #[must_use]
+    pub fn held(&self) -> Money {
+        self.held
+    }
+
+    /// The available and held funds of the account
+    #[must_use]
+    pub fn total(&self) -> Money {
+        self.total
+    }
+
+    /// What the total loses when the account is written rounded. Always zero unless the rounding
+    /// is left to the output or fewer decimal places are written than kept.
+    #[must_use]
+    pub fn rounding_remainder(&self) -> Money {
+        let rounding = self.config.rounding.for_display();
+        let rounded = self.available.round(rounding).amount() + self.held.round(rounding).amount();
+        Money::with_currency(self.total.amount() - rounded, self.config.currency)
+    }
+
+    /// Sums the values of the transactions currently in dispute. It should always match the held
+    /// funds. Transactions that can't be read from a stored history are left out.
+    #[must_use]
+    pub fn disputed_total(&self) -> Money {
+        let total = self
+            .disputed
+            .iter()
+            .filter_map(|tx| self.history(*tx).ok().flatten())
+            .map(|t| t.value().amount())
+            .sum();
+        Money::with_currency(total, self.config.currency)
+    }
+
+    /// Checks the held funds cover the value of a disputed transaction. This should never fail, so
+    /// when it does the account may be locked to avoid further damage, depending on the config.
+    fn ensure_held(&mut self, value: Money, tx: u32) -> Result<(), TransactionError> {
+        let covered = self
+            .held
+            .checked_cmp(value)
+            .map_err(TransactionError::currency_mismatch(self.client, tx))?;
+        if covered.is_ge() {
+            return Ok(());
+        }
+        if self.config.freeze_on_inconsistency {
+            self.locked = true;
+        }
+        Err(TransactionError::InconsistentState {
+            client: self.client,
+            tx,
+            held: self.held,
+            amount: value,
+        })
+    }
+
+    /// An amount of a transaction in the account's currency
+    fn in_currency(&self, value: Money, tx: u32) -> Result<Money, TransactionError> {
+        value
+            .of(self.config.currency)
+            .map_err(TransactionError::currency_mismatch(self.client, tx))
+    }
+
+    /// The sum of two amounts of a transaction
+    fn add(&self, left: Money, right: Money, tx: u32) -> Result<Money, TransactionError> {
+        (left + right).map_err(TransactionError::currency_mismatch(self.client, tx))
+    }
+
+    /// The difference of two amounts of a transaction
+    fn subtract(&self, left: Money, right: Money, tx: u32) -> Result<Money, TransactionError> {
+        (left - right).map_err(TransactionError::currency_mismatch(self.client, tx))
+    }
+
+    /// Updates the total value of the account and rounds the amounts as set, unless the rounding
+    /// is left to the output. Should be called after every transaction.
+    fn update_total_round(&mut self, tx: u32) -> Result<(), TransactionError> {
+        let total = self.add(self.held, self.available, tx)?;
+        if self.config.late_rounding {
+            self.total = total;
+            return Ok(());
+        }
+        let rounding = self.config.rounding;
+        self.total = total.round(rounding);
+        self.available = self.available.round(rounding);
+        self.held = self.held.round(rounding);
+        Ok(())
     }
 }
 
@@ -205,267 +1162,567 @@ impl Account {
 mod tests {
     use rust_decimal_macros::dec;
 
-    use crate::model::{Account, TransactionError};
+    use crate::config::{DisputeAmounts, EngineConfig, Rounding, RoundingMode, WithdrawalDisputes};
+    use crate::model::{
+        Account, AccountState, AdminAction, Transaction, TransactionError, TransactionType,
+    };
+    use crate::money::{Currency, Money};
+    use crate::policy::{Limits, Policy, Standard};
 
     #[test]
     fn test_rounding() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(140.12344), 2).unwrap();
-        assert_eq!(account.total, dec!(140.1234));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(140.1234));
-        account.deposit(dec!(100.00002), 1).unwrap();
-        assert_eq!(account.total, dec!(240.1234));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(240.1234));
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(140.12344).into(), 2).unwrap();
+        assert_eq!(account.total.amount(), dec!(140.1234));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(140.1234));
+        account.deposit(dec!(100.00002).into(), 1).unwrap();
+        assert_eq!(account.total.amount(), dec!(240.1234));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(240.1234));
+    }
+
+    #[test]
+    fn test_rounding_precision_and_mode() {
+        let config = EngineConfig {
+            rounding: Rounding {
+                precision: 2,
+                display: 2,
+                mode: RoundingMode::HalfUp,
+            },
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, config);
+        account.deposit(dec!(1.005).into(), 1).unwrap();
+        assert_eq!(account.available.amount(), dec!(1.01));
+        account.withdraw(dec!(0.333).into(), 2).unwrap();
+        assert_eq!(account.total.amount(), dec!(0.68));
     }
 
     #[test]
     fn test_deposit() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
-        assert_eq!(account.total, dec!(240.26));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(240.26));
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
+        assert_eq!(account.total.amount(), dec!(240.26));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(240.26));
     }
 
     #[test]
     fn test_deposit_locked() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
         account.dispute(1).unwrap();
         account.chargeback(1).unwrap();
-        let err = account.deposit(dec!(140.14), 2).unwrap_err();
-        assert!(matches!(err, TransactionError::AccountLocked));
-        assert_eq!(account.total, dec!(0));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(0));
+        let err = account.deposit(dec!(140.14).into(), 2).unwrap_err();
+        assert!(matches!(err, TransactionError::AccountLocked { .. }));
+        assert_eq!(account.total.amount(), dec!(0));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(0));
     }
 
     #[test]
     fn test_withdrawal() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
-        account.withdraw(dec!(40), 3).unwrap();
-        assert_eq!(account.total, dec!(200.26));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(200.26));
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
+        account.withdraw(dec!(40).into(), 3).unwrap();
+        assert_eq!(account.total.amount(), dec!(200.26));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(200.26));
     }
 
     #[test]
     fn test_withdrawal_locked() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
         account.dispute(1).unwrap();
         account.chargeback(1).unwrap();
-        let err = account.withdraw(dec!(140.14), 2).unwrap_err();
-        assert!(matches!(err, TransactionError::AccountLocked));
-        assert_eq!(account.total, dec!(0));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(0));
+        let err = account.withdraw(dec!(140.14).into(), 2).unwrap_err();
+        assert!(matches!(err, TransactionError::AccountLocked { .. }));
+        assert_eq!(account.total.amount(), dec!(0));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(0));
     }
 
     #[test]
     fn test_withdrawal_no_funds() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
-        let err = account.withdraw(dec!(340.14), 2).unwrap_err();
-        assert!(matches!(err, TransactionError::InsufficientFunds));
-        assert_eq!(account.total, dec!(240.26));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(240.26));
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
+        let err = account.withdraw(dec!(340.14).into(), 2).unwrap_err();
+        assert!(matches!(err, TransactionError::InsufficientFunds { .. }));
+        assert_eq!(account.total.amount(), dec!(240.26));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(240.26));
+    }
+
+    #[test]
+    fn test_withdrawal_no_funds_display() {
+        let mut account = Account::new(7, EngineConfig::default());
+        account.deposit(dec!(10).into(), 1).unwrap();
+        let err = account.withdraw(dec!(25.5).into(), 3).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "insufficient funds in the account of client 7 for 25.5 (tx 3)"
+        );
     }
 
     #[test]
     fn test_dispute() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
         account.dispute(2).unwrap();
-        account.withdraw(dec!(40.04), 3).unwrap();
-        assert_eq!(account.total, dec!(200.22));
-        assert_eq!(account.held, dec!(140.14));
-        assert_eq!(account.available, dec!(60.08));
+        account.withdraw(dec!(40.04).into(), 3).unwrap();
+        assert_eq!(account.total.amount(), dec!(200.22));
+        assert_eq!(account.held.amount(), dec!(140.14));
+        assert_eq!(account.available.amount(), dec!(60.08));
     }
 
     #[test]
     fn test_dispute_locked() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(200), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(200).into(), 2).unwrap();
         account.dispute(1).unwrap();
         account.chargeback(1).unwrap();
         let err = account.dispute(2).unwrap_err();
-        assert!(matches!(err, TransactionError::AccountLocked));
-        assert_eq!(account.total, dec!(200));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(200));
+        assert!(matches!(err, TransactionError::AccountLocked { .. }));
+        assert_eq!(account.total.amount(), dec!(200));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(200));
     }
 
     #[test]
     fn test_dispute_already_in_dispute() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
-        account.withdraw(dec!(40.04), 3).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
+        account.withdraw(dec!(40.04).into(), 3).unwrap();
         account.dispute(2).unwrap();
         let err = account.dispute(2).unwrap_err();
-        assert!(matches!(err, TransactionError::TransactionAlreadyInDispute));
-        assert_eq!(account.total, dec!(200.22));
-        assert_eq!(account.held, dec!(140.14));
-        assert_eq!(account.available, dec!(60.08));
+        assert!(matches!(
+            err,
+            TransactionError::TransactionAlreadyInDispute { .. }
+        ));
+        assert_eq!(account.total.amount(), dec!(200.22));
+        assert_eq!(account.held.amount(), dec!(140.14));
+        assert_eq!(account.available.amount(), dec!(60.08));
+    }
+
+    #[test]
+    fn test_dispute_already_resolved() {
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.dispute(1).unwrap();
+        account.resolve(1).unwrap();
+        account.dispute(1).unwrap();
+        account.resolve(1).unwrap();
+
+        let config = EngineConfig {
+            block_redisputes: true,
+            ..EngineConfig::default()
+        };
+        let mut account = Account::from_state(AccountState::from(&account), config);
+        let err = account.dispute(1).unwrap_err();
+        assert!(matches!(
+            err,
+            TransactionError::TransactionAlreadyResolved { .. }
+        ));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(100.12));
     }
 
     #[test]
     fn test_dispute_tx_not_found() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
         let err = account.dispute(3).unwrap_err();
-        assert!(matches!(err, TransactionError::TransactionNotFound));
-        assert_eq!(account.total, dec!(240.26));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(240.26));
+        assert!(matches!(err, TransactionError::TransactionNotFound { .. }));
+        assert_eq!(account.total.amount(), dec!(240.26));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(240.26));
     }
 
     #[test]
     fn test_dispute_insufficient_funds() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
-        account.withdraw(dec!(200), 3).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
+        account.withdraw(dec!(200).into(), 3).unwrap();
         let err = account.dispute(1).unwrap_err();
-        assert!(matches!(err, TransactionError::InsufficientFunds));
-        assert_eq!(account.total, dec!(40.26));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(40.26));
+        assert!(matches!(err, TransactionError::InsufficientFunds { .. }));
+        assert_eq!(account.total.amount(), dec!(40.26));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(40.26));
     }
 
     #[test]
     fn test_dispute_invalid_operation() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
-        account.withdraw(dec!(200), 3).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
+        account.withdraw(dec!(200).into(), 3).unwrap();
         let err = account.dispute(3).unwrap_err();
-        assert!(matches!(err, TransactionError::InvalidOperation));
-        assert_eq!(account.total, dec!(40.26));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(40.26));
+        assert!(matches!(err, TransactionError::InvalidOperation { .. }));
+        assert_eq!(account.total.amount(), dec!(40.26));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(40.26));
     }
 
     #[test]
     fn test_resolve() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
         account.dispute(2).unwrap();
-        account.withdraw(dec!(40.04), 3).unwrap();
+        account.withdraw(dec!(40.04).into(), 3).unwrap();
         account.resolve(2).unwrap();
-        assert_eq!(account.total, dec!(200.22));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(200.22));
+        assert_eq!(account.total.amount(), dec!(200.22));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(200.22));
     }
 
     #[test]
     fn test_resolve_locked() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(200), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(200).into(), 2).unwrap();
         account.dispute(1).unwrap();
         account.dispute(2).unwrap();
         account.chargeback(1).unwrap();
         let err = account.resolve(2).unwrap_err();
-        assert!(matches!(err, TransactionError::AccountLocked));
-        assert_eq!(account.total, dec!(200));
-        assert_eq!(account.held, dec!(200));
-        assert_eq!(account.available, dec!(0));
+        assert!(matches!(err, TransactionError::AccountLocked { .. }));
+        assert_eq!(account.total.amount(), dec!(200));
+        assert_eq!(account.held.amount(), dec!(200));
+        assert_eq!(account.available.amount(), dec!(0));
     }
 
     #[test]
     fn test_resolve_not_in_dispute() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(200), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(200).into(), 2).unwrap();
         account.dispute(1).unwrap();
         let err = account.resolve(2).unwrap_err();
-        assert!(matches!(err, TransactionError::TransactionNotInDispute));
-        assert_eq!(account.total, dec!(300.12));
-        assert_eq!(account.held, dec!(100.12));
-        assert_eq!(account.available, dec!(200));
+        assert!(matches!(
+            err,
+            TransactionError::TransactionNotInDispute { .. }
+        ));
+        assert_eq!(account.total.amount(), dec!(300.12));
+        assert_eq!(account.held.amount(), dec!(100.12));
+        assert_eq!(account.available.amount(), dec!(200));
     }
 
     #[test]
     fn test_resolve_not_found() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(200), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(200).into(), 2).unwrap();
         account.dispute(1).unwrap();
         let err = account.resolve(4).unwrap_err();
-        assert!(matches!(err, TransactionError::TransactionNotFound));
-        assert_eq!(account.total, dec!(300.12));
-        assert_eq!(account.held, dec!(100.12));
-        assert_eq!(account.available, dec!(200));
+        assert!(matches!(err, TransactionError::TransactionNotFound { .. }));
+        assert_eq!(account.total.amount(), dec!(300.12));
+        assert_eq!(account.held.amount(), dec!(100.12));
+        assert_eq!(account.available.amount(), dec!(200));
     }
 
     #[test]
     fn test_chargeback() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(140.14), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(140.14).into(), 2).unwrap();
         account.dispute(2).unwrap();
-        account.withdraw(dec!(40.04), 3).unwrap();
+        account.withdraw(dec!(40.04).into(), 3).unwrap();
         account.chargeback(2).unwrap();
-        assert_eq!(account.total, dec!(60.08));
-        assert_eq!(account.held, dec!(0));
-        assert_eq!(account.available, dec!(60.08));
+        assert_eq!(account.total.amount(), dec!(60.08));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(60.08));
         assert!(account.locked);
     }
 
     #[test]
     fn test_chargeback_locked() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(200), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(200).into(), 2).unwrap();
         account.dispute(1).unwrap();
         account.dispute(2).unwrap();
         account.chargeback(1).unwrap();
         let err = account.chargeback(2).unwrap_err();
-        assert!(matches!(err, TransactionError::AccountLocked));
-        assert_eq!(account.total, dec!(200));
-        assert_eq!(account.held, dec!(200));
-        assert_eq!(account.available, dec!(0));
+        assert!(matches!(err, TransactionError::AccountLocked { .. }));
+        assert_eq!(account.total.amount(), dec!(200));
+        assert_eq!(account.held.amount(), dec!(200));
+        assert_eq!(account.available.amount(), dec!(0));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_admin_actions() {
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(10).into(), 1).unwrap();
+        account.dispute(1).unwrap();
+        account.chargeback(1).unwrap();
+        assert!(account.locked);
+        account.administer(AdminAction::Unlock, 2).unwrap();
+        account.deposit(dec!(5).into(), 3).unwrap();
+        account.administer(AdminAction::Freeze, 4).unwrap();
+        let err = account.withdraw(dec!(5).into(), 5).unwrap_err();
+        assert!(matches!(err, TransactionError::AccountLocked { .. }));
+        let err = account.administer(AdminAction::Close, 6).unwrap_err();
+        assert!(matches!(err, TransactionError::AccountNotSettled { .. }));
+        account.administer(AdminAction::Unlock, 7).unwrap();
+        account.withdraw(dec!(5).into(), 8).unwrap();
+        account.administer(AdminAction::Close, 9).unwrap();
+        let err = account.administer(AdminAction::Unlock, 10).unwrap_err();
+        assert!(matches!(err, TransactionError::AccountClosed { .. }));
         assert!(account.locked);
     }
 
     #[test]
     fn test_chargeback_not_in_dispute() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(200), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(200).into(), 2).unwrap();
         account.dispute(1).unwrap();
         let err = account.chargeback(2).unwrap_err();
-        assert!(matches!(err, TransactionError::TransactionNotInDispute));
-        assert_eq!(account.total, dec!(300.12));
-        assert_eq!(account.held, dec!(100.12));
-        assert_eq!(account.available, dec!(200));
+        assert!(matches!(
+            err,
+            TransactionError::TransactionNotInDispute { .. }
+        ));
+        assert_eq!(account.total.amount(), dec!(300.12));
+        assert_eq!(account.held.amount(), dec!(100.12));
+        assert_eq!(account.available.amount(), dec!(200));
         assert!(!account.locked);
     }
 
     #[test]
     fn test_chargeback_not_found() {
-        let mut account = Account::new(1);
-        account.deposit(dec!(100.12), 1).unwrap();
-        account.deposit(dec!(200), 2).unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(200).into(), 2).unwrap();
         account.dispute(1).unwrap();
         let err = account.chargeback(4).unwrap_err();
-        assert!(matches!(err, TransactionError::TransactionNotFound));
-        assert_eq!(account.total, dec!(300.12));
-        assert_eq!(account.held, dec!(100.12));
-        assert_eq!(account.available, dec!(200));
+        assert!(matches!(err, TransactionError::TransactionNotFound { .. }));
+        assert_eq!(account.total.amount(), dec!(300.12));
+        assert_eq!(account.held.amount(), dec!(100.12));
+        assert_eq!(account.available.amount(), dec!(200));
         assert!(!account.locked);
     }
+
+    #[test]
+    fn test_resolve_inconsistent_state() {
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.dispute(1).unwrap();
+        account.held = dec!(50).into();
+        let err = account.resolve(1).unwrap_err();
+        assert!(matches!(
+            err,
+            TransactionError::InconsistentState { client: 1, tx: 1, held, amount }
+                if held.amount() == dec!(50) && amount.amount() == dec!(100.12)
+        ));
+        assert_eq!(account.held.amount(), dec!(50));
+        assert_eq!(account.available.amount(), dec!(0));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_chargeback_inconsistent_state_freeze() {
+        let config = EngineConfig {
+            freeze_on_inconsistency: true,
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, config);
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.dispute(1).unwrap();
+        account.held = dec!(50).into();
+        let err = account.chargeback(1).unwrap_err();
+        assert!(matches!(err, TransactionError::InconsistentState { .. }));
+        assert_eq!(account.held.amount(), dec!(50));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_withdraw_with_segment_limits() {
+        let config = EngineConfig {
+            policy: Policy::Standard(Standard {
+                limits: Limits {
+                    max_withdrawal: Some(dec!(100).into()),
+                    withdrawal_fee: dec!(1.5).into(),
+                    block_disputes: true,
+                    max_open_disputes: None,
+                },
+            }),
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, config);
+        account.deposit(dec!(200).into(), 1).unwrap();
+        let err = account.withdraw(dec!(150).into(), 2).unwrap_err();
+        assert!(
+            matches!(err, TransactionError::LimitExceeded { limit, .. } if limit.amount() == dec!(100))
+        );
+        account.withdraw(dec!(100).into(), 3).unwrap();
+        assert_eq!(account.available.amount(), dec!(98.5));
+        let err = account.withdraw(dec!(98).into(), 4).unwrap_err();
+        assert!(matches!(err, TransactionError::InsufficientFunds { .. }));
+        let err = account.dispute(1).unwrap_err();
+        assert!(matches!(err, TransactionError::DisputesBlocked { .. }));
+    }
+
+    #[test]
+    fn test_max_open_disputes() {
+        let config = EngineConfig {
+            policy: Policy::Standard(Standard {
+                limits: Limits {
+                    max_open_disputes: Some(2),
+                    ..Limits::default()
+                },
+            }),
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, config);
+        for tx in 1..=3 {
+            account.deposit(dec!(10).into(), tx).unwrap();
+        }
+        account.dispute(1).unwrap();
+        account.dispute(2).unwrap();
+        let err = account.dispute(3).unwrap_err();
+        assert!(matches!(err, TransactionError::TooManyOpenDisputes { .. }));
+        account.resolve(1).unwrap();
+        account.dispute(3).unwrap();
+        assert_eq!(account.held.amount(), dec!(20));
+    }
+
+    #[test]
+    fn test_withdrawal_disputes() {
+        let config = EngineConfig {
+            withdrawal_disputes: WithdrawalDisputes::Hold,
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, config);
+        account.deposit(dec!(100).into(), 1).unwrap();
+        account.withdraw(dec!(30).into(), 2).unwrap();
+        account.withdraw(dec!(20).into(), 3).unwrap();
+        account.dispute(2).unwrap();
+        assert_eq!(account.available.amount(), dec!(50));
+        assert_eq!(account.held.amount(), dec!(30));
+        assert_eq!(account.total.amount(), dec!(80));
+        account.resolve(2).unwrap();
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.total.amount(), dec!(50));
+
+        account.dispute(3).unwrap();
+        account.chargeback(3).unwrap();
+        assert_eq!(account.available.amount(), dec!(70));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.total.amount(), dec!(70));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_amounts_in_another_currency_are_rejected() {
+        let jpy = Currency::parse("JPY").unwrap();
+        let config = EngineConfig {
+            currency: jpy,
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, config);
+        account.deposit(dec!(100).into(), 1).unwrap();
+        assert_eq!(account.total(), Money::with_currency(dec!(100), jpy));
+        let err = account
+            .deposit(Money::with_currency(dec!(5), Currency::USD), 2)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransactionError::CurrencyMismatch {
+                client: 1,
+                tx: 2,
+                currency: Currency::USD,
+                expected: jpy,
+            }
+        );
+        assert_eq!(account.total().amount(), dec!(100));
+    }
+
+    #[test]
+    fn test_verify_dispute_amounts() {
+        let config = EngineConfig {
+            dispute_amounts: DisputeAmounts::Verify,
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, config);
+        account.deposit(dec!(10).into(), 1).unwrap();
+        let dispute = |amount| Transaction::for_test(TransactionType::Dispute, 1, 1, amount);
+        let err = account.apply(&dispute(Some(dec!(12)))).unwrap_err();
+        assert_eq!(
+            err,
+            TransactionError::AmountMismatch {
+                client: 1,
+                tx: 1,
+                amount: Money::with_currency(dec!(12), Currency::USD),
+                expected: Money::with_currency(dec!(10), Currency::USD),
+            }
+        );
+        account.apply(&dispute(Some(dec!(10.00)))).unwrap();
+        account.resolve(1).unwrap();
+        account.apply(&dispute(None)).unwrap();
+    }
+
+    #[test]
+    fn test_group_steps() {
+        let mut account = Account::new(1, EngineConfig::default());
+        let grouped = |transaction_type, tx, amount| {
+            Transaction::for_test(transaction_type, 1, tx, amount).with_reference(7)
+        };
+        account.deposit(dec!(10).into(), 10).unwrap();
+        account
+            .apply(&grouped(TransactionType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        account
+            .apply(&grouped(TransactionType::Deposit, 2, Some(dec!(5))))
+            .unwrap();
+        account
+            .apply(&grouped(TransactionType::Withdrawal, 3, Some(dec!(4))))
+            .unwrap();
+        account.dispute(1).unwrap();
+        account
+            .apply(&grouped(TransactionType::Dispute, 4, None))
+            .unwrap();
+        assert_eq!(account.held.amount(), dec!(15));
+        let err = account
+            .apply(&grouped(TransactionType::Dispute, 5, None))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TransactionError::TransactionAlreadyInDispute { .. }
+        ));
+        account
+            .apply(&grouped(TransactionType::Chargeback, 6, None))
+            .unwrap();
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.total.amount(), dec!(6));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(100.12).into(), 1).unwrap();
+        account.deposit(dec!(200).into(), 2).unwrap();
+        account.withdraw(dec!(50).into(), 3).unwrap();
+        account.dispute(1).unwrap();
+        let state = AccountState::from(&account);
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: AccountState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, state);
+        let mut account = Account::from_state(restored, EngineConfig::default());
+        account.resolve(1).unwrap();
+        assert_eq!(account.total.amount(), dec!(250.12));
+        assert_eq!(account.held.amount(), dec!(0));
+        assert_eq!(account.available.amount(), dec!(250.12));
+    }
 }
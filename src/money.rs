@@ -0,0 +1,409 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::config::Rounding;
+
+/// The decimal places amounts are rounded to by default, the four of the input
+pub const DEFAULT_SCALE: u32 = 4;
+
+/// The ISO 4217 code of a currency, three letters
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    /// The currency of the accounts without one
+    pub const USD: Self = Self(*b"USD");
+
+    /// The currency of a code, in any case
+    pub fn parse(code: &str) -> Option<Self> {
+        let code: [u8; 3] = code.as_bytes().try_into().ok()?;
+        code.iter()
+            .all(u8::is_ascii_alphabetic)
+            .then(|| Self(code.map(|letter| letter.to_ascii_uppercase())))
+    }
+
+    /// The upper case code
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("A currency code is made of ascii letters")
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self::USD
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl Debug for Currency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Self::parse(&code).ok_or_else(|| {
+            de::Error::invalid_value(Unexpected::Str(&code), &"a three letter currency code")
+        })
+    }
+}
+
+/// Amounts of different currencies were added, subtracted or compared
+#[derive(Error, Clone, Copy, PartialEq, Debug)]
+#[error("amounts in {0} and {1} can't be combined")]
+pub struct CurrencyMismatch(pub Currency, pub Currency);
+
+/// An amount, in a currency once it reaches an account. The amounts of the input and the settings
+/// aren't in one yet, and take the currency of the amounts they are combined with. Amounts of
+/// different currencies can't be added, subtracted or compared. It is written as a plain
+/// decimal, the currency is only known to the engine.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Money {
+    amount: Decimal,
+    currency: Option<Currency>,
+}
+
+impl Money {
+    pub const ZERO: Self = Self::new(Decimal::ZERO);
+
+    /// An amount not in a currency yet
+    pub const fn new(amount: Decimal) -> Self {
+        Self {
+            amount,
+            currency: None,
+        }
+    }
+
+    /// An amount of a currency
+    pub const fn with_currency(amount: Decimal, currency: Currency) -> Self {
+        Self {
+            amount,
+            currency: Some(currency),
+        }
+    }
+
+    /// The amount, without its currency
+    pub fn amount(self) -> Decimal {
+        self.amount
+    }
+
+    /// The currency of the amount, if it is in one yet
+    pub fn currency(self) -> Option<Currency> {
+        self.currency
+    }
+
+    /// The amount in `currency`
+    ///
+    /// # Errors
+    /// If the amount is already in another currency, an error will be returned
+    pub fn of(self, currency: Currency) -> Result<Self, CurrencyMismatch> {
+        match self.currency {
+            Some(own) if own != currency => Err(CurrencyMismatch(own, currency)),
+            _ => Ok(Self::with_currency(self.amount, currency)),
+        }
+    }
+
+    /// The currency of an amount combined with this one
+    fn common_currency(self, rhs: Self) -> Result<Option<Currency>, CurrencyMismatch> {
+        match (self.currency, rhs.currency) {
+            (Some(left), Some(right)) if left != right => Err(CurrencyMismatch(left, right)),
+            (left, right) => Ok(left.or(right)),
+        }
+    }
+
+    /// Compares two amounts of the same currency
+    ///
+    /// # Errors
+    /// If the amounts are in different currencies, an error will be returned
+    pub fn checked_cmp(self, rhs: Self) -> Result<Ordering, CurrencyMismatch> {
+        self.common_currency(rhs)?;
+        Ok(self.amount.cmp(&rhs.amount))
+    }
+
+    /// The sum of amounts of the same currency
+    ///
+    /// # Errors
+    /// If the amounts are in different currencies, an error will be returned
+    pub fn sum<'a>(amounts: impl IntoIterator<Item = &'a Self>) -> Result<Self, CurrencyMismatch> {
+        amounts
+            .into_iter()
+            .try_fold(Self::ZERO, |sum, amount| sum + *amount)
+    }
+
+    /// The amount rounded as the ledger of its currency asks, still in its currency
+    #[must_use]
+    pub fn round(self, rounding: Rounding) -> Self {
+        Self {
+            amount: self
+                .amount
+                .round_dp_with_strategy(rounding.precision, rounding.mode.strategy()),
+            ..self
+        }
+    }
+}
+
+impl PartialOrd for Money {
+    /// Amounts of different currencies aren't ordered
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.checked_cmp(*other).ok()
+    }
+}
+
+impl From<Decimal> for Money {
+    fn from(amount: Decimal) -> Self {
+        Self::new(amount)
+    }
+}
+
+impl FromStr for Money {
+    type Err = rust_decimal::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_plain(s)
+            .map_or_else(|| Decimal::from_str(s), Ok)
+            .map(Self::new)
+    }
+}
+
+/// The most decimal places a `Decimal` holds
+const MAX_SCALE: u32 = 28;
+
+/// Parses the plain amounts the input is made of, digits with an optional dot, faster than the
+/// general parser of `Decimal`. Anything else, like signs, exponents or more digits than fit an
+/// `i64`, is left to the general parser.
+fn parse_plain(s: &str) -> Option<Decimal> {
+    let mut mantissa: i64 = 0;
+    let (mut digits, mut scale) = (false, None);
+    for byte in s.bytes() {
+        match byte {
+            b'0'..=b'9' => {
+                mantissa = mantissa
+                    .checked_mul(10)?
+                    .checked_add(i64::from(byte - b'0'))?;
+                digits = true;
+                scale = scale.map(|scale| scale + 1);
+            }
+            b'.' if scale.is_none() => scale = Some(0),
+            _ => return None,
+        }
+    }
+    let scale = scale.unwrap_or(0);
+    (digits && scale <= MAX_SCALE).then(|| Decimal::new(mantissa, scale))
+}
+
+impl Add for Money {
+    type Output = Result<Self, CurrencyMismatch>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let currency = self.common_currency(rhs)?;
+        Ok(Self {
+            amount: self.amount + rhs.amount,
+            currency,
+        })
+    }
+}
+
+impl Sub for Money {
+    type Output = Result<Self, CurrencyMismatch>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let currency = self.common_currency(rhs)?;
+        Ok(Self {
+            amount: self.amount - rhs.amount,
+            currency,
+        })
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.amount, f)
+    }
+}
+
+impl Debug for Money {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.currency {
+            Some(currency) => write!(f, "{} {currency}", self.amount),
+            None => Debug::fmt(&self.amount, f),
+        }
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Serialize::serialize(&self.amount, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(AmountVisitor).map(Self::new)
+    }
+}
+
+/// Reads amounts like `Decimal` does, going through the parser of plain amounts first
+struct AmountVisitor;
+
+impl Visitor<'_> for AmountVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("a decimal amount")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Decimal, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Decimal, E> {
+        Ok(value.into())
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Decimal, E> {
+        Decimal::from_str(&value.to_string())
+            .map_err(|_| E::invalid_value(Unexpected::Float(value), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Decimal, E> {
+        parse_plain(value)
+            .map_or_else(
+                || Decimal::from_str(value).or_else(|_| Decimal::from_scientific(value)),
+                Ok,
+            )
+            .map_err(|_| E::invalid_value(Unexpected::Str(value), &self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use proptest::prelude::*;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::config::{Rounding, RoundingMode};
+    use crate::money::{parse_plain, Currency, CurrencyMismatch, Money};
+
+    #[test]
+    fn test_money_is_written_as_a_decimal() {
+        let money: Money = dec!(140.12345).into();
+        assert_eq!(money.round(Rounding::default()).amount(), dec!(140.1234));
+        let json = serde_json::to_string(&money).unwrap();
+        assert_eq!(json, "\"140.12345\"");
+        assert_eq!(serde_json::from_str::<Money>(&json).unwrap(), money);
+    }
+
+    #[test]
+    fn test_amounts_of_different_currencies_dont_combine() {
+        let jpy = Currency::parse("jpy").unwrap();
+        let yen = Money::with_currency(dec!(100), jpy);
+        let dollars = Money::with_currency(dec!(1), Currency::USD);
+        assert_eq!(yen + dollars, Err(CurrencyMismatch(jpy, Currency::USD)));
+        assert_eq!(dollars - yen, Err(CurrencyMismatch(Currency::USD, jpy)));
+        assert_eq!(yen.partial_cmp(&dollars), None);
+        assert!(yen.of(Currency::USD).is_err());
+        let sum = (yen + Money::new(dec!(5))).unwrap();
+        assert_eq!(sum, Money::with_currency(dec!(105), jpy));
+        assert!(sum > yen);
+        assert_eq!(format!("{sum:?}"), "105 JPY");
+        assert_eq!(Currency::parse("usd"), Some(Currency::USD));
+        assert_eq!(Currency::parse("US"), None);
+        assert_eq!(Currency::parse("U$D"), None);
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let round = |amount: Decimal, mode| {
+            let money: Money = amount.into();
+            money
+                .round(Rounding {
+                    precision: 2,
+                    display: 2,
+                    mode,
+                })
+                .amount()
+        };
+        assert_eq!(round(dec!(1.125), RoundingMode::HalfUp), dec!(1.13));
+        assert_eq!(round(dec!(1.125), RoundingMode::HalfEven), dec!(1.12));
+        assert_eq!(round(dec!(1.129), RoundingMode::Truncate), dec!(1.12));
+        assert_eq!(round(dec!(-1.125), RoundingMode::HalfUp), dec!(-1.13));
+        assert_eq!(round(dec!(-1.129), RoundingMode::Truncate), dec!(-1.12));
+
+        let jpy = Currency::parse("JPY").unwrap();
+        let whole = Rounding {
+            precision: 0,
+            display: 0,
+            mode: RoundingMode::HalfUp,
+        };
+        let yen = Money::with_currency(dec!(1.5), jpy).round(whole);
+        assert_eq!(yen, Money::with_currency(dec!(2), jpy));
+    }
+
+    #[test]
+    fn test_plain_amounts_parse_like_decimal() {
+        for amount in [
+            "0",
+            "1.",
+            ".5",
+            "007.2500",
+            "123456789012345678",
+            "0.000000000000000001",
+            "9223372036854775807",
+        ] {
+            let plain = parse_plain(amount).unwrap();
+            assert_eq!(plain, Decimal::from_str(amount).unwrap());
+            assert_eq!(
+                plain.to_string(),
+                Decimal::from_str(amount).unwrap().to_string()
+            );
+        }
+        for odd in [
+            "",
+            ".",
+            "-1",
+            "+1",
+            "1e3",
+            "1_000",
+            " 1",
+            "1.2.3",
+            "9223372036854775808",
+        ] {
+            assert_eq!(parse_plain(odd), None, "{odd}");
+        }
+        let money: Money = serde_json::from_str("\"1e3\"").unwrap();
+        assert_eq!(money.amount(), dec!(1000));
+        assert_eq!(Money::from_str("-2.5").unwrap().amount(), dec!(-2.5));
+    }
+
+    proptest! {
+        #[test]
+        fn test_plain_parser_agrees_with_decimal(amount in "[0-9]{0,20}(\\.[0-9]{0,30})?") {
+            if let Some(plain) = parse_plain(&amount) {
+                let decimal = Decimal::from_str(&amount).unwrap();
+                prop_assert_eq!(plain.to_string(), decimal.to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use csv_async::AsyncReaderBuilder;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_stream::StreamExt;
+
+use crate::csv::write_records;
+
+/// How the accounts are written
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A json array with an object per account, its amounts as strings to keep their precision
+    Json,
+    /// A table with aligned columns, for a person to read
+    Table,
+}
+
+impl OutputFormat {
+    /// Writes the records into the provided writer with the backend of the format
+    ///
+    /// # Errors
+    /// If the writer fails, an error will be returned
+    pub async fn write<R: Serialize>(
+        self,
+        writer: impl AsyncWrite + Unpin,
+        records: impl IntoIterator<Item = R>,
+    ) -> Result<()> {
+        match self {
+            Self::Csv => CsvOutput.write(writer, records).await,
+            Self::Json => JsonOutput.write(writer, records).await,
+            Self::Table => TableOutput.write(writer, records).await,
+        }
+    }
+}
+
+/// Writes the account rows in a format
+pub trait OutputWriter {
+    /// Writes every record into the provided writer, flushing it at the end
+    async fn write<R: Serialize>(
+        &self,
+        writer: impl AsyncWrite + Unpin,
+        records: impl IntoIterator<Item = R>,
+    ) -> Result<()>;
+}
+
+/// Writes a csv with a header row
+pub struct CsvOutput;
+
+impl OutputWriter for CsvOutput {
+    async fn write<R: Serialize>(
+        &self,
+        writer: impl AsyncWrite + Unpin,
+        records: impl IntoIterator<Item = R>,
+    ) -> Result<()> {
+        write_records(writer, records).await
+    }
+}
+
+/// Writes a json array, an object per line
+pub struct JsonOutput;
+
+impl OutputWriter for JsonOutput {
+    async fn write<R: Serialize>(
+        &self,
+        mut writer: impl AsyncWrite + Unpin,
+        records: impl IntoIterator<Item = R>,
+    ) -> Result<()> {
+        let mut content = b"[".to_vec();
+        for (n, record) in records.into_iter().enumerate() {
+            content.extend_from_slice(if n == 0 { b"\n" } else { b",\n" });
+            serde_json::to_writer(&mut content, &record)?;
+        }
+        content.extend_from_slice(b"\n]\n");
+        writer.write_all(&content).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Writes the columns of the csv aligned, the numeric ones to the right, under a ruled header
+pub struct TableOutput;
+
+impl OutputWriter for TableOutput {
+    async fn write<R: Serialize>(
+        &self,
+        mut writer: impl AsyncWrite + Unpin,
+        records: impl IntoIterator<Item = R>,
+    ) -> Result<()> {
+        // the csv serializer already flattens the records into named columns
+        let mut csv = Vec::new();
+        write_records(&mut csv, records).await?;
+        let mut reader = AsyncReaderBuilder::new()
+            .has_headers(false)
+            .create_reader(csv.as_slice());
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut records = reader.records();
+        while let Some(record) = records.next().await {
+            rows.push(record?.iter().map(str::to_owned).collect());
+        }
+        writer.write_all(table(&rows).as_bytes()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Lays out the rows, the first being the header, in columns as wide as their widest value
+fn table(rows: &[Vec<String>]) -> String {
+    let Some((header, values)) = rows.split_first() else {
+        return String::new();
+    };
+    let columns = header.len();
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|value| value.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let numeric: Vec<bool> = (0..columns)
+        .map(|column| {
+            !values.is_empty()
+                && values.iter().all(|row| {
+                    row.get(column)
+                        .is_some_and(|value| value.parse::<Decimal>().is_ok())
+                })
+        })
+        .collect();
+    let line = |row: &[String], numeric: &[bool]| {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .zip(numeric)
+            .map(|((value, &width), &right)| {
+                if right {
+                    format!("{value:>width$}")
+                } else {
+                    format!("{value:<width$}")
+                }
+            })
+            .collect();
+        let mut line = cells.join("  ").trim_end().to_owned();
+        line.push('\n');
+        line
+    };
+    let mut table = line(header, &numeric);
+    let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    table.push_str(&line(&rule, &numeric));
+    for row in values {
+        table.push_str(&line(row, &numeric));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::model::AccountRecord;
+    use crate::output::OutputFormat;
+
+    async fn written(format: OutputFormat, records: &[AccountRecord]) -> String {
+        let mut output = Vec::new();
+        format.write(&mut output, records).await.unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[actix::test]
+    async fn test_output_formats() {
+        let records = [
+            AccountRecord {
+                client: 1,
+                available: dec!(1.5),
+                held: dec!(0),
+                total: dec!(1.5),
+                locked: false,
+            },
+            AccountRecord {
+                client: 12,
+                available: dec!(100.25),
+                held: dec!(2),
+                total: dec!(102.25),
+                locked: true,
+            },
+        ];
+        assert_eq!(
+            written(OutputFormat::Csv, &records).await,
+            "client,available,held,total,locked\n1,1.5,0,1.5,false\n12,100.25,2,102.25,true\n"
+        );
+        let json = written(OutputFormat::Json, &records).await;
+        let parsed: Vec<AccountRecord> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, records);
+        assert!(json.contains("\"available\":\"100.25\""));
+        let table = [
+            "client  available  held   total  locked",
+            "------  ---------  ----  ------  ------",
+            "     1        1.5     0     1.5  false",
+            "    12     100.25     2  102.25  true",
+        ];
+        assert_eq!(
+            written(OutputFormat::Table, &records).await,
+            table.join("\n") + "\n"
+        );
+    }
+}
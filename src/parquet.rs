@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use anyhow::Result;
+#[cfg(feature = "parquet")]
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+
+use crate::model::{AccountRecord, Transaction};
+#[cfg(feature = "parquet")]
+use crate::snapshot::write_atomically;
+
+/// How many accepted transactions are kept before they are written, as a row group
+#[cfg(feature = "parquet")]
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// Writes the accounts into a Parquet file at `path`, replacing it once complete
+///
+/// # Errors
+/// If the file can't be written, an error will be returned
+#[cfg(feature = "parquet")]
+pub async fn write_accounts(path: &Path, records: &[AccountRecord]) -> Result<()> {
+    let batch = crate::arrow::record_batch(records)?;
+    let mut content = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut content, batch.schema(), Some(properties()))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    write_atomically(path, &content).await
+}
+
+/// Fails, as the crate was built without the `parquet` feature
+#[cfg(not(feature = "parquet"))]
+#[allow(clippy::unused_async)]
+pub async fn write_accounts(path: &Path, _records: &[AccountRecord]) -> Result<()> {
+    anyhow::bail!(
+        "Can't write the accounts into {}: built without the `parquet` feature",
+        path.display()
+    )
+}
+
+/// Writes every transaction the engine accepts into a Parquet file. The transactions are written
+/// a row group at a time and the file is only readable once `finish` wrote its footer, so a crash
+/// loses it, unlike the capture file.
+#[cfg(feature = "parquet")]
+pub struct TransactionParquetWriter {
+    writer: ArrowWriter<std::fs::File>,
+    pending: Vec<Transaction>,
+}
+
+#[cfg(feature = "parquet")]
+impl TransactionParquetWriter {
+    /// Creates the file, replacing it if it exists
+    ///
+    /// # Errors
+    /// If the file can't be created, an error will be returned
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = std::fs::File::create(path)?;
+        let schema = std::sync::Arc::new(crate::arrow::transaction_schema());
+        Ok(Self {
+            writer: ArrowWriter::try_new(file, schema, Some(properties()))?,
+            pending: Vec::with_capacity(ROW_GROUP_SIZE),
+        })
+    }
+
+    /// Adds an accepted transaction, writing a row group once there are enough of them
+    ///
+    /// # Errors
+    /// If the file can't be written, an error will be returned
+    pub fn write(&mut self, transaction: &Transaction) -> Result<()> {
+        self.pending.push(transaction.clone());
+        if self.pending.len() >= ROW_GROUP_SIZE {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the transactions left and the footer of the file
+    ///
+    /// # Errors
+    /// If the file can't be written, an error will be returned
+    pub fn finish(mut self) -> Result<()> {
+        self.write_pending()?;
+        self.writer.close()?;
+        Ok(())
+    }
+
+    fn write_pending(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.writer
+            .write(&crate::arrow::transaction_batch(&self.pending)?)?;
+        self.writer.flush()?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Stands for the writer of the transactions, which can't be created as the crate was built
+/// without the `parquet` feature
+#[cfg(not(feature = "parquet"))]
+pub enum TransactionParquetWriter {}
+
+#[cfg(not(feature = "parquet"))]
+impl TransactionParquetWriter {
+    /// Fails, as the crate was built without the `parquet` feature
+    pub fn create(path: &Path) -> Result<Self> {
+        anyhow::bail!(
+            "Can't write the transactions into {}: built without the `parquet` feature",
+            path.display()
+        )
+    }
+
+    pub fn write(&mut self, _: &Transaction) -> Result<()> {
+        match *self {}
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self {}
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn properties() -> WriterProperties {
+    WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build()
+}
+
+#[cfg(all(test, feature = "parquet"))]
+mod tests {
+    use arrow_array::{Array, Decimal128Array, StringArray, UInt16Array};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use rust_decimal_macros::dec;
+
+    use crate::model::{AccountRecord, Transaction, TransactionType};
+    use crate::parquet::{write_accounts, TransactionParquetWriter};
+
+    #[actix::test]
+    async fn test_parquet_files_read_back() {
+        let dir = std::env::temp_dir().join(format!("parquet-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let record = AccountRecord::for_test(3, dec!(2.5), dec!(1));
+        write_accounts(&dir.join("accounts.parquet"), &[record])
+            .await
+            .unwrap();
+        let file = std::fs::File::open(dir.join("accounts.parquet")).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let total = batch.column_by_name("total").unwrap().as_any();
+        let total = total.downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(total.value_as_string(0), "3.5");
+
+        let mut writer = TransactionParquetWriter::create(&dir.join("accepted.parquet")).unwrap();
+        for (transaction_type, tx, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(1.25))),
+            (TransactionType::Dispute, 1, None),
+        ] {
+            let transaction = Transaction::for_test(transaction_type, 3, tx, amount);
+            writer.write(&transaction).unwrap();
+        }
+        writer.finish().unwrap();
+        let file = std::fs::File::open(dir.join("accepted.parquet")).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let column = |name| batch.column_by_name(name).unwrap().as_any();
+        let types = column("type").downcast_ref::<StringArray>().unwrap();
+        assert_eq!(types.value(0), "Deposit");
+        assert_eq!(types.value(1), "Dispute");
+        let clients = column("client").downcast_ref::<UInt16Array>().unwrap();
+        assert_eq!(clients.values(), &[3, 3]);
+        let amounts = column("amount").downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(amounts.value(0), 1_250_000_000_000_000_000);
+        assert!(amounts.is_null(1));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use tokio::fs::{self, File};
+
+use crate::csv::write_records;
+use crate::signing::DigestWriter;
+
+/// A partition file written, with its digest so a manifest can describe it
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PartitionFile {
+    /// The file name, inside the partition directory
+    pub file: String,
+    /// How many accounts the file holds
+    pub accounts: usize,
+    /// The hex encoded sha256 digest of the file
+    pub sha256: String,
+}
+
+/// How accounts are assigned to the output partitions
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PartitionScheme {
+    /// By a hash of the client id, spreading the clients evenly whatever their ids
+    Hash,
+    /// By contiguous ranges of client ids, so each file covers a known range
+    Range,
+}
+
+impl PartitionScheme {
+    /// The partition of a client, out of `partitions`
+    pub fn partition_of(self, client: u16, partitions: usize) -> usize {
+        match self {
+            Self::Hash => hash_bucket(client, partitions),
+            Self::Range => usize::from(client) * partitions / (usize::from(u16::MAX) + 1),
+        }
+    }
+}
+
+/// The bucket of a client out of `buckets`, by a multiplicative (Fibonacci) hash of its id, so
+/// sequential or strided ids spread as evenly as random ones. A client is always in the same
+/// bucket.
+pub fn hash_bucket(client: u16, buckets: usize) -> usize {
+    let hash = u64::from(client).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    // the high bits of the product are the mixed ones, so the hash is scaled into the range
+    // instead of keeping its remainder
+    let bucket = (u128::from(hash) * buckets as u128) >> 64;
+    usize::try_from(bucket).expect("The bucket is below the number of buckets")
+}
+
+/// Writes the records into `partitions` csv files named `accounts-<n>.csv` inside `dir`. Every
+/// file is written, even if no client falls into it, so loaders can expect all of them. Each
+/// file is digested as it is written.
+///
+/// # Errors
+/// If a file can't be written, an error will be returned
+pub async fn write_partitioned<R: Serialize>(
+    dir: &Path,
+    partitions: usize,
+    scheme: PartitionScheme,
+    records: impl IntoIterator<Item = (u16, R)>,
+) -> Result<Vec<PartitionFile>> {
+    let partitions = partitions.max(1);
+    let mut grouped: Vec<Vec<R>> = (0..partitions).map(|_| Vec::new()).collect();
+    for (client, record) in records {
+        grouped[scheme.partition_of(client, partitions)].push(record);
+    }
+    fs::create_dir_all(dir).await?;
+    let mut files = Vec::with_capacity(partitions);
+    for (n, records) in grouped.into_iter().enumerate() {
+        let file = format!("accounts-{n}.csv");
+        let mut writer = DigestWriter::new(File::create(dir.join(&file)).await?);
+        let accounts = records.len();
+        write_records(&mut writer, records).await?;
+        files.push(PartitionFile {
+            file,
+            accounts,
+            sha256: writer.finish(),
+        });
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::fs;
+
+    use crate::partition::{write_partitioned, PartitionScheme};
+    use crate::signing::sha256_hex;
+
+    #[test]
+    fn test_partition_of() {
+        // sequential and strided ids spread evenly
+        for stride in [1, 4, 16] {
+            let mut counts = [0; 4];
+            for client in (0..4000).step_by(stride) {
+                counts[PartitionScheme::Hash.partition_of(client, 4)] += 1;
+            }
+            let expected = 4000 / stride / 4;
+            let even = expected * 9 / 10..=expected * 11 / 10;
+            assert!(
+                counts.iter().all(|count| even.contains(count)),
+                "{counts:?}"
+            );
+        }
+        assert_eq!(PartitionScheme::Range.partition_of(0, 4), 0);
+        assert_eq!(PartitionScheme::Range.partition_of(16_383, 4), 0);
+        assert_eq!(PartitionScheme::Range.partition_of(16_384, 4), 1);
+        assert_eq!(PartitionScheme::Range.partition_of(u16::MAX, 4), 3);
+    }
+
+    #[actix::test]
+    async fn test_write_partitioned_digests_every_file() {
+        let dir = std::env::temp_dir().join(format!("partitions-{}", std::process::id()));
+        let records = (0..10_u16).map(|client| (client, [client]));
+        let files = write_partitioned(&dir, 3, PartitionScheme::Hash, records)
+            .await
+            .unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files.iter().map(|file| file.accounts).sum::<usize>(), 10);
+        for file in &files {
+            let content = fs::read_to_string(dir.join(&file.file)).await.unwrap();
+            assert_eq!(file.sha256, sha256_hex(content.as_bytes()));
+            assert_eq!(content.lines().count(), file.accounts);
+        }
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+use crate::money::{CurrencyMismatch, Money};
+
+/// The product an account belongs to, as named by the segment policies
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountKind {
+    /// Only the available funds can be withdrawn or held
+    #[default]
+    Standard,
+    /// Withdrawals and disputes may take the available funds below zero, down to the overdraft
+    /// limit
+    Overdraft,
+    /// The funds are kept on behalf of a custodian who covers disputes and chargebacks, so
+    /// disputes always hold the amount and chargebacks don't lock the account
+    Custodial,
+}
+
+/// The limits of the withdrawals and disputes of an account, whatever its product
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// The largest amount a single withdrawal may take, if limited
+    pub max_withdrawal: Option<Money>,
+    /// Charged on top of every withdrawal
+    pub withdrawal_fee: Money,
+    /// Rejects every dispute of the account
+    pub block_disputes: bool,
+    /// How many disputes of the account may be open at the same time, if limited
+    pub max_open_disputes: Option<usize>,
+}
+
+/// The rules of an account's product, with the settings they need
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    Standard(Standard),
+    Overdraft(Overdraft),
+    Custodial(Custodial),
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::Standard(Standard::default())
+    }
+}
+
+impl Policy {
+    /// The rules of an account of the kind. Only overdraft accounts keep the overdraft limit.
+    #[must_use]
+    pub fn new(kind: AccountKind, limits: Limits, overdraft_limit: Money) -> Self {
+        match kind {
+            AccountKind::Standard => Self::Standard(Standard { limits }),
+            AccountKind::Overdraft => Self::Overdraft(Overdraft {
+                limits,
+                limit: overdraft_limit,
+            }),
+            AccountKind::Custodial => Self::Custodial(Custodial { limits }),
+        }
+    }
+
+    /// The product the rules are of
+    #[must_use]
+    pub fn kind(&self) -> AccountKind {
+        match self {
+            Self::Standard(_) => AccountKind::Standard,
+            Self::Overdraft(_) => AccountKind::Overdraft,
+            Self::Custodial(_) => AccountKind::Custodial,
+        }
+    }
+
+    /// The rules as the account applies them
+    #[must_use]
+    pub fn rules(&self) -> &dyn AccountPolicy {
+        match self {
+            Self::Standard(policy) => policy,
+            Self::Overdraft(policy) => policy,
+            Self::Custodial(policy) => policy,
+        }
+    }
+}
+
+/// The decisions of the account state machine that differ between products. The account keeps
+/// the balances and the history, the policy only tells whether an operation is allowed and what
+/// follows from it.
+pub trait AccountPolicy: Sync {
+    /// The limits of the account's withdrawals and disputes
+    fn limits(&self) -> &Limits;
+
+    /// The largest amount a single withdrawal may take, if limited
+    fn max_withdrawal(&self) -> Option<Money> {
+        self.limits().max_withdrawal
+    }
+
+    /// Charged on top of every withdrawal, before it is rounded
+    fn withdrawal_fee(&self) -> Money {
+        self.limits().withdrawal_fee
+    }
+
+    /// Whether `debit` can be taken out of the available funds by a withdrawal
+    ///
+    /// # Errors
+    /// If the amounts are in different currencies, an error will be returned
+    fn allows_withdrawal(&self, available: Money, debit: Money) -> Result<bool, CurrencyMismatch> {
+        Ok(available.checked_cmp(debit)?.is_ge())
+    }
+
+    /// Whether the account takes disputes at all
+    fn allows_disputes(&self) -> bool {
+        !self.limits().block_disputes
+    }
+
+    /// Whether another dispute can be opened while `open` already are
+    fn allows_open_dispute(&self, open: usize) -> bool {
+        self.limits()
+            .max_open_disputes
+            .is_none_or(|limit| open < limit)
+    }
+
+    /// Whether `amount` can be moved from the available funds to the held ones by a dispute
+    ///
+    /// # Errors
+    /// If the amounts are in different currencies, an error will be returned
+    fn allows_dispute(&self, available: Money, amount: Money) -> Result<bool, CurrencyMismatch> {
+        Ok(available.checked_cmp(amount)?.is_ge())
+    }
+
+    /// Whether a chargeback locks the account
+    fn locks_on_chargeback(&self) -> bool {
+        true
+    }
+}
+
+/// Withdrawals and disputes need the available funds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Standard {
+    pub limits: Limits,
+}
+
+impl AccountPolicy for Standard {
+    fn limits(&self) -> &Limits {
+        &self.limits
+    }
+}
+
+/// Withdrawals and disputes may take the available funds below zero
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Overdraft {
+    pub limits: Limits,
+    /// How far below zero the available funds may go
+    pub limit: Money,
+}
+
+impl AccountPolicy for Overdraft {
+    fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    fn allows_withdrawal(&self, available: Money, debit: Money) -> Result<bool, CurrencyMismatch> {
+        Ok((available + self.limit)?.checked_cmp(debit)?.is_ge())
+    }
+
+    fn allows_dispute(&self, available: Money, amount: Money) -> Result<bool, CurrencyMismatch> {
+        self.allows_withdrawal(available, amount)
+    }
+}
+
+/// A custodian covers the disputes and chargebacks
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Custodial {
+    pub limits: Limits,
+}
+
+impl AccountPolicy for Custodial {
+    fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    fn allows_dispute(&self, _: Money, _: Money) -> Result<bool, CurrencyMismatch> {
+        Ok(true)
+    }
+
+    fn locks_on_chargeback(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::config::EngineConfig;
+    use crate::model::{Account, AccountRecord, TransactionError};
+    use crate::policy::{Custodial, Overdraft, Policy};
+
+    #[test]
+    fn test_account_kinds() {
+        let overdraft = EngineConfig {
+            policy: Policy::Overdraft(Overdraft {
+                limit: dec!(50).into(),
+                ..Overdraft::default()
+            }),
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, overdraft);
+        account.deposit(dec!(100).into(), 1).unwrap();
+        account.withdraw(dec!(140).into(), 2).unwrap();
+        let err = account.withdraw(dec!(20).into(), 3).unwrap_err();
+        assert!(matches!(err, TransactionError::InsufficientFunds { .. }));
+        assert_eq!(AccountRecord::from(&account).available, dec!(-40));
+
+        let custodial = EngineConfig {
+            policy: Policy::Custodial(Custodial::default()),
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1, custodial);
+        account.deposit(dec!(100).into(), 1).unwrap();
+        account.withdraw(dec!(80).into(), 2).unwrap();
+        account.dispute(1).unwrap();
+        account.chargeback(1).unwrap();
+        let record = AccountRecord::from(&account);
+        assert_eq!((record.available, record.held), (dec!(-80), dec!(0)));
+        assert!(!record.locked);
+        account.deposit(dec!(100).into(), 3).unwrap();
+    }
+}
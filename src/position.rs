@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, ensure, Result};
+use rust_decimal::Decimal;
+use tokio::fs::File;
+use tokio::io::{stdout, BufReader};
+use tracing::info;
+
+use crate::cli::PositionArgs;
+use crate::csv::{read_records, write_records};
+use crate::model::AccountRecord;
+use crate::money::Currency;
+use crate::registry::ClientRegistry;
+
+/// The decimal places the amounts in the base currency are rounded to
+const BASE_SCALE: u32 = 4;
+
+/// An amount converted to the base currency, rounded and without trailing zeros
+fn to_base(amount: Decimal) -> Decimal {
+    amount.round_dp(BASE_SCALE).normalize()
+}
+
+/// A row of the rates table: the value of one unit of a currency in the currency the table is
+/// quoted in, which doesn't need to be the base currency of the report
+#[derive(Deserialize, Debug)]
+struct Rate {
+    currency: String,
+    rate: Decimal,
+}
+
+/// A row of the position report: the accounts of a currency and their sums, in the currency and
+/// in the base currency, or `total` for the sums of every currency in the base currency
+#[derive(Serialize, Debug, PartialEq)]
+struct PositionRow {
+    currency: String,
+    accounts: usize,
+    available: Option<Decimal>,
+    held: Option<Decimal>,
+    total: Option<Decimal>,
+    /// The value of one unit of the currency in the base currency
+    rate: Option<Decimal>,
+    base_available: Decimal,
+    base_held: Decimal,
+    base_total: Decimal,
+}
+
+/// The sums of the accounts kept in a currency
+#[derive(Default)]
+struct Subtotal {
+    accounts: usize,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+}
+
+/// Writes the subtotals of the accounts of every currency, normalized to the base currency, and
+/// their sum to the std out. The currency of an account is the one of its client in the registry,
+/// the engine's own for clients without one.
+///
+/// # Errors
+/// If the files can't be read or a currency of the accounts has no rate, an error will be
+/// returned
+pub async fn position(args: &PositionArgs) -> Result<()> {
+    let file = File::open(&args.accounts).await?;
+    let accounts = read_records::<AccountRecord>(BufReader::new(file)).await;
+    let file = File::open(&args.rates).await?;
+    let rates = read_records::<Rate>(BufReader::new(file))
+        .await
+        .into_iter()
+        .map(|rate| (rate.currency.to_uppercase(), rate.rate))
+        .collect();
+    let registry = match &args.clients {
+        Some(path) => Some(ClientRegistry::load(path).await?),
+        None => None,
+    };
+    let currency_of = |client| {
+        registry
+            .as_ref()
+            .and_then(|registry| registry.get(client))
+            .and_then(|info| info.currency)
+            .unwrap_or(Currency::USD)
+            .to_string()
+    };
+    let rows = position_rows(&accounts, currency_of, &rates, &args.base_currency)?;
+    if let Some(total) = rows.last() {
+        info!(
+            "Total position: {} {}",
+            total.base_total, args.base_currency
+        );
+    }
+    write_records(stdout(), rows).await
+}
+
+/// The row of every currency of the accounts, in the order of their codes, followed by the total
+fn position_rows(
+    accounts: &[AccountRecord],
+    currency_of: impl Fn(u16) -> String,
+    rates: &HashMap<String, Decimal>,
+    base: &str,
+) -> Result<Vec<PositionRow>> {
+    let base = base.to_uppercase();
+    let base_rate = *rates
+        .get(&base)
+        .ok_or_else(|| anyhow!("The rates table has no rate for the base currency {base}"))?;
+    ensure!(
+        !base_rate.is_zero(),
+        "The rate of the base currency {base} is zero"
+    );
+    let mut subtotals: BTreeMap<String, Subtotal> = BTreeMap::new();
+    for account in accounts {
+        let subtotal = subtotals.entry(currency_of(account.client)).or_default();
+        subtotal.accounts += 1;
+        subtotal.available += account.available;
+        subtotal.held += account.held;
+        subtotal.total += account.total;
+    }
+    let mut sums = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+    let mut rows = Vec::with_capacity(subtotals.len() + 1);
+    for (currency, subtotal) in subtotals {
+        let rate = rates
+            .get(&currency)
+            .ok_or_else(|| anyhow!("The rates table has no rate for {currency}"))?
+            / base_rate;
+        let (available, held, total) = (
+            subtotal.available * rate,
+            subtotal.held * rate,
+            subtotal.total * rate,
+        );
+        sums = (sums.0 + available, sums.1 + held, sums.2 + total);
+        rows.push(PositionRow {
+            currency,
+            accounts: subtotal.accounts,
+            available: Some(subtotal.available),
+            held: Some(subtotal.held),
+            total: Some(subtotal.total),
+            rate: Some(rate.normalize()),
+            base_available: to_base(available),
+            base_held: to_base(held),
+            base_total: to_base(total),
+        });
+    }
+    rows.push(PositionRow {
+        currency: "total".into(),
+        accounts: accounts.len(),
+        available: None,
+        held: None,
+        total: None,
+        rate: None,
+        base_available: to_base(sums.0),
+        base_held: to_base(sums.1),
+        base_total: to_base(sums.2),
+    });
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal_macros::dec;
+
+    use crate::model::AccountRecord;
+    use crate::position::position_rows;
+
+    #[test]
+    fn test_position_normalizes_to_the_base_currency() {
+        let accounts = [
+            AccountRecord::for_test(1, dec!(10), dec!(0)),
+            AccountRecord::for_test(2, dec!(20), dec!(5)),
+            AccountRecord::for_test(3, dec!(100), dec!(0)),
+        ];
+        let currency_of = |client| if client == 3 { "EUR" } else { "USD" }.to_string();
+        // quoted in GBP
+        let rates = HashMap::from([("USD".into(), dec!(0.8)), ("EUR".into(), dec!(0.86))]);
+        let rows = position_rows(&accounts, currency_of, &rates, "usd").unwrap();
+        let summary: Vec<_> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.currency.as_str(),
+                    row.accounts,
+                    row.total,
+                    row.base_total,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("EUR", 1, Some(dec!(100)), dec!(107.5)),
+                ("USD", 2, Some(dec!(35)), dec!(35)),
+                ("total", 3, None, dec!(142.5)),
+            ]
+        );
+        assert_eq!(rows[0].rate, Some(dec!(1.075)));
+        assert_eq!(rows[2].base_held, dec!(5));
+
+        assert!(position_rows(&accounts, currency_of, &rates, "CHF").is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Result};
+use ring::digest::{Context, SHA256};
+use serde_json::{Map, Value};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use tracing::info;
+
+use crate::journal::now_ms;
+use crate::model::AccountState;
+use crate::snapshot::Snapshot;
+
+/// An input file applied to the accounts of a snapshot. The snapshot keeps the files applied to
+/// its accounts, so they are both replaced by the same atomic write.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ProcessedFile {
+    /// The file, as given to the run
+    pub path: PathBuf,
+    /// The hex encoded sha256 digest of its content
+    pub sha256: String,
+    /// How many of the transactions of the run's files were given to the accounts
+    pub rows: u64,
+    /// The digest of the accounts before the run applied it
+    pub state_before: String,
+    /// The digest of the accounts once the run applied it
+    pub state_after: String,
+    /// Milliseconds since the unix epoch when the run finished
+    pub processed_ms: u64,
+}
+
+/// A run applying input files exactly once to the accounts of a snapshot
+pub struct ExactlyOnce {
+    /// The files of the run and their digest
+    files: Vec<(PathBuf, String)>,
+    /// The files applied to the accounts of the snapshot before
+    processed: Vec<ProcessedFile>,
+    state_before: String,
+}
+
+impl ExactlyOnce {
+    /// Checks the input files against the ones applied to the accounts of the snapshot at
+    /// `path`, returning the run and the snapshot to start from, or `None` if every file was
+    /// already applied and the run has nothing to do. A missing snapshot starts a new chain of
+    /// runs from empty accounts.
+    ///
+    /// # Errors
+    /// If a file or the snapshot can't be read, only some of the files were applied, or the
+    /// accounts of the snapshot aren't the ones its last file left, an error will be returned
+    pub async fn start(files: &[PathBuf], path: &Path) -> Result<Option<(Self, Snapshot)>> {
+        let snapshot = if path.exists() {
+            Snapshot::read(path).await?
+        } else {
+            Snapshot::from_states(0, Vec::new())
+        };
+        let state = state_digest(&snapshot.accounts)?;
+        if let Some(last) = snapshot.processed.last() {
+            ensure!(
+                last.state_after == state,
+                "The accounts of {} changed since {} was applied to them",
+                path.display(),
+                last.path.display()
+            );
+        }
+        let mut digests = Vec::with_capacity(files.len());
+        for file in files {
+            digests.push((file.clone(), sha256_file(file).await?));
+        }
+        let applied: Vec<_> = digests
+            .iter()
+            .filter_map(|(file, digest)| {
+                let previous = snapshot.processed.iter().find(|p| &p.sha256 == digest)?;
+                Some((file, previous))
+            })
+            .collect();
+        if applied.len() == digests.len() {
+            for (file, previous) in applied {
+                info!(
+                    "{} was already applied as {}, skipping it",
+                    file.display(),
+                    previous.path.display()
+                );
+            }
+            return Ok(None);
+        }
+        if let Some((file, previous)) = applied.first() {
+            bail!(
+                "{} was already applied as {}, but not the other files of the run",
+                file.display(),
+                previous.path.display()
+            );
+        }
+        let run = Self {
+            files: digests,
+            processed: snapshot.processed.clone(),
+            state_before: state,
+        };
+        Ok(Some((run, snapshot)))
+    }
+
+    /// The snapshot of the accounts the run left after applying `rows` transactions, recording
+    /// its files along with the ones applied before
+    ///
+    /// # Errors
+    /// If the accounts can't be serialized, an error will be returned
+    pub fn finish(self, rows: u64, snapshot: Snapshot) -> Result<Snapshot> {
+        let state_after = state_digest(&snapshot.accounts)?;
+        let processed_ms = now_ms();
+        let mut processed = self.processed;
+        processed.extend(self.files.into_iter().map(|(path, sha256)| ProcessedFile {
+            path,
+            sha256,
+            rows,
+            state_before: self.state_before.clone(),
+            state_after: state_after.clone(),
+            processed_ms,
+        }));
+        Ok(snapshot.with_processed(processed))
+    }
+}
+
+/// The hex encoded sha256 digest of a file, read a chunk at a time
+async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await?;
+    let mut context = Context::new(&SHA256);
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(hex::encode(context.finish()));
+        }
+        context.update(&chunk[..read]);
+    }
+}
+
+/// The hex encoded sha256 digest of the accounts, the same for the same accounts whatever their
+/// order or the order of their sets
+///
+/// # Errors
+/// If an account can't be serialized, an error will be returned
+pub fn state_digest(accounts: &[AccountState]) -> Result<String> {
+    let mut accounts: Vec<_> = accounts.iter().collect();
+    accounts.sort_unstable_by_key(|state| state.client());
+    let canonical = canonical(serde_json::to_value(accounts)?);
+    let mut context = Context::new(&SHA256);
+    context.update(&serde_json::to_vec(&canonical)?);
+    Ok(hex::encode(context.finish()))
+}
+
+/// The value with the keys of its objects and the items of its arrays sorted. The only arrays of
+/// an account are sets, whose order means nothing.
+fn canonical(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object
+                .into_iter()
+                .map(|(key, value)| (key, canonical(value)))
+                .collect();
+            entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(entries.into_iter().collect::<Map<_, _>>())
+        }
+        Value::Array(items) => {
+            let mut items: Vec<_> = items.into_iter().map(canonical).collect();
+            items.sort_by_cached_key(Value::to_string);
+            Value::Array(items)
+        }
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::config::EngineConfig;
+    use crate::model::{Account, AccountState};
+    use crate::processed::ExactlyOnce;
+    use crate::snapshot::Snapshot;
+
+    #[actix::test]
+    async fn test_files_are_applied_once() {
+        let dir = std::env::temp_dir().join(format!("processed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("first.csv"), dir.join("second.csv"));
+        std::fs::write(&first, "type,client,tx,amount\nDeposit,1,1,2\n").unwrap();
+        std::fs::write(&second, "type,client,tx,amount\nDeposit,1,2,3\n").unwrap();
+        let snapshot_path = dir.join("snapshot.json");
+
+        let (run, snapshot) = ExactlyOnce::start(std::slice::from_ref(&first), &snapshot_path)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(snapshot.accounts.is_empty());
+        let mut account = Account::new(1, EngineConfig::default());
+        account.deposit(dec!(2).into(), 1).unwrap();
+        let snapshot = run
+            .finish(1, Snapshot::new(1, std::slice::from_ref(&account)))
+            .unwrap();
+        assert_eq!(snapshot.processed.len(), 1);
+        assert_eq!(snapshot.processed[0].path, first);
+        snapshot.write(&snapshot_path).await.unwrap();
+
+        // running the same step again does nothing
+        let files = std::slice::from_ref(&first);
+        assert!(ExactlyOnce::start(files, &snapshot_path)
+            .await
+            .unwrap()
+            .is_none());
+        let (_, snapshot) = ExactlyOnce::start(std::slice::from_ref(&second), &snapshot_path)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.accounts.len(), 1);
+        let error = ExactlyOnce::start(&[first, second], &snapshot_path)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("but not the other files"));
+
+        // accounts changed behind the back of the runs are refused
+        let mut changed = snapshot;
+        account.deposit(dec!(1).into(), 9).unwrap();
+        changed.accounts = vec![AccountState::from(&account)];
+        changed.write(&snapshot_path).await.unwrap();
+        let third = dir.join("third.csv");
+        std::fs::write(&third, "type,client,tx,amount\n").unwrap();
+        let error = ExactlyOnce::start(&[third], &snapshot_path)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("changed since"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use anyhow::Result;
+use tokio::fs::File;
+
+use crate::csv::write_records;
+use crate::model::Account;
+
+/// A row of the quality report: how the transactions of a client ended
+#[derive(Serialize, Debug, PartialEq)]
+struct QualityRow {
+    client: u16,
+    applied: u64,
+    rejected: u64,
+    disputes_opened: u64,
+    disputes_closed: u64,
+    /// The rejections by kind, like `insufficient_funds:2;account_locked:1`
+    reasons: String,
+}
+
+/// The counters of every account, ordered by client
+fn quality_rows(accounts: &[Account]) -> Vec<QualityRow> {
+    let mut rows: Vec<_> = accounts
+        .iter()
+        .map(|account| {
+            let counters = account.counters();
+            let reasons: Vec<_> = counters
+                .rejected
+                .iter()
+                .map(|(kind, count)| format!("{kind}:{count}"))
+                .collect();
+            QualityRow {
+                client: account.client(),
+                applied: counters.applied,
+                rejected: counters.rejections(),
+                disputes_opened: counters.disputes_opened,
+                disputes_closed: counters.disputes_closed,
+                reasons: reasons.join(";"),
+            }
+        })
+        .collect();
+    rows.sort_unstable_by_key(|row| row.client);
+    rows
+}
+
+/// Writes how the transactions of every client ended, applied, rejected by reason and the
+/// disputes they opened and closed
+///
+/// # Errors
+/// If the file can't be written, an error will be returned
+pub async fn write_quality_report(path: &Path, accounts: &[Account]) -> Result<()> {
+    write_records(File::create(path).await?, quality_rows(accounts)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::config::EngineConfig;
+    use crate::model::{Account, AccountState, Transaction, TransactionType};
+    use crate::quality::{quality_rows, QualityRow};
+
+    #[test]
+    fn test_quality_rows() {
+        let mut account = Account::new(1, EngineConfig::default());
+        for transaction in [
+            Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::for_test(TransactionType::Withdrawal, 1, 2, Some(dec!(20))),
+            Transaction::for_test(TransactionType::Dispute, 1, 1, None),
+            Transaction::for_test(TransactionType::Dispute, 1, 1, None),
+            Transaction::for_test(TransactionType::Resolve, 1, 1, None),
+            Transaction::for_test(TransactionType::Withdrawal, 1, 3, Some(dec!(30))),
+            Transaction::for_test(TransactionType::Resolve, 1, 9, None),
+        ] {
+            let _ = account.apply(&transaction);
+        }
+        // the counters are kept with the state of the account
+        let account = Account::from_state(AccountState::from(&account), EngineConfig::default());
+        assert_eq!(
+            quality_rows(&[account, Account::new(0, EngineConfig::default())]),
+            vec![
+                QualityRow {
+                    client: 0,
+                    applied: 0,
+                    rejected: 0,
+                    disputes_opened: 0,
+                    disputes_closed: 0,
+                    reasons: String::new(),
+                },
+                QualityRow {
+                    client: 1,
+                    applied: 3,
+                    rejected: 4,
+                    disputes_opened: 1,
+                    disputes_closed: 1,
+                    reasons: "already_in_dispute:1;insufficient_funds:2;not_found:1".into(),
+                },
+            ]
+        );
+    }
+}
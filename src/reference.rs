@@ -0,0 +1,534 @@
+//! A reference model of the account rules and a property test checking `Account` against it.
+//!
+//! The model derives the balances from a ledger of the deposits and withdrawals instead of
+//! updating them in place, so it shares no code with `Account`. Authors of new rules can reuse it
+//! from their tests, through the `test-support` feature: extend `ReferenceAccount` with the rule,
+//! then run `check_against_reference` over `engine_configs` and `transactions`.
+
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+use proptest::sample::Index;
+use rust_decimal::Decimal;
+
+use crate::config::{DisputeAmounts, EngineConfig, WithdrawalDisputes};
+use crate::model::{Account, AccountRecord, Transaction, TransactionError, TransactionType};
+use crate::money::Money;
+use crate::policy::{AccountKind, Custodial, Limits, Overdraft, Policy, Standard};
+
+const CLIENT: u16 = 1;
+
+/// A deposit or withdrawal of the ledger
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    /// The amount deposited, or debited by a withdrawal including its fee
+    amount: Decimal,
+    deposit: bool,
+    disputed: bool,
+    reference: Option<u32>,
+}
+
+/// The expected behavior of an account, for transactions with unique ids and amounts of up to
+/// four decimal places, which are never rounded. The amounts are kept as plain decimals, in the
+/// currency of the account.
+#[derive(Clone, Debug)]
+pub struct ReferenceAccount {
+    client: u16,
+    config: EngineConfig,
+    ledger: BTreeMap<u32, Entry>,
+    /// The opening balances credited, which are out of the ledger as they can't be disputed
+    opened: Decimal,
+    charged_back: Decimal,
+    /// The withdrawals charged back, credited back to the account
+    reversed: Decimal,
+    locked: bool,
+}
+
+impl ReferenceAccount {
+    #[must_use]
+    pub fn new(client: u16, config: EngineConfig) -> Self {
+        Self {
+            client,
+            config,
+            ledger: BTreeMap::new(),
+            opened: Decimal::ZERO,
+            charged_back: Decimal::ZERO,
+            reversed: Decimal::ZERO,
+            locked: false,
+        }
+    }
+
+    /// The opening balances and the deposits left after chargebacks, minus the withdrawals that
+    /// weren't charged back, plus the pending credits of the disputed withdrawals
+    fn total(&self) -> Decimal {
+        let balance = self.ledger.values().fold(self.opened, |sum, entry| {
+            match (entry.deposit, entry.disputed) {
+                (true, _) => sum + entry.amount,
+                (false, false) => sum - entry.amount,
+                (false, true) => sum,
+            }
+        });
+        balance - self.charged_back + self.reversed
+    }
+
+    fn held(&self) -> Decimal {
+        self.ledger
+            .values()
+            .filter(|entry| entry.disputed)
+            .map(|entry| entry.amount)
+            .sum()
+    }
+
+    fn available(&self) -> Decimal {
+        self.total() - self.held()
+    }
+
+    /// An amount in the currency of the account, as `Account` reports it
+    fn money(&self, amount: Decimal) -> Money {
+        Money::with_currency(amount, self.config.currency)
+    }
+
+    /// The limits of the account, whatever its kind
+    fn limits(&self) -> Limits {
+        match self.config.policy {
+            Policy::Standard(Standard { limits })
+            | Policy::Overdraft(Overdraft { limits, .. })
+            | Policy::Custodial(Custodial { limits }) => limits,
+        }
+    }
+
+    /// The lowest the available funds may go by a withdrawal or dispute
+    fn floor(&self) -> Option<Decimal> {
+        match self.config.policy {
+            Policy::Standard(_) => Some(Decimal::ZERO),
+            Policy::Overdraft(overdraft) => Some(-overdraft.limit.amount()),
+            Policy::Custodial(_) => None,
+        }
+    }
+
+    /// The values of the account as they are written in the output
+    #[must_use]
+    pub fn record(&self) -> AccountRecord {
+        AccountRecord {
+            client: self.client,
+            available: self.available(),
+            held: self.held(),
+            total: self.total(),
+            locked: self.locked,
+        }
+    }
+
+    /// Applies a transaction, with the same outcome `Account::apply` must have
+    ///
+    /// # Errors
+    /// The error `Account::apply` must return for the transaction
+    pub fn apply(&mut self, tx: &Transaction) -> Result<(), TransactionError> {
+        let settles = matches!(
+            tx.transaction_type,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        );
+        if let (true, Some(reference)) = (settles, tx.reference) {
+            return self.apply_to_group(tx.transaction_type, tx.tx, reference);
+        }
+        let verify = self.config.dispute_amounts == DisputeAmounts::Verify;
+        if let (true, true, Some(amount)) = (verify, settles, tx.amount) {
+            match self.ledger.get(&tx.tx) {
+                Some(entry) if entry.deposit && entry.amount != amount.amount() => {
+                    return Err(TransactionError::AmountMismatch {
+                        client: self.client,
+                        tx: tx.tx,
+                        amount: self.money(amount.amount()),
+                        expected: self.money(entry.amount),
+                    });
+                }
+                _ => {}
+            }
+        }
+        // malformed transactions are rejected before the account is looked at
+        let invalid = TransactionError::InvalidOperation {
+            client: self.client,
+            tx: tx.tx,
+        };
+        let amount = match tx.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                Some(tx.amount.ok_or(invalid)?.amount())
+            }
+            TransactionType::Savepoint => return Err(invalid),
+            _ => None,
+        };
+        if self.locked {
+            return Err(TransactionError::AccountLocked {
+                client: self.client,
+                tx: tx.tx,
+            });
+        }
+        match (tx.transaction_type, amount) {
+            (TransactionType::Deposit, Some(amount)) => {
+                self.record_entry(tx, amount, true);
+                Ok(())
+            }
+            (TransactionType::Withdrawal, Some(amount)) => {
+                if let Some(limit) = self.limits().max_withdrawal {
+                    if amount > limit.amount() {
+                        return Err(TransactionError::LimitExceeded {
+                            client: self.client,
+                            tx: tx.tx,
+                            amount: self.money(amount),
+                            limit: self.money(limit.amount()),
+                        });
+                    }
+                }
+                let debit = amount + self.limits().withdrawal_fee.amount();
+                // a custodian covers disputes only, withdrawals need the available funds
+                let floor = self.floor().unwrap_or(Decimal::ZERO);
+                if self.available() - debit < floor {
+                    return Err(TransactionError::InsufficientFunds {
+                        client: self.client,
+                        tx: tx.tx,
+                        amount: self.money(debit),
+                    });
+                }
+                self.record_entry(tx, debit, false);
+                Ok(())
+            }
+            (TransactionType::Open, _) => {
+                self.opened += tx.amount.unwrap_or_default().amount();
+                Ok(())
+            }
+            (TransactionType::Dispute, _) => self.dispute(tx.tx),
+            (TransactionType::Resolve, _) => self.settle(tx.tx, false),
+            (TransactionType::Chargeback, _) => self.settle(tx.tx, true),
+            _ => unreachable!(),
+        }
+    }
+
+    fn record_entry(&mut self, tx: &Transaction, amount: Decimal, deposit: bool) {
+        let entry = Entry {
+            amount,
+            deposit,
+            disputed: false,
+            reference: tx.reference,
+        };
+        self.ledger.insert(tx.tx, entry);
+    }
+
+    /// Takes the step for every disputable transaction of the group that can take it, or for none
+    fn apply_to_group(
+        &mut self,
+        transaction_type: TransactionType,
+        step: u32,
+        reference: u32,
+    ) -> Result<(), TransactionError> {
+        let client = self.client;
+        if self.locked {
+            return Err(TransactionError::AccountLocked { client, tx: step });
+        }
+        let group: Vec<(u32, Entry)> = self
+            .ledger
+            .iter()
+            .filter(|(_, entry)| entry.reference == Some(reference))
+            .map(|(tx, entry)| (*tx, *entry))
+            .collect();
+        if group.is_empty() {
+            return Err(TransactionError::TransactionNotFound { client, tx: step });
+        }
+        let dispute = transaction_type == TransactionType::Dispute;
+        let eligible: Vec<u32> = group
+            .into_iter()
+            .filter(|(_, entry)| self.disputable(entry) && entry.disputed != dispute)
+            .map(|(tx, _)| tx)
+            .collect();
+        if eligible.is_empty() {
+            return Err(if dispute {
+                TransactionError::TransactionAlreadyInDispute { client, tx: step }
+            } else {
+                TransactionError::TransactionNotInDispute { client, tx: step }
+            });
+        }
+        let before = self.clone();
+        for tx in eligible {
+            let result = match transaction_type {
+                TransactionType::Dispute => self.dispute(tx),
+                TransactionType::Resolve => self.settle(tx, false),
+                _ => self.settle(tx, true),
+            };
+            if let Err(e) = result {
+                *self = before;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn disputable(&self, entry: &Entry) -> bool {
+        entry.deposit || self.config.withdrawal_disputes == WithdrawalDisputes::Hold
+    }
+
+    fn dispute(&mut self, tx: u32) -> Result<(), TransactionError> {
+        let client = self.client;
+        if self.limits().block_disputes {
+            return Err(TransactionError::DisputesBlocked { client, tx });
+        }
+        let floor = self.floor();
+        let max_open = self.limits().max_open_disputes;
+        let available = self.available();
+        let open = self.ledger.values().filter(|entry| entry.disputed).count();
+        let withdrawals = self.config.withdrawal_disputes == WithdrawalDisputes::Hold;
+        let currency = self.config.currency;
+        let entry = self
+            .ledger
+            .get_mut(&tx)
+            .ok_or(TransactionError::TransactionNotFound { client, tx })?;
+        if entry.disputed {
+            return Err(TransactionError::TransactionAlreadyInDispute { client, tx });
+        }
+        if !(entry.deposit || withdrawals) {
+            return Err(TransactionError::InvalidOperation { client, tx });
+        }
+        if max_open.is_some_and(|limit| open >= limit) {
+            return Err(TransactionError::TooManyOpenDisputes { client, tx });
+        }
+        // a disputed withdrawal only holds a pending credit
+        let debit = if entry.deposit {
+            entry.amount
+        } else {
+            Decimal::ZERO
+        };
+        if floor.is_some_and(|floor| available - debit < floor) {
+            return Err(TransactionError::InsufficientFunds {
+                client,
+                tx,
+                amount: Money::with_currency(entry.amount, currency),
+            });
+        }
+        entry.disputed = true;
+        Ok(())
+    }
+
+    /// Ends a dispute. A resolve releases a deposit's amount or drops a withdrawal's pending
+    /// credit, a chargeback removes the deposit's amount or credits the withdrawal's.
+    fn settle(&mut self, tx: u32, chargeback: bool) -> Result<(), TransactionError> {
+        let client = self.client;
+        let entry = self
+            .ledger
+            .get_mut(&tx)
+            .ok_or(TransactionError::TransactionNotFound { client, tx })?;
+        if !entry.disputed {
+            return Err(TransactionError::TransactionNotInDispute { client, tx });
+        }
+        entry.disputed = false;
+        if chargeback {
+            if entry.deposit {
+                self.charged_back += entry.amount;
+            } else {
+                self.reversed += entry.amount;
+            }
+            self.locked = !matches!(self.config.policy, Policy::Custodial(_));
+        }
+        Ok(())
+    }
+}
+
+/// Applies the transactions to an `Account` and the reference model, asserting they accept and
+/// reject the same transactions with the same errors, end every step with the same balances and
+/// keep the invariants of an account
+///
+/// # Panics
+/// If the account and the model diverge or an invariant is broken
+pub fn check_against_reference(config: EngineConfig, transactions: &[Transaction]) {
+    let mut account = Account::new(CLIENT, config);
+    let mut reference = ReferenceAccount::new(CLIENT, config);
+    for (step, transaction) in transactions.iter().enumerate() {
+        let was_locked = AccountRecord::from(&account).locked;
+        let outcome = account.apply(transaction);
+        assert_eq!(
+            outcome,
+            reference.apply(transaction),
+            "outcome of step {step}, {transaction:?}"
+        );
+        let record = AccountRecord::from(&account);
+        assert_eq!(
+            record,
+            reference.record(),
+            "balances after step {step}, {transaction:?}"
+        );
+        assert_eq!(record.total, record.available + record.held);
+        assert_eq!(account.held(), account.disputed_total());
+        assert!(record.held >= Decimal::ZERO);
+        assert!(
+            !was_locked || record.locked,
+            "step {step} unlocked the account"
+        );
+        if matches!(config.policy, Policy::Standard(_)) {
+            assert!(record.available >= Decimal::ZERO);
+        }
+    }
+}
+
+/// Engine settings covering every account kind, with and without limits and fees
+pub fn engine_configs() -> impl Strategy<Value = EngineConfig> {
+    let kinds = prop_oneof![
+        Just(AccountKind::Standard),
+        Just(AccountKind::Overdraft),
+        Just(AccountKind::Custodial),
+    ];
+    (
+        kinds,
+        prop::option::of(1..50_000_i64),
+        prop_oneof![3 => Just(0_i64), 1 => 1..500_i64],
+        prop::bool::weighted(0.1),
+        0..20_000_i64,
+        prop::option::of(0..4_usize),
+        prop_oneof![Just(DisputeAmounts::Ignore), Just(DisputeAmounts::Verify)],
+        prop_oneof![
+            Just(WithdrawalDisputes::Reject),
+            Just(WithdrawalDisputes::Hold)
+        ],
+    )
+        .prop_map(
+            |(
+                kind,
+                max_withdrawal,
+                fee,
+                block,
+                overdraft_limit,
+                max_open,
+                amounts,
+                withdrawals,
+            )| {
+                let limits = Limits {
+                    max_withdrawal: max_withdrawal.map(cents),
+                    withdrawal_fee: cents(fee),
+                    block_disputes: block,
+                    max_open_disputes: max_open,
+                };
+                EngineConfig {
+                    policy: Policy::new(kind, limits, cents(overdraft_limit)),
+                    dispute_amounts: amounts,
+                    withdrawal_disputes: withdrawals,
+                    ..EngineConfig::default()
+                }
+            },
+        )
+}
+
+/// An operation, turned into a transaction once the ids used before it are known
+#[derive(Clone, Debug)]
+enum Op {
+    Deposit(i64, Option<u32>),
+    Withdrawal(i64, Option<u32>),
+    /// Refers to a deposit or withdrawal made before, or to an unknown one without any. It may
+    /// repeat the amount of the transaction or give another one, or refer to a group instead.
+    Settle(TransactionType, Option<Index>, SettleAmount, Option<u32>),
+    /// A deposit or withdrawal without an amount
+    Missing(TransactionType),
+    /// An opening balance, the engine rejects it for existing accounts but the account credits it
+    Open(Option<i64>),
+}
+
+/// The amount given by a dispute, resolve or chargeback
+#[derive(Clone, Debug)]
+enum SettleAmount {
+    None,
+    Original,
+    Other(i64),
+}
+
+/// Transactions of a single client with unique ids. Disputes, resolves and chargebacks mostly
+/// refer to earlier transactions, sometimes to unknown ones.
+///
+/// # Panics
+/// If `len` doesn't fit in a transaction id
+pub fn transactions(len: usize) -> impl Strategy<Value = Vec<Transaction>> {
+    let settle = prop_oneof![
+        Just(TransactionType::Dispute),
+        Just(TransactionType::Resolve),
+        Just(TransactionType::Chargeback),
+    ];
+    let settle_amount = prop_oneof![
+        6 => Just(SettleAmount::None),
+        1 => Just(SettleAmount::Original),
+        1 => (1..100_000_i64).prop_map(SettleAmount::Other),
+    ];
+    // three groups, the settles may refer to a fourth unknown one
+    let reference = prop::option::weighted(0.3, 0..3_u32);
+    let op = prop_oneof![
+        4 => (1..100_000_i64, reference.clone()).prop_map(|(amount, r)| Op::Deposit(amount, r)),
+        3 => (1..100_000_i64, reference).prop_map(|(amount, r)| Op::Withdrawal(amount, r)),
+        4 => (
+            settle,
+            prop::option::weighted(0.9, any::<Index>()),
+            settle_amount,
+            prop::option::weighted(0.15, 0..4_u32),
+        )
+            .prop_map(|(kind, target, amount, group)| Op::Settle(kind, target, amount, group)),
+        1 => prop_oneof![
+            Just(Op::Missing(TransactionType::Deposit)),
+            Just(Op::Missing(TransactionType::Withdrawal)),
+        ],
+        1 => prop::option::of(1..100_000_i64).prop_map(Op::Open),
+    ];
+    prop::collection::vec(op, 0..len).prop_map(|ops| {
+        // the ids used so far, with their amounts
+        let mut ids: Vec<(u32, Option<Money>)> = Vec::new();
+        ops.into_iter()
+            .map(|op| {
+                let next = u32::try_from(ids.len()).unwrap() + 1;
+                let (transaction_type, tx, amount, reference) = match op {
+                    Op::Deposit(amount, reference) => (
+                        TransactionType::Deposit,
+                        next,
+                        Some(cents(amount)),
+                        reference,
+                    ),
+                    Op::Withdrawal(amount, reference) => (
+                        TransactionType::Withdrawal,
+                        next,
+                        Some(cents(amount)),
+                        reference,
+                    ),
+                    Op::Missing(kind) => (kind, next, None, None),
+                    Op::Open(amount) => (TransactionType::Open, next, amount.map(cents), None),
+                    Op::Settle(kind, target, given, group) => {
+                        let (tx, original) = match target {
+                            Some(index) if !ids.is_empty() => ids[index.index(ids.len())],
+                            _ => (u32::MAX, None),
+                        };
+                        let amount = match given {
+                            SettleAmount::None => None,
+                            SettleAmount::Original => original,
+                            SettleAmount::Other(amount) => Some(cents(amount)),
+                        };
+                        (kind, tx, amount, group)
+                    }
+                };
+                if tx == next {
+                    ids.push((tx, amount));
+                }
+                let transaction =
+                    Transaction::for_test(transaction_type, CLIENT, tx, amount.map(Money::amount));
+                match reference {
+                    Some(reference) => transaction.with_reference(reference),
+                    None => transaction,
+                }
+            })
+            .collect()
+    })
+}
+
+/// An amount with four decimal places
+fn cents(value: i64) -> Money {
+    Decimal::new(value, 4).into()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn test_account_matches_reference(
+        config in engine_configs(),
+        transactions in transactions(60),
+    ) {
+        check_against_reference(config, &transactions);
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio::fs::File;
+use tokio::io::BufReader;
+
+use crate::config::{EngineConfig, Rounding, RoundingMode};
+use crate::csv::read_records;
+use crate::model::{Account, AccountRecord};
+use crate::money::{Currency, Money};
+use crate::policy::{AccountKind, Limits, Policy};
+use crate::tags::Tags;
+
+/// Descriptive data of a client, side-loaded from a registry file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClientInfo {
+    pub client: u16,
+    pub name: String,
+    pub segment: String,
+    /// The ISO 4217 code of the currency the client's account is kept in, the engine's own when
+    /// empty
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    /// The tags of the client's account, separated by `;`
+    #[serde(default)]
+    pub tags: String,
+}
+
+impl ClientInfo {
+    /// The tags of the client's account
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.tags
+            .split(';')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+    }
+}
+
+/// Overrides of the engine settings for the clients of a segment. Empty values keep the settings
+/// given on the command line.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SegmentPolicy {
+    pub segment: String,
+    pub max_withdrawal: Option<Money>,
+    pub withdrawal_fee: Option<Money>,
+    pub block_disputes: Option<bool>,
+    #[serde(default)]
+    pub account_kind: Option<AccountKind>,
+    #[serde(default)]
+    pub overdraft_limit: Option<Money>,
+    #[serde(default)]
+    pub max_open_disputes: Option<usize>,
+}
+
+impl SegmentPolicy {
+    /// The settings with the overrides of the segment applied
+    pub fn apply(&self, config: EngineConfig) -> EngineConfig {
+        let limits = config.policy.rules().limits();
+        let limits = Limits {
+            max_withdrawal: self.max_withdrawal.or(limits.max_withdrawal),
+            withdrawal_fee: self.withdrawal_fee.unwrap_or(limits.withdrawal_fee),
+            block_disputes: self.block_disputes.unwrap_or(limits.block_disputes),
+            max_open_disputes: self.max_open_disputes.or(limits.max_open_disputes),
+        };
+        let overdraft_limit = match config.policy {
+            Policy::Overdraft(overdraft) => overdraft.limit,
+            _ => Money::ZERO,
+        };
+        EngineConfig {
+            policy: Policy::new(
+                self.account_kind.unwrap_or(config.policy.kind()),
+                limits,
+                self.overdraft_limit.unwrap_or(overdraft_limit),
+            ),
+            ..config
+        }
+    }
+}
+
+/// How the amounts of the accounts of a currency are rounded, as currencies have different minor
+/// units: JPY has none, BHD has three, USD is kept with four and written with two
+#[derive(Deserialize, Clone, Debug)]
+pub struct CurrencyRule {
+    pub currency: String,
+    /// The decimal places the amounts are kept with, the fees included
+    pub precision: u32,
+    /// The decimal places the amounts are written with
+    pub display: u32,
+    /// How the amounts are rounded, the engine's own rounding mode when empty
+    #[serde(default)]
+    pub rounding: Option<RoundingMode>,
+}
+
+impl CurrencyRule {
+    /// The rounding with the decimal places of the currency
+    pub fn apply(&self, rounding: Rounding) -> Rounding {
+        Rounding {
+            precision: self.precision,
+            display: self.display,
+            mode: self.rounding.unwrap_or(rounding.mode),
+        }
+    }
+}
+
+/// The known clients, indexed by id, the policies of their segments and the rounding rules of
+/// their currencies
+pub struct ClientRegistry {
+    clients: HashMap<u16, ClientInfo>,
+    policies: HashMap<String, SegmentPolicy>,
+    currencies: HashMap<String, CurrencyRule>,
+}
+
+impl ClientRegistry {
+    /// Loads a registry csv with the `client,name,segment` columns and optional `currency` and
+    /// `tags` ones
+    ///
+    /// # Errors
+    /// If the file can't be opened, an error will be returned
+    pub async fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).await?;
+        let clients = read_records::<ClientInfo>(BufReader::new(file))
+            .await
+            .into_iter()
+            .map(|info| (info.client, info))
+            .collect();
+        Ok(Self {
+            clients,
+            policies: HashMap::new(),
+            currencies: HashMap::new(),
+        })
+    }
+
+    /// Loads a `segment,max_withdrawal,withdrawal_fee,block_disputes,account_kind,overdraft_limit,
+    /// max_open_disputes` csv with the overrides of every segment. The last three columns may be
+    /// left out.
+    ///
+    /// # Errors
+    /// If the file can't be opened, an error will be returned
+    pub async fn with_policies(mut self, path: &Path) -> Result<Self> {
+        let file = File::open(path).await?;
+        self.policies = read_records::<SegmentPolicy>(BufReader::new(file))
+            .await
+            .into_iter()
+            .map(|policy| (policy.segment.clone(), policy))
+            .collect();
+        Ok(self)
+    }
+
+    /// Loads a `currency,precision,display,rounding` csv with the rounding rules of every
+    /// currency. The last column may be left out.
+    ///
+    /// # Errors
+    /// If the file can't be opened, an error will be returned
+    pub async fn with_currency_rules(mut self, path: &Path) -> Result<Self> {
+        let file = File::open(path).await?;
+        self.currencies = read_records::<CurrencyRule>(BufReader::new(file))
+            .await
+            .into_iter()
+            .map(|rule| (rule.currency.to_uppercase(), rule))
+            .collect();
+        Ok(self)
+    }
+
+    /// The settings of a client's account: the overrides of its segment and the rounding rule of
+    /// its currency applied to `config`. Unknown clients are in USD, segments without a policy and
+    /// currencies without a rule keep `config`.
+    pub fn config_for(&self, client: u16, config: EngineConfig) -> EngineConfig {
+        let info = self.get(client);
+        let config = info
+            .and_then(|info| self.policies.get(&info.segment))
+            .map_or(config, |policy| policy.apply(config));
+        let currency = info
+            .and_then(|info| info.currency)
+            .unwrap_or(Currency::USD);
+        let config = EngineConfig { currency, ..config };
+        match self.currencies.get(currency.code()) {
+            Some(rule) => EngineConfig {
+                rounding: rule.apply(config.rounding),
+                ..config
+            },
+            None => config,
+        }
+    }
+
+    /// The tags of the clients' accounts
+    pub fn tags(&self) -> Tags {
+        let mut tags = Tags::default();
+        for info in self.clients.values() {
+            for tag in info.tags() {
+                tags.insert(info.client, tag);
+            }
+        }
+        tags
+    }
+
+    /// Looks up a client
+    pub fn get(&self, client: u16) -> Option<&ClientInfo> {
+        self.clients.get(&client)
+    }
+
+    /// Adds the client data to an account row. Unknown clients get empty values.
+    pub fn enrich(&self, account: &Account) -> EnrichedRecord {
+        let record = AccountRecord::from(account);
+        let info = self.get(record.client);
+        EnrichedRecord {
+            client: record.client,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+            locked: record.locked,
+            name: info.map(|i| i.name.clone()).unwrap_or_default(),
+            segment: info.map(|i| i.segment.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+/// An account row followed by the registry data of its client
+#[derive(Serialize)]
+pub struct EnrichedRecord {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    name: String,
+    segment: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rust_decimal_macros::dec;
+    use tokio::fs::{remove_file, File};
+
+    use crate::config::{EngineConfig, RoundingMode};
+    use crate::csv::write_records;
+    use crate::model::{Account, AccountRecord};
+    use crate::money::{Currency, Money};
+    use crate::policy::{AccountKind, Custodial, Limits, Policy, Standard};
+    use crate::registry::{ClientInfo, ClientRegistry, CurrencyRule, SegmentPolicy};
+
+    #[test]
+    fn test_config_for_applies_the_segment_policy() {
+        let info = |client, segment: &str| ClientInfo {
+            client,
+            name: format!("client {client}"),
+            segment: segment.into(),
+            currency: None,
+            tags: String::new(),
+        };
+        let policy = SegmentPolicy {
+            segment: "retail".into(),
+            max_withdrawal: Some(dec!(100).into()),
+            withdrawal_fee: None,
+            block_disputes: Some(true),
+            account_kind: Some(AccountKind::Custodial),
+            overdraft_limit: None,
+            max_open_disputes: None,
+        };
+        let registry = ClientRegistry {
+            clients: HashMap::from([(1, info(1, "retail")), (2, info(2, "institutional"))]),
+            policies: HashMap::from([("retail".into(), policy)]),
+            currencies: HashMap::new(),
+        };
+        let base = EngineConfig {
+            policy: Policy::Standard(Standard {
+                limits: Limits {
+                    withdrawal_fee: dec!(0.5).into(),
+                    ..Limits::default()
+                },
+            }),
+            ..EngineConfig::default()
+        };
+        let retail = registry.config_for(1, base).policy;
+        assert_eq!(
+            retail,
+            Policy::Custodial(Custodial {
+                limits: Limits {
+                    max_withdrawal: Some(dec!(100).into()),
+                    withdrawal_fee: dec!(0.5).into(),
+                    block_disputes: true,
+                    max_open_disputes: None,
+                },
+            })
+        );
+        assert_eq!(registry.config_for(2, base).policy, base.policy);
+        assert_eq!(registry.config_for(3, base).policy, base.policy);
+    }
+
+    #[test]
+    fn test_config_for_applies_the_currency_rule() {
+        let info = |client, currency: Option<&str>| ClientInfo {
+            client,
+            name: format!("client {client}"),
+            segment: "retail".into(),
+            currency: currency.and_then(Currency::parse),
+            tags: String::new(),
+        };
+        let rule = |currency: &str, precision, display| CurrencyRule {
+            currency: currency.into(),
+            precision,
+            display,
+            rounding: (currency == "JPY").then_some(RoundingMode::HalfUp),
+        };
+        let registry = ClientRegistry {
+            clients: HashMap::from([(1, info(1, Some("jpy"))), (2, info(2, Some("BHD")))]),
+            policies: HashMap::new(),
+            currencies: HashMap::from([
+                ("JPY".into(), rule("JPY", 0, 0)),
+                ("BHD".into(), rule("BHD", 3, 3)),
+                ("USD".into(), rule("USD", 4, 2)),
+            ]),
+        };
+        let rounding = |client| {
+            let rounding = registry
+                .config_for(client, EngineConfig::default())
+                .rounding;
+            (rounding.precision, rounding.display)
+        };
+        assert_eq!(rounding(1), (0, 0));
+        assert_eq!(rounding(2), (3, 3));
+        assert_eq!(
+            registry.config_for(1, EngineConfig::default()).currency,
+            Currency::parse("JPY").unwrap()
+        );
+        // unknown clients are in USD
+        assert_eq!(rounding(3), (4, 2));
+
+        // half cents are kept, only the written amounts lose them
+        let mut account = Account::new(3, registry.config_for(3, EngineConfig::default()));
+        account.deposit(dec!(0.005).into(), 1).unwrap();
+        assert_eq!(AccountRecord::from(&account).total, dec!(0));
+        account.deposit(dec!(0.005).into(), 2).unwrap();
+        assert_eq!(AccountRecord::from(&account).total, dec!(0.01));
+
+        let yen = EngineConfig {
+            policy: Policy::Standard(Standard {
+                limits: Limits {
+                    withdrawal_fee: dec!(0.5).into(),
+                    ..Limits::default()
+                },
+            }),
+            ..registry.config_for(1, EngineConfig::default())
+        };
+        let mut account = Account::new(1, yen);
+        account.deposit(dec!(100).into(), 1).unwrap();
+        // the fee is rounded up to a whole yen before it is debited, not the balance after it
+        account.withdraw(dec!(10).into(), 2).unwrap();
+        assert_eq!(AccountRecord::from(&account).available, dec!(89));
+        // the rounded balances stay in the currency whose rule rounded them
+        let jpy = Currency::parse("JPY").unwrap();
+        assert_eq!(account.total(), Money::with_currency(dec!(89), jpy));
+        assert_eq!(account.rounding_remainder().currency(), Some(jpy));
+    }
+
+    #[actix::test]
+    async fn test_registry_round_trips_through_its_csv() {
+        let clients = [
+            ClientInfo {
+                client: 1,
+                name: "Ada".into(),
+                segment: "retail".into(),
+                currency: Currency::parse("JPY"),
+                tags: "vip;watch".into(),
+            },
+            ClientInfo {
+                client: 2,
+                name: "Babbage, Inc".into(),
+                segment: "institutional".into(),
+                currency: None,
+                tags: String::new(),
+            },
+        ];
+        let path = std::env::temp_dir().join(format!("registry-{}.csv", std::process::id()));
+        write_records(File::create(&path).await.unwrap(), &clients)
+            .await
+            .unwrap();
+        let registry = ClientRegistry::load(&path).await.unwrap();
+        remove_file(&path).await.unwrap();
+
+        for info in &clients {
+            let loaded = registry.get(info.client).unwrap();
+            assert_eq!(loaded.name, info.name);
+            assert_eq!(loaded.segment, info.segment);
+            assert_eq!(loaded.currency, info.currency);
+            assert_eq!(loaded.tags, info.tags);
+        }
+        assert!(registry.get(3).is_none());
+        assert_eq!(registry.tags().of(1), ["vip", "watch"]);
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use anyhow::Result;
+use csv_async::AsyncSerializer;
+use tokio::fs::File;
+use tokio::io::BufWriter;
+
+use crate::model::{Transaction, TransactionType};
+use crate::money::Money;
+
+/// A transaction that wasn't applied, as it is written in the rejects file
+#[derive(Serialize)]
+struct RejectRecord<'a> {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Money>,
+    reason: &'a str,
+}
+
+/// Writes every transaction rejected or undelivered by the engine into a csv with the columns of
+/// the input and the reason it failed, so the accepted and rejected rows can be reconciled
+pub struct RejectWriter {
+    serializer: AsyncSerializer<BufWriter<File>>,
+}
+
+impl RejectWriter {
+    /// Creates the rejects file, replacing it if it exists
+    ///
+    /// # Errors
+    /// If the file can't be created, an error will be returned
+    pub async fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).await?;
+        Ok(Self {
+            serializer: AsyncSerializer::from_writer(BufWriter::new(file)),
+        })
+    }
+
+    /// Writes a failed transaction with the reason it failed
+    ///
+    /// # Errors
+    /// If the file can't be written, an error will be returned
+    pub async fn write(&mut self, transaction: &Transaction, reason: &str) -> Result<()> {
+        self.serializer
+            .serialize(RejectRecord {
+                transaction_type: transaction.transaction_type,
+                client: transaction.client,
+                tx: transaction.tx,
+                amount: transaction.amount,
+                reason,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Writes the buffered rows to the file
+    ///
+    /// # Errors
+    /// If the file can't be written, an error will be returned
+    pub async fn flush(&mut self) -> Result<()> {
+        self.serializer.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::engine::Engine;
+    use crate::model::{Transaction, TransactionType};
+    use crate::rejects::RejectWriter;
+
+    #[actix::test]
+    async fn test_rejected_transactions_are_written() {
+        let path = std::env::temp_dir().join(format!("rejects-{}.csv", std::process::id()));
+        let rejects = RejectWriter::create(&path).await.unwrap();
+        let mut engine =
+            Engine::new(DispatchConfig::default(), EngineConfig::default()).with_rejects(rejects);
+        for (transaction_type, tx, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(5))),
+            (TransactionType::Withdrawal, 2, Some(dec!(10))),
+            (TransactionType::Dispute, 3, None),
+        ] {
+            let transaction = Transaction::for_test(transaction_type, 1, tx, amount);
+            engine.apply(transaction).await.unwrap();
+        }
+        engine.collect().await.unwrap();
+        let written = tokio::fs::read_to_string(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // disputes of unknown transactions aren't errors
+        assert_eq!(
+            written,
+            "type,client,tx,amount,reason\nWithdrawal,1,2,10,insufficient funds in the account of client 1 for 10 (tx 2)\n"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use anyhow::Result;
+use tokio::fs::File;
+use tokio::io::{stdout, BufReader};
+use tracing::info;
+
+use crate::cli::RepairArgs;
+use crate::csv::{process_transactions, read_records, write_records};
+use crate::engine::Engine;
+use crate::model::AccountRecord;
+
+/// A field of an account that doesn't hold the expected value
+#[derive(Serialize, Debug, PartialEq)]
+struct RepairEntry {
+    client: u16,
+    field: &'static str,
+    stored: String,
+    expected: String,
+}
+
+impl RepairEntry {
+    fn new(client: u16, field: &'static str, stored: impl Display, expected: impl Display) -> Self {
+        Self {
+            client,
+            field,
+            stored: stored.to_string(),
+            expected: expected.to_string(),
+        }
+    }
+}
+
+/// Checks the accounts of a csv file for inconsistencies, writing a report of every divergent
+/// field to the std out and optionally the repaired accounts into a file
+///
+/// # Errors
+/// If the files can't be read or written, an error will be returned
+pub async fn repair(args: &RepairArgs) -> Result<()> {
+    let accounts_file = File::open(&args.accounts).await?;
+    let stored: BTreeMap<u16, AccountRecord> =
+        read_records::<AccountRecord>(BufReader::new(accounts_file))
+            .await
+            .into_iter()
+            .map(|record| (record.client, record))
+            .collect();
+
+    let mut entries = Vec::new();
+    let expected = match &args.transactions {
+        Some(path) => {
+            let transactions_file = File::open(path).await?;
+            let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine());
+            process_transactions(BufReader::new(transactions_file), &mut engine).await?;
+            let mut expected = BTreeMap::new();
+            for account in engine.collect().await? {
+                if account.held() != account.disputed_total() {
+                    entries.push(RepairEntry::new(
+                        account.client(),
+                        "held",
+                        account.held(),
+                        account.disputed_total(),
+                    ));
+                }
+                expected.insert(account.client(), AccountRecord::from(&account));
+            }
+            expected
+        }
+        None => stored
+            .values()
+            .map(|record| (record.client, recompute(record)))
+            .collect(),
+    };
+
+    for (client, record) in &stored {
+        match expected.get(client) {
+            Some(expected) => entries.extend(compare(record, expected)),
+            None => entries.push(RepairEntry::new(*client, "account", "present", "missing")),
+        }
+    }
+    for client in expected.keys().filter(|c| !stored.contains_key(c)) {
+        entries.push(RepairEntry::new(*client, "account", "missing", "present"));
+    }
+    info!("Found {} inconsistencies", entries.len());
+    write_records(stdout(), entries).await?;
+
+    if let Some(path) = &args.output {
+        let output_file = File::create(path).await?;
+        write_records(output_file, expected.into_values()).await?;
+    }
+    Ok(())
+}
+
+/// Recomputes the derived fields of an account from the stored ones
+fn recompute(record: &AccountRecord) -> AccountRecord {
+    AccountRecord {
+        total: record.available + record.held,
+        ..record.clone()
+    }
+}
+
+/// Compares every field of both accounts, returning an entry for each one that differs
+fn compare(stored: &AccountRecord, expected: &AccountRecord) -> Vec<RepairEntry> {
+    let client = stored.client;
+    let mut entries = Vec::new();
+    if stored.available != expected.available {
+        entries.push(RepairEntry::new(
+            client,
+            "available",
+            stored.available,
+            expected.available,
+        ));
+    }
+    if stored.held != expected.held {
+        entries.push(RepairEntry::new(client, "held", stored.held, expected.held));
+    }
+    if stored.total != expected.total {
+        entries.push(RepairEntry::new(
+            client,
+            "total",
+            stored.total,
+            expected.total,
+        ));
+    }
+    if stored.locked != expected.locked {
+        entries.push(RepairEntry::new(
+            client,
+            "locked",
+            stored.locked,
+            expected.locked,
+        ));
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::model::AccountRecord;
+    use crate::repair::{compare, recompute, RepairEntry};
+
+    #[test]
+    fn test_recompute_total() {
+        let stored = AccountRecord {
+            total: dec!(100),
+            ..AccountRecord::for_test(1, dec!(60.5), dec!(20))
+        };
+        let entries = compare(&stored, &recompute(&stored));
+        assert_eq!(entries, vec![RepairEntry::new(1, "total", "100", "80.5")]);
+    }
+
+    #[test]
+    fn test_compare_consistent() {
+        let stored = AccountRecord {
+            total: dec!(100),
+            ..AccountRecord::for_test(1, dec!(80), dec!(20))
+        };
+        assert!(compare(&stored, &recompute(&stored)).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+#[cfg(feature = "http")]
+use std::collections::{BTreeMap, HashSet};
+#[cfg(feature = "http")]
+use std::path::PathBuf;
+#[cfg(feature = "http")]
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+#[cfg(feature = "http")]
+use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
+#[cfg(feature = "http")]
+use anyhow::ensure;
+use anyhow::Result;
+#[cfg(feature = "http")]
+use tracing::{error, info};
+
+use crate::cli::ReplicaArgs;
+#[cfg(feature = "http")]
+use crate::engine::Engine;
+#[cfg(feature = "http")]
+use crate::journal::JournalReader;
+#[cfg(feature = "http")]
+use crate::model::AccountRecord;
+#[cfg(feature = "http")]
+use crate::snapshot::Snapshot;
+
+/// The balances served by the replica, updated after every batch of journal events
+#[cfg(feature = "http")]
+type Accounts = RwLock<BTreeMap<u16, AccountRecord>>;
+
+/// Follows the journal of a primary instance, applying its events to local accounts, and serves
+/// their balances over http until the process is stopped
+///
+/// # Errors
+/// If the snapshot or the journal can't be read or the server can't listen, an error will be
+/// returned
+#[cfg(feature = "http")]
+pub async fn replica(args: &ReplicaArgs) -> Result<()> {
+    let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine());
+    let accounts = Arc::new(Accounts::default());
+    let mut seq = 0;
+    if let Some(path) = &args.restore {
+        let snapshot = Snapshot::read(path).await?;
+        seq = snapshot.journal_seq;
+        accounts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(snapshot.accounts.iter().map(|s| (s.client(), s.into())));
+        engine.restore(snapshot)?;
+    }
+    let reader = JournalReader::open_after(&args.journal, seq).await?;
+    ensure!(
+        reader.base_seq() <= seq,
+        "The journal starts after event {seq}, restore a newer snapshot"
+    );
+    let follower = JournalFollower {
+        path: args.journal.clone(),
+        reader,
+        engine,
+        seq,
+        accounts: Arc::clone(&accounts),
+    };
+    actix::spawn(follower.run(Duration::from_millis(args.poll_interval)));
+
+    info!("Serving balances on {}", args.listen);
+    let accounts = web::Data::from(accounts);
+    HttpServer::new(move || {
+        App::new()
+            .app_data(accounts.clone())
+            .service(get_account)
+            .service(list_accounts)
+    })
+    .bind(&args.listen)?
+    .run()
+    .await?;
+    Ok(())
+}
+
+/// Fails, as the crate was built without the `http` feature
+#[cfg(not(feature = "http"))]
+#[allow(clippy::unused_async)]
+pub async fn replica(args: &ReplicaArgs) -> Result<()> {
+    anyhow::bail!(
+        "Can't serve the balances on {}: built without the `http` feature",
+        args.listen
+    )
+}
+
+/// Applies the events appended to the journal, reopening it when it is compacted
+#[cfg(feature = "http")]
+struct JournalFollower {
+    path: PathBuf,
+    reader: JournalReader,
+    engine: Engine,
+    /// The sequence number of the last event applied
+    seq: u64,
+    accounts: Arc<Accounts>,
+}
+
+#[cfg(feature = "http")]
+impl JournalFollower {
+    async fn run(mut self, interval: Duration) {
+        loop {
+            if let Err(e) = self.catch_up().await {
+                error!("Could not follow the journal: {e}");
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Applies every new event and refreshes the balances of the accounts they changed
+    async fn catch_up(&mut self) -> Result<()> {
+        let mut changed = HashSet::new();
+        while let Some(event) = self.reader.poll_event().await? {
+            if event.seq <= self.seq {
+                continue;
+            }
+            changed.insert(event.transaction.client);
+            self.engine.apply(event.transaction).await?;
+            self.seq = event.seq;
+        }
+        for client in changed {
+            if let Some(state) = self.engine.state(client).await? {
+                self.accounts
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(client, (&state).into());
+            }
+        }
+
+        // a compaction or a rotation replaces the file, the events kept in it or moved into a
+        // segment still have the same numbers
+        let current = JournalReader::open_after(&self.path, u64::MAX).await?;
+        if current.active_base_seq() != self.reader.active_base_seq() {
+            let reopened = JournalReader::open_after(&self.path, self.seq).await?;
+            ensure!(
+                reopened.base_seq() <= self.seq,
+                "The journal was compacted past event {}, restart from a newer snapshot",
+                self.seq
+            );
+            info!("The journal was compacted or rotated, reopening it");
+            self.reader = reopened;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "http")]
+fn read(accounts: &Accounts) -> RwLockReadGuard<'_, BTreeMap<u16, AccountRecord>> {
+    accounts.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(feature = "http")]
+#[get("/accounts/{client}")]
+async fn get_account(accounts: web::Data<Accounts>, client: web::Path<u16>) -> impl Responder {
+    match read(&accounts).get(&client) {
+        Some(record) => HttpResponse::Ok().json(record),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[cfg(feature = "http")]
+#[get("/accounts")]
+async fn list_accounts(accounts: web::Data<Accounts>) -> impl Responder {
+    HttpResponse::Ok().json(read(&accounts).values().collect::<Vec<_>>())
+}
@@ -0,0 +1,184 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail, ensure, Result};
+use tokio::io::stdout;
+use tracing::info;
+
+use crate::cli::RollbackArgs;
+use crate::csv::write_records;
+use crate::engine::Engine;
+use crate::ids::generator;
+use crate::journal::{JournalReader, JournalWriter};
+use crate::model::{AccountState, Transaction, TransactionType};
+use crate::snapshot::Snapshot;
+
+/// Rebuilds the accounts from the journal and applies a transaction reversing the requested one.
+/// History is never changed: the compensating transaction goes through the same rules as any other
+/// and is appended to the journal, next to the one it reverses.
+///
+/// # Errors
+/// If the files can't be read or written, the transaction can't be reversed or was already, or the
+/// compensating transaction is rejected, an error will be returned
+pub async fn rollback(args: &RollbackArgs) -> Result<()> {
+    let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine());
+    let mut after = 0;
+    if let Some(path) = &args.restore {
+        let snapshot = Snapshot::read(path).await?;
+        after = snapshot.journal_seq;
+        engine.restore(snapshot)?;
+    }
+    let mut journal = JournalReader::open(&args.journal).await?;
+    ensure!(
+        journal.base_seq() <= after,
+        "The journal starts after event {after}, restore a newer snapshot"
+    );
+
+    let mut original = None;
+    // the ids of the events compacted into the snapshot are still used
+    let mut used: HashSet<u32> = engine
+        .states()
+        .await?
+        .iter()
+        .flat_map(AccountState::tx_ids)
+        .collect();
+    while let Some(event) = journal.next_event().await? {
+        used.insert(event.transaction.tx);
+        if event.transaction.tx == args.tx && event.reverses.is_none() {
+            original = Some(event.transaction.clone());
+        }
+        if event.reverses == Some(args.tx) {
+            bail!(
+                "Transaction {} was already reversed by transaction {}",
+                args.tx,
+                event.transaction.tx
+            );
+        }
+        if event.seq > after {
+            engine.apply(event.transaction).await?;
+        }
+    }
+    let original =
+        original.ok_or_else(|| anyhow!("Transaction {} is not in the journal", args.tx))?;
+    let tx = generator(args.ids.ids()).next_id(&mut used)?;
+    let compensation = compensate(&original, tx)?;
+    info!(
+        "Reversing transaction {} with transaction {}",
+        original.tx, compensation.tx
+    );
+
+    let journal = JournalWriter::open(&args.journal)
+        .await?
+        .reversing(original.tx);
+    let mut engine = engine.with_journal(journal);
+    engine.begin();
+    engine.apply(compensation).await?;
+    if engine.staged_failure().is_some() {
+        engine.rollback().await?;
+        bail!("The compensating transaction was rejected");
+    }
+    engine.commit().await?;
+
+    let journal_seq = engine.journal_seq();
+    let accounts = engine.collect().await?;
+    if let Some(path) = &args.snapshot {
+        Snapshot::new(journal_seq, &accounts).write(path).await?;
+    }
+    write_records(stdout(), &accounts).await
+}
+
+/// The transaction moving back the funds moved by `original`
+fn compensate(original: &Transaction, tx: u32) -> Result<Transaction> {
+    let transaction_type = match original.transaction_type {
+        TransactionType::Deposit => TransactionType::Withdrawal,
+        TransactionType::Withdrawal => TransactionType::Deposit,
+        TransactionType::Transfer => TransactionType::Transfer,
+        _ => bail!("Only deposits, withdrawals and transfers can be rolled back"),
+    };
+    // a transfer is moved back by one the other way around
+    let (client, to_client) = match (original.transaction_type, original.to_client) {
+        (TransactionType::Transfer, Some(to_client)) => (to_client, Some(original.client)),
+        _ => (original.client, None),
+    };
+    Ok(Transaction {
+        transaction_type,
+        client,
+        tx,
+        amount: original.amount,
+        reference: original.reference,
+        to_client,
+        action: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rust_decimal_macros::dec;
+
+    use crate::cli::{Cli, Command};
+    use crate::config::EngineConfig;
+    use crate::journal::{rewrite_journal, JournalReader, JournalWriter};
+    use crate::model::{Account, Transaction, TransactionType};
+    use crate::rollback::{compensate, rollback};
+    use crate::snapshot::Snapshot;
+
+    #[test]
+    fn test_compensate() {
+        let deposit = Transaction::test_deposit(3, 1, dec!(2.5));
+        let withdrawal = compensate(&deposit, 9).unwrap();
+        assert!(withdrawal.transaction_type == TransactionType::Withdrawal);
+        assert_eq!((withdrawal.client, withdrawal.tx), (3, 9));
+        assert_eq!(withdrawal.amount, Some(dec!(2.5).into()));
+
+        let dispute = Transaction::for_test(TransactionType::Dispute, 3, 1, None);
+        assert!(compensate(&dispute, 9).is_err());
+    }
+
+    #[actix::test]
+    async fn test_transactions_are_reversed_once() {
+        let dir = std::env::temp_dir().join(format!("rollback-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (journal, snapshot) = (dir.join("journal.ndjson"), dir.join("snapshot.json"));
+        let mut writer = JournalWriter::open(&journal).await.unwrap();
+        let mut account = Account::new(1, EngineConfig::default());
+        for tx in 1..=2 {
+            let deposit = Transaction::test_deposit(1, tx, dec!(5));
+            writer.append(&deposit).await.unwrap();
+            if tx == 1 {
+                account.apply(&deposit).unwrap();
+            }
+        }
+        writer.flush().await.unwrap();
+        // the first deposit only lives in the snapshot, as after a compaction
+        Snapshot::new(1, &[account]).write(&snapshot).await.unwrap();
+        rewrite_journal(&journal, 1).await.unwrap();
+
+        let argv = [
+            "test",
+            "rollback",
+            "2",
+            "--journal",
+            journal.to_str().unwrap(),
+            "--restore",
+            snapshot.to_str().unwrap(),
+            "--id-scheme",
+            "sequential",
+        ];
+        let Some(Command::Rollback(args)) = Cli::parse_from(argv).command else {
+            unreachable!()
+        };
+        rollback(&args).await.unwrap();
+        let mut reader = JournalReader::open(&journal).await.unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = reader.next_event().await.unwrap() {
+            events.push(event);
+        }
+        let compensation = events.last().unwrap();
+        assert_eq!(compensation.reverses, Some(2));
+        assert_eq!(compensation.transaction.tx, 3);
+
+        let error = rollback(&args).await.err().unwrap();
+        assert!(error.to_string().contains("already reversed"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio::fs::File;
+use tracing::info;
+
+use crate::csv::write_records;
+use crate::model::Account;
+
+/// A row of the rounding report: a client, or `suspense` for the sum of every client
+#[derive(Serialize, Debug, PartialEq)]
+struct RemainderRow {
+    client: String,
+    remainder: Decimal,
+}
+
+/// The clients whose printed total lost something to rounding, ordered by client, followed by
+/// the suspense row
+fn remainder_rows(accounts: &[Account]) -> Vec<RemainderRow> {
+    let mut remainders: Vec<_> = accounts
+        .iter()
+        .map(|account| (account.client(), account.rounding_remainder().amount()))
+        .filter(|(_, remainder)| !remainder.is_zero())
+        .collect();
+    remainders.sort_unstable_by_key(|(client, _)| *client);
+    let suspense: Decimal = remainders.iter().map(|(_, remainder)| remainder).sum();
+    remainders
+        .into_iter()
+        .map(|(client, remainder)| RemainderRow {
+            client: client.to_string(),
+            remainder,
+        })
+        .chain([RemainderRow {
+            client: "suspense".into(),
+            remainder: suspense,
+        }])
+        .collect()
+}
+
+/// Writes the rounding remainder of every client whose printed total differs from its exact one,
+/// and the rounding suspense of the run
+///
+/// # Errors
+/// If the file can't be written, an error will be returned
+pub async fn write_rounding_report(path: &Path, accounts: &[Account]) -> Result<()> {
+    let rows = remainder_rows(accounts);
+    if let Some(suspense) = rows.last() {
+        info!("Rounding suspense: {}", suspense.remainder);
+    }
+    write_records(File::create(path).await?, rows).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::config::EngineConfig;
+    use crate::model::{Account, AccountRecord};
+    use crate::rounding::{remainder_rows, RemainderRow};
+
+    #[test]
+    fn test_remainder_rows() {
+        let config = EngineConfig {
+            late_rounding: true,
+            ..EngineConfig::default()
+        };
+        let mut first = Account::new(2, config);
+        first.deposit(dec!(0.00004).into(), 1).unwrap();
+        first.deposit(dec!(0.00004).into(), 2).unwrap();
+        first.deposit(dec!(1.00003).into(), 3).unwrap();
+        let mut second = Account::new(1, config);
+        second.deposit(dec!(2.5).into(), 4).unwrap();
+        let mut third = Account::new(3, config);
+        third.deposit(dec!(0.99996).into(), 5).unwrap();
+
+        assert_eq!(AccountRecord::from(&first).total, dec!(1.0001));
+        let rows = remainder_rows(&[first, second, third]);
+        let row = |client: &str, remainder| RemainderRow {
+            client: client.into(),
+            remainder,
+        };
+        assert_eq!(
+            rows,
+            vec![
+                row("2", dec!(0.00001)),
+                row("3", dec!(-0.00004)),
+                row("suspense", dec!(-0.00003)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure, Error};
+
+use crate::model::Transaction;
+
+/// Which transactions of the input a sampling run processes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleSpec {
+    /// The transactions of this percentage of the clients. Whole clients are taken, so disputes
+    /// still find the deposits they refer to.
+    Percent(u8),
+    /// The first transactions of the input
+    First(usize),
+}
+
+impl FromStr for SampleSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%') {
+            Some(percent) => {
+                let percent: u8 = percent.trim().parse()?;
+                ensure!(
+                    (1..=100).contains(&percent),
+                    "The percentage must be between 1 and 100"
+                );
+                Ok(Self::Percent(percent))
+            }
+            None => s
+                .trim()
+                .parse()
+                .map(Self::First)
+                .map_err(|_| anyhow!("Expected a percentage like `1%` or a number of rows")),
+        }
+    }
+}
+
+impl Display for SampleSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Percent(percent) => write!(f, "{percent}% of the clients"),
+            Self::First(rows) => write!(f, "the first {rows} transactions"),
+        }
+    }
+}
+
+/// Picks the transactions of a sample as they are read
+pub struct Sampler {
+    spec: SampleSpec,
+    seen: usize,
+    taken: usize,
+}
+
+impl Sampler {
+    pub fn new(spec: SampleSpec) -> Self {
+        Self {
+            spec,
+            seen: 0,
+            taken: 0,
+        }
+    }
+
+    /// Whether the transaction is part of the sample
+    pub fn take(&mut self, transaction: &Transaction) -> bool {
+        self.seen += 1;
+        let taken = match self.spec {
+            SampleSpec::Percent(percent) => transaction.client % 100 < u16::from(percent),
+            SampleSpec::First(rows) => self.taken < rows,
+        };
+        if taken {
+            self.taken += 1;
+        }
+        taken
+    }
+
+    /// Whether no further transaction can be part of the sample, so the input can stop being read
+    pub fn is_complete(&self) -> bool {
+        matches!(self.spec, SampleSpec::First(rows) if self.taken >= rows)
+    }
+
+    /// How many transactions were read and how many of them were taken
+    pub fn counts(&self) -> (usize, usize) {
+        (self.seen, self.taken)
+    }
+
+    pub fn spec(&self) -> SampleSpec {
+        self.spec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sample::SampleSpec;
+
+    #[test]
+    fn test_parse_sample_spec() {
+        assert_eq!("1%".parse::<SampleSpec>().unwrap(), SampleSpec::Percent(1));
+        assert_eq!("250".parse::<SampleSpec>().unwrap(), SampleSpec::First(250));
+        assert!("0%".parse::<SampleSpec>().is_err());
+        assert!("150%".parse::<SampleSpec>().is_err());
+        assert!("some".parse::<SampleSpec>().is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use tokio::io::{stdout, AsyncWriteExt};
+
+use crate::model::Transaction;
+
+/// The formats the input contract can be exported in
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SchemaFormat {
+    /// A JSON Schema of a transaction, for json and ndjson input
+    Json,
+    /// A CSV on the Web (csvw) table schema of the csv input
+    Csv,
+}
+
+/// The JSON Schema of a transaction, generated from the model
+pub fn json_schema() -> Value {
+    schemars::schema_for!(Transaction).to_value()
+}
+
+/// A csvw table schema describing the csv columns, derived from the JSON Schema so both always
+/// describe the same contract
+///
+/// # Errors
+/// If the JSON Schema has a shape that can't be described as a column, an error will be returned
+pub fn csv_schema(schema: &Value) -> Result<Value> {
+    let properties = schema["properties"]
+        .as_object()
+        .ok_or_else(|| anyhow!("The schema has no properties"))?;
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let columns = properties
+        .iter()
+        .map(|(name, property)| {
+            let property = resolve(schema, property)?;
+            let mut column = Map::new();
+            column.insert("name".into(), name.as_str().into());
+            column.insert("titles".into(), name.as_str().into());
+            if let Some(description) = property.get("description") {
+                column.insert("dc:description".into(), description.clone());
+            }
+            column.insert("datatype".into(), datatype(property)?);
+            column.insert("required".into(), required.contains(&name.as_str()).into());
+            Ok(Value::Object(column))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(json!({
+        "@context": "http://www.w3.org/ns/csvw",
+        "dc:title": schema["title"],
+        "dialect": { "header": true, "delimiter": ",", "trim": true },
+        "tableSchema": { "columns": columns },
+    }))
+}
+
+/// Follows a `$ref` to the definitions of the schema, also for optional properties, which are
+/// either a reference or null
+fn resolve<'a>(schema: &'a Value, property: &'a Value) -> Result<&'a Value> {
+    let property = match property.get("anyOf").and_then(Value::as_array) {
+        Some(variants) => variants
+            .iter()
+            .find(|variant| variant["type"] != "null")
+            .ok_or_else(|| anyhow!("No type for {property}"))?,
+        None => property,
+    };
+    match property.get("$ref").and_then(Value::as_str) {
+        Some(reference) => reference
+            .strip_prefix('#')
+            .and_then(|pointer| schema.pointer(pointer))
+            .ok_or_else(|| anyhow!("Unknown reference {reference}")),
+        None => Ok(property),
+    }
+}
+
+/// The csvw datatype of a property
+fn datatype(property: &Value) -> Result<Value> {
+    if let Some(variants) = property.get("oneOf").or(property.get("enum")) {
+        let values: Vec<&str> = variants
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.get("const").unwrap_or(v).as_str())
+            .collect();
+        return Ok(json!({ "base": "string", "format": values.join("|") }));
+    }
+    let datatype = match property.get("format").and_then(Value::as_str) {
+        Some("uint16") => json!("unsignedShort"),
+        Some("uint32") => json!("unsignedInt"),
+        Some("decimal") => json!("decimal"),
+        _ => match property.get("type").and_then(Value::as_str) {
+            Some("integer") => json!("integer"),
+            Some("boolean") => json!("boolean"),
+            Some("string") => json!("string"),
+            _ => return Err(anyhow!("No csv datatype for {property}")),
+        },
+    };
+    Ok(datatype)
+}
+
+/// Writes the schema of the input in the requested format to the std out
+///
+/// # Errors
+/// If the schema can't be converted or written, an error will be returned
+pub async fn export_schema(format: SchemaFormat) -> Result<()> {
+    let schema = json_schema();
+    let schema = match format {
+        SchemaFormat::Json => schema,
+        SchemaFormat::Csv => csv_schema(&schema)?,
+    };
+    let mut content = serde_json::to_vec_pretty(&schema)?;
+    content.push(b'\n');
+    let mut out = stdout();
+    out.write_all(&content).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema::{csv_schema, json_schema};
+
+    #[test]
+    fn test_csv_schema_matches_model() {
+        let schema = csv_schema(&json_schema()).unwrap();
+        let columns = schema["tableSchema"]["columns"].as_array().unwrap();
+        let names: Vec<_> = columns.iter().map(|c| c["name"].clone()).collect();
+        assert_eq!(
+            names,
+            vec![
+                json!("type"),
+                json!("client"),
+                json!("tx"),
+                json!("amount"),
+                json!("reference"),
+                json!("to_client"),
+                json!("action")
+            ]
+        );
+        assert_eq!(
+            columns[0]["datatype"]["format"],
+            json!("Deposit|Withdrawal|Dispute|Resolve|Chargeback|Savepoint|Open|Transfer|Admin")
+        );
+        assert_eq!(columns[1]["datatype"], json!("unsignedShort"));
+        assert_eq!(columns[3]["datatype"], json!("decimal"));
+        assert_eq!(columns[3]["required"], json!(false));
+        assert_eq!(columns[4]["datatype"], json!("unsignedInt"));
+        assert_eq!(columns[4]["required"], json!(false));
+        assert_eq!(
+            columns[6]["datatype"]["format"],
+            json!("Unlock|Freeze|Close")
+        );
+    }
+}
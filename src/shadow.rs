@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use tracing::error;
+
+use crate::model::{Account, AccountState, Balances, Transaction, TransactionError};
+
+/// A second implementation of the engine, keeping the accounts in a map and applying the
+/// transactions in place, without actors. Every transaction delivered to an actor is also applied
+/// here, and any difference in how it ended or in the resulting account is logged.
+#[derive(Default)]
+pub struct Shadow {
+    accounts: HashMap<u16, Account>,
+    divergences: u64,
+}
+
+impl Shadow {
+    /// Starts following an account, as it was given to its actor
+    pub fn insert(&mut self, account: Account) {
+        self.accounts.insert(account.client(), account);
+    }
+
+    /// Stops following the account of a client
+    pub fn remove(&mut self, client: u16) {
+        self.accounts.remove(&client);
+    }
+
+    /// How many divergences were found so far
+    pub fn divergences(&self) -> u64 {
+        self.divergences
+    }
+
+    /// Applies a transaction the actor ended with `outcome`, logging a divergence if the shadow
+    /// account ends it differently
+    pub fn apply(&mut self, transaction: &Transaction, outcome: &Result<(), TransactionError>) {
+        let client = transaction.client;
+        let Some(account) = self.accounts.get_mut(&client) else {
+            self.diverged(
+                client,
+                format_args!("no account for transaction {}", transaction.tx),
+            );
+            return;
+        };
+        let expected = account.apply(transaction);
+        if &expected != outcome {
+            self.diverged(
+                client,
+                format_args!(
+                    "transaction {} ended with {outcome:?} instead of {expected:?}",
+                    transaction.tx
+                ),
+            );
+        }
+    }
+
+    /// Compares the balances and the lock of the actor's account with the shadow one, returning
+    /// false on a divergence. The histories aren't compared, so the check doesn't grow with them.
+    pub fn compare(&mut self, client: u16, balances: Balances, tx: u32) -> bool {
+        let Some(account) = self.accounts.get(&client) else {
+            return true;
+        };
+        if Balances::from(account) == balances {
+            return true;
+        }
+        self.diverged(
+            client,
+            format_args!("the account differs after transaction {tx}"),
+        );
+        false
+    }
+
+    /// Replaces the shadow account with the state of the actor's after a divergence, so the
+    /// following transactions are checked on their own
+    pub fn resync(&mut self, state: AccountState) {
+        if let Some(account) = self.accounts.get_mut(&state.client()) {
+            let config = account.config();
+            *account = Account::from_state(state, config);
+        }
+    }
+
+    fn diverged(&mut self, client: u16, details: std::fmt::Arguments) {
+        self.divergences += 1;
+        error!("Shadow divergence for client {client}: {details}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::config::EngineConfig;
+    use crate::model::{Account, AccountState, Balances, Transaction};
+    use crate::shadow::Shadow;
+
+    #[test]
+    fn test_shadow_logs_divergences() {
+        let mut shadow = Shadow::default();
+        shadow.insert(Account::new(1, EngineConfig::default()));
+        let deposit = Transaction::test_deposit(1, 1, dec!(10));
+        let mut actor = Account::new(1, EngineConfig::default());
+        let outcome = actor.apply(&deposit);
+        shadow.apply(&deposit, &outcome);
+        assert!(shadow.compare(1, Balances::from(&actor), 1));
+        assert_eq!(shadow.divergences(), 0);
+
+        actor.deposit(dec!(5).into(), 2).unwrap();
+        assert!(!shadow.compare(1, Balances::from(&actor), 2));
+        assert_eq!(shadow.divergences(), 1);
+        // the shadow follows the actor once resynced
+        shadow.resync(AccountState::from(&actor));
+        assert!(shadow.compare(1, Balances::from(&actor), 2));
+        assert_eq!(shadow.divergences(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix::{Actor, Addr, ArbiterHandle, AsyncContext, Context, Handler, Message};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::info;
+
+use crate::hooks::{HookPoint, ProcessingHook};
+use crate::model::{Account, AccountState, Balances, Collect, GetState, Restore, TransactionError};
+use crate::partition::hash_bucket;
+use crate::store::{MarkDirty, StoreWriter};
+use crate::transaction::{
+    run_hook, AccountAddr, AccountRef, Deliver, ForClient, PriorityRequest, Reply, ServePriority,
+};
+
+/// Actor holding the accounts of a partition of the clients, instead of an actor per client, so
+/// a run with many clients doesn't pay for an actor and a mailbox per account. The accounts of a
+/// shard are applied one at a time, and a crash takes all of them down.
+pub struct ShardedAccountHandler {
+    shard: u16,
+    accounts: HashMap<u16, Account>,
+    store: Option<Addr<StoreWriter>>,
+    priority: UnboundedReceiver<PriorityRequest>,
+    hook: Option<Arc<dyn ProcessingHook>>,
+}
+
+/// Hands an account to the actor of its shard, replacing the one it held for the client, if any
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Adopt(Account);
+
+impl ShardedAccountHandler {
+    /// Answers the pending priority requests, like `AccountHandler` does
+    fn serve_priority(&mut self) {
+        while let Ok(request) = self.priority.try_recv() {
+            match request {
+                PriorityRequest::GetState(client, reply) => {
+                    if let Some(account) = self.accounts.get(&client) {
+                        let _ = reply.send(AccountState::from(account));
+                    }
+                }
+                PriorityRequest::GetBalances(client, reply) => {
+                    if let Some(account) = self.accounts.get(&client) {
+                        let _ = reply.send(Balances::from(account));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reports a change of the account of a client to the store writer, if there's one
+    fn mark_dirty(&self, client: u16, ctx: &mut Context<Self>) {
+        if let Some(store) = &self.store {
+            store.do_send(MarkDirty {
+                client,
+                actor: AccountAddr::Shard(ctx.address(), client),
+            });
+        }
+    }
+}
+
+impl Actor for ShardedAccountHandler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // the engine bounds the transactions sent ahead of their answer itself
+        ctx.set_mailbox_capacity(0);
+        info!("Actor of shard {} started.", self.shard);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        info!("Actor of shard {} stopped.", self.shard);
+    }
+}
+
+impl Handler<Adopt> for ShardedAccountHandler {
+    type Result = ();
+
+    fn handle(&mut self, Adopt(account): Adopt, _: &mut Self::Context) -> Self::Result {
+        self.accounts.insert(account.client(), account);
+    }
+}
+
+impl Handler<ForClient<Deliver>> for ShardedAccountHandler {
+    type Result = Reply<Result<(), TransactionError>>;
+
+    fn handle(&mut self, msg: ForClient<Deliver>, ctx: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+        let ForClient(
+            client,
+            Deliver {
+                transaction: tx,
+                ticket,
+            },
+        ) = msg;
+        let hook = self.hook.clone();
+        let Some(account) = self.accounts.get_mut(&client) else {
+            return Reply(None);
+        };
+        if !run_hook(hook.as_ref(), HookPoint::PreApply, &tx) {
+            return Reply(None);
+        }
+        let Some(result) = ticket.apply(|| account.apply(&tx)) else {
+            return Reply(None);
+        };
+        if result.is_ok() {
+            self.mark_dirty(client, ctx);
+        }
+        if !run_hook(hook.as_ref(), HookPoint::PostApply, &tx) {
+            return Reply(None);
+        }
+        Reply(Some(result))
+    }
+}
+
+impl Handler<ForClient<Collect>> for ShardedAccountHandler {
+    type Result = Reply<Account>;
+
+    fn handle(&mut self, msg: ForClient<Collect>, _: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+        Reply(self.accounts.remove(&msg.0))
+    }
+}
+
+impl Handler<ForClient<GetState>> for ShardedAccountHandler {
+    type Result = Reply<AccountState>;
+
+    fn handle(&mut self, msg: ForClient<GetState>, _: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+        Reply(self.accounts.get(&msg.0).map(AccountState::from))
+    }
+}
+
+impl Handler<ForClient<Restore>> for ShardedAccountHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: ForClient<Restore>, ctx: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+        let ForClient(client, Restore(state)) = msg;
+        if let Some(account) = self.accounts.get_mut(&client) {
+            account.restore(state);
+            self.mark_dirty(client, ctx);
+        }
+    }
+}
+
+impl Handler<ServePriority> for ShardedAccountHandler {
+    type Result = ();
+
+    fn handle(&mut self, _: ServePriority, _: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+    }
+}
+
+/// The actor of a shard along with its priority lane
+struct Shard {
+    addr: Addr<ShardedAccountHandler>,
+    priority: UnboundedSender<PriorityRequest>,
+}
+
+/// A fixed pool of actors holding the accounts, the client ids being partitioned among them
+pub struct Shards {
+    count: u16,
+    /// The actor of every shard, started when the first account of its partition is found
+    actors: Vec<Option<Shard>>,
+}
+
+impl Shards {
+    pub fn new(count: u16) -> Self {
+        let count = count.max(1);
+        Self {
+            count,
+            actors: (0..count).map(|_| None).collect(),
+        }
+    }
+
+    /// The shard holding the account of a client. A client always goes to the same shard.
+    pub fn index_for(&self, client: u16) -> u16 {
+        let index = hash_bucket(client, usize::from(self.count));
+        u16::try_from(index).expect("The shard is below the number of shards")
+    }
+
+    /// Hands an account to the actor of its shard, starting it in the arbiter given if it's the
+    /// first account of its partition. Changes are reported to the store writer, if there's one,
+    /// and the hook is called around every transaction.
+    pub fn adopt(
+        &mut self,
+        account: Account,
+        store: Option<Addr<StoreWriter>>,
+        hook: Option<Arc<dyn ProcessingHook>>,
+        arbiter: &ArbiterHandle,
+    ) -> AccountRef {
+        let (client, shard) = (account.client(), self.index_for(account.client()));
+        let actor = self.actors[usize::from(shard)].get_or_insert_with(|| {
+            let (priority, requests) = mpsc::unbounded_channel();
+            let addr =
+                ShardedAccountHandler::start_in_arbiter(arbiter, move |_| ShardedAccountHandler {
+                    shard,
+                    accounts: HashMap::new(),
+                    store,
+                    priority: requests,
+                    hook,
+                });
+            Shard { addr, priority }
+        });
+        actor.addr.do_send(Adopt(account));
+        AccountRef::new(
+            AccountAddr::Shard(actor.addr.clone(), client),
+            client,
+            actor.priority.clone(),
+        )
+    }
+}
@@ -0,0 +1,185 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Context as TaskContext, Poll};
+
+use anyhow::{anyhow, ensure, Result};
+use ring::digest::{digest, Context, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use tokio::fs;
+use tokio::io::AsyncWrite;
+use tracing::info;
+
+use crate::cli::VerifyArgs;
+use crate::manifest::RunManifest;
+use crate::snapshot::write_atomically;
+
+/// The hex encoded sha256 digest of the content
+pub fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(digest(&SHA256, content))
+}
+
+/// Passes the bytes written on to a writer, taking the sha256 digest of what it accepted, so an
+/// output is digested as it is written instead of being kept in memory
+pub struct DigestWriter<W> {
+    inner: W,
+    digest: Context,
+}
+
+impl<W> DigestWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            digest: Context::new(&SHA256),
+        }
+    }
+
+    /// The hex encoded sha256 digest of everything written
+    pub fn finish(self) -> String {
+        hex::encode(self.digest.finish())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for DigestWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.digest.update(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// The path of the detached signature of a file
+pub fn signature_path_for(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".sig");
+    path.with_file_name(name)
+}
+
+/// Generates an ed25519 key pair, writing the hex encoded private key (a pkcs8 document) and
+/// public key into their files
+///
+/// # Errors
+/// If the files can't be written, an error will be returned
+pub async fn generate_keys(private_key: &Path, public_key: &Path) -> Result<()> {
+    let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|e| anyhow!("Could not generate a key: {e}"))?;
+    let pair = load_pair(document.as_ref())?;
+    write_atomically(private_key, hex::encode(document.as_ref()).as_bytes()).await?;
+    write_atomically(public_key, hex::encode(pair.public_key()).as_bytes()).await
+}
+
+/// Signs a file with the private key, writing the detached signature next to it
+///
+/// # Errors
+/// If the files can't be read or written or the key is invalid, an error will be returned
+pub async fn sign_file(path: &Path, private_key: &Path) -> Result<()> {
+    let key = hex::decode(fs::read_to_string(private_key).await?.trim())?;
+    let signature = load_pair(&key)?.sign(&fs::read(path).await?);
+    write_atomically(&signature_path_for(path), hex::encode(signature).as_bytes()).await
+}
+
+/// Checks the detached signature of a file against the public key
+///
+/// # Errors
+/// If the files can't be read or the signature doesn't match, an error will be returned
+pub async fn verify_file(path: &Path, public_key: &Path) -> Result<()> {
+    let key = hex::decode(fs::read_to_string(public_key).await?.trim())?;
+    let signature = hex::decode(fs::read_to_string(signature_path_for(path)).await?.trim())?;
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(&fs::read(path).await?, &signature)
+        .map_err(|_| anyhow!("The signature of {} doesn't match", path.display()))
+}
+
+/// Checks the signature of a manifest and, if given, that the output matches it
+///
+/// # Errors
+/// If the files can't be read or they were tampered with, an error will be returned
+pub async fn verify(args: &VerifyArgs) -> Result<()> {
+    verify_file(&args.manifest, &args.public_key).await?;
+    if let Some(output) = &args.output {
+        let manifest = RunManifest::read(&args.manifest).await?;
+        let expected = manifest
+            .sha256_of(output)
+            .ok_or_else(|| anyhow!("{} isn't in the manifest", output.display()))?;
+        ensure!(
+            sha256_hex(&fs::read(output).await?) == expected,
+            "{} doesn't match the manifest",
+            output.display()
+        );
+    }
+    info!("{} is valid", args.manifest.display());
+    Ok(())
+}
+
+fn load_pair(pkcs8: &[u8]) -> Result<Ed25519KeyPair> {
+    Ed25519KeyPair::from_pkcs8(pkcs8).map_err(|e| anyhow!("Invalid private key: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::VerifyArgs;
+    use crate::manifest::{OutputDigest, RunManifest};
+    use crate::partition::{write_partitioned, PartitionScheme};
+    use crate::signing::{generate_keys, sign_file, signature_path_for, verify, verify_file};
+
+    #[actix::test]
+    async fn test_sign_and_verify() {
+        let dir = std::env::temp_dir().join(format!("signing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (private_key, public_key) = (dir.join("key"), dir.join("key.pub"));
+        let file = dir.join("manifest.json");
+        generate_keys(&private_key, &public_key).await.unwrap();
+        std::fs::write(&file, "content").unwrap();
+
+        sign_file(&file, &private_key).await.unwrap();
+        assert!(signature_path_for(&file).exists());
+        verify_file(&file, &public_key).await.unwrap();
+        std::fs::write(&file, "tampered").unwrap();
+        assert!(verify_file(&file, &public_key).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[actix::test]
+    async fn test_verify_partitioned_output() {
+        let dir = std::env::temp_dir().join(format!("signing-partitions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (private_key, public_key) = (dir.join("key"), dir.join("key.pub"));
+        let manifest = dir.join("manifest.json");
+        generate_keys(&private_key, &public_key).await.unwrap();
+        let records = (0..10_u16).map(|client| (client, [client]));
+        let files = write_partitioned(&dir, 2, PartitionScheme::Range, records)
+            .await
+            .unwrap();
+        RunManifest::new(None, 0, 10, OutputDigest::Partitioned(files))
+            .write(&manifest)
+            .await
+            .unwrap();
+        sign_file(&manifest, &private_key).await.unwrap();
+        let args = |output: &str| VerifyArgs {
+            manifest: manifest.clone(),
+            public_key: public_key.clone(),
+            output: Some(dir.join(output)),
+        };
+
+        verify(&args("accounts-0.csv")).await.unwrap();
+        verify(&args("accounts-1.csv")).await.unwrap();
+        assert!(verify(&args("accounts-2.csv")).await.is_err());
+        std::fs::write(dir.join("accounts-1.csv"), "tampered").unwrap();
+        assert!(verify(&args("accounts-1.csv")).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+#[cfg(any(test, feature = "sqlite", feature = "postgres"))]
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+#[cfg(any(test, feature = "sqlite", feature = "postgres"))]
+use tracing::warn;
+
+use crate::cli::ProcessArgs;
+use crate::model::AccountRecord;
+
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Settings used when writing the accounts into a database
+#[cfg(any(test, feature = "sqlite", feature = "postgres"))]
+#[derive(Clone, Copy, Debug)]
+pub struct SinkConfig {
+    /// How many accounts are inserted in each database transaction
+    pub batch_size: usize,
+    /// How many extra attempts are made for a batch failing with a transient error
+    pub retries: u32,
+    /// How long to wait before the first retry. It doubles on every attempt.
+    pub backoff: Duration,
+}
+
+/// An error writing a batch into a database
+#[cfg(any(test, feature = "sqlite", feature = "postgres"))]
+pub enum SinkError {
+    /// The batch may succeed if tried again, e.g. the database was locked or the connection dropped
+    Transient(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+/// A database table the accounts can be written to
+#[cfg(any(test, feature = "sqlite", feature = "postgres"))]
+pub trait SqlSink {
+    /// Upserts every account of the batch inside a single database transaction
+    async fn write_batch(&mut self, batch: &[AccountRecord]) -> Result<(), SinkError>;
+}
+
+/// Writes the accounts into the database behind the url, `sqlite://<path>` or `postgres://...`,
+/// in batches as the arguments of the run say
+///
+/// # Errors
+/// If the url is not supported or a batch fails, an error will be returned
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub async fn write_to_database(
+    url: &str,
+    records: &[AccountRecord],
+    args: &ProcessArgs,
+) -> Result<()> {
+    let config = args.sink();
+    if let Some(path) = url.strip_prefix("sqlite://") {
+        #[cfg(feature = "sqlite")]
+        return write_batched(&mut sqlite::SqliteSink::open(path)?, records, config).await;
+        #[cfg(not(feature = "sqlite"))]
+        bail!("Can't write to {path}: built without the `sqlite` feature");
+    }
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        #[cfg(feature = "postgres")]
+        return write_batched(
+            &mut postgres::PostgresSink::connect(url).await?,
+            records,
+            config,
+        )
+        .await;
+        #[cfg(not(feature = "postgres"))]
+        bail!("Can't write to postgres: built without the `postgres` feature");
+    }
+    bail!("Unsupported database url {url}")
+}
+
+/// Fails, as the crate was built without a database feature
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+#[allow(clippy::unused_async)]
+pub async fn write_to_database(
+    url: &str,
+    _records: &[AccountRecord],
+    _args: &ProcessArgs,
+) -> Result<()> {
+    bail!("Can't write to {url}: built without the `sqlite` and `postgres` features")
+}
+
+/// Writes the accounts in batches of `config.batch_size`, retrying batches that fail with a
+/// transient error
+///
+/// # Errors
+/// If a batch fails with a fatal error or after every retry, an error will be returned
+#[cfg(any(test, feature = "sqlite", feature = "postgres"))]
+pub async fn write_batched(
+    sink: &mut impl SqlSink,
+    records: &[AccountRecord],
+    config: SinkConfig,
+) -> Result<()> {
+    for batch in records.chunks(config.batch_size.max(1)) {
+        let mut attempt = 0;
+        let mut backoff = config.backoff;
+        loop {
+            match sink.write_batch(batch).await {
+                Ok(()) => break,
+                Err(SinkError::Transient(e)) if attempt < config.retries => {
+                    attempt += 1;
+                    warn!("Could not write batch: {e}, trying again (attempt {attempt})");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(SinkError::Transient(e) | SinkError::Fatal(e)) => return Err(e),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+    use rust_decimal_macros::dec;
+
+    use crate::model::AccountRecord;
+    use crate::sink::{write_batched, SinkConfig, SinkError, SqlSink};
+
+    /// Fails the first `failures` batches with the error built by `error`
+    struct FlakySink {
+        failures: u32,
+        error: fn() -> SinkError,
+        batches: Vec<usize>,
+    }
+
+    impl SqlSink for FlakySink {
+        async fn write_batch(&mut self, batch: &[AccountRecord]) -> Result<(), SinkError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err((self.error)());
+            }
+            self.batches.push(batch.len());
+            Ok(())
+        }
+    }
+
+    fn records(count: u16) -> Vec<AccountRecord> {
+        (0..count)
+            .map(|client| AccountRecord::for_test(client, dec!(1), dec!(0)))
+            .collect()
+    }
+
+    const CONFIG: SinkConfig = SinkConfig {
+        batch_size: 2,
+        retries: 2,
+        backoff: Duration::from_millis(1),
+    };
+
+    #[actix::test]
+    async fn test_write_batched_retries_transient() {
+        let mut sink = FlakySink {
+            failures: 2,
+            error: || SinkError::Transient(anyhow!("busy")),
+            batches: Vec::new(),
+        };
+        write_batched(&mut sink, &records(5), CONFIG).await.unwrap();
+        assert_eq!(sink.batches, vec![2, 2, 1]);
+    }
+
+    #[actix::test]
+    async fn test_write_batched_gives_up() {
+        let mut sink = FlakySink {
+            failures: 3,
+            error: || SinkError::Transient(anyhow!("busy")),
+            batches: Vec::new(),
+        };
+        assert!(write_batched(&mut sink, &records(5), CONFIG).await.is_err());
+        let mut sink = FlakySink {
+            failures: 1,
+            error: || SinkError::Fatal(anyhow!("no table")),
+            batches: Vec::new(),
+        };
+        assert!(write_batched(&mut sink, &records(5), CONFIG).await.is_err());
+        assert!(sink.batches.is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, NoTls};
+use tracing::error;
+
+use crate::model::AccountRecord;
+use crate::sink::{SinkError, SqlSink};
+
+/// Postgres accepts at most 65535 parameters per statement, five are used per account
+const MAX_ROWS_PER_STATEMENT: usize = 13_000;
+
+/// Writes the accounts into the `accounts` table of a postgres database
+pub struct PostgresSink {
+    client: Client,
+}
+
+impl PostgresSink {
+    /// Connects to the database, creating the `accounts` table if needed
+    ///
+    /// # Errors
+    /// If the database can't be reached, an error will be returned
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        actix::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("Postgres connection error: {e}");
+            }
+        });
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS accounts (
+                    client INTEGER PRIMARY KEY,
+                    available NUMERIC NOT NULL,
+                    held NUMERIC NOT NULL,
+                    total NUMERIC NOT NULL,
+                    locked BOOLEAN NOT NULL
+                )",
+            )
+            .await?;
+        Ok(Self { client })
+    }
+}
+
+impl SqlSink for PostgresSink {
+    async fn write_batch(&mut self, batch: &[AccountRecord]) -> Result<(), SinkError> {
+        let transaction = self.client.transaction().await.map_err(classify)?;
+        for rows in batch.chunks(MAX_ROWS_PER_STATEMENT) {
+            let (statement, params) = upsert_statement(rows);
+            let params: Vec<&(dyn ToSql + Sync)> = params
+                .iter()
+                .flat_map(|(client, available, held, total, locked)| {
+                    [
+                        client as &(dyn ToSql + Sync),
+                        available,
+                        held,
+                        total,
+                        locked,
+                    ]
+                })
+                .collect();
+            transaction
+                .execute(statement.as_str(), &params)
+                .await
+                .map_err(classify)?;
+        }
+        transaction.commit().await.map_err(classify)
+    }
+}
+
+type UpsertParams = (i32, String, String, String, bool);
+
+/// Builds a single multi-row upsert for the accounts, so each statement is one round trip
+fn upsert_statement(batch: &[AccountRecord]) -> (String, Vec<UpsertParams>) {
+    let mut values = Vec::with_capacity(batch.len());
+    let mut rows = Vec::with_capacity(batch.len());
+    for (i, record) in batch.iter().enumerate() {
+        let n = i * 5;
+        values.push(format!(
+            "(${}, ${}::text::numeric, ${}::text::numeric, ${}::text::numeric, ${})",
+            n + 1,
+            n + 2,
+            n + 3,
+            n + 4,
+            n + 5
+        ));
+        rows.push((
+            i32::from(record.client),
+            record.available.to_string(),
+            record.held.to_string(),
+            record.total.to_string(),
+            record.locked,
+        ));
+    }
+    let statement = format!(
+        "INSERT INTO accounts (client, available, held, total, locked) VALUES {}
+            ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available,
+            held = EXCLUDED.held, total = EXCLUDED.total, locked = EXCLUDED.locked",
+        values.join(", ")
+    );
+    (statement, rows)
+}
+
+/// Connection problems, serialization failures, deadlocks and lack of resources are transient,
+/// every other error is fatal
+fn classify(e: tokio_postgres::Error) -> SinkError {
+    let transient = e.is_closed()
+        || e.code().is_some_and(|code| {
+            let code = code.code();
+            code.starts_with("08") || code.starts_with("40") || code.starts_with("53")
+        });
+    if transient {
+        SinkError::Transient(e.into())
+    } else {
+        SinkError::Fatal(e.into())
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use rusqlite::{params, Connection, ErrorCode};
+
+use crate::model::AccountRecord;
+use crate::sink::{SinkError, SqlSink};
+
+/// Writes the accounts into the `accounts` table of a sqlite database
+pub struct SqliteSink {
+    connection: Connection,
+}
+
+impl SqliteSink {
+    /// Opens the database, creating the `accounts` table if needed
+    ///
+    /// # Errors
+    /// If the database can't be opened, an error will be returned
+    pub fn open(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                client INTEGER PRIMARY KEY,
+                available TEXT NOT NULL,
+                held TEXT NOT NULL,
+                total TEXT NOT NULL,
+                locked INTEGER NOT NULL
+            )",
+        )?;
+        Ok(Self { connection })
+    }
+}
+
+impl SqlSink for SqliteSink {
+    async fn write_batch(&mut self, batch: &[AccountRecord]) -> Result<(), SinkError> {
+        let transaction = self.connection.transaction().map_err(classify)?;
+        {
+            let mut statement = transaction
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO accounts (client, available, held, total, locked)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                )
+                .map_err(classify)?;
+            for record in batch {
+                statement
+                    .execute(params![
+                        record.client,
+                        record.available.to_string(),
+                        record.held.to_string(),
+                        record.total.to_string(),
+                        record.locked,
+                    ])
+                    .map_err(classify)?;
+            }
+        }
+        transaction.commit().map_err(classify)
+    }
+}
+
+/// Busy and locked databases are transient, every other error is fatal
+fn classify(e: rusqlite::Error) -> SinkError {
+    match e.sqlite_error_code() {
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => SinkError::Transient(e.into()),
+        _ => SinkError::Fatal(e.into()),
+    }
+}
@@ -0,0 +1,107 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Result};
+use serde_json::Value;
+use tokio::fs;
+
+use crate::migration::{header_of, migrate, DocumentKind, Migration};
+use crate::model::{Account, AccountState};
+use crate::processed::ProcessedFile;
+
+/// The current version of the snapshot format
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Migrations from older snapshot versions, applied when a snapshot is read
+const SNAPSHOT_MIGRATIONS: &[Migration] = &[];
+
+/// The state of every account after a number of journal events were applied
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    kind: DocumentKind,
+    version: u32,
+    /// The sequence number of the last journal event included in the snapshot
+    pub journal_seq: u64,
+    pub accounts: Vec<AccountState>,
+    /// The input files applied to the accounts by the runs with `--exactly-once`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processed: Vec<ProcessedFile>,
+}
+
+impl Snapshot {
+    /// Creates a snapshot of the provided accounts
+    pub fn new(journal_seq: u64, accounts: &[Account]) -> Self {
+        Self::from_states(
+            journal_seq,
+            accounts.iter().map(AccountState::from).collect(),
+        )
+    }
+
+    /// Creates a snapshot of the provided account states
+    pub fn from_states(journal_seq: u64, accounts: Vec<AccountState>) -> Self {
+        Self {
+            kind: DocumentKind::Snapshot,
+            version: SNAPSHOT_VERSION,
+            journal_seq,
+            accounts,
+            processed: Vec::new(),
+        }
+    }
+
+    /// Records the input files applied to the accounts
+    #[must_use]
+    pub fn with_processed(mut self, processed: Vec<ProcessedFile>) -> Self {
+        self.processed = processed;
+        self
+    }
+
+    /// Parses a snapshot of any supported version, upgrading it to the current one
+    ///
+    /// # Errors
+    /// If the document is not a snapshot or can't be upgraded, an error will be returned
+    pub fn from_value(value: Value) -> Result<Self> {
+        let (kind, version) = header_of(&value)?;
+        ensure!(kind == DocumentKind::Snapshot, "Not a snapshot");
+        let mut value = migrate(value, version, SNAPSHOT_VERSION, SNAPSHOT_MIGRATIONS)?;
+        value["version"] = SNAPSHOT_VERSION.into();
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Reads a snapshot file, upgrading it to the current version
+    ///
+    /// # Errors
+    /// If the file can't be read or is not a valid snapshot, an error will be returned
+    pub async fn read(path: &Path) -> Result<Self> {
+        let content = fs::read(path).await?;
+        Self::from_value(serde_json::from_slice(&content)?)
+    }
+
+    /// Writes the snapshot into a file, replacing it atomically
+    ///
+    /// # Errors
+    /// If the file can't be written, an error will be returned
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        let mut content = serde_json::to_vec(self)?;
+        content.push(b'\n');
+        write_atomically(path, &content).await
+    }
+}
+
+/// Writes the content into a temporary file next to `path` and renames it, so readers never see a
+/// partially written file
+///
+/// # Errors
+/// If the file can't be written or renamed, an error will be returned
+pub async fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let temp_path = temp_path_for(path);
+    fs::write(&temp_path, content).await?;
+    fs::rename(&temp_path, path).await?;
+    Ok(())
+}
+
+/// The path of the temporary file used to replace `path` atomically
+pub fn temp_path_for(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
@@ -0,0 +1,345 @@
+use std::io::{BufRead, BufReader, Read};
+
+use anyhow::{bail, ensure, Context, Result};
+use apache_avro::reader::datum::GenericDatumReader;
+use apache_avro::schema::{NamesRef, ResolvedSchema};
+use apache_avro::types::Value;
+use apache_avro::{Reader, Schema};
+use rust_decimal::Decimal;
+use serde_json::Value as Json;
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc;
+use tokio::task::{spawn_blocking, JoinHandle};
+use tokio_util::io::SyncIoBridge;
+use tracing::error;
+
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource};
+
+/// The first bytes of an Avro object container file
+const CONTAINER_MAGIC: &[u8] = b"Obj\x01";
+/// The first byte of a datum framed by a schema registry, followed by the 4 bytes of its schema id
+const REGISTRY_MAGIC: u8 = 0;
+/// How many bytes are read from the input at a time
+const READ_SIZE: usize = 64 * 1024;
+/// How many decoded transactions wait for the engine before the decoding pauses
+const DECODED_BUFFER: usize = 1024;
+
+/// A parsed Avro schema
+#[derive(Clone, Debug)]
+pub struct AvroSchema(Schema);
+
+impl AvroSchema {
+    /// Parses the json of a schema, like an `.avsc` file
+    ///
+    /// # Errors
+    /// If the json isn't a valid schema, an error will be returned
+    pub fn parse(json: &[u8]) -> Result<Self> {
+        let json = std::str::from_utf8(json).context("The Avro schema isn't json")?;
+        Ok(Self(Schema::parse_str(json)?))
+    }
+}
+
+/// The decimal of a big-endian two's complement unscaled value
+fn decimal(bytes: &[u8], scale: u32) -> Result<Decimal> {
+    ensure!(bytes.len() <= 16, "The Avro decimal is too large");
+    let fill = if bytes.first().is_some_and(|byte| byte & 0x80 != 0) {
+        0xff
+    } else {
+        0
+    };
+    let mut unscaled = [fill; 16];
+    unscaled[16 - bytes.len()..].copy_from_slice(bytes);
+    Ok(Decimal::try_from_i128_with_scale(
+        i128::from_be_bytes(unscaled),
+        scale,
+    )?)
+}
+
+/// The json of a field of a record. Decimals take the scale of their schema, and decimals and
+/// floating point numbers are written as text, so amounts are parsed like the csv ones.
+fn field(value: Value, schema: &Schema, names: &NamesRef) -> Result<Json> {
+    let schema = match schema {
+        Schema::Ref { name } => names.get(name).context("Unknown Avro named type")?,
+        schema => schema,
+    };
+    Ok(match (value, schema) {
+        (Value::Union(index, value), Schema::Union(union)) => {
+            let branch = union
+                .variants()
+                .get(usize::try_from(index)?)
+                .context("Invalid Avro union branch")?;
+            field(*value, branch, names)?
+        }
+        (Value::Decimal(unscaled), Schema::Decimal(schema)) => {
+            let scale = u32::try_from(schema.scale)?;
+            Json::String(decimal(&Vec::try_from(&unscaled)?, scale)?.to_string())
+        }
+        (Value::BigDecimal(decimal), _) => Json::String(decimal.to_string()),
+        (Value::Float(float), _) => Json::String(float.to_string()),
+        (Value::Double(double), _) => Json::String(double.to_string()),
+        (value, _) => Json::try_from(value)?,
+    })
+}
+
+/// The transaction of a decoded record: its fields are named like the columns of the csv input,
+/// and the symbols of `type` and `action` may be in any case, like `DEPOSIT`
+fn transaction(datum: Value, schema: &Schema, names: &NamesRef) -> Result<Transaction> {
+    let (Value::Record(values), Schema::Record(record)) = (datum, schema) else {
+        bail!("The datum isn't a record");
+    };
+    let mut fields = serde_json::Map::new();
+    for (name, value) in values {
+        let schema = record
+            .lookup
+            .get(&name)
+            .map(|&position| &record.fields[position].schema)
+            .with_context(|| format!("The Avro field {name} isn't in the schema"))?;
+        fields.insert(name, field(value, schema, names)?);
+    }
+    for name in ["type", "action"] {
+        if let Some(Json::String(symbol)) = fields.get_mut(name) {
+            let lower = symbol.to_lowercase();
+            let mut chars = lower.chars();
+            *symbol = chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default();
+        }
+    }
+    Ok(serde_json::from_value(Json::Object(fields))?)
+}
+
+/// Hands the transaction of a datum over to the source, logging the datums that aren't a valid
+/// transaction. Tells whether the source still takes transactions.
+fn deliver(
+    transactions: &mpsc::Sender<Result<Transaction>>,
+    datum: usize,
+    transaction: Result<Transaction>,
+) -> bool {
+    match transaction {
+        Ok(transaction) => transactions.blocking_send(Ok(transaction)).is_ok(),
+        Err(e) => {
+            error!("Could not parse Avro datum {datum}: {e}");
+            true
+        }
+    }
+}
+
+/// Decodes the transactions of the input, telling the container files from the framed datums by
+/// their first bytes
+fn decode(
+    input: impl Read,
+    schema: Option<AvroSchema>,
+    transactions: &mpsc::Sender<Result<Transaction>>,
+) -> Result<()> {
+    let mut input = BufReader::with_capacity(READ_SIZE, input);
+    let mut magic = Vec::with_capacity(CONTAINER_MAGIC.len());
+    input
+        .by_ref()
+        .take(CONTAINER_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let mut input = magic.as_slice().chain(input);
+    if magic == CONTAINER_MAGIC {
+        let reader = Reader::new(input)?;
+        let schema = reader.writer_schema().clone();
+        let names = ResolvedSchema::try_from(&schema)?;
+        for (datum, value) in reader.enumerate() {
+            let transaction = transaction(value?, &schema, names.get_names());
+            if !deliver(transactions, datum + 1, transaction) {
+                break;
+            }
+        }
+        return Ok(());
+    }
+    let AvroSchema(schema) = schema.context(
+        "The Avro input isn't a container file, its schema must be given with --schema",
+    )?;
+    let names = ResolvedSchema::try_from(&schema)?;
+    let datums = GenericDatumReader::builder(&schema).build()?;
+    let mut first_schema_id = None;
+    for datum in 1.. {
+        if input.fill_buf()?.is_empty() {
+            break;
+        }
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        ensure!(
+            header[0] == REGISTRY_MAGIC,
+            "The Avro datum isn't framed by a schema registry"
+        );
+        let id = u32::from_be_bytes(header[1..].try_into()?);
+        let first = *first_schema_id.get_or_insert(id);
+        ensure!(
+            first == id,
+            "Avro datum {datum} was written with schema {id}, the input is decoded with the one of \
+             schema {first}"
+        );
+        let transaction = transaction(datums.read_value(&mut input)?, &schema, names.get_names());
+        if !deliver(transactions, datum, transaction) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The transactions of an Avro input, either an object container file, decoded with the schema
+/// of its header, or datums framed by a schema registry, each a magic byte and the 4 bytes of its
+/// schema id followed by the datum, decoded with the schema given. The datums are decoded on a
+/// blocking thread, ahead of the engine. Records that aren't a valid transaction are logged and
+/// skipped, and the reader doesn't take acknowledgements.
+pub struct AvroSource<R> {
+    /// The input, until the decoding starts
+    reader: Option<R>,
+    schema: Option<AvroSchema>,
+    decoding: Option<(mpsc::Receiver<Result<Transaction>>, JoinHandle<()>)>,
+    delivered: DeliveryTag,
+}
+
+impl<R: AsyncRead + Send + Unpin + 'static> AvroSource<R> {
+    /// A source decoding the datums with `schema`, needed unless the input is a container file
+    pub fn new(reader: R, schema: Option<AvroSchema>) -> Self {
+        Self {
+            reader: Some(reader),
+            schema,
+            decoding: None,
+            delivered: 0,
+        }
+    }
+
+    /// Starts decoding the input on a blocking thread
+    fn start(&mut self, reader: R) {
+        let (transactions, decoded) = mpsc::channel(DECODED_BUFFER);
+        let (input, schema) = (SyncIoBridge::new(reader), self.schema.take());
+        let worker = spawn_blocking(move || {
+            if let Err(e) = decode(input, schema, &transactions) {
+                let _ = transactions.blocking_send(Err(e));
+            }
+        });
+        self.decoding = Some((decoded, worker));
+    }
+}
+
+impl<R: AsyncRead + Send + Unpin + 'static> InputSource for AvroSource<R> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        if let Some(reader) = self.reader.take() {
+            self.start(reader);
+        }
+        let Some((decoded, _)) = &mut self.decoding else {
+            return Ok(None);
+        };
+        if let Some(transaction) = decoded.recv().await {
+            let transaction = transaction?;
+            self.delivered += 1;
+            return Ok(Some((self.delivered, transaction)));
+        }
+        // the decoder is done, and only stops early on an error it sent or a panic
+        if let Some((_, worker)) = self.decoding.take() {
+            worker.await?;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rust_decimal_macros::dec;
+
+    use crate::model::TransactionType;
+    use crate::source::{AvroSchema, AvroSource, InputSource};
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Transaction",
+        "namespace": "payments",
+        "fields": [
+            {"name": "type", "type": {"type": "enum", "name": "Type",
+                "symbols": ["DEPOSIT", "WITHDRAWAL", "DISPUTE"]}},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": ["null",
+                {"type": "bytes", "logicalType": "decimal", "precision": 18, "scale": 4}]},
+            {"name": "parent", "type": ["null", "Transaction"]}
+        ]
+    }"#;
+
+    fn long(value: i64) -> Vec<u8> {
+        let mut zigzag = ((value << 1) ^ (value >> 63)).cast_unsigned();
+        let mut bytes = Vec::new();
+        while zigzag >= 0x80 {
+            bytes.push(u8::try_from(zigzag & 0x7f).unwrap() | 0x80);
+            zigzag >>= 7;
+        }
+        bytes.push(u8::try_from(zigzag).unwrap());
+        bytes
+    }
+
+    /// A transaction of the schema, its amount as an unscaled value of 4 decimal places
+    fn datum(symbol: i64, client: i64, tx: i64, amount: Option<i16>) -> Vec<u8> {
+        let mut datum = [long(symbol), long(client), long(tx)].concat();
+        match amount {
+            Some(amount) => {
+                datum.extend(long(1));
+                datum.extend(long(2));
+                datum.extend(amount.to_be_bytes());
+            }
+            None => datum.extend(long(0)),
+        }
+        // no parent
+        datum.extend(long(0));
+        datum
+    }
+
+    #[actix::test]
+    async fn test_avro_inputs() {
+        let datums = [
+            datum(0, 1, 1, Some(25_000)),
+            datum(1, 1, 2, Some(-1)),
+            // not a transaction, clients can't be negative
+            datum(1, -1, 3, None),
+            datum(2, 1, 1, None),
+        ];
+
+        let mut framed = Vec::new();
+        for datum in &datums {
+            framed.push(0);
+            framed.extend(7u32.to_be_bytes());
+            framed.extend(datum);
+        }
+        let schema = AvroSchema::parse(SCHEMA.as_bytes()).unwrap();
+        let mut source = AvroSource::new(Cursor::new(framed), Some(schema));
+        let (tag, deposit) = source.next().await.unwrap().unwrap();
+        assert_eq!(tag, 1);
+        assert!(deposit.transaction_type == TransactionType::Deposit);
+        assert_eq!(deposit.amount, Some(dec!(2.5).into()));
+        let (_, withdrawal) = source.next().await.unwrap().unwrap();
+        assert_eq!(withdrawal.amount, Some(dec!(-0.0001).into()));
+        let (tag, dispute) = source.next().await.unwrap().unwrap();
+        assert_eq!(tag, 3);
+        assert!(dispute.transaction_type == TransactionType::Dispute);
+        assert!(source.next().await.unwrap().is_none());
+
+        let mut container = b"Obj\x01".to_vec();
+        container.extend(long(1));
+        container.extend(long(11));
+        container.extend(b"avro.schema");
+        container.extend(long(i64::try_from(SCHEMA.len()).unwrap()));
+        container.extend(SCHEMA.as_bytes());
+        container.extend(long(0));
+        let sync = [9; 16];
+        container.extend(sync);
+        let block = datums.concat();
+        container.extend(long(4));
+        container.extend(long(i64::try_from(block.len()).unwrap()));
+        container.extend(block);
+        container.extend(sync);
+        let mut source = AvroSource::new(Cursor::new(container), None);
+        let mut clients = Vec::new();
+        while let Some((_, transaction)) = source.next().await.unwrap() {
+            clients.push((transaction.client, transaction.tx));
+        }
+        assert_eq!(clients, [(1, 1), (1, 2), (1, 1)]);
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, Lines};
+use tokio::time::{sleep_until, Instant};
+use tracing::error;
+
+use crate::capture::CapturedTransaction;
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource};
+
+/// The transactions of a capture file, delivered at the pace they were accepted when they were
+/// recorded, sped up `speed` times. A speed of zero delivers them as fast as they are read. Lines
+/// that can't be parsed are logged and skipped.
+pub struct CaptureSource<R> {
+    lines: Lines<R>,
+    line: u64,
+    delivered: DeliveryTag,
+    speed: f64,
+    /// When the replay started and the time the first transaction was recorded at
+    start: Option<(Instant, u64)>,
+}
+
+impl<R: AsyncBufRead + Unpin> CaptureSource<R> {
+    pub fn new(buf_reader: R, speed: f64) -> Self {
+        Self {
+            lines: buf_reader.lines(),
+            line: 0,
+            delivered: 0,
+            speed,
+            start: None,
+        }
+    }
+
+    /// Waits until the transaction recorded `at` is due
+    async fn pace(&mut self, at: u64) {
+        if self.speed <= 0.0 {
+            return;
+        }
+        let (started, first) = *self.start.get_or_insert((Instant::now(), at));
+        let elapsed = Duration::from_millis(at.saturating_sub(first));
+        sleep_until(started + elapsed.div_f64(self.speed)).await;
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> InputSource for CaptureSource<R> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        while let Some(line) = self.lines.next_line().await? {
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<CapturedTransaction>(&line) {
+                Ok(captured) => {
+                    self.pace(captured.at).await;
+                    self.delivered += 1;
+                    return Ok(Some((self.delivered, captured.transaction)));
+                }
+                Err(e) => error!("Could not parse line {} of the capture: {e}", self.line),
+            }
+        }
+        Ok(None)
+    }
+}
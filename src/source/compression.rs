@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The compressions an input can be read from
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of a file by its extension, `.gz` or `.zst`
+    fn of_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Self::Gzip),
+            "zst" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The compression of an input by its first bytes
+    fn of_header(header: &[u8]) -> Option<Self> {
+        if header.starts_with(GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// The content of an input, decompressed if it is gzip or zstd compressed. The compression is
+/// told by the extension of the file, or by the first bytes of the input without one, like the
+/// std in. Other inputs are read as they are.
+///
+/// # Errors
+/// If the start of the input can't be read, an error will be returned
+pub async fn decompressed(
+    mut input: impl AsyncBufRead + Send + Unpin + 'static,
+    path: Option<&Path>,
+) -> std::io::Result<Box<dyn AsyncBufRead + Send + Unpin>> {
+    let compression = match path.and_then(Compression::of_path) {
+        Some(compression) => Some(compression),
+        // doesn't consume the bytes peeked
+        None => Compression::of_header(input.fill_buf().await?),
+    };
+    Ok(match compression {
+        Some(Compression::Gzip) => {
+            let mut decoder = GzipDecoder::new(input);
+            // concatenated archives, like `cat a.gz b.gz`, are read whole
+            decoder.multiple_members(true);
+            Box::new(BufReader::new(decoder))
+        }
+        Some(Compression::Zstd) => {
+            let mut decoder = ZstdDecoder::new(input);
+            decoder.multiple_members(true);
+            Box::new(BufReader::new(decoder))
+        }
+        None => Box::new(input),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::Path;
+
+    use flate2::write::GzEncoder;
+    use tokio::io::AsyncReadExt;
+
+    use crate::source::decompressed;
+
+    async fn read(input: Vec<u8>, path: Option<&Path>) -> String {
+        let mut content = String::new();
+        decompressed(std::io::Cursor::new(input), path)
+            .await
+            .unwrap()
+            .read_to_string(&mut content)
+            .await
+            .unwrap();
+        content
+    }
+
+    #[actix::test]
+    async fn test_gzip_detected_by_its_header() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"type,client,tx,amount\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(read(compressed, None).await, "type,client,tx,amount\n");
+        let plain = b"type,client,tx,amount\n".to_vec();
+        assert_eq!(read(plain, None).await, "type,client,tx,amount\n");
+    }
+}
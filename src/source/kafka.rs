@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+
+use anyhow::Result;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Message;
+use rdkafka::{Offset, TopicPartitionList};
+use tracing::{error, info, warn};
+
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource, KafkaConfig};
+
+/// Reads transactions, one json object per message, from a Kafka topic until the process is
+/// stopped. Offsets are only committed once the transactions before them are acknowledged, so a
+/// restart starts again from the first transaction not durably applied. Every client's
+/// transactions must be in the same partition, like when the messages are keyed by client, to be
+/// applied in order.
+pub struct KafkaSource {
+    consumer: StreamConsumer,
+    config: KafkaConfig,
+    offsets: PendingOffsets,
+    /// Ends the input once the process is asked to stop
+    stopped: Pin<Box<dyn Future<Output = io::Result<()>>>>,
+}
+
+/// The messages read and not acknowledged yet, and the offsets to commit once they are
+#[derive(Default)]
+struct PendingOffsets {
+    /// The partition and offset of every message read and not acknowledged yet, by delivery tag.
+    /// Invalid messages take the tag of the last transaction delivered before them.
+    unacked: VecDeque<(DeliveryTag, i32, i64)>,
+    delivered: DeliveryTag,
+}
+
+impl PendingOffsets {
+    /// Records a message holding a transaction, returning the tag it's delivered with
+    fn deliver(&mut self, partition: i32, offset: i64) -> DeliveryTag {
+        self.delivered += 1;
+        self.unacked.push_back((self.delivered, partition, offset));
+        self.delivered
+    }
+
+    /// Records a message skipped as invalid, committed along with the next acknowledgement
+    fn skip(&mut self, partition: i32, offset: i64) {
+        self.unacked.push_back((self.delivered, partition, offset));
+    }
+
+    /// Forgets the messages acknowledged up to `tag`, returning the next offset to consume of
+    /// every partition they were in
+    fn ack(&mut self, tag: DeliveryTag) -> BTreeMap<i32, i64> {
+        let split = self
+            .unacked
+            .partition_point(|(delivered, _, _)| *delivered <= tag);
+        // the offsets of a partition are read in order, the last one is the highest
+        self.unacked
+            .drain(..split)
+            .map(|(_, partition, offset)| (partition, offset + 1))
+            .collect()
+    }
+}
+
+impl KafkaSource {
+    /// Joins the consumer group and subscribes to the topic
+    ///
+    /// # Errors
+    /// If the consumer can't be created or subscribed, an error will be returned
+    pub fn connect(config: KafkaConfig) -> Result<Self> {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("group.id", &config.group)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+        consumer.subscribe(&[&config.topic])?;
+        info!("Consuming {} as {}", config.topic, config.group);
+        Ok(Self {
+            consumer,
+            config,
+            offsets: PendingOffsets::default(),
+            stopped: Box::pin(tokio::signal::ctrl_c()),
+        })
+    }
+}
+
+impl InputSource for KafkaSource {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        loop {
+            let message = tokio::select! {
+                message = self.consumer.recv() => match message {
+                    Ok(message) => message,
+                    // like a broker going away, the consumer reconnects on its own
+                    Err(KafkaError::MessageConsumption(code)) => {
+                        warn!("Could not consume {}: {code}", self.config.topic);
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+                _ = &mut self.stopped => {
+                    info!("Stopping the consumption of {}", self.config.topic);
+                    return Ok(None);
+                }
+            };
+            let (partition, offset) = (message.partition(), message.offset());
+            let body = message.payload().unwrap_or_default();
+            match serde_json::from_slice::<Transaction>(body) {
+                Ok(transaction) => {
+                    return Ok(Some((self.offsets.deliver(partition, offset), transaction)));
+                }
+                Err(e) => {
+                    error!("Could not parse message {offset} of partition {partition}: {e}");
+                    self.offsets.skip(partition, offset);
+                }
+            }
+        }
+    }
+
+    fn acknowledges(&self) -> bool {
+        true
+    }
+
+    async fn ack(&mut self, tag: DeliveryTag) -> Result<()> {
+        let next = self.offsets.ack(tag);
+        if next.is_empty() {
+            return Ok(());
+        }
+        let mut offsets = TopicPartitionList::new();
+        for (partition, offset) in next {
+            offsets.add_partition_offset(&self.config.topic, partition, Offset::Offset(offset))?;
+        }
+        self.consumer.commit(&offsets, CommitMode::Async)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::source::kafka::PendingOffsets;
+
+    #[test]
+    fn test_offsets_are_committed_once_acknowledged() {
+        let mut offsets = PendingOffsets::default();
+        assert_eq!(offsets.deliver(0, 10), 1);
+        assert_eq!(offsets.deliver(1, 5), 2);
+        assert_eq!(offsets.deliver(0, 11), 3);
+        // nothing was processed yet
+        assert!(offsets.ack(0).is_empty());
+        assert_eq!(offsets.ack(1), BTreeMap::from([(0, 11)]));
+        // the highest offset of every partition acknowledged
+        assert_eq!(offsets.ack(3), BTreeMap::from([(0, 12), (1, 6)]));
+        assert!(offsets.ack(3).is_empty());
+    }
+
+    #[test]
+    fn test_skipped_messages_are_committed_with_the_next_acknowledgement() {
+        let mut offsets = PendingOffsets::default();
+        offsets.skip(0, 0);
+        assert_eq!(offsets.deliver(0, 1), 1);
+        offsets.skip(0, 2);
+        assert_eq!(offsets.deliver(0, 3), 2);
+        // the message skipped after the first transaction goes with it
+        assert_eq!(offsets.ack(1), BTreeMap::from([(0, 3)]));
+        assert_eq!(offsets.ack(2), BTreeMap::from([(0, 4)]));
+    }
+}
@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::{bail, Result};
+use tokio::io::AsyncBufRead;
+use tracing::{error, warn};
+
+use crate::csv::CsvSource;
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource};
+
+/// The transactions of several csv files merged into a single stream in the order of a timestamp
+/// column, like daily exports replayed together. Every file is expected in chronological order;
+/// rows with the same timestamp keep the order the files were given in. A row whose timestamp
+/// can't be read is logged and keeps the timestamp of the row before it in its file.
+pub struct MergedSource<R: AsyncBufRead + Send + Unpin> {
+    lanes: Vec<Lane<R>>,
+    delivered: DeliveryTag,
+}
+
+/// One of the merged files, with its next transaction
+struct Lane<R: AsyncBufRead + Send + Unpin> {
+    name: String,
+    source: CsvSource<R>,
+    /// The index of the timestamp column
+    column: usize,
+    /// The timestamp of the last row read
+    last: SystemTime,
+    next: Option<(SystemTime, Transaction)>,
+}
+
+impl<R: AsyncBufRead + Send + Unpin> Lane<R> {
+    /// Reads the next transaction of the file along with its timestamp
+    async fn advance(&mut self) -> Result<()> {
+        self.next = None;
+        let Some((_, transaction)) = self.source.next().await? else {
+            return Ok(());
+        };
+        let value = self.source.column(self.column).unwrap_or_default();
+        match humantime::parse_rfc3339_weak(value) {
+            Ok(timestamp) if timestamp < self.last => warn!(
+                "Transaction {} of {} is older than the rows before it, the files are merged \
+                out of order",
+                transaction.tx, self.name
+            ),
+            Ok(timestamp) => self.last = timestamp,
+            Err(e) => error!(
+                "Could not read the timestamp `{value}` of transaction {} in {}: {e}",
+                transaction.tx, self.name
+            ),
+        }
+        self.next = Some((self.last, transaction));
+        Ok(())
+    }
+}
+
+impl<R: AsyncBufRead + Send + Unpin> MergedSource<R> {
+    /// Merges the sources, named after their file, by the column with the timestamps
+    ///
+    /// # Errors
+    /// If a source doesn't have the column or its first row can't be read, an error will be
+    /// returned
+    pub async fn open(sources: Vec<(String, CsvSource<R>)>, column: &str) -> Result<Self> {
+        let mut lanes = Vec::with_capacity(sources.len());
+        for (name, source) in sources {
+            let Some(index) = source.column_index(column) else {
+                bail!("{name} has no `{column}` column to merge by");
+            };
+            let mut lane = Lane {
+                name,
+                source,
+                column: index,
+                last: SystemTime::UNIX_EPOCH,
+                next: None,
+            };
+            lane.advance().await?;
+            lanes.push(lane);
+        }
+        Ok(Self {
+            lanes,
+            delivered: 0,
+        })
+    }
+}
+
+impl<R: AsyncBufRead + Send + Unpin> InputSource for MergedSource<R> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        // ties go to the first file, the lowest index
+        let earliest = self
+            .lanes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, lane)| lane.next.as_ref().map(|(timestamp, _)| (*timestamp, i)))
+            .min();
+        let Some((_, i)) = earliest else {
+            return Ok(None);
+        };
+        let lane = &mut self.lanes[i];
+        let Some((_, transaction)) = lane.next.take() else {
+            return Ok(None);
+        };
+        lane.advance().await?;
+        self.delivered += 1;
+        Ok(Some((self.delivered, transaction)))
+    }
+}
+
+/// Expands the glob patterns among the paths, like `exports/*.csv`, into the files they match in
+/// alphabetical order. Other paths are kept as they are.
+///
+/// # Errors
+/// If a pattern is invalid or matches no file, an error will be returned
+pub fn expand_globs(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if !pattern.contains(['*', '?', '[']) {
+            files.push(path.clone());
+            continue;
+        }
+        let matched = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matched.is_empty() {
+            bail!("No file matches {pattern}");
+        }
+        files.extend(matched);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csv::CsvSource;
+    use crate::source::{InputSource, MergedSource};
+
+    #[actix::test]
+    async fn test_merge_by_timestamp() {
+        let monday: &[u8] = b"type,client,tx,amount,timestamp\n\
+            Deposit,1,1,5,2024-01-01T09:00:00Z\n\
+            Deposit,1,3,5,2024-01-01T11:00:00Z\n\
+            Deposit,1,5,5,not a timestamp\n";
+        let tuesday: &[u8] = b"type,client,tx,amount,timestamp\n\
+            Deposit,2,2,5,2024-01-01T10:00:00Z\n\
+            Deposit,2,4,5,2024-01-01T11:00:00Z\n";
+        let sources = vec![
+            ("monday".into(), CsvSource::open(monday).await.unwrap()),
+            ("tuesday".into(), CsvSource::open(tuesday).await.unwrap()),
+        ];
+        let mut merged = MergedSource::open(sources, "timestamp").await.unwrap();
+        let mut txs = Vec::new();
+        while let Some((tag, transaction)) = merged.next().await.unwrap() {
+            assert_eq!(tag, u64::try_from(txs.len()).unwrap() + 1);
+            txs.push(transaction.tx);
+        }
+        assert_eq!(txs, vec![1, 2, 3, 5, 4]);
+    }
+
+    #[actix::test]
+    async fn test_merge_needs_the_column() {
+        let input: &[u8] = b"type,client,tx,amount\nDeposit,1,1,5\n";
+        let sources = vec![("input".into(), CsvSource::open(input).await.unwrap())];
+        let err = MergedSource::open(sources, "timestamp")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "input has no `timestamp` column to merge by"
+        );
+    }
+}
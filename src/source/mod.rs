@@ -0,0 +1,500 @@
+use std::future::Future;
+#[cfg(feature = "sqs")]
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use tokio::time::{sleep_until, Instant};
+use tracing::warn;
+
+use crate::config::AckConfig;
+use crate::engine::Engine;
+use crate::model::{Transaction, TransactionType};
+
+mod avro;
+mod capture;
+mod compression;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod merge;
+mod ndjson;
+mod parallel;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod priority;
+mod protobuf;
+#[cfg(feature = "sqs")]
+mod sqs;
+
+pub use avro::{AvroSchema, AvroSource};
+pub use capture::CaptureSource;
+pub use compression::decompressed;
+pub use merge::{expand_globs, MergedSource};
+pub use ndjson::NdjsonSource;
+pub use parallel::ParallelCsvSource;
+pub use priority::RiskFirst;
+pub use protobuf::ProtobufSource;
+
+/// The formats an input file can be read in
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum InputFormat {
+    /// A csv with a header row
+    #[default]
+    Csv,
+    /// Newline delimited json, a transaction object per line
+    Json,
+    /// A capture file recorded with `--capture`, replayed at the pace it was recorded
+    Capture,
+    /// An Avro object container file, or Avro datums framed by a schema registry and written with
+    /// the schema of `--schema`
+    Avro,
+    /// Length-delimited protobuf messages of the grpc service. Needs the `grpc` feature.
+    Protobuf,
+}
+
+/// Settings used when reading the rows inserted into a postgres table through logical replication
+#[derive(Clone, Debug)]
+pub struct CdcConfig {
+    /// The url of the database, `postgres://...`
+    #[cfg(feature = "postgres")]
+    pub url: String,
+    /// The logical replication slot, created with the wal2json plugin
+    #[cfg(feature = "postgres")]
+    pub slot: String,
+    /// The table of the transactions, `schema.table`
+    pub table: String,
+}
+
+/// Settings used when reading transactions from an SQS queue
+#[derive(Clone, Debug)]
+pub struct SqsConfig {
+    pub queue_url: String,
+    /// Where messages that aren't a valid transaction are moved to. They are left in the queue,
+    /// for its own redrive policy, if not set.
+    #[cfg(feature = "sqs")]
+    pub dlq_url: Option<String>,
+    /// How long received messages stay hidden from other consumers. It is extended while their
+    /// transactions wait to be acknowledged.
+    #[cfg(feature = "sqs")]
+    pub visibility_timeout: Duration,
+}
+
+/// Settings used when consuming transactions from a Kafka topic
+#[derive(Clone, Debug)]
+pub struct KafkaConfig {
+    /// The bootstrap brokers, `host:port` separated by commas
+    #[cfg(feature = "kafka")]
+    pub brokers: String,
+    pub topic: String,
+    /// The consumer group, whose committed offsets are where a restart starts from
+    #[cfg(feature = "kafka")]
+    pub group: String,
+}
+
+/// Identifies a transaction delivered by a source, increasing with every delivery
+pub type DeliveryTag = u64;
+
+/// Where the transactions come from. Sources that redeliver what wasn't acknowledged, like message
+/// brokers, are only acknowledged once their transactions are durably applied.
+pub trait InputSource {
+    /// The next transaction along with its delivery tag, or `None` once the source is exhausted.
+    /// The future can be dropped before it completes, when acknowledgements are due, so it must
+    /// not lose what it read until then.
+    ///
+    /// # Errors
+    /// If the source fails, an error will be returned
+    fn next(&mut self) -> impl Future<Output = Result<Option<(DeliveryTag, Transaction)>>>;
+
+    /// Whether the source takes acknowledgements. Applied transactions are never persisted on
+    /// behalf of sources that don't.
+    fn acknowledges(&self) -> bool {
+        false
+    }
+
+    /// Acknowledges every transaction delivered up to `tag`, included
+    ///
+    /// # Errors
+    /// If the acknowledgement can't be delivered, an error will be returned
+    fn ack(&mut self, _tag: DeliveryTag) -> impl Future<Output = Result<()>> {
+        async { Ok(()) }
+    }
+}
+
+/// Applies the transactions of the source through the engine. For sources that take
+/// acknowledgements, the journal and the store are persisted and the applied transactions
+/// acknowledged in batches, as set by `ack`.
+///
+/// # Errors
+/// If the source fails, the engine fails to record a transaction or the applied transactions
+/// can't be persisted or acknowledged, an error will be returned
+pub async fn process_source(
+    mut source: impl InputSource,
+    engine: &mut Engine,
+    ack: AckConfig,
+) -> Result<()> {
+    let mut pending = None;
+    let mut batch = 0;
+    let mut last_ack = Instant::now();
+    loop {
+        // the applied transactions are acknowledged once the interval passes, even while the
+        // source waits for the next one
+        let due = last_ack
+            .checked_add(ack.interval)
+            .filter(|_| pending.is_some());
+        let next = tokio::select! {
+            next = source.next() => Some(next?),
+            () = sleep_until(due.unwrap_or(last_ack)), if due.is_some() => None,
+        };
+        let Some(next) = next else {
+            if let Some(tag) = pending.take() {
+                acknowledge(&mut source, engine, tag).await?;
+            }
+            batch = 0;
+            last_ack = Instant::now();
+            continue;
+        };
+        let Some((tag, transaction)) = next else {
+            break;
+        };
+        if engine.sample_complete() {
+            break;
+        }
+        engine.apply(transaction).await?;
+        if !source.acknowledges() {
+            continue;
+        }
+        pending = Some(tag);
+        batch += 1;
+        if batch >= ack.batch_size {
+            acknowledge(&mut source, engine, tag).await?;
+            pending = None;
+            batch = 0;
+            last_ack = Instant::now();
+        }
+    }
+    if let Some(tag) = pending {
+        acknowledge(&mut source, engine, tag).await?;
+    }
+    engine.settle().await
+}
+
+/// Applies every valid transaction of the source or none of them: the transactions are staged
+/// and only committed once the whole source was read. Invalid rows and rejected transactions are
+/// logged and ignored, as usual.
+///
+/// # Errors
+/// If the source fails, a transaction can't be delivered or the engine fails to record a
+/// transaction, the staged transactions are undone and an error will be returned
+pub async fn process_atomically(source: impl InputSource, engine: &mut Engine) -> Result<()> {
+    engine.begin();
+    if let Err(e) = apply_staged(source, engine).await {
+        engine.rollback().await?;
+        return Err(e.context("No transaction of the file was applied"));
+    }
+    engine.commit().await
+}
+
+async fn apply_staged(mut source: impl InputSource, engine: &mut Engine) -> Result<()> {
+    while let Some((_, transaction)) = source.next().await? {
+        if engine.sample_complete() {
+            break;
+        }
+        engine.apply(transaction).await?;
+    }
+    Ok(())
+}
+
+/// Applies the transactions in segments, ending one every `every` rows, if set, and on every
+/// savepoint row. A segment with a transaction that was rejected or couldn't be delivered is
+/// rolled back as a whole, the following segments are still applied.
+///
+/// # Errors
+/// If the source fails, the engine fails to record a transaction or to roll back a segment, an
+/// error will be returned
+pub async fn process_with_savepoints(
+    mut source: impl InputSource,
+    engine: &mut Engine,
+    every: Option<usize>,
+) -> Result<()> {
+    let mut segment = 1;
+    let mut rows = 0;
+    engine.begin();
+    while let Some((_, transaction)) = source.next().await? {
+        if engine.sample_complete() {
+            break;
+        }
+        let marker = transaction.transaction_type == TransactionType::Savepoint;
+        if !marker {
+            engine.apply(transaction).await?;
+            rows += 1;
+        }
+        if marker || every.is_some_and(|every| rows >= every) {
+            end_segment(engine, segment).await?;
+            segment += 1;
+            rows = 0;
+            engine.begin();
+        }
+    }
+    end_segment(engine, segment).await
+}
+
+/// Commits the staged segment, or rolls it back if one of its transactions failed
+async fn end_segment(engine: &mut Engine, segment: u32) -> Result<()> {
+    match engine.staged_failure() {
+        Some(tx) => {
+            warn!("Rolling back segment {segment}: transaction {tx} failed");
+            engine.rollback().await
+        }
+        None => engine.commit().await,
+    }
+}
+
+/// Applies the transactions of an SQS queue through the engine, acknowledging them as set by
+/// `ack`, until the process is stopped. The dispute steps of every batch of
+/// `risk_first_batch` transactions are applied first, see `RiskFirst`.
+///
+/// # Errors
+/// If the queue can't be read or acknowledged, or the engine fails to record a transaction, an
+/// error will be returned
+#[cfg(feature = "sqs")]
+pub async fn process_sqs(
+    config: &SqsConfig,
+    engine: &mut Engine,
+    ack: AckConfig,
+    risk_first_batch: usize,
+) -> Result<()> {
+    process_source(
+        RiskFirst::new(
+            sqs::SqsSource::connect(config.clone()).await,
+            risk_first_batch,
+        ),
+        engine,
+        ack,
+    )
+    .await
+}
+
+/// Fails, as the crate was built without the `sqs` feature
+#[cfg(not(feature = "sqs"))]
+#[allow(clippy::unused_async)]
+pub async fn process_sqs(
+    config: &SqsConfig,
+    _engine: &mut Engine,
+    _ack: AckConfig,
+    _risk_first_batch: usize,
+) -> Result<()> {
+    anyhow::bail!(
+        "Can't read from {}: built without the `sqs` feature",
+        config.queue_url
+    );
+}
+
+/// Applies the rows inserted into a postgres table through the engine, read from a logical
+/// replication slot, acknowledging them as set by `ack`, until the slot has no more changes. The
+/// dispute steps of every batch of `risk_first_batch` rows are applied first, see `RiskFirst`.
+///
+/// # Errors
+/// If the slot can't be read or advanced, or the engine fails to record a transaction, an error
+/// will be returned
+#[cfg(feature = "postgres")]
+pub async fn process_cdc(
+    config: &CdcConfig,
+    engine: &mut Engine,
+    ack: AckConfig,
+    risk_first_batch: usize,
+) -> Result<()> {
+    process_source(
+        RiskFirst::new(
+            postgres::PostgresCdcSource::connect(config.clone()).await?,
+            risk_first_batch,
+        ),
+        engine,
+        ack,
+    )
+    .await
+}
+
+/// Fails, as the crate was built without the `postgres` feature
+#[cfg(not(feature = "postgres"))]
+#[allow(clippy::unused_async)]
+pub async fn process_cdc(
+    config: &CdcConfig,
+    _engine: &mut Engine,
+    _ack: AckConfig,
+    _risk_first_batch: usize,
+) -> Result<()> {
+    anyhow::bail!(
+        "Can't read from {}: built without the `postgres` feature",
+        config.table
+    );
+}
+
+/// Applies the transactions of a Kafka topic through the engine until the process is stopped,
+/// committing their offsets as set by `ack`. The dispute steps of every batch of
+/// `risk_first_batch` transactions are applied first, see `RiskFirst`.
+///
+/// # Errors
+/// If the topic can't be consumed or the offsets committed, or the engine fails to record a
+/// transaction, an error will be returned
+#[cfg(feature = "kafka")]
+pub async fn process_kafka(
+    config: &KafkaConfig,
+    engine: &mut Engine,
+    ack: AckConfig,
+    risk_first_batch: usize,
+) -> Result<()> {
+    process_source(
+        RiskFirst::new(
+            kafka::KafkaSource::connect(config.clone())?,
+            risk_first_batch,
+        ),
+        engine,
+        ack,
+    )
+    .await
+}
+
+/// Fails, as the crate was built without the `kafka` feature
+#[cfg(not(feature = "kafka"))]
+#[allow(clippy::unused_async)]
+pub async fn process_kafka(
+    config: &KafkaConfig,
+    _engine: &mut Engine,
+    _ack: AckConfig,
+    _risk_first_batch: usize,
+) -> Result<()> {
+    anyhow::bail!(
+        "Can't consume {}: built without the `kafka` feature",
+        config.topic
+    );
+}
+
+async fn acknowledge(
+    source: &mut impl InputSource,
+    engine: &mut Engine,
+    tag: DeliveryTag,
+) -> Result<()> {
+    engine.persist().await?;
+    source.ack(tag).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    use crate::config::{AckConfig, DispatchConfig, EngineConfig};
+    use crate::engine::Engine;
+    use crate::journal::{JournalReader, JournalWriter};
+    use crate::model::{AccountRecord, Transaction};
+    use crate::source::{
+        process_source, process_with_savepoints, DeliveryTag, InputSource, NdjsonSource,
+    };
+
+    struct AckingSource {
+        delivered: DeliveryTag,
+        count: DeliveryTag,
+        acks: Vec<DeliveryTag>,
+        /// Waits for more transactions forever once `count` were delivered, instead of ending
+        idle: bool,
+    }
+
+    impl InputSource for &mut AckingSource {
+        async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+            if self.delivered == self.count {
+                if self.idle {
+                    std::future::pending::<()>().await;
+                }
+                return Ok(None);
+            }
+            self.delivered += 1;
+            let transaction = Transaction::test_deposit(1, u32::try_from(self.delivered)?, dec!(1));
+            Ok(Some((self.delivered, transaction)))
+        }
+
+        fn acknowledges(&self) -> bool {
+            true
+        }
+
+        async fn ack(&mut self, tag: DeliveryTag) -> Result<()> {
+            self.acks.push(tag);
+            Ok(())
+        }
+    }
+
+    #[actix::test]
+    async fn test_acks_in_batches() {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let mut source = AckingSource {
+            delivered: 0,
+            count: 5,
+            acks: Vec::new(),
+            idle: false,
+        };
+        let ack = AckConfig {
+            batch_size: 2,
+            interval: Duration::MAX,
+        };
+        process_source(&mut source, &mut engine, ack).await.unwrap();
+        assert_eq!(source.acks, vec![2, 4, 5]);
+    }
+
+    #[actix::test]
+    async fn test_acks_on_interval_while_idle() {
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        let mut source = AckingSource {
+            delivered: 0,
+            count: 3,
+            acks: Vec::new(),
+            idle: true,
+        };
+        let ack = AckConfig {
+            batch_size: 10,
+            interval: Duration::from_millis(20),
+        };
+        let processed = process_source(&mut source, &mut engine, ack);
+        let timeout = tokio::time::timeout(Duration::from_millis(200), processed).await;
+        assert!(timeout.is_err());
+        assert_eq!(source.acks, vec![3]);
+    }
+
+    #[actix::test]
+    async fn test_failed_savepoint_segment_is_rolled_back() {
+        let path = std::env::temp_dir().join(format!("savepoints-{}", std::process::id()));
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_journal(JournalWriter::open(&path).await.unwrap());
+        let input = concat!(
+            "{\"type\":\"Deposit\",\"client\":1,\"tx\":1,\"amount\":\"10\"}\n",
+            "{\"type\":\"Savepoint\",\"client\":0,\"tx\":0}\n",
+            "{\"type\":\"Deposit\",\"client\":2,\"tx\":2,\"amount\":\"5\"}\n",
+            "{\"type\":\"Deposit\",\"client\":1,\"tx\":3,\"amount\":\"20\"}\n",
+            // more than the 30 available, so the whole second segment is rolled back
+            "{\"type\":\"Withdrawal\",\"client\":1,\"tx\":4,\"amount\":\"50\"}\n",
+            "{\"type\":\"Savepoint\",\"client\":0,\"tx\":0}\n",
+            "{\"type\":\"Deposit\",\"client\":1,\"tx\":5,\"amount\":\"1\"}\n",
+        );
+        process_with_savepoints(NdjsonSource::new(input.as_bytes()), &mut engine, None)
+            .await
+            .unwrap();
+
+        let accounts = engine.collect().await.unwrap();
+        let balances: Vec<_> = accounts
+            .iter()
+            .map(|account| {
+                let record = AccountRecord::from(account);
+                (record.client, record.available)
+            })
+            .collect();
+        // the client started by the rolled back segment has no account
+        assert_eq!(balances, [(1, dec!(11))]);
+        let mut reader = JournalReader::open(&path).await.unwrap();
+        let mut journaled = Vec::new();
+        while let Some(event) = reader.next_event().await.unwrap() {
+            journaled.push(event.transaction.tx);
+        }
+        assert_eq!(journaled, [1, 5]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
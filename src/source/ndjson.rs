@@ -0,0 +1,91 @@
+use anyhow::Result;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, Lines};
+use tracing::error;
+
+use crate::csv::ValidationError;
+use crate::diagnostics::{Diagnostics, Observation};
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource};
+
+/// The transactions of a newline delimited json reader, one object per line, like the messages of
+/// the queue. Lines that can't be parsed are logged and skipped, blank lines are only noted in the
+/// diagnostics, and the reader doesn't take acknowledgements.
+pub struct NdjsonSource<R> {
+    lines: Lines<R>,
+    line: u64,
+    delivered: DeliveryTag,
+    diagnostics: Diagnostics,
+}
+
+impl<R: AsyncBufRead + Unpin> NdjsonSource<R> {
+    pub fn new(buf_reader: R) -> Self {
+        Self {
+            lines: buf_reader.lines(),
+            line: 0,
+            delivered: 0,
+            diagnostics: Diagnostics::default(),
+        }
+    }
+
+    /// Notes the data quality observations into `diagnostics`
+    #[must_use]
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> InputSource for NdjsonSource<R> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        while let Some(line) = self.lines.next_line().await? {
+            self.line += 1;
+            if line.trim().is_empty() {
+                self.diagnostics.observe(Observation::BlankLine, self.line);
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(transaction) => {
+                    self.diagnostics.inspect(&transaction, self.line);
+                    self.delivered += 1;
+                    return Ok(Some((self.delivered, transaction)));
+                }
+                Err(e) => {
+                    let invalid = ValidationError {
+                        line: Some(self.line),
+                        field: None,
+                        reason: e.to_string(),
+                    };
+                    error!("Could not parse {invalid}");
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::model::TransactionType;
+    use crate::source::{InputSource, NdjsonSource};
+
+    #[actix::test]
+    async fn test_ndjson_skips_invalid_lines() {
+        let input = concat!(
+            "{\"type\":\"Deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.5\"}\n",
+            "\n",
+            "{\"type\":\"Deposit\",\"client\":1}\n",
+            "{\"type\":\"Dispute\",\"client\":1,\"tx\":1}\n",
+        );
+        let mut source = NdjsonSource::new(input.as_bytes());
+        let (tag, deposit) = source.next().await.unwrap().unwrap();
+        assert_eq!(tag, 1);
+        assert_eq!(deposit.amount, Some(dec!(2.5).into()));
+        let (tag, dispute) = source.next().await.unwrap().unwrap();
+        assert_eq!(tag, 2);
+        assert!(dispute.transaction_type == TransactionType::Dispute);
+        assert!(dispute.amount.is_none());
+        assert!(source.next().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+use actix::Arbiter;
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::sync::oneshot;
+
+use crate::config::CsvConfig;
+use crate::csv::CsvSource;
+use crate::diagnostics::{Diagnostics, Tallies};
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource};
+
+/// The rows of a chunk handed to a parser
+const CHUNK_ROWS: usize = 8192;
+
+/// The chunks parsed ahead of the engine by every parser
+const CHUNKS_AHEAD: usize = 2;
+
+/// The transactions of a parsed chunk, with the data quality observations made on them
+type Parsed = Result<(Vec<Transaction>, Tallies)>;
+
+/// The transactions of a csv reader, parsed in chunks of rows on parser threads while the engine
+/// applies the ones already parsed. The chunks are delivered in the order of the input, so every
+/// client keeps the order of its transactions, and so do the transfers and reused ids spanning
+/// clients. Only the rows are split on the reader's thread, where a line break inside a quoted
+/// field doesn't end a row.
+pub struct ParallelCsvSource<R> {
+    reader: R,
+    /// The header row, heading every chunk
+    header: Vec<u8>,
+    /// The lines of the header
+    header_lines: u64,
+    config: CsvConfig,
+    parsers: Vec<Arbiter>,
+    /// The parser the next chunk goes to
+    next_parser: usize,
+    /// The lines read so far, the header included
+    line: u64,
+    exhausted: bool,
+    /// The chunks handed to the parsers, in the order of the input
+    pending: VecDeque<oneshot::Receiver<Parsed>>,
+    parsed: std::vec::IntoIter<Transaction>,
+    delivered: DeliveryTag,
+    diagnostics: Diagnostics,
+}
+
+impl<R: AsyncBufRead + Unpin> ParallelCsvSource<R> {
+    /// Reads the headers of a csv quoted as the config says and starts `parsers` parser threads
+    ///
+    /// # Errors
+    /// If the headers can't be read, an error will be returned
+    pub async fn open(mut reader: R, config: CsvConfig, parsers: usize) -> Result<Self> {
+        let mut header = Vec::new();
+        let header_lines = read_row(&mut reader, &mut header, config).await?;
+        Ok(Self {
+            reader,
+            header,
+            header_lines,
+            config,
+            parsers: (0..parsers.max(1)).map(|_| Arbiter::new()).collect(),
+            next_parser: 0,
+            line: header_lines,
+            exhausted: false,
+            pending: VecDeque::new(),
+            parsed: Vec::new().into_iter(),
+            delivered: 0,
+            diagnostics: Diagnostics::default(),
+        })
+    }
+
+    /// Notes the data quality observations into `diagnostics`
+    #[must_use]
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Reads the rows of the next chunk and hands them to the next parser
+    ///
+    /// # Errors
+    /// If the input can't be read, an error will be returned
+    async fn read_chunk(&mut self) -> Result<()> {
+        let mut chunk = self.header.clone();
+        let (mut rows, mut lines) = (0, 0);
+        while rows < CHUNK_ROWS {
+            let read = read_row(&mut self.reader, &mut chunk, self.config).await?;
+            if read == 0 {
+                self.exhausted = true;
+                break;
+            }
+            rows += 1;
+            lines += read;
+        }
+        if rows == 0 {
+            return Ok(());
+        }
+        // the lines of the chunk are numbered from its header on
+        let offset = self.line - self.header_lines;
+        self.line += lines;
+        let (sender, receiver) = oneshot::channel();
+        let config = self.config;
+        // the diagnostics of the parser can't leave its thread, so neither can the parsing
+        self.parsers[self.next_parser].spawn_fn(move || {
+            actix::spawn(async move {
+                let _ = sender.send(parse_chunk(chunk, config, offset).await);
+            });
+        });
+        self.next_parser = (self.next_parser + 1) % self.parsers.len();
+        self.pending.push_back(receiver);
+        Ok(())
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> InputSource for ParallelCsvSource<R> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        loop {
+            if let Some(transaction) = self.parsed.next() {
+                self.delivered += 1;
+                return Ok(Some((self.delivered, transaction)));
+            }
+            while !self.exhausted && self.pending.len() < self.parsers.len() * CHUNKS_AHEAD {
+                self.read_chunk().await?;
+            }
+            let Some(chunk) = self.pending.pop_front() else {
+                return Ok(None);
+            };
+            let (transactions, tallies) = chunk.await.context("A csv parser stopped")??;
+            self.diagnostics.merge(tallies);
+            self.parsed = transactions.into_iter();
+        }
+    }
+}
+
+impl<R> Drop for ParallelCsvSource<R> {
+    fn drop(&mut self) {
+        for parser in &self.parsers {
+            parser.stop();
+        }
+    }
+}
+
+/// Parses the rows of a chunk, reporting the lines they have in the input
+async fn parse_chunk(chunk: Vec<u8>, config: CsvConfig, offset: u64) -> Parsed {
+    let diagnostics = Diagnostics::default();
+    let mut source = CsvSource::open_with_config(chunk.as_slice(), config)
+        .await?
+        .with_diagnostics(diagnostics.clone())
+        .with_line_offset(offset);
+    let mut transactions = Vec::new();
+    while let Some((_, transaction)) = source.next().await? {
+        transactions.push(transaction);
+    }
+    Ok((transactions, diagnostics.take()))
+}
+
+/// Appends the next row of the input to `buf`, telling how many lines it takes, none at the end of
+/// the input. A row goes on past the end of its line while a quote is left open.
+///
+/// # Errors
+/// If the input can't be read, an error will be returned
+async fn read_row(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    buf: &mut Vec<u8>,
+    config: CsvConfig,
+) -> Result<u64> {
+    let mut quoted = false;
+    let mut lines = 0;
+    loop {
+        let start = buf.len();
+        if reader.read_until(b'\n', buf).await? == 0 {
+            return Ok(lines);
+        }
+        lines += 1;
+        let mut bytes = buf[start..].iter();
+        while let Some(&byte) = bytes.next() {
+            if Some(byte) == config.escape {
+                bytes.next();
+            } else if byte == config.quote {
+                quoted = !quoted;
+            }
+        }
+        if !quoted {
+            return Ok(lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::CsvConfig;
+    use crate::diagnostics::Diagnostics;
+    use crate::source::parallel::ParallelCsvSource;
+    use crate::source::InputSource;
+
+    #[actix::test]
+    async fn test_chunks_are_delivered_in_the_order_of_the_input() {
+        let rows = (1..=20_000).map(|tx| format!("Deposit,{},{tx},1.0\n", tx % 7));
+        let mut input: String = std::iter::once("type,client,tx,amount\n".into())
+            .chain(rows)
+            .collect();
+        input.push_str("Deposit,1,\"20\n001\",1.0\n   \nDeposit,1,20002,1.0\n");
+        let diagnostics = Diagnostics::default();
+        let mut source = ParallelCsvSource::open(input.as_bytes(), CsvConfig::default(), 3)
+            .await
+            .unwrap()
+            .with_diagnostics(diagnostics.clone());
+        let mut delivered = Vec::new();
+        while let Some((tag, transaction)) = source.next().await.unwrap() {
+            assert_eq!(tag, delivered.len() as u64 + 1);
+            delivered.push(transaction.tx);
+        }
+        // the quoted line break makes the row unparseable, but doesn't end it
+        assert_eq!(delivered, (1..=20_000).chain([20_002]).collect::<Vec<_>>());
+        assert_eq!(
+            diagnostics.to_string(),
+            "Data quality:\n  1 blank lines (first at line 20004)"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+use tokio_postgres::{Client, NoTls};
+use tracing::{error, warn};
+
+use crate::model::Transaction;
+use crate::source::{CdcConfig, DeliveryTag, InputSource};
+
+/// The most new changes decoded from the slot at once
+const MAX_CHANGES: i64 = 1000;
+
+/// A change of the slot, as written by wal2json with `format-version` 2
+#[derive(Deserialize)]
+struct Change {
+    action: String,
+    #[serde(default)]
+    columns: Vec<Column>,
+}
+
+#[derive(Deserialize)]
+struct Column {
+    name: String,
+    value: Value,
+}
+
+/// Reads the rows inserted into a transactions table from a logical replication slot using the
+/// wal2json plugin. Changes are peeked and only consumed from the slot once acknowledged, up to the
+/// commit of the last database transaction whose rows were all acknowledged.
+pub struct PostgresCdcSource {
+    client: Client,
+    config: CdcConfig,
+    received: VecDeque<(DeliveryTag, Transaction)>,
+    slot: SlotPosition,
+    delivered: DeliveryTag,
+}
+
+/// What was read from a slot and what was consumed from it. Peeking starts from the last change
+/// consumed, and positions aren't unique across changes, so the changes read but not consumed yet
+/// are decoded again and skipped by count.
+#[derive(Default)]
+struct SlotPosition {
+    /// The commit position of every database transaction read and not acknowledged yet, along
+    /// with the last delivery tag of its rows and the number of changes read up to it
+    commits: VecDeque<(DeliveryTag, String, i64)>,
+    /// How many changes were read since the start
+    read: i64,
+    /// How many changes were consumed from the slot since the start
+    consumed: i64,
+}
+
+impl SlotPosition {
+    /// How many changes to skip, as they were already read, and how many to decode at most for
+    /// the next read. Decoding stops at the end of the database transaction going past it.
+    fn next_read(&self) -> Result<(i64, i32)> {
+        let skip = self.read - self.consumed;
+        Ok((skip, i32::try_from(skip + MAX_CHANGES)?))
+    }
+
+    /// Records the commit of a database transaction, whose last row has the tag `last`
+    fn commit(&mut self, last: DeliveryTag, lsn: &str) {
+        self.commits.push_back((last, lsn.to_string(), self.read));
+    }
+
+    /// Consumes the database transactions whose rows were all delivered up to `tag`, returning
+    /// the commit position the slot has to be advanced to, if any
+    fn ack(&mut self, tag: DeliveryTag) -> Option<String> {
+        let split = self.commits.partition_point(|(last, _, _)| *last <= tag);
+        let (_, lsn, read) = self.commits.drain(..split).next_back()?;
+        self.consumed = read;
+        Some(lsn)
+    }
+}
+
+impl PostgresCdcSource {
+    /// Connects to the database of the slot
+    ///
+    /// # Errors
+    /// If the database can't be reached, an error will be returned
+    pub async fn connect(config: CdcConfig) -> Result<Self> {
+        let (client, connection) = tokio_postgres::connect(&config.url, NoTls).await?;
+        actix::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("Postgres connection error: {e}");
+            }
+        });
+        Ok(Self {
+            client,
+            config,
+            received: VecDeque::new(),
+            slot: SlotPosition::default(),
+            delivered: 0,
+        })
+    }
+
+    /// Reads the next changes of the slot after the ones already read. Returns false when there
+    /// are none.
+    async fn read_changes(&mut self) -> Result<bool> {
+        let (skip, upto) = self.slot.next_read()?;
+        let rows = self
+            .client
+            .query(
+                "SELECT lsn::text, data FROM pg_logical_slot_peek_changes(
+                    $1, NULL, $3,
+                    'format-version', '2',
+                    'add-tables', $2,
+                    'numeric-data-types-as-string', '1'
+                )
+                OFFSET $4",
+                &[&self.config.slot, &self.config.table, &upto, &skip],
+            )
+            .await?;
+        if rows.is_empty() {
+            return Ok(false);
+        }
+        for row in rows {
+            let lsn: String = row.get(0);
+            let data: String = row.get(1);
+            self.slot.read += 1;
+            self.read_change(&lsn, &data)?;
+        }
+        Ok(true)
+    }
+
+    fn read_change(&mut self, lsn: &str, data: &str) -> Result<()> {
+        let change: Change = serde_json::from_str(data)?;
+        match change.action.as_str() {
+            "I" => match transaction_of(change.columns) {
+                Ok(transaction) => {
+                    self.delivered += 1;
+                    self.received.push_back((self.delivered, transaction));
+                }
+                Err(e) => error!("Could not parse the row inserted at {lsn}: {e}"),
+            },
+            "C" => self.slot.commit(self.delivered, lsn),
+            "U" | "D" => warn!("Ignoring a change other than an insert at {lsn}"),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Builds a transaction from the columns of an inserted row, named like the csv headers
+fn transaction_of(columns: Vec<Column>) -> Result<Transaction> {
+    let row: Map<String, Value> = columns
+        .into_iter()
+        .map(|column| (column.name, column.value))
+        .collect();
+    serde_json::from_value(Value::Object(row)).map_err(|e| anyhow!(e))
+}
+
+impl InputSource for PostgresCdcSource {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        while self.received.is_empty() {
+            if !self.read_changes().await? {
+                return Ok(None);
+            }
+        }
+        Ok(self.received.pop_front())
+    }
+
+    fn acknowledges(&self) -> bool {
+        true
+    }
+
+    async fn ack(&mut self, tag: DeliveryTag) -> Result<()> {
+        let Some(lsn) = self.slot.ack(tag) else {
+            return Ok(());
+        };
+        self.client
+            .execute(
+                "SELECT pg_replication_slot_advance($1, $2::text::pg_lsn)",
+                &[&self.config.slot, &lsn],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::model::TransactionType;
+    use crate::source::postgres::{transaction_of, Change, SlotPosition, MAX_CHANGES};
+
+    #[test]
+    fn test_transaction_of_inserted_row() {
+        let data = r#"{"action":"I","schema":"public","table":"transactions","columns":[
+            {"name":"type","type":"text","value":"Deposit"},
+            {"name":"client","type":"integer","value":1},
+            {"name":"tx","type":"bigint","value":7},
+            {"name":"amount","type":"numeric","value":"1.5"}]}"#;
+        let change: Change = serde_json::from_str(data).unwrap();
+        let transaction = transaction_of(change.columns).unwrap();
+        assert!(transaction.transaction_type == TransactionType::Deposit);
+        assert_eq!((transaction.client, transaction.tx), (1, 7));
+        assert_eq!(transaction.amount, Some(dec!(1.5).into()));
+    }
+
+    #[test]
+    fn test_slot_is_advanced_to_the_acknowledged_commits() {
+        let mut slot = SlotPosition::default();
+        assert_eq!(slot.next_read().unwrap(), (0, 1000));
+        // two database transactions, of two rows and one row, each begun and committed
+        slot.read += 4;
+        slot.commit(2, "0/10");
+        slot.read += 3;
+        slot.commit(3, "0/20");
+        assert_eq!(slot.next_read().unwrap().0, 7);
+
+        assert_eq!(slot.ack(1), None);
+        assert_eq!(slot.ack(2).as_deref(), Some("0/10"));
+        // peeking starts after the consumed changes
+        let (skip, upto) = slot.next_read().unwrap();
+        assert_eq!((skip, i64::from(upto)), (3, 3 + MAX_CHANGES));
+        assert_eq!(slot.ack(3).as_deref(), Some("0/20"));
+        assert_eq!(slot.next_read().unwrap().0, 0);
+        assert_eq!(slot.ack(3), None);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource};
+
+/// Reads the transactions of a source in batches and delivers the disputes, resolves and
+/// chargebacks of every batch ahead of its deposits and withdrawals, keeping the order of each.
+/// Steps referring to a transaction of the same batch stay after it, as they can't be applied
+/// before their deposit.
+///
+/// Acknowledging a transaction of a reordered batch would acknowledge the ones delivered before
+/// it by the source but not applied yet, so the transactions of a batch carry the tag of the end
+/// of the previous batch, except the last one, which carries the end of its own.
+pub struct RiskFirst<S> {
+    source: S,
+    batch_size: usize,
+    batch: VecDeque<(DeliveryTag, Transaction)>,
+    /// The batch being read, kept along the source so a dropped `next` doesn't lose it
+    reading: Vec<(DeliveryTag, Transaction)>,
+    /// The tag of the last transaction of the batches delivered completely
+    delivered: DeliveryTag,
+    /// The tag of the last transaction of the current batch
+    batch_end: DeliveryTag,
+}
+
+impl<S: InputSource> RiskFirst<S> {
+    /// Reorders the batches of `batch_size` transactions of the source. Batches of one
+    /// transaction leave the source as it is.
+    pub fn new(source: S, batch_size: usize) -> Self {
+        Self {
+            source,
+            batch_size: batch_size.max(1),
+            batch: VecDeque::new(),
+            reading: Vec::new(),
+            delivered: 0,
+            batch_end: 0,
+        }
+    }
+
+    async fn read_batch(&mut self) -> Result<()> {
+        while self.reading.len() < self.batch_size {
+            match self.source.next().await? {
+                Some(delivery) => self.reading.push(delivery),
+                None => break,
+            }
+        }
+        let batch = std::mem::take(&mut self.reading);
+        if let Some((tag, _)) = batch.last() {
+            self.batch_end = *tag;
+        }
+        let (first, rest): (Vec<_>, Vec<_>) = batch
+            .iter()
+            .cloned()
+            .partition(|(_, transaction)| takes_precedence(transaction, &batch));
+        self.batch = first.into_iter().chain(rest).collect();
+        Ok(())
+    }
+}
+
+/// Whether a transaction is a dispute step whose deposit, or group, isn't part of the batch
+fn takes_precedence(transaction: &Transaction, batch: &[(DeliveryTag, Transaction)]) -> bool {
+    if !transaction.transaction_type.is_dispute_step() {
+        return false;
+    }
+    batch
+        .iter()
+        .map(|(_, other)| other)
+        .filter(|other| other.client == transaction.client)
+        .filter(|other| !other.transaction_type.is_dispute_step())
+        .all(|other| match transaction.reference {
+            Some(reference) => other.reference != Some(reference),
+            None => other.tx != transaction.tx,
+        })
+}
+
+impl<S: InputSource> InputSource for RiskFirst<S> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        if self.batch.is_empty() {
+            self.read_batch().await?;
+        }
+        let Some((_, transaction)) = self.batch.pop_front() else {
+            return Ok(None);
+        };
+        if self.batch.is_empty() {
+            self.delivered = self.batch_end;
+        }
+        Ok(Some((self.delivered, transaction)))
+    }
+
+    fn acknowledges(&self) -> bool {
+        self.source.acknowledges()
+    }
+
+    async fn ack(&mut self, tag: DeliveryTag) -> Result<()> {
+        self.source.ack(tag).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use rust_decimal_macros::dec;
+
+    use crate::model::{Transaction, TransactionType};
+    use crate::source::{DeliveryTag, InputSource, RiskFirst};
+
+    struct VecSource(std::vec::IntoIter<Transaction>, DeliveryTag);
+
+    impl InputSource for VecSource {
+        async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+            self.1 += 1;
+            Ok(self.0.next().map(|transaction| (self.1, transaction)))
+        }
+    }
+
+    #[actix::test]
+    async fn test_dispute_steps_come_first_in_their_batch() {
+        let input = vec![
+            Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1))),
+            Transaction::for_test(TransactionType::Deposit, 1, 2, Some(dec!(1))),
+            Transaction::for_test(TransactionType::Dispute, 1, 1, None),
+            Transaction::for_test(TransactionType::Withdrawal, 1, 3, Some(dec!(1))),
+            Transaction::for_test(TransactionType::Dispute, 1, 2, None),
+            Transaction::for_test(TransactionType::Chargeback, 1, 1, None),
+            Transaction::for_test(TransactionType::Deposit, 1, 4, Some(dec!(1))),
+        ];
+        let mut source = RiskFirst::new(VecSource(input.into_iter(), 0), 4);
+        let mut delivered = Vec::new();
+        while let Some((tag, transaction)) = source.next().await.unwrap() {
+            delivered.push((tag, transaction.transaction_type, transaction.tx));
+        }
+        assert_eq!(
+            delivered,
+            vec![
+                (0, TransactionType::Deposit, 1),
+                (0, TransactionType::Deposit, 2),
+                (0, TransactionType::Dispute, 1),
+                (4, TransactionType::Withdrawal, 3),
+                (4, TransactionType::Dispute, 2),
+                (4, TransactionType::Chargeback, 1),
+                (7, TransactionType::Deposit, 4),
+            ]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{ensure, Result};
+#[cfg(feature = "grpc")]
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncReadExt};
+#[cfg(feature = "grpc")]
+use tracing::error;
+
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource};
+
+/// The largest message read, far larger than any transaction, so a corrupted length isn't
+/// allocated
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+/// The transactions of a stream of length-delimited `transactions.Transaction` protobuf messages,
+/// the message of the grpc service, each preceded by its length as a varint. Messages that aren't
+/// a valid transaction are logged and skipped, and the reader doesn't take acknowledgements.
+pub struct ProtobufSource<R> {
+    reader: R,
+    message: u64,
+    #[cfg(feature = "grpc")]
+    delivered: DeliveryTag,
+}
+
+impl<R: AsyncRead + Unpin> ProtobufSource<R> {
+    /// # Errors
+    /// If the binary was built without the `grpc` feature, an error will be returned
+    pub fn new(reader: R) -> Result<Self> {
+        ensure!(
+            cfg!(feature = "grpc"),
+            "Can't read protobuf transactions: built without the `grpc` feature"
+        );
+        Ok(Self {
+            reader,
+            message: 0,
+            #[cfg(feature = "grpc")]
+            delivered: 0,
+        })
+    }
+
+    /// The length of the next message, or `None` at the end of the input
+    async fn length(&mut self) -> Result<Option<u64>> {
+        let mut length = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = match self.reader.read_u8().await {
+                Ok(byte) => byte,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && shift == 0 => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            length |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(Some(length));
+            }
+        }
+        anyhow::bail!("Invalid length of protobuf message {}", self.message + 1)
+    }
+}
+
+impl<R: AsyncRead + Unpin> InputSource for ProtobufSource<R> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        while let Some(length) = self.length().await? {
+            self.message += 1;
+            ensure!(
+                length <= MAX_MESSAGE_SIZE,
+                "Protobuf message {} is {length} bytes long",
+                self.message
+            );
+            let mut bytes = vec![0; usize::try_from(length)?];
+            self.reader.read_exact(&mut bytes).await?;
+            #[cfg(feature = "grpc")]
+            match crate::grpc::proto::Transaction::decode(bytes.as_slice()) {
+                Ok(message) => match crate::grpc::transaction(message) {
+                    Ok(transaction) => {
+                        self.delivered += 1;
+                        return Ok(Some((self.delivered, transaction)));
+                    }
+                    Err(e) => error!("Could not parse protobuf message {}: {e}", self.message),
+                },
+                Err(e) => error!("Could not decode protobuf message {}: {e}", self.message),
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(all(test, feature = "grpc"))]
+mod tests {
+    use prost::Message;
+    use rust_decimal_macros::dec;
+
+    use crate::grpc::{message, proto};
+    use crate::model::{Transaction, TransactionType};
+    use crate::source::{InputSource, ProtobufSource};
+
+    #[actix::test]
+    async fn test_protobuf_messages() {
+        let mut input = Vec::new();
+        let deposit = Transaction::for_test(TransactionType::Deposit, 4, 1, Some(dec!(2.5)));
+        let untyped = Transaction::for_test(TransactionType::Deposit, 4, 2, None);
+        let dispute = Transaction::for_test(TransactionType::Dispute, 4, 1, None);
+        let messages = [
+            message(&deposit),
+            // no type
+            proto::Transaction {
+                r#type: proto::TransactionType::Unspecified.into(),
+                ..message(&untyped)
+            },
+            message(&dispute),
+        ];
+        for message in messages {
+            message.encode_length_delimited(&mut input).unwrap();
+        }
+        let mut source = ProtobufSource::new(input.as_slice()).unwrap();
+        let (tag, deposit) = source.next().await.unwrap().unwrap();
+        assert_eq!((tag, deposit.client, deposit.tx), (1, 4, 1));
+        let (tag, dispute) = source.next().await.unwrap().unwrap();
+        assert_eq!(tag, 2);
+        assert!(dispute.transaction_type == TransactionType::Dispute);
+        assert!(source.next().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use anyhow::{ensure, Result};
+use aws_sdk_sqs::types::{
+    ChangeMessageVisibilityBatchRequestEntry, DeleteMessageBatchRequestEntry,
+};
+use aws_sdk_sqs::Client;
+use tracing::{error, info};
+
+use crate::model::Transaction;
+use crate::source::{DeliveryTag, InputSource, SqsConfig};
+
+/// The most messages SQS receives in a single request
+const MAX_RECEIVE: i32 = 10;
+/// The most entries of a batch request
+const MAX_BATCH: usize = 10;
+/// How long a receive waits for messages
+const WAIT_SECONDS: i32 = 20;
+/// How long before a receive could end the visibility of the unacknowledged messages is
+/// extended, covering the requests around it
+const VISIBILITY_MARGIN: Duration = Duration::from_secs(5);
+
+/// A message received from the queue
+pub(super) struct Message {
+    body: String,
+    receipt_handle: String,
+}
+
+/// The requests made to the queue
+pub(super) trait Queue {
+    /// Receives up to `MAX_RECEIVE` messages, waiting up to `WAIT_SECONDS` for one, hidden from
+    /// other consumers for `visibility` seconds
+    async fn receive(&self, queue_url: &str, visibility: i32) -> Result<Vec<Message>>;
+
+    /// Hides the messages of the receipt handles for `visibility` more seconds, returning how many
+    /// failed
+    async fn change_visibility(
+        &self,
+        queue_url: &str,
+        entries: &[(DeliveryTag, String)],
+        visibility: i32,
+    ) -> Result<usize>;
+
+    /// Deletes the messages of the receipt handles, returning how many failed
+    async fn delete(&self, queue_url: &str, entries: &[(DeliveryTag, String)]) -> Result<usize>;
+
+    /// Sends a message to another queue and deletes it from this one
+    async fn move_to(&self, queue_url: &str, to: &str, message: &Message) -> Result<()>;
+}
+
+impl Queue for Client {
+    async fn receive(&self, queue_url: &str, visibility: i32) -> Result<Vec<Message>> {
+        let output = self
+            .receive_message()
+            .queue_url(queue_url)
+            .max_number_of_messages(MAX_RECEIVE)
+            .wait_time_seconds(WAIT_SECONDS)
+            .visibility_timeout(visibility)
+            .send()
+            .await?;
+        let messages = output.messages().iter().filter_map(|message| {
+            Some(Message {
+                body: message.body()?.to_string(),
+                receipt_handle: message.receipt_handle()?.to_string(),
+            })
+        });
+        Ok(messages.collect())
+    }
+
+    async fn change_visibility(
+        &self,
+        queue_url: &str,
+        entries: &[(DeliveryTag, String)],
+        visibility: i32,
+    ) -> Result<usize> {
+        let entries = entries
+            .iter()
+            .map(|(tag, handle)| {
+                ChangeMessageVisibilityBatchRequestEntry::builder()
+                    .id(tag.to_string())
+                    .receipt_handle(handle)
+                    .visibility_timeout(visibility)
+                    .build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let output = self
+            .change_message_visibility_batch()
+            .queue_url(queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await?;
+        Ok(output.failed().len())
+    }
+
+    async fn delete(&self, queue_url: &str, entries: &[(DeliveryTag, String)]) -> Result<usize> {
+        let entries = entries
+            .iter()
+            .map(|(tag, handle)| {
+                DeleteMessageBatchRequestEntry::builder()
+                    .id(tag.to_string())
+                    .receipt_handle(handle)
+                    .build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let output = self
+            .delete_message_batch()
+            .queue_url(queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await?;
+        Ok(output.failed().len())
+    }
+
+    async fn move_to(&self, queue_url: &str, to: &str, message: &Message) -> Result<()> {
+        self.send_message()
+            .queue_url(to)
+            .message_body(&message.body)
+            .send()
+            .await?;
+        self.delete_message()
+            .queue_url(queue_url)
+            .receipt_handle(&message.receipt_handle)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Reads transactions, one json object per message, from an SQS queue until the process is
+/// stopped. Messages are deleted once acknowledged and their visibility is extended while they
+/// wait for it, so they aren't delivered again in the meantime.
+pub struct SqsSource<Q = Client> {
+    queue: Q,
+    config: SqsConfig,
+    /// Received messages not handed to the engine yet
+    received: VecDeque<(DeliveryTag, Transaction)>,
+    /// The receipt handles of the messages not acknowledged yet, by delivery tag
+    unacked: VecDeque<(DeliveryTag, String)>,
+    /// When the visibility of the oldest unacknowledged message ends
+    hidden_until: Option<Instant>,
+    delivered: DeliveryTag,
+    /// Ends the input once the process is asked to stop
+    stopped: Pin<Box<dyn Future<Output = io::Result<()>>>>,
+}
+
+impl SqsSource {
+    /// Creates a client with the credentials and region of the environment
+    pub async fn connect(config: SqsConfig) -> Self {
+        let aws = aws_config::load_from_env().await;
+        Self::new(Client::new(&aws), config, tokio::signal::ctrl_c())
+    }
+}
+
+impl<Q: Queue> SqsSource<Q> {
+    fn new(
+        queue: Q,
+        config: SqsConfig,
+        stopped: impl Future<Output = io::Result<()>> + 'static,
+    ) -> Self {
+        Self {
+            queue,
+            config,
+            received: VecDeque::new(),
+            unacked: VecDeque::new(),
+            hidden_until: None,
+            delivered: 0,
+            stopped: Box::pin(stopped),
+        }
+    }
+
+    fn visibility_seconds(&self) -> i32 {
+        i32::try_from(self.config.visibility_timeout.as_secs()).unwrap_or(i32::MAX)
+    }
+
+    /// Queues the received messages, moving the invalid ones to the dead letter queue
+    async fn accept(&mut self, messages: Vec<Message>) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        if self.unacked.is_empty() {
+            self.hidden_until = Some(Instant::now() + self.config.visibility_timeout);
+        }
+        for message in messages {
+            match serde_json::from_str::<Transaction>(&message.body) {
+                Ok(transaction) => {
+                    self.delivered += 1;
+                    self.received.push_back((self.delivered, transaction));
+                    self.unacked
+                        .push_back((self.delivered, message.receipt_handle));
+                }
+                Err(e) => {
+                    error!("Could not parse message {}: {e}", message.body);
+                    self.dead_letter(&message).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves a message to the dead letter queue, if there's one
+    async fn dead_letter(&self, message: &Message) -> Result<()> {
+        let Some(dlq) = &self.config.dlq_url else {
+            return Ok(());
+        };
+        self.queue
+            .move_to(&self.config.queue_url, dlq, message)
+            .await?;
+        info!("Moved message to the dead letter queue");
+        Ok(())
+    }
+
+    /// Extends the visibility of every unacknowledged message unless it outlasts the longest
+    /// receive, so they stay hidden while the source waits for messages
+    async fn keep_hidden(&mut self) -> Result<()> {
+        let Some(hidden_until) = self.hidden_until else {
+            return Ok(());
+        };
+        let wait = Duration::from_secs(WAIT_SECONDS.unsigned_abs().into());
+        if hidden_until.saturating_duration_since(Instant::now()) > wait + VISIBILITY_MARGIN {
+            return Ok(());
+        }
+        let unacked: Vec<_> = self.unacked.iter().cloned().collect();
+        for chunk in unacked.chunks(MAX_BATCH) {
+            let failed = self
+                .queue
+                .change_visibility(&self.config.queue_url, chunk, self.visibility_seconds())
+                .await?;
+            ensure!(
+                failed == 0,
+                "Could not extend the visibility of {failed} messages"
+            );
+        }
+        self.hidden_until = Some(Instant::now() + self.config.visibility_timeout);
+        Ok(())
+    }
+}
+
+impl<Q: Queue> InputSource for SqsSource<Q> {
+    async fn next(&mut self) -> Result<Option<(DeliveryTag, Transaction)>> {
+        while self.received.is_empty() {
+            self.keep_hidden().await?;
+            let visibility = self.visibility_seconds();
+            let messages = tokio::select! {
+                messages = self.queue.receive(&self.config.queue_url, visibility) => messages?,
+                _ = &mut self.stopped => {
+                    info!("Stopping the consumption of {}", self.config.queue_url);
+                    return Ok(None);
+                }
+            };
+            self.accept(messages).await?;
+        }
+        Ok(self.received.pop_front())
+    }
+
+    fn acknowledges(&self) -> bool {
+        true
+    }
+
+    async fn ack(&mut self, tag: DeliveryTag) -> Result<()> {
+        let split = self
+            .unacked
+            .partition_point(|(delivered, _)| *delivered <= tag);
+        let acked: Vec<_> = self.unacked.drain(..split).collect();
+        for chunk in acked.chunks(MAX_BATCH) {
+            let failed = self.queue.delete(&self.config.queue_url, chunk).await?;
+            ensure!(
+                failed == 0,
+                "Could not delete {failed} acknowledged messages"
+            );
+        }
+        if self.unacked.is_empty() {
+            self.hidden_until = None;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::time::{Duration, Instant};
+
+    use anyhow::Result;
+
+    use crate::source::sqs::{Message, Queue, SqsSource};
+    use crate::source::{DeliveryTag, InputSource, SqsConfig};
+
+    /// A queue answering receives from a list of batches and recording the other requests
+    #[derive(Default)]
+    struct MockQueue {
+        batches: RefCell<VecDeque<Vec<&'static str>>>,
+        hidden: RefCell<Vec<DeliveryTag>>,
+        deleted: RefCell<Vec<DeliveryTag>>,
+        moved: RefCell<Vec<String>>,
+    }
+
+    impl Queue for MockQueue {
+        async fn receive(&self, _queue_url: &str, _visibility: i32) -> Result<Vec<Message>> {
+            let batch = self.batches.borrow_mut().pop_front().unwrap_or_default();
+            let messages = batch.into_iter().enumerate().map(|(n, body)| Message {
+                body: body.to_string(),
+                receipt_handle: format!("handle-{n}"),
+            });
+            Ok(messages.collect())
+        }
+
+        async fn change_visibility(
+            &self,
+            _queue_url: &str,
+            entries: &[(DeliveryTag, String)],
+            _visibility: i32,
+        ) -> Result<usize> {
+            let tags = entries.iter().map(|(tag, _)| *tag);
+            self.hidden.borrow_mut().extend(tags);
+            Ok(0)
+        }
+
+        async fn delete(
+            &self,
+            _queue_url: &str,
+            entries: &[(DeliveryTag, String)],
+        ) -> Result<usize> {
+            let tags = entries.iter().map(|(tag, _)| *tag);
+            self.deleted.borrow_mut().extend(tags);
+            Ok(0)
+        }
+
+        async fn move_to(&self, _queue_url: &str, to: &str, message: &Message) -> Result<()> {
+            self.moved
+                .borrow_mut()
+                .push(format!("{to}: {}", message.body));
+            Ok(())
+        }
+    }
+
+    #[actix::test]
+    async fn test_messages_are_hidden_until_deleted() {
+        let deposit = r#"{"type":"Deposit","client":1,"tx":1,"amount":"2"}"#;
+        let queue = MockQueue::default();
+        queue.batches.borrow_mut().extend([
+            vec![deposit, "invalid", deposit],
+            vec![],
+            vec![deposit],
+        ]);
+        let config = SqsConfig {
+            queue_url: "queue".to_string(),
+            dlq_url: Some("dlq".to_string()),
+            visibility_timeout: Duration::from_mins(1),
+        };
+        let mut source = SqsSource::new(queue, config, std::future::pending());
+
+        assert_eq!(source.next().await.unwrap().unwrap().0, 1);
+        assert_eq!(source.next().await.unwrap().unwrap().0, 2);
+        assert_eq!(*source.queue.moved.borrow(), ["dlq: invalid"]);
+        source.ack(1).await.unwrap();
+        assert_eq!(*source.queue.deleted.borrow(), [1]);
+
+        // a receive could outlast the visibility left, which is extended first, and an empty
+        // receive doesn't end the source
+        source.hidden_until = Some(Instant::now() + Duration::from_secs(10));
+        assert_eq!(source.next().await.unwrap().unwrap().0, 3);
+        assert_eq!(*source.queue.hidden.borrow(), [2]);
+        source.ack(3).await.unwrap();
+        assert_eq!(*source.queue.deleted.borrow(), [1, 2, 3]);
+        assert!(source.hidden_until.is_none());
+    }
+}
@@ -0,0 +1,199 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Result};
+use csv_async::Trim::All;
+use csv_async::{AsyncReaderBuilder, AsyncWriter, StringRecord};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tracing::{error, info};
+
+use crate::cli::SplitArgs;
+use crate::model::{Transaction, TransactionType};
+use crate::partition::PartitionScheme;
+use crate::source::InputFormat;
+
+/// Where the rows of the input go
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Route {
+    /// To the shard of the client
+    Shard(usize),
+    /// To every shard, for savepoint markers, so every shard keeps the segments of the input
+    All,
+}
+
+impl Route {
+    /// The route of a transaction. A transfer between clients of different shards can't be
+    /// applied by either of them, so it fails the split.
+    fn new(transaction: &Transaction, scheme: PartitionScheme, shards: usize) -> Result<Self> {
+        if transaction.transaction_type == TransactionType::Savepoint {
+            return Ok(Self::All);
+        }
+        let shard = scheme.partition_of(transaction.client, shards);
+        if let (TransactionType::Transfer, Some(to_client)) =
+            (transaction.transaction_type, transaction.to_client)
+        {
+            ensure!(
+                scheme.partition_of(to_client, shards) == shard,
+                "Transfer {} goes from client {} to client {} of another shard",
+                transaction.tx,
+                transaction.client,
+                to_client
+            );
+        }
+        Ok(Self::Shard(shard))
+    }
+
+    fn shards(self, shards: usize) -> std::ops::Range<usize> {
+        match self {
+            Self::Shard(n) => n..n + 1,
+            Self::All => 0..shards,
+        }
+    }
+}
+
+/// Splits the input into shard files by client, `transactions-<n>.csv` (or `.ndjson`) in the
+/// directory of the arguments, so separate processes can each apply a shard. The rows are copied
+/// as they are, keeping the order of every client. Rows that can't be parsed are logged and left
+/// out, as a run would skip them.
+///
+/// # Errors
+/// If the input can't be read, a shard can't be written or a transfer goes between shards, an
+/// error will be returned
+pub async fn split(args: &SplitArgs) -> Result<()> {
+    ensure!(
+        matches!(
+            args.format,
+            InputFormat::Csv | InputFormat::Json | InputFormat::Capture
+        ),
+        "Only csv and json inputs can be split"
+    );
+    let shards = usize::from(args.shards);
+    fs::create_dir_all(&args.dir).await?;
+    let file = BufReader::new(File::open(&args.input).await?);
+    let rows = match args.format {
+        InputFormat::Csv => split_csv(file, args, shards).await?,
+        _ => split_ndjson(file, args, shards).await?,
+    };
+    info!("Split {rows} rows into {shards} shards");
+    Ok(())
+}
+
+fn shard_path(dir: &Path, n: usize, format: InputFormat) -> PathBuf {
+    let extension = match format {
+        InputFormat::Csv => "csv",
+        _ => "ndjson",
+    };
+    dir.join(format!("transactions-{n}.{extension}"))
+}
+
+async fn split_csv(file: BufReader<File>, args: &SplitArgs, shards: usize) -> Result<u64> {
+    let mut reader = AsyncReaderBuilder::new()
+        .has_headers(true)
+        .trim(All)
+        .flexible(true)
+        .create_reader(file);
+    let headers = reader.headers().await?.clone();
+    let mut writers = Vec::with_capacity(shards);
+    for n in 0..shards {
+        let file = File::create(shard_path(&args.dir, n, args.format)).await?;
+        let mut writer = AsyncWriter::from_writer(BufWriter::new(file));
+        writer.write_record(&headers).await?;
+        writers.push(writer);
+    }
+    let mut record = StringRecord::new();
+    let mut rows = 0;
+    while reader.read_record(&mut record).await? {
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let route = match record.deserialize::<Transaction>(Some(&headers)) {
+            Ok(t) => Route::new(&t, args.by, shards)?,
+            Err(e) => {
+                let line = record.position().map_or(0, csv_async::Position::line);
+                error!("Could not parse line {line}: {e}");
+                continue;
+            }
+        };
+        for n in route.shards(shards) {
+            writers[n].write_record(&record).await?;
+        }
+        rows += 1;
+    }
+    for mut writer in writers {
+        writer.flush().await?;
+    }
+    Ok(rows)
+}
+
+async fn split_ndjson(file: BufReader<File>, args: &SplitArgs, shards: usize) -> Result<u64> {
+    let mut writers = Vec::with_capacity(shards);
+    for n in 0..shards {
+        let file = File::create(shard_path(&args.dir, n, args.format)).await?;
+        writers.push(BufWriter::new(file));
+    }
+    let mut lines = file.lines();
+    let (mut line, mut rows) = (0, 0);
+    while let Some(text) = lines.next_line().await? {
+        line += 1;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let route = match serde_json::from_str::<Transaction>(&text) {
+            Ok(t) => Route::new(&t, args.by, shards)?,
+            Err(e) => {
+                error!("Could not parse line {line}: {e}");
+                continue;
+            }
+        };
+        for n in route.shards(shards) {
+            let writer = &mut writers[n];
+            writer.write_all(text.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+        }
+        rows += 1;
+    }
+    for mut writer in writers {
+        writer.flush().await?;
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::model::{Transaction, TransactionType};
+    use crate::partition::PartitionScheme;
+    use crate::split::Route;
+
+    #[test]
+    fn test_savepoints_go_to_every_shard() {
+        let deposit = Transaction::for_test(TransactionType::Deposit, 7, 1, Some(dec!(1)));
+        let route = Route::new(&deposit, PartitionScheme::Hash, 4).unwrap();
+        let shard = PartitionScheme::Hash.partition_of(7, 4);
+        assert_eq!(route, Route::Shard(shard));
+        assert_eq!(route.shards(4), shard..shard + 1);
+        let marker = Transaction::for_test(TransactionType::Savepoint, 0, 1, None);
+        let marker = Route::new(&marker, PartitionScheme::Hash, 4).unwrap();
+        assert_eq!(marker.shards(4), 0..4);
+    }
+
+    #[test]
+    fn test_transfers_between_shards_are_refused() {
+        let scheme = PartitionScheme::Hash;
+        let other = (0..u16::MAX)
+            .find(|client| scheme.partition_of(*client, 4) != scheme.partition_of(7, 4))
+            .unwrap();
+        let same = (0..u16::MAX)
+            .find(|client| {
+                *client != 7 && scheme.partition_of(*client, 4) == scheme.partition_of(7, 4)
+            })
+            .unwrap();
+        let within = Transaction::for_test(TransactionType::Transfer, 7, 1, Some(dec!(1)))
+            .with_recipient(same);
+        assert!(Route::new(&within, scheme, 4).is_ok());
+        let across = Transaction::for_test(TransactionType::Transfer, 7, 1, Some(dec!(1)))
+            .with_recipient(other);
+        assert!(Route::new(&across, scheme, 4).is_err());
+    }
+}
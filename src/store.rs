@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use actix::{
+    Actor, ActorFutureExt, AsyncContext, Context, Handler, Message, ResponseActFuture, WrapFuture,
+};
+use anyhow::{Context as _, Result};
+use tracing::{error, info};
+
+use crate::model::{AccountState, GetState};
+use crate::snapshot::temp_path_for;
+use crate::transaction::AccountAddr;
+
+/// Persists the state of the accounts between runs
+pub trait AccountStore: Send + Sync {
+    /// Loads the state of a client's account, if it was ever saved
+    ///
+    /// # Errors
+    /// If the store can't be read, an error will be returned
+    fn load(&self, client: u16) -> Result<Option<AccountState>>;
+
+    /// Saves the state of the accounts, replacing their previous state
+    ///
+    /// # Errors
+    /// If the store can't be written, an error will be returned
+    fn save(&self, accounts: &[AccountState]) -> Result<()>;
+
+    /// The clients whose account was saved most recently, the latest first, at most `limit`
+    ///
+    /// # Errors
+    /// If the store can't be read, an error will be returned
+    fn recent(&self, limit: usize) -> Result<Vec<u16>>;
+}
+
+/// Stores every account as a json file named after the client in a directory. Settled accounts
+/// can be moved into an archive directory, from which they are restored when their client is
+/// found again.
+pub struct FileAccountStore {
+    dir: PathBuf,
+    archive: Option<PathBuf>,
+}
+
+impl FileAccountStore {
+    /// Opens the store, creating the directory if needed
+    ///
+    /// # Errors
+    /// If the directory can't be created, an error will be returned
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, archive: None })
+    }
+
+    /// Restores the accounts of the archive in `dir` when they are loaded, creating the directory
+    /// if needed
+    ///
+    /// # Errors
+    /// If the directory can't be created, an error will be returned
+    pub fn with_archive(mut self, dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        self.archive = Some(dir);
+        Ok(self)
+    }
+
+    fn path(&self, client: u16) -> PathBuf {
+        self.dir.join(format!("{client}.json"))
+    }
+
+    /// Moves the settled accounts that weren't saved for `idle` into the archive, returning their
+    /// clients in order. They are loaded like any other account, so archiving doesn't change the
+    /// outcome of a later run.
+    ///
+    /// # Errors
+    /// If the store has no archive or can't be read, or an account can't be moved, an error will
+    /// be returned
+    pub fn archive_idle(&self, idle: Duration) -> Result<Vec<u16>> {
+        let archive = self.archive.as_ref().context("The store has no archive")?;
+        let now = SystemTime::now();
+        let mut archived = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            // temporary files of interrupted saves are skipped along with any other file
+            let Some(client) = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_suffix(".json")?.parse::<u16>().ok())
+            else {
+                continue;
+            };
+            let saved = fs::metadata(&path)?.modified()?;
+            if now.duration_since(saved).unwrap_or_default() < idle {
+                continue;
+            }
+            let state: AccountState = serde_json::from_slice(&fs::read(&path)?)?;
+            if state.is_settled() {
+                fs::rename(&path, archive.join(format!("{client}.json")))?;
+                archived.push(client);
+            }
+        }
+        archived.sort_unstable();
+        Ok(archived)
+    }
+
+    /// Moves the archived account of a client back into the store, if there's one
+    fn restore_archived(&self, client: u16) -> Result<bool> {
+        let Some(archive) = &self.archive else {
+            return Ok(false);
+        };
+        match fs::rename(archive.join(format!("{client}.json")), self.path(client)) {
+            Ok(()) => {
+                info!("Restored the archived account of client {client}");
+                Ok(true)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl AccountStore for FileAccountStore {
+    fn load(&self, client: u16) -> Result<Option<AccountState>> {
+        match fs::read(self.path(client)) {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound && self.restore_archived(client)? => {
+                self.load(client)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, accounts: &[AccountState]) -> Result<()> {
+        for state in accounts {
+            let path = self.path(state.client());
+            let temp_path = temp_path_for(&path);
+            fs::write(&temp_path, serde_json::to_vec(state)?)?;
+            fs::rename(&temp_path, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Archived accounts are left out, they are only restored by a transaction of their client
+    fn recent(&self, limit: usize) -> Result<Vec<u16>> {
+        let mut saved = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let Some(client) = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_suffix(".json")?.parse::<u16>().ok())
+            else {
+                continue;
+            };
+            saved.push((fs::metadata(&path)?.modified()?, client));
+        }
+        saved.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(saved
+            .into_iter()
+            .take(limit)
+            .map(|(_, client)| client)
+            .collect())
+    }
+}
+
+/// Marks an account as changed, so it is saved on the next flush
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MarkDirty {
+    pub client: u16,
+    pub actor: AccountAddr,
+}
+
+/// Saves every changed account right away
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct Flush;
+
+/// Actor saving changed accounts into the store periodically, so transactions never wait for a
+/// store write. Accounts are only marked as changed when a transaction is applied, their state is
+/// fetched from their actors when flushing.
+pub struct StoreWriter {
+    store: Arc<dyn AccountStore>,
+    interval: Duration,
+    dirty: HashMap<u16, AccountAddr>,
+}
+
+impl StoreWriter {
+    /// Creates a writer flushing the changed accounts every `interval`
+    pub fn new(store: Arc<dyn AccountStore>, interval: Duration) -> Self {
+        Self {
+            store,
+            interval,
+            dirty: HashMap::new(),
+        }
+    }
+
+    /// Fetches the state of every changed account and saves it
+    fn flush(&mut self) -> ResponseActFuture<Self, Result<()>> {
+        let dirty = std::mem::take(&mut self.dirty);
+        let store = Arc::clone(&self.store);
+        Box::pin(
+            async move {
+                let mut states = Vec::with_capacity(dirty.len());
+                for (client, actor) in dirty {
+                    match actor.send(GetState).await {
+                        Ok(state) => states.push(state),
+                        Err(e) => error!("Could not fetch the state of account {client}: {e}"),
+                    }
+                }
+                states
+            }
+            .into_actor(self)
+            .map(move |states, _, _| {
+                if !states.is_empty() {
+                    info!("Saving {} accounts", states.len());
+                }
+                store.save(&states)
+            }),
+        )
+    }
+}
+
+impl Actor for StoreWriter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // waits for every flush before handling other messages, so an explicit `Flush` never
+        // finishes before a periodic one that is still fetching states
+        ctx.run_interval(self.interval, |writer, ctx| {
+            ctx.wait(writer.flush().map(|result, _, _| {
+                if let Err(e) = result {
+                    error!("Could not save accounts: {e}");
+                }
+            }));
+        });
+    }
+}
+
+impl Handler<MarkDirty> for StoreWriter {
+    type Result = ();
+
+    fn handle(&mut self, msg: MarkDirty, _: &mut Self::Context) -> Self::Result {
+        self.dirty.insert(msg.client, msg.actor);
+    }
+}
+
+impl Handler<Flush> for StoreWriter {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, _: Flush, _: &mut Self::Context) -> Self::Result {
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rust_decimal_macros::dec;
+
+    use crate::config::EngineConfig;
+    use crate::model::{Account, AccountState};
+    use crate::store::{AccountStore, FileAccountStore};
+
+    #[test]
+    fn test_file_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("account-store-{}", std::process::id()));
+        let store = FileAccountStore::open(dir.clone()).unwrap();
+        let mut account = Account::new(7, EngineConfig::default());
+        account.deposit(dec!(10.5).into(), 1).unwrap();
+        let state = AccountState::from(&account);
+        store.save(std::slice::from_ref(&state)).unwrap();
+        assert_eq!(store.load(7).unwrap(), Some(state));
+        assert_eq!(store.load(8).unwrap(), None);
+        assert_eq!(store.recent(10).unwrap(), [7]);
+        assert!(store.recent(0).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_settled_accounts_are_archived_and_restored() {
+        let dir = std::env::temp_dir().join(format!("account-archive-{}", std::process::id()));
+        let store = FileAccountStore::open(dir.join("store"))
+            .unwrap()
+            .with_archive(dir.join("archive"))
+            .unwrap();
+        let mut settled = Account::new(1, EngineConfig::default());
+        settled.deposit(dec!(10).into(), 1).unwrap();
+        settled.withdraw(dec!(10).into(), 2).unwrap();
+        let mut funded = Account::new(2, EngineConfig::default());
+        funded.deposit(dec!(5).into(), 3).unwrap();
+        let states = [AccountState::from(&settled), AccountState::from(&funded)];
+        store.save(&states).unwrap();
+
+        assert!(store
+            .archive_idle(Duration::from_hours(1))
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.archive_idle(Duration::ZERO).unwrap(), [1]);
+        assert!(!dir.join("store/1.json").exists());
+        assert!(dir.join("archive/1.json").exists());
+
+        // a late transaction of the client loads the account back into the store
+        assert_eq!(store.load(1).unwrap(), Some(states[0].clone()));
+        assert!(dir.join("store/1.json").exists());
+        assert_eq!(store.load(3).unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use csv_async::AsyncSerializer;
+use tokio::io::AsyncWrite;
+
+use crate::engine::Engine;
+use crate::model::TransactionType;
+use crate::registry::ClientRegistry;
+use crate::source::InputSource;
+
+/// Writes the accounts in csv format while the input is processed, a row for every account
+/// changed since the previous batch, so the last row of a client is its final state
+pub struct AccountStream<W: AsyncWrite + Unpin> {
+    serializer: AsyncSerializer<W>,
+    registry: Option<Arc<ClientRegistry>>,
+}
+
+impl<W: AsyncWrite + Unpin> AccountStream<W> {
+    /// Creates a stream writing into `writer`, adding the client data of the registry, if any,
+    /// to every row
+    pub fn new(writer: W, registry: Option<Arc<ClientRegistry>>) -> Self {
+        Self {
+            serializer: AsyncSerializer::from_writer(writer),
+            registry,
+        }
+    }
+
+    /// Writes the accounts changed since the last batch, see `Engine::drain`
+    ///
+    /// # Errors
+    /// If the accounts can't be drained or the writer fails, an error will be returned
+    pub async fn write_batch(&mut self, engine: &mut Engine) -> Result<()> {
+        for account in engine.drain().await? {
+            match &self.registry {
+                Some(registry) => self.serializer.serialize(registry.enrich(&account)).await?,
+                None => self.serializer.serialize(&account).await?,
+            }
+        }
+        self.serializer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Applies the transactions of the source through the engine, writing the changed accounts into
+/// the stream every `every` transactions, on every `Savepoint` row and at the end
+///
+/// # Errors
+/// If the source fails, the engine fails to record a transaction or the accounts can't be
+/// written, an error will be returned
+pub async fn process_streaming<W: AsyncWrite + Unpin>(
+    mut source: impl InputSource,
+    engine: &mut Engine,
+    stream: &mut AccountStream<W>,
+    every: usize,
+) -> Result<()> {
+    let mut rows = 0;
+    while let Some((_, transaction)) = source.next().await? {
+        if engine.sample_complete() {
+            break;
+        }
+        let marker = transaction.transaction_type == TransactionType::Savepoint;
+        if !marker {
+            engine.apply(transaction).await?;
+            rows += 1;
+        }
+        if marker || rows >= every {
+            stream.write_batch(engine).await?;
+            rows = 0;
+        }
+    }
+    stream.write_batch(engine).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::csv::CsvSource;
+    use crate::engine::Engine;
+    use crate::store::FileAccountStore;
+    use crate::stream::{process_streaming, AccountStream};
+
+    #[actix::test]
+    async fn test_streaming_writes_changed_accounts() {
+        let dir = std::env::temp_dir().join(format!("stream-test-{}", std::process::id()));
+        let store = Arc::new(FileAccountStore::open(dir.clone()).unwrap());
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default())
+            .with_store(store, Duration::from_mins(1));
+        let input: &[u8] = b"type,client,tx,amount\nDeposit,1,1,5\nDeposit,2,2,3\n\
+            Withdrawal,1,3,1\nSavepoint,0,0,\nDeposit,2,4,1\n";
+        let source = CsvSource::open(input).await.unwrap();
+        let mut output = Vec::new();
+        let mut stream = AccountStream::new(&mut output, None);
+        process_streaming(source, &mut engine, &mut stream, 2)
+            .await
+            .unwrap();
+        drop(stream);
+        let accounts = engine.collect().await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+            1,5,0.0000,5,false\n2,3,0.0000,3,false\n1,4,0.0000,4,false\n\
+            2,4,0.0000,4,false\n"
+        );
+        // every account was handed over to the store once written
+        assert!(accounts.is_empty());
+    }
+}
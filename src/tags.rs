@@ -0,0 +1,79 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::model::Account;
+
+/// Free-form labels of the accounts, like `vip` or `under_review`, loaded from the registry or
+/// attached through the admin API, to pick the accounts exported, queried and reported on
+#[derive(Clone, Debug, Default)]
+pub struct Tags(HashMap<u16, BTreeSet<String>>);
+
+impl Tags {
+    /// Tags the account of a client, telling whether it wasn't already
+    pub fn insert(&mut self, client: u16, tag: &str) -> bool {
+        self.0.entry(client).or_default().insert(tag.to_owned())
+    }
+
+    /// Removes a tag of the account of a client, telling whether it had it
+    #[cfg(any(test, feature = "http"))]
+    pub fn remove(&mut self, client: u16, tag: &str) -> bool {
+        let Some(tags) = self.0.get_mut(&client) else {
+            return false;
+        };
+        let removed = tags.remove(tag);
+        if tags.is_empty() {
+            self.0.remove(&client);
+        }
+        removed
+    }
+
+    /// The tags of the account of a client, in alphabetical order
+    #[cfg(any(test, feature = "http"))]
+    pub fn of(&self, client: u16) -> Vec<&str> {
+        self.0
+            .get(&client)
+            .map(|tags| tags.iter().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether the account of a client has any of the tags of the filter. Every account passes an
+    /// empty filter.
+    pub fn matches<S: AsRef<str>>(&self, client: u16, filter: &[S]) -> bool {
+        filter.is_empty()
+            || self
+                .0
+                .get(&client)
+                .is_some_and(|tags| filter.iter().any(|tag| tags.contains(tag.as_ref())))
+    }
+
+    /// The accounts with any of the tags of the filter
+    pub fn filter<S: AsRef<str>>(&self, mut accounts: Vec<Account>, filter: &[S]) -> Vec<Account> {
+        accounts.retain(|account| self.matches(account.client(), filter));
+        accounts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tags::Tags;
+
+    #[test]
+    fn test_filter_keeps_the_accounts_with_any_tag() {
+        let mut tags = Tags::default();
+        assert!(tags.insert(1, "vip"));
+        assert!(!tags.insert(1, "vip"));
+        tags.insert(1, "under_review");
+        tags.insert(2, "under_review");
+        tags.insert(3, "dormant");
+        assert_eq!(tags.of(1), ["under_review", "vip"]);
+
+        let filter = ["vip", "under_review"];
+        let matching: Vec<_> = (1..=4).filter(|&c| tags.matches(c, &filter)).collect();
+        assert_eq!(matching, [1, 2]);
+        assert!(tags.matches::<&str>(4, &[]));
+
+        assert!(tags.remove(2, "under_review"));
+        assert!(!tags.remove(2, "under_review"));
+        assert!(!tags.matches(2, &filter));
+        assert!(tags.of(2).is_empty());
+    }
+}
@@ -1,28 +1,300 @@
-use actix::{Actor, ActorContext, Addr, Context, Handler, MessageResult, Supervised, Supervisor};
-use log::info;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
 
-use crate::model::{Account, Collect, Transaction, TransactionError, TransactionType};
+use actix::dev::{MessageResponse, OneshotSender};
+use actix::{
+    Actor, ActorContext, Addr, ArbiterHandle, AsyncContext, Context, Handler, MailboxError,
+    Message, MessageResult, Supervised, Supervisor,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+use crate::config::DispatchConfig;
+use crate::hooks::{HookAction, HookPoint, ProcessingHook};
+use crate::model::{
+    Account, AccountState, Balances, Collect, GetState, Restore, Transaction, TransactionError,
+};
+use crate::shard::ShardedAccountHandler;
+use crate::store::{MarkDirty, StoreWriter};
 
 /// Actor to hold the state of each client's account
 pub struct AccountHandler {
     client: u16,
     account: Account,
+    store: Option<Addr<StoreWriter>>,
+    priority: UnboundedReceiver<PriorityRequest>,
+    hook: Option<Arc<dyn ProcessingHook>>,
+}
+
+/// Requests answered ahead of the transactions waiting in the actor's mailbox, like queries, so
+/// they aren't stuck behind a backlog
+pub enum PriorityRequest {
+    /// The state of the account of a client, which a shard picks among its accounts
+    GetState(u16, oneshot::Sender<AccountState>),
+    /// The balances of the account of a client, without copying its history
+    GetBalances(u16, oneshot::Sender<Balances>),
+}
+
+/// How far an account got with a transaction delivered to it. The engine and the actor share it,
+/// so an engine giving up on the transaction and the actor applying it exclude each other, and a
+/// transaction sent again after its answer was lost isn't applied twice.
+#[derive(Clone, Default)]
+pub struct Ticket(Arc<Mutex<Progress>>);
+
+#[derive(Default)]
+enum Progress {
+    #[default]
+    Pending,
+    /// The engine gave up on the transaction, so it must not be applied anymore
+    Cancelled,
+    /// The account applied the transaction, which ended as given
+    Applied(Result<(), TransactionError>),
+}
+
+impl Ticket {
+    /// Applies the transaction with `apply` unless it was applied already, telling how it ended,
+    /// or nothing if the engine gave up on it
+    pub fn apply(
+        &self,
+        apply: impl FnOnce() -> Result<(), TransactionError>,
+    ) -> Option<Result<(), TransactionError>> {
+        let mut progress = self.lock();
+        match &*progress {
+            Progress::Cancelled => None,
+            Progress::Applied(result) => Some(result.clone()),
+            Progress::Pending => {
+                let result = apply();
+                *progress = Progress::Applied(result.clone());
+                Some(result)
+            }
+        }
+    }
+
+    /// Gives up on the transaction, so its account won't apply it anymore. Tells how it ended if
+    /// the account applied it already, even though its answer never arrived.
+    pub fn cancel(&self) -> Option<Result<(), TransactionError>> {
+        let mut progress = self.lock();
+        match &*progress {
+            Progress::Applied(result) => Some(result.clone()),
+            Progress::Pending | Progress::Cancelled => {
+                *progress = Progress::Cancelled;
+                None
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Progress> {
+        // an account panicking while applying never recorded an outcome
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A transaction for an account, along with the ticket telling how far the account got with it
+#[derive(Message, Clone)]
+#[rtype(result = "Result<(), TransactionError>")]
+pub struct Deliver {
+    pub transaction: Transaction,
+    pub ticket: Ticket,
+}
+
+impl Deliver {
+    pub fn new(transaction: Transaction) -> Self {
+        Self {
+            transaction,
+            ticket: Ticket::default(),
+        }
+    }
+}
+
+/// Wakes the actor to answer its priority requests, in case no other message arrives before
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ServePriority;
+
+/// A message for the account of a client, among the accounts of a shard
+pub struct ForClient<M>(pub u16, pub M);
+
+impl<M: Message> Message for ForClient<M> {
+    type Result = M::Result;
+}
+
+/// The answer of an account actor to a message, once it arrives
+pub type Pending<T> = Pin<Box<dyn Future<Output = Result<T, MailboxError>> + Send>>;
+
+/// Where the messages for the account of a client go: its own actor, or the actor of its shard
+#[derive(Clone)]
+pub enum AccountAddr {
+    Actor(Addr<AccountHandler>),
+    Shard(Addr<ShardedAccountHandler>, u16),
+}
+
+impl AccountAddr {
+    /// Sends a message to the account, to be answered once the messages before it are handled
+    pub fn send<M>(&self, msg: M) -> Pending<M::Result>
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        AccountHandler: Handler<M>,
+        ShardedAccountHandler: Handler<ForClient<M>>,
+    {
+        match self {
+            Self::Actor(addr) => Box::pin(addr.send(msg)),
+            Self::Shard(addr, client) => Box::pin(addr.send(ForClient(*client, msg))),
+        }
+    }
+
+    /// Whether the actor holding the account is still running
+    pub fn connected(&self) -> bool {
+        match self {
+            Self::Actor(addr) => addr.connected(),
+            Self::Shard(addr, _) => addr.connected(),
+        }
+    }
+
+    fn serve_priority(&self) {
+        match self {
+            Self::Actor(addr) => addr.do_send(ServePriority),
+            Self::Shard(addr, _) => addr.do_send(ServePriority),
+        }
+    }
+}
+
+/// The address of an account along with the priority lane of its actor
+#[derive(Clone)]
+pub struct AccountRef {
+    pub addr: AccountAddr,
+    client: u16,
+    priority: UnboundedSender<PriorityRequest>,
+}
+
+impl AccountRef {
+    pub fn new(addr: AccountAddr, client: u16, priority: UnboundedSender<PriorityRequest>) -> Self {
+        Self {
+            addr,
+            client,
+            priority,
+        }
+    }
+
+    /// Fetches the state of the account ahead of the messages waiting in its mailbox
+    ///
+    /// # Errors
+    /// If the actor stopped or doesn't answer within `wait`, an error will be returned
+    pub async fn priority_state(&self, wait: Duration) -> Result<AccountState, MailboxError> {
+        let (reply, response) = oneshot::channel();
+        self.send_priority(PriorityRequest::GetState(self.client, reply))?;
+        Self::answer(response, wait).await
+    }
+
+    /// Fetches the balances of the account ahead of the messages waiting in its mailbox
+    ///
+    /// # Errors
+    /// If the actor stopped or doesn't answer within `wait`, an error will be returned
+    pub async fn priority_balances(&self, wait: Duration) -> Result<Balances, MailboxError> {
+        let (reply, response) = oneshot::channel();
+        self.send_priority(PriorityRequest::GetBalances(self.client, reply))?;
+        Self::answer(response, wait).await
+    }
+
+    async fn answer<T>(response: oneshot::Receiver<T>, wait: Duration) -> Result<T, MailboxError> {
+        match timeout(wait, response).await {
+            Ok(Ok(state)) => Ok(state),
+            Ok(Err(_)) => Err(MailboxError::Closed),
+            Err(_) => Err(MailboxError::Timeout),
+        }
+    }
+
+    fn send_priority(&self, request: PriorityRequest) -> Result<(), MailboxError> {
+        self.priority
+            .send(request)
+            .map_err(|_| MailboxError::Closed)?;
+        self.addr.serve_priority();
+        Ok(())
+    }
 }
 
 impl AccountHandler {
-    /// Creates a new account and starts the actor
-    pub fn new(client_id: u16) -> Addr<Self> {
-        Supervisor::start(move |_| Self {
-            client: client_id,
-            account: Account::new(client_id),
-        })
+    /// Starts the actor with an existing account, in the arbiter given or the current one.
+    /// Changes are reported to the store writer, if there's one, and the hook is called around
+    /// every transaction.
+    pub fn from_account(
+        account: Account,
+        store: Option<Addr<StoreWriter>>,
+        hook: Option<Arc<dyn ProcessingHook>>,
+        arbiter: Option<&ArbiterHandle>,
+    ) -> AccountRef {
+        let (priority, requests) = mpsc::unbounded_channel();
+        let client = account.client();
+        let actor = move |_: &mut Context<Self>| Self {
+            client,
+            account,
+            store,
+            priority: requests,
+            hook,
+        };
+        let addr = match arbiter {
+            Some(arbiter) => Supervisor::start_in_arbiter(arbiter, actor),
+            None => Supervisor::start(actor),
+        };
+        AccountRef::new(AccountAddr::Actor(addr), client, priority)
+    }
+
+    /// Answers the pending priority requests. Every handler starts with it, so those requests
+    /// never wait for more than the message being handled.
+    fn serve_priority(&mut self) {
+        while let Ok(request) = self.priority.try_recv() {
+            match request {
+                // the requester may have given up waiting
+                PriorityRequest::GetState(_, reply) => {
+                    let _ = reply.send(AccountState::from(&self.account));
+                }
+                PriorityRequest::GetBalances(_, reply) => {
+                    let _ = reply.send(Balances::from(&self.account));
+                }
+            }
+        }
+    }
+
+    /// Reports a change of the account to the store writer, if there's one
+    fn mark_dirty(&self, ctx: &mut Context<Self>) {
+        if let Some(store) = &self.store {
+            store.do_send(MarkDirty {
+                client: self.client,
+                actor: AccountAddr::Actor(ctx.address()),
+            });
+        }
+    }
+}
+
+/// Calls the hook at a point of the transaction, if there's one, telling whether to go on
+pub fn run_hook(
+    hook: Option<&Arc<dyn ProcessingHook>>,
+    point: HookPoint,
+    tx: &Transaction,
+) -> bool {
+    match hook.map(|hook| hook.on(point, tx)) {
+        None | Some(HookAction::Continue) => true,
+        // a slow account blocks its thread
+        Some(HookAction::Delay(delay)) => {
+            std::thread::sleep(delay);
+            true
+        }
+        Some(HookAction::Fail) => false,
+        Some(HookAction::Panic) => panic!("Account {} crashed on {}", tx.client, tx.tx),
     }
 }
 
 impl Actor for AccountHandler {
     type Context = Context<Self>;
 
-    fn started(&mut self, _: &mut Self::Context) {
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // the engine bounds the transactions sent ahead of their answer itself, while a full
+        // mailbox would defer the messages sent to it, which could then overtake each other
+        ctx.set_mailbox_capacity(0);
         info!("Actor from account {} started.", self.client);
     }
 
@@ -37,21 +309,45 @@ impl Supervised for AccountHandler {
     }
 }
 
-impl Handler<Transaction> for AccountHandler {
-    type Result = Result<(), TransactionError>;
+/// The answer to a message, left out when a hook fails the transaction or a shard doesn't hold
+/// the account
+pub struct Reply<T>(pub Option<T>);
+
+impl<A, M> MessageResponse<A, M> for Reply<M::Result>
+where
+    A: Actor,
+    M: Message,
+{
+    fn handle(self, _: &mut A::Context, tx: Option<OneshotSender<M::Result>>) {
+        // dropping the sender closes the request, as if the actor stopped before answering
+        if let (Some(result), Some(tx)) = (self.0, tx) {
+            let _ = tx.send(result);
+        }
+    }
+}
+
+impl Handler<Deliver> for AccountHandler {
+    type Result = Reply<Result<(), TransactionError>>;
 
-    fn handle(&mut self, tx: Transaction, _ctx: &mut Self::Context) -> Self::Result {
-        match tx.transaction_type {
-            TransactionType::Deposit => self
-                .account
-                .deposit(tx.amount.ok_or(TransactionError::InvalidOperation)?, tx.tx),
-            TransactionType::Withdrawal => self
-                .account
-                .withdraw(tx.amount.ok_or(TransactionError::InvalidOperation)?, tx.tx),
-            TransactionType::Dispute => self.account.dispute(tx.tx),
-            TransactionType::Resolve => self.account.resolve(tx.tx),
-            TransactionType::Chargeback => self.account.chargeback(tx.tx),
+    fn handle(&mut self, msg: Deliver, ctx: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+        let Deliver {
+            transaction: tx,
+            ticket,
+        } = msg;
+        if !run_hook(self.hook.as_ref(), HookPoint::PreApply, &tx) {
+            return Reply(None);
+        }
+        let Some(result) = ticket.apply(|| self.account.apply(&tx)) else {
+            return Reply(None);
+        };
+        if result.is_ok() {
+            self.mark_dirty(ctx);
         }
+        if !run_hook(self.hook.as_ref(), HookPoint::PostApply, &tx) {
+            return Reply(None);
+        }
+        Reply(Some(result))
     }
 }
 
@@ -59,7 +355,184 @@ impl Handler<Collect> for AccountHandler {
     type Result = MessageResult<Collect>;
 
     fn handle(&mut self, _: Collect, ctx: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
         ctx.stop();
         MessageResult(self.account.clone())
     }
 }
+
+impl Handler<GetState> for AccountHandler {
+    type Result = MessageResult<GetState>;
+
+    fn handle(&mut self, _: GetState, _: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+        MessageResult(AccountState::from(&self.account))
+    }
+}
+
+impl Handler<Restore> for AccountHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: Restore, ctx: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+        self.account.restore(msg.0);
+        self.mark_dirty(ctx);
+    }
+}
+
+impl Handler<ServePriority> for AccountHandler {
+    type Result = ();
+
+    fn handle(&mut self, _: ServePriority, _: &mut Self::Context) -> Self::Result {
+        self.serve_priority();
+    }
+}
+
+/// Sends a message to the actor, waiting at most `config.timeout` for each attempt.
+///
+/// A timed out attempt keeps waiting on the same request instead of sending the message again,
+/// since the message may already be sitting in the actor's mailbox. The message is only sent
+/// again when the mailbox reports it was closed, which the actor may do after handling it: a
+/// transaction is sent again along with its ticket, so the actor answers how it ended instead of
+/// applying it twice.
+///
+/// # Errors
+/// If the actor doesn't answer after `config.retries` extra attempts, the last mailbox error is
+/// returned
+pub async fn send_with_retry<M>(
+    actor: &AccountAddr,
+    msg: M,
+    config: DispatchConfig,
+) -> Result<M::Result, MailboxError>
+where
+    M: Message + Clone + Send + 'static,
+    M::Result: Send,
+    AccountHandler: Handler<M>,
+    ShardedAccountHandler: Handler<ForClient<M>>,
+{
+    let mut attempt = 0;
+    loop {
+        let mut request = actor.send(msg.clone());
+        let error = loop {
+            match timeout(config.timeout, &mut request).await {
+                Ok(Ok(result)) => return Ok(result),
+                Ok(Err(e)) => break e,
+                Err(_) if attempt < config.retries => {
+                    attempt += 1;
+                    warn!("Actor did not answer in time, waiting again (attempt {attempt})");
+                }
+                Err(_) => return Err(MailboxError::Timeout),
+            }
+        };
+        if attempt >= config.retries {
+            return Err(error);
+        }
+        attempt += 1;
+        warn!("Could not deliver message: {error}, sending again (attempt {attempt})");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use actix::{Arbiter, MailboxError};
+    use rust_decimal_macros::dec;
+
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::hooks::{HookAction, HookPoint, ProcessingHook};
+    use crate::model::{Account, AccountRecord, GetState, Transaction, TransactionType};
+    use crate::transaction::{send_with_retry, AccountAddr, AccountHandler, Deliver};
+
+    /// Takes the same action before applying every transaction, counting them
+    struct Stall {
+        action: HookAction,
+        calls: AtomicU32,
+    }
+
+    impl ProcessingHook for Stall {
+        fn on(&self, point: HookPoint, _: &Transaction) -> HookAction {
+            if point != HookPoint::PreApply {
+                return HookAction::Continue;
+            }
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.action
+        }
+    }
+
+    /// Starts an account on its own arbiter, so a hook blocking it doesn't block the test
+    fn stalled_account(action: HookAction) -> (Arbiter, AccountAddr, Arc<Stall>) {
+        let arbiter = Arbiter::new();
+        let hook = Arc::new(Stall {
+            action,
+            calls: AtomicU32::new(0),
+        });
+        let account = AccountHandler::from_account(
+            Account::new(1, EngineConfig::default()),
+            None,
+            Some(hook.clone()),
+            Some(&arbiter.handle()),
+        );
+        (arbiter, account.addr, hook)
+    }
+
+    #[actix::test]
+    async fn test_priority_state_skips_queued_transactions() {
+        let account = AccountHandler::from_account(
+            Account::new(1, EngineConfig::default()),
+            None,
+            None,
+            None,
+        );
+        let AccountAddr::Actor(addr) = &account.addr else {
+            unreachable!("The account was started in its own actor");
+        };
+        for tx in 0..100 {
+            let deposit = Transaction::for_test(TransactionType::Deposit, 1, tx, Some(dec!(1)));
+            addr.do_send(Deliver::new(deposit));
+        }
+        let state = account
+            .priority_state(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(AccountRecord::from(&state).total, dec!(0));
+        let state = addr.send(GetState).await.unwrap();
+        assert_eq!(AccountRecord::from(&state).total, dec!(100));
+    }
+
+    #[actix::test]
+    async fn test_send_with_retry_waits_for_a_silent_actor_then_times_out() {
+        let (arbiter, addr, hook) = stalled_account(HookAction::Delay(Duration::from_millis(500)));
+        let config = DispatchConfig {
+            timeout: Duration::from_millis(20),
+            retries: 3,
+        };
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1)));
+        let start = Instant::now();
+        let result = send_with_retry(&addr, Deliver::new(deposit), config).await;
+        let elapsed = start.elapsed();
+        assert!(matches!(result, Err(MailboxError::Timeout)));
+        // the first attempt and every retry waited the whole timeout, well before the answer
+        assert!(elapsed >= config.timeout * (config.retries + 1));
+        assert!(elapsed < Duration::from_millis(500));
+        // a timed out attempt keeps waiting on the same request instead of sending it again
+        assert_eq!(hook.calls.load(Ordering::SeqCst), 1);
+        arbiter.stop();
+    }
+
+    #[actix::test]
+    async fn test_send_with_retry_resends_until_the_retries_run_out() {
+        let (arbiter, addr, hook) = stalled_account(HookAction::Fail);
+        let config = DispatchConfig {
+            timeout: Duration::from_secs(1),
+            retries: 3,
+        };
+        let deposit = Transaction::for_test(TransactionType::Deposit, 1, 1, Some(dec!(1)));
+        let result = send_with_retry(&addr, Deliver::new(deposit), config).await;
+        assert!(matches!(result, Err(MailboxError::Closed)));
+        assert_eq!(hook.calls.load(Ordering::SeqCst), config.retries + 1);
+        arbiter.stop();
+    }
+}
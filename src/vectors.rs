@@ -0,0 +1,256 @@
+//! A corpus of tricky scenarios with the accounts expected under the default engine options, run
+//! through an engine configured like a deployment to certify its options before a rollout: a
+//! vector that fails shows a behavior the options change.
+
+use anyhow::{ensure, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::cli::TestVectorsArgs;
+use crate::config::{DispatchConfig, EngineConfig};
+use crate::engine::Engine;
+use crate::model::{Account, AccountRecord, Transaction, TransactionType};
+
+/// A scenario: the transactions in the order they are applied and the accounts expected after
+/// them, sorted by client
+struct Vector {
+    name: &'static str,
+    transactions: Vec<Transaction>,
+    expected: Vec<AccountRecord>,
+}
+
+fn transaction(
+    transaction_type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+) -> Transaction {
+    Transaction {
+        transaction_type,
+        client,
+        tx,
+        amount: amount.map(Into::into),
+        reference: None,
+        to_client: None,
+        action: None,
+    }
+}
+
+fn deposit(client: u16, tx: u32, amount: Decimal) -> Transaction {
+    transaction(TransactionType::Deposit, client, tx, Some(amount))
+}
+
+fn withdrawal(client: u16, tx: u32, amount: Decimal) -> Transaction {
+    transaction(TransactionType::Withdrawal, client, tx, Some(amount))
+}
+
+fn dispute(client: u16, tx: u32) -> Transaction {
+    transaction(TransactionType::Dispute, client, tx, None)
+}
+
+fn resolve(client: u16, tx: u32) -> Transaction {
+    transaction(TransactionType::Resolve, client, tx, None)
+}
+
+fn chargeback(client: u16, tx: u32) -> Transaction {
+    transaction(TransactionType::Chargeback, client, tx, None)
+}
+
+fn account(client: u16, available: Decimal, held: Decimal, locked: bool) -> AccountRecord {
+    AccountRecord {
+        client,
+        available,
+        held,
+        total: available + held,
+        locked,
+    }
+}
+
+fn corpus() -> Vec<Vector> {
+    vec![
+        Vector {
+            name: "dispute after withdrawal",
+            // the deposit was mostly withdrawn, so there's no funds left to hold
+            transactions: vec![
+                deposit(1, 1, dec!(10)),
+                withdrawal(1, 2, dec!(8)),
+                dispute(1, 1),
+            ],
+            expected: vec![account(1, dec!(2), dec!(0), false)],
+        },
+        Vector {
+            name: "dispute of a withdrawal",
+            transactions: vec![
+                deposit(1, 1, dec!(10)),
+                withdrawal(1, 2, dec!(4)),
+                dispute(1, 2),
+            ],
+            expected: vec![account(1, dec!(6), dec!(0), false)],
+        },
+        Vector {
+            name: "chargeback then deposit",
+            // the chargeback locks the account, so the later deposit is rejected
+            transactions: vec![
+                deposit(1, 1, dec!(10)),
+                deposit(1, 2, dec!(5)),
+                dispute(1, 1),
+                chargeback(1, 1),
+                deposit(1, 3, dec!(7)),
+            ],
+            expected: vec![account(1, dec!(5), dec!(0), true)],
+        },
+        Vector {
+            name: "resolve then chargeback",
+            transactions: vec![
+                deposit(1, 1, dec!(10)),
+                dispute(1, 1),
+                resolve(1, 1),
+                chargeback(1, 1),
+            ],
+            expected: vec![account(1, dec!(10), dec!(0), false)],
+        },
+        Vector {
+            name: "dispute twice",
+            transactions: vec![deposit(1, 1, dec!(10)), dispute(1, 1), dispute(1, 1)],
+            expected: vec![account(1, dec!(0), dec!(10), false)],
+        },
+        Vector {
+            name: "dispute of another client's transaction",
+            transactions: vec![
+                deposit(1, 1, dec!(10)),
+                deposit(2, 2, dec!(3)),
+                dispute(2, 1),
+                dispute(1, 9),
+            ],
+            expected: vec![
+                account(1, dec!(10), dec!(0), false),
+                account(2, dec!(3), dec!(0), false),
+            ],
+        },
+        Vector {
+            name: "duplicate tx ids",
+            // a repeated id is credited again and its dispute holds the last amount
+            transactions: vec![
+                deposit(1, 1, dec!(10)),
+                deposit(1, 1, dec!(4)),
+                deposit(2, 1, dec!(6)),
+                dispute(1, 1),
+            ],
+            expected: vec![
+                account(1, dec!(10), dec!(4), false),
+                account(2, dec!(6), dec!(0), false),
+            ],
+        },
+        Vector {
+            name: "withdrawal of every available fund",
+            transactions: vec![
+                deposit(1, 1, dec!(1.0001)),
+                withdrawal(1, 2, dec!(1.0001)),
+                withdrawal(1, 3, dec!(0.0001)),
+            ],
+            expected: vec![account(1, dec!(0), dec!(0), false)],
+        },
+        Vector {
+            name: "precision edge cases",
+            // the amounts are rounded half to even on the fourth decimal place
+            transactions: vec![
+                deposit(1, 1, dec!(0.00005)),
+                deposit(1, 2, dec!(0.00015)),
+                deposit(1, 3, dec!(79228162514264.3375)),
+                withdrawal(1, 4, dec!(79228162514264.3375)),
+            ],
+            expected: vec![account(1, dec!(0.0002), dec!(0), false)],
+        },
+    ]
+}
+
+/// Runs the transactions of a vector through an engine of the options given
+///
+/// # Errors
+/// If an account doesn't answer, an error will be returned
+async fn run(
+    vector: &Vector,
+    dispatch: DispatchConfig,
+    config: EngineConfig,
+) -> Result<Vec<AccountRecord>> {
+    let mut engine = Engine::new(dispatch, config);
+    for transaction in &vector.transactions {
+        engine.apply(transaction.clone()).await?;
+    }
+    let mut accounts = engine.collect().await?;
+    accounts.sort_unstable_by_key(Account::client);
+    Ok(accounts.iter().map(AccountRecord::from).collect())
+}
+
+/// Runs every vector through an engine of the options given and prints whether it passed, with
+/// the accounts that differ from the expected ones
+///
+/// # Errors
+/// If an account doesn't answer or a vector fails, an error will be returned
+pub async fn test_vectors(args: &TestVectorsArgs) -> Result<()> {
+    let corpus = corpus();
+    let mut failed = 0;
+    for vector in &corpus {
+        let records = run(vector, args.engine.dispatch(), args.engine.engine()).await?;
+        if records == vector.expected {
+            println!("PASS {}", vector.name);
+            continue;
+        }
+        failed += 1;
+        println!("FAIL {}", vector.name);
+        for expected in &vector.expected {
+            let actual = records
+                .iter()
+                .find(|record| record.client == expected.client);
+            if actual != Some(expected) {
+                println!("  expected {expected:?}, got {actual:?}");
+            }
+        }
+        for actual in &records {
+            if !vector.expected.iter().any(|e| e.client == actual.client) {
+                println!("  unexpected {actual:?}");
+            }
+        }
+    }
+    eprintln!(
+        "{} of {} vectors passed",
+        corpus.len() - failed,
+        corpus.len()
+    );
+    ensure!(failed == 0, "{failed} vectors failed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DispatchConfig, EngineConfig, WithdrawalDisputes};
+    use crate::vectors::{corpus, run};
+
+    #[actix::test]
+    async fn test_default_options_pass_every_vector() {
+        for vector in corpus() {
+            let records = run(&vector, DispatchConfig::default(), EngineConfig::default())
+                .await
+                .unwrap();
+            assert_eq!(records, vector.expected, "{}", vector.name);
+        }
+    }
+
+    #[actix::test]
+    async fn test_options_changing_a_behavior_fail_its_vector() {
+        let config = EngineConfig {
+            withdrawal_disputes: WithdrawalDisputes::Hold,
+            ..EngineConfig::default()
+        };
+        let mut failed = Vec::new();
+        for vector in corpus() {
+            let records = run(&vector, DispatchConfig::default(), config)
+                .await
+                .unwrap();
+            if records != vector.expected {
+                failed.push(vector.name);
+            }
+        }
+        assert_eq!(failed, ["dispute of a withdrawal"]);
+    }
+}
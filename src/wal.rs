@@ -0,0 +1,131 @@
+use std::path::Path;
+
+use anyhow::{ensure, Result};
+use tokio::io::stdout;
+use tracing::info;
+
+use crate::cli::ReplayArgs;
+use crate::config::JournalConfig;
+use crate::csv::write_records;
+use crate::engine::Engine;
+use crate::journal::{JournalReader, JournalWriter};
+use crate::model::{Account, Transaction};
+use crate::snapshot::Snapshot;
+
+/// Appends every transaction delivered to an account to a log, in the journal format, and waits
+/// until it reaches the disk before the account applies it. Unlike the journal, which only gets
+/// the accepted transactions once they were applied, a crash never loses a transaction an account
+/// may have applied: replaying the log applies it again, and rejects again the ones that were
+/// rejected.
+pub struct WriteAheadLog {
+    log: JournalWriter,
+}
+
+impl WriteAheadLog {
+    /// Opens the log for appending, creating it if needed
+    ///
+    /// # Errors
+    /// If the file can't be read or written or it has an outdated version, an error will be
+    /// returned
+    pub async fn open(path: &Path) -> Result<Self> {
+        let config = JournalConfig {
+            sync_every: Some(1),
+            ..JournalConfig::default()
+        };
+        let log = JournalWriter::open(path).await?.with_config(config);
+        Ok(Self { log })
+    }
+
+    /// Appends a transaction to the log, returning once it reached the disk
+    ///
+    /// # Errors
+    /// If the log can't be written, an error will be returned
+    pub async fn append(&mut self, transaction: &Transaction) -> Result<()> {
+        self.log.append(transaction).await
+    }
+}
+
+/// Rebuilds the accounts from a write-ahead log, or a journal, and prints them
+///
+/// # Errors
+/// If the files can't be read, or the log starts after the snapshot, an error will be returned
+pub async fn replay(args: &ReplayArgs) -> Result<()> {
+    let mut engine = Engine::new(args.engine.dispatch(), args.engine.engine());
+    let mut after = 0;
+    if let Some(path) = &args.restore {
+        let snapshot = Snapshot::read(path).await?;
+        after = snapshot.journal_seq;
+        engine.restore(snapshot)?;
+    }
+    let mut log = JournalReader::open(&args.log).await?;
+    ensure!(
+        log.base_seq() <= after,
+        "The log starts after event {after}, restore a newer snapshot"
+    );
+    let last_seq = engine.replay(&mut log, after, args.until).await?;
+    info!("Replayed events {} to {last_seq}", after + 1);
+    let mut accounts = engine.collect().await?;
+    accounts.sort_unstable_by_key(Account::client);
+    write_records(stdout(), &accounts).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::config::{DispatchConfig, EngineConfig};
+    use crate::engine::Engine;
+    use crate::journal::JournalReader;
+    use crate::model::{Account, AccountRecord, Transaction, TransactionType};
+    use crate::wal::WriteAheadLog;
+
+    fn records(accounts: &[Account]) -> Vec<AccountRecord> {
+        accounts.iter().map(AccountRecord::from).collect()
+    }
+
+    #[actix::test]
+    async fn test_replaying_the_log_rebuilds_the_accounts() {
+        let path = std::env::temp_dir().join(format!("wal-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let wal = WriteAheadLog::open(&path).await.unwrap();
+        let mut engine =
+            Engine::new(DispatchConfig::default(), EngineConfig::default()).with_wal(wal);
+        engine
+            .apply(Transaction::for_test(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(dec!(10)),
+            ))
+            .await
+            .unwrap();
+        // rejected, so replaying it rejects it again
+        engine
+            .apply(Transaction::for_test(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(dec!(20)),
+            ))
+            .await
+            .unwrap();
+        engine
+            .apply(Transaction::for_test(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Some(dec!(4)),
+            ))
+            .await
+            .unwrap();
+        let accounts = engine.collect().await.unwrap();
+
+        let mut log = JournalReader::open(&path).await.unwrap();
+        let mut engine = Engine::new(DispatchConfig::default(), EngineConfig::default());
+        assert_eq!(engine.replay(&mut log, 0, None).await.unwrap(), 3);
+        let replayed = engine.collect().await.unwrap();
+        assert_eq!(records(&replayed), records(&accounts));
+        assert_eq!(records(&replayed)[0].total, dec!(6));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use anyhow::{ensure, Result};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::webhook::{EmailConfig, Notification, Notifier};
+
+/// How long a mail may take to be accepted by the server before it is counted as failed
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Mails every notification through an SMTP server, its summary as the subject and the
+/// notification as json in the body
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl EmailNotifier {
+    /// Creates a client of the SMTP server of the config
+    ///
+    /// # Errors
+    /// If the url or an address is invalid, or there's no recipient, an error will be returned
+    pub fn new(config: &EmailConfig) -> Result<Self> {
+        ensure!(!config.to.is_empty(), "The notifications have no recipient");
+        Ok(Self {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(&config.url)?
+                .timeout(Some(TIMEOUT))
+                .build(),
+            from: config.from.parse()?,
+            to: config
+                .to
+                .iter()
+                .map(|to| to.parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Notifier for EmailNotifier {
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(notification.event.to_string())
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let body = serde_json::to_string_pretty(notification)?;
+        self.transport.send(message.body(body)?).await?;
+        Ok(())
+    }
+}
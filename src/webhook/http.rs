@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::Client;
+
+use crate::webhook::{Notification, Notifier};
+
+/// How long a delivery may take before it is counted as failed
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts every notification as a json object to an http endpoint, which must answer with a
+/// success status
+pub struct HttpSender {
+    client: Client,
+    url: String,
+}
+
+impl HttpSender {
+    /// Creates a client posting to the url
+    ///
+    /// # Errors
+    /// If the http client can't be built, an error will be returned
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            url: url.to_owned(),
+        })
+    }
+}
+
+impl Notifier for HttpSender {
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(notification)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
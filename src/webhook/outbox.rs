@@ -0,0 +1,169 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+use crate::journal::now_ms;
+use crate::snapshot::write_atomically;
+use crate::webhook::{Event, Notification};
+
+/// A notification waiting in the outbox, along with how its deliveries went so far
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OutboxEntry {
+    pub notification: Notification,
+    /// How many deliveries failed
+    pub attempts: u32,
+    /// Milliseconds since the unix epoch before which the notification isn't sent again
+    pub next_attempt_ms: u64,
+    /// Why the last delivery failed
+    pub last_error: Option<String>,
+    /// The channels the notification was delivered to, not sent again when it is retried
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub delivered: Vec<String>,
+}
+
+/// Keeps the notifications to deliver in a directory, one json file per notification, so they
+/// survive restarts. Notifications are only removed once delivered, or moved to the `dead`
+/// directory once they run out of attempts.
+pub struct Outbox {
+    pending: PathBuf,
+    dead: PathBuf,
+    /// The id of the last notification added
+    last_id: Mutex<u64>,
+}
+
+impl Outbox {
+    /// Opens the outbox kept in `dir`, creating it if needed
+    ///
+    /// # Errors
+    /// If the directory can't be created or read, an error will be returned
+    pub async fn open(dir: &Path) -> Result<Self> {
+        let (pending, dead) = (dir.join("pending"), dir.join("dead"));
+        fs::create_dir_all(&pending).await?;
+        fs::create_dir_all(&dead).await?;
+        let last_id = last_id(&pending).await?.max(last_id(&dead).await?);
+        Ok(Self {
+            pending,
+            dead,
+            last_id: Mutex::new(last_id),
+        })
+    }
+
+    /// Adds the notification of an event, to be delivered as soon as possible
+    ///
+    /// # Errors
+    /// If the notification can't be written, an error will be returned
+    pub async fn push(&self, event: Event) -> Result<u64> {
+        let mut last_id = self.last_id.lock().await;
+        let id = *last_id + 1;
+        let entry = OutboxEntry {
+            notification: Notification {
+                id,
+                timestamp_ms: now_ms(),
+                event,
+            },
+            attempts: 0,
+            next_attempt_ms: 0,
+            last_error: None,
+            delivered: Vec::new(),
+        };
+        self.write(&entry).await?;
+        *last_id = id;
+        Ok(id)
+    }
+
+    /// The notifications not delivered yet, ordered by id
+    ///
+    /// # Errors
+    /// If the directory or a notification can't be read, an error will be returned
+    pub async fn pending(&self) -> Result<Vec<OutboxEntry>> {
+        read_entries(&self.pending).await
+    }
+
+    /// The notifications that ran out of attempts, ordered by id
+    #[cfg(test)]
+    pub async fn dead(&self) -> Result<Vec<OutboxEntry>> {
+        read_entries(&self.dead).await
+    }
+
+    /// Replaces a pending notification, like after a failed delivery
+    ///
+    /// # Errors
+    /// If the notification can't be written, an error will be returned
+    pub async fn write(&self, entry: &OutboxEntry) -> Result<()> {
+        let path = entry_path(&self.pending, entry.notification.id);
+        write_atomically(&path, &serde_json::to_vec(entry)?).await
+    }
+
+    /// Removes a delivered notification
+    ///
+    /// # Errors
+    /// If the notification can't be removed, an error will be returned
+    pub async fn remove(&self, id: u64) -> Result<()> {
+        fs::remove_file(entry_path(&self.pending, id)).await?;
+        Ok(())
+    }
+
+    /// Moves a notification that ran out of attempts to the dead letters
+    ///
+    /// # Errors
+    /// If the notification can't be moved, an error will be returned
+    pub async fn bury(&self, entry: &OutboxEntry) -> Result<()> {
+        let id = entry.notification.id;
+        let path = entry_path(&self.dead, id);
+        write_atomically(&path, &serde_json::to_vec(entry)?).await?;
+        self.remove(id).await
+    }
+}
+
+fn entry_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id}.json"))
+}
+
+/// The notifications of a directory, ordered by id
+async fn read_entries(dir: &Path) -> Result<Vec<OutboxEntry>> {
+    let mut entries = Vec::new();
+    for path in entry_paths(dir).await? {
+        let bytes = match fs::read(path).await {
+            Ok(bytes) => bytes,
+            // delivered or buried since the directory was listed
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        entries.push(serde_json::from_slice(&bytes)?);
+    }
+    entries.sort_unstable_by_key(|entry: &OutboxEntry| entry.notification.id);
+    Ok(entries)
+}
+
+/// The notification files of a directory, leaving out the temporary ones
+async fn entry_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(paths),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// The highest id of the notifications of a directory
+async fn last_id(dir: &Path) -> Result<u64> {
+    Ok(entry_paths(dir)
+        .await?
+        .iter()
+        .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+        .max()
+        .unwrap_or(0))
+}
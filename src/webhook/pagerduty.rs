@@ -0,0 +1,57 @@
+use anyhow::Result;
+use reqwest::Client;
+use serde_json::json;
+
+use crate::webhook::http::TIMEOUT;
+use crate::webhook::{EventKind, Notification, Notifier};
+
+/// The endpoint of the PagerDuty Events API v2
+const EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+/// Triggers a PagerDuty incident for every notification. The incidents are deduplicated by the
+/// id of the notification, so a notification delivered twice opens a single incident.
+pub struct PagerDutyNotifier {
+    client: Client,
+    routing_key: String,
+}
+
+impl PagerDutyNotifier {
+    /// Creates a client triggering the incidents of the integration with the routing key
+    ///
+    /// # Errors
+    /// If the http client can't be built, an error will be returned
+    pub fn new(routing_key: &str) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            routing_key: routing_key.to_owned(),
+        })
+    }
+}
+
+impl Notifier for PagerDutyNotifier {
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let severity = match notification.event.kind() {
+            EventKind::Breaker => "critical",
+            EventKind::Lock => "error",
+            EventKind::Chargeback => "warning",
+        };
+        let event = json!({
+            "routing_key": self.routing_key,
+            "event_action": "trigger",
+            "dedup_key": format!("transaction_test-{}", notification.id),
+            "payload": {
+                "summary": notification.event.to_string(),
+                "source": "transaction_test",
+                "severity": severity,
+                "custom_details": notification,
+            },
+        });
+        self.client
+            .post(EVENTS_URL)
+            .json(&event)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
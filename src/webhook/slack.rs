@@ -0,0 +1,37 @@
+use anyhow::Result;
+use reqwest::Client;
+
+use crate::webhook::http::TIMEOUT;
+use crate::webhook::{Notification, Notifier};
+
+/// Posts a message for every notification to a Slack incoming webhook
+pub struct SlackNotifier {
+    client: Client,
+    url: String,
+}
+
+impl SlackNotifier {
+    /// Creates a client posting to the incoming webhook url
+    ///
+    /// # Errors
+    /// If the http client can't be built, an error will be returned
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            url: url.to_owned(),
+        })
+    }
+}
+
+impl Notifier for SlackNotifier {
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let text = format!("{} (notification {})", notification.event, notification.id);
+        self.client
+            .post(&self.url)
+            .json(&serde_json::json!({ "text": text }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
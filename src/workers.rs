@@ -0,0 +1,59 @@
+use actix::{Arbiter, ArbiterHandle};
+use tracing::{error, info};
+
+/// Worker threads running the account actors instead of the main thread, each pinned to a core
+pub struct Workers {
+    arbiters: Vec<Arbiter>,
+}
+
+impl Workers {
+    /// Starts one arbiter per core id, pinning its thread to that core. Cores that can't be pinned
+    /// are logged and their arbiter runs unpinned.
+    pub fn pinned(cores: &[usize]) -> Self {
+        let arbiters = cores
+            .iter()
+            .map(|&core| {
+                let arbiter = Arbiter::new();
+                arbiter.spawn_fn(move || match pin_to_core(core) {
+                    Ok(()) => info!("Worker pinned to core {core}"),
+                    Err(e) => error!("Could not pin worker to core {core}: {e}"),
+                });
+                arbiter
+            })
+            .collect();
+        Self { arbiters }
+    }
+
+    /// The arbiter running the actor of a client. A client always runs on the same worker.
+    pub fn for_client(&self, client: u16) -> ArbiterHandle {
+        self.arbiters[self.index_for(client)].handle()
+    }
+
+    /// The index of the worker running the actor of a client, in the order of the cores
+    pub fn index_for(&self, client: u16) -> usize {
+        usize::from(client) % self.arbiters.len()
+    }
+
+    /// Stops every worker once the messages already sent to it are handled
+    pub fn stop(self) {
+        for arbiter in self.arbiters {
+            arbiter.stop();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_core(core: usize) -> nix::Result<()> {
+    use nix::sched::{sched_setaffinity, CpuSet};
+    use nix::unistd::Pid;
+
+    let mut set = CpuSet::new();
+    set.set(core)?;
+    // pid 0 is the calling thread
+    sched_setaffinity(Pid::from_raw(0), &set)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_: usize) -> Result<(), &'static str> {
+    Err("cpu affinity is only supported on linux")
+}
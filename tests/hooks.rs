@@ -0,0 +1,49 @@
+//! Drives the engine through the hooks exported with the `test-support` feature, as tests outside
+//! of the crate do
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust_decimal_macros::dec;
+use transaction_test::config::{DispatchConfig, EngineConfig};
+use transaction_test::engine::{Applied, Engine};
+use transaction_test::hooks::{HookAction, HookPoint, ProcessingHook};
+use transaction_test::model::{AccountRecord, Transaction, TransactionType};
+
+/// Delays the first transactions applied by the accounts
+struct SlowStart {
+    delay: Duration,
+    times: u32,
+    calls: AtomicU32,
+}
+
+impl ProcessingHook for SlowStart {
+    fn on(&self, point: HookPoint, _: &Transaction) -> HookAction {
+        if point != HookPoint::PreApply || self.calls.fetch_add(1, Ordering::SeqCst) >= self.times {
+            return HookAction::Continue;
+        }
+        HookAction::Delay(self.delay)
+    }
+}
+
+#[actix::test]
+async fn test_slow_accounts_are_retried_until_they_answer() {
+    let hook = Arc::new(SlowStart {
+        delay: Duration::from_millis(150),
+        times: 1,
+        calls: AtomicU32::new(0),
+    });
+    let dispatch = DispatchConfig {
+        timeout: Duration::from_millis(100),
+        retries: 3,
+    };
+    let mut engine = Engine::new(dispatch, EngineConfig::default())
+        .with_workers(&[0])
+        .with_hook(hook);
+    let deposit = |tx| Transaction::for_test(TransactionType::Deposit, 1, tx, Some(dec!(1)));
+    assert_eq!(engine.submit(deposit(1)).await.unwrap(), Applied::Accepted);
+    assert_eq!(engine.submit(deposit(2)).await.unwrap(), Applied::Accepted);
+    let accounts = engine.collect().await.unwrap();
+    assert_eq!(AccountRecord::from(&accounts[0]).total, dec!(2));
+}